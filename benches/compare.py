@@ -0,0 +1,58 @@
+#!/usr/bin/env python3
+"""Compare the latest `hot_paths` results with the baseline in `benches/baseline.json`.
+
+Run after `cargo bench --features bench --bench hot_paths`. Exits non zero if the mean time of
+any benchmark regressed by more than the threshold, 20% by default. `--save` replaces the
+baseline with the latest results, do that on the reference machine before a release.
+"""
+import argparse
+import json
+import os
+import sys
+
+ROOT = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+BASELINE = os.path.join(ROOT, "benches", "baseline.json")
+CRITERION = os.path.join(ROOT, "target", "criterion")
+BENCHES = ["serialize/10k_nodes", "dispatch/100k_messages", "relay/50_subscribers"]
+
+
+def latest(bench):
+    path = os.path.join(CRITERION, bench, "new", "estimates.json")
+    with open(path) as f:
+        return json.load(f)["mean"]["point_estimate"]
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("--threshold", type=float, default=0.2)
+    parser.add_argument("--save", action="store_true")
+    args = parser.parse_args()
+
+    results = {b: latest(b) for b in BENCHES}
+    if args.save:
+        with open(BASELINE, "w") as f:
+            json.dump({"mean_ns": results}, f, indent=2, sort_keys=True)
+            f.write("\n")
+        return 0
+
+    with open(BASELINE) as f:
+        baseline = json.load(f)["mean_ns"]
+    failed = False
+    for bench, mean in results.items():
+        base = baseline.get(bench)
+        if base is None:
+            print("{:<24} {:>14.0f} ns   no baseline".format(bench, mean))
+            continue
+        change = mean / base - 1
+        regressed = change > args.threshold
+        failed |= regressed
+        print(
+            "{:<24} {:>14.0f} ns {:>+8.1%}{}".format(
+                bench, mean, change, "   REGRESSED" if regressed else ""
+            )
+        )
+    return 1 if failed else 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
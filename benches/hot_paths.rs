@@ -0,0 +1,107 @@
+//! The hot paths: OSC dispatch to exact paths, full tree JSON serialization and the websocket
+//! relay fan-out.
+//!
+//! Run with `cargo bench --features bench --bench hot_paths`, then compare the results with the
+//! baseline with `benches/compare.py`.
+use ::atomic::Atomic;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use oscquery::node::{Container, GetSet};
+use oscquery::osc::{OscMessage, OscPacket, OscType};
+use oscquery::param::ParamGetSet;
+use oscquery::root::Root;
+use oscquery::service::websocket::RelayBench;
+use oscquery::value::{ClipMode, Range, ValueBuilder};
+use std::sync::Arc;
+use std::time::Duration;
+
+const MESSAGES: usize = 100_000;
+const RELAYED: usize = 1000;
+const SUBSCRIBERS: usize = 50;
+
+//containers of float leafs, returns the leaf paths
+fn tree(containers: usize, leafs: usize) -> (Root, Vec<String>) {
+    let root = Root::new(Some("bench".into()));
+    let mut paths = Vec::new();
+    for c in 0..containers {
+        let parent = root
+            .add_node(
+                Container::new(format!("c{}", c), Some("container")).unwrap(),
+                None,
+            )
+            .unwrap();
+        for l in 0..leafs {
+            let p = ParamGetSet::Float(
+                ValueBuilder::new(Arc::new(Atomic::new(0f32)) as _)
+                    .with_range(Range::MinMax(0.0, 1.0))
+                    .with_clip_mode(ClipMode::Both)
+                    .with_unit("gain".into())
+                    .build(),
+            );
+            root.add_node(
+                GetSet::new(format!("l{}", l), Some("leaf"), vec![p], None).unwrap(),
+                Some(parent),
+            )
+            .unwrap();
+            paths.push(format!("/c{}/l{}", c, l));
+        }
+    }
+    (root, paths)
+}
+
+fn message(addr: String, v: f32) -> OscMessage {
+    OscMessage {
+        addr,
+        args: vec![OscType::Float(v)],
+    }
+}
+
+fn serialize(c: &mut Criterion) {
+    let (root, _) = tree(100, 99);
+    let mut group = c.benchmark_group("serialize");
+    group.sample_size(10);
+    group.bench_function("10k_nodes", |b| {
+        b.iter(|| serde_json::to_vec(&root).unwrap())
+    });
+    group.finish();
+}
+
+fn dispatch(c: &mut Criterion) {
+    let (root, paths) = tree(10, 99);
+    let packets: Vec<OscPacket> = (0..MESSAGES)
+        .map(|i| {
+            OscPacket::Message(message(
+                paths[i % paths.len()].clone(),
+                (i % 100) as f32 / 100.0,
+            ))
+        })
+        .collect();
+    let mut group = c.benchmark_group("dispatch");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+    group.bench_function("100k_messages", |b| {
+        b.iter(|| {
+            for p in &packets {
+                root.handle_osc_packet(p, None);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn relay(c: &mut Criterion) {
+    let mut relay = RelayBench::new(SUBSCRIBERS, "/foo");
+    let msgs: Vec<OscMessage> = (0..RELAYED)
+        .map(|i| message("/foo".into(), i as f32))
+        .collect();
+    let mut group = c.benchmark_group("relay");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+    group.throughput(Throughput::Elements(RELAYED as u64));
+    group.bench_function("50_subscribers", |b| {
+        b.iter(|| assert_eq!(relay.relay(&msgs), RELAYED * SUBSCRIBERS))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, serialize, dispatch, relay);
+criterion_main!(benches);
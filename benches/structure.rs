@@ -0,0 +1,83 @@
+//! Compare the startup cost of a large tree built from code, parsed from JSON and imported from a
+//! structure snapshot.
+//!
+//! Run with `cargo bench --features snapshot`.
+use ::atomic::Atomic;
+use oscquery::node::{Access, Container, GetSet};
+use oscquery::param::ParamGetSet;
+use oscquery::root::{ParamStorage, PathSpec, Root};
+use oscquery::value::{ClipMode, Range, ValueBuilder};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const CONTAINERS: usize = 1000;
+const LEAFS: usize = 49;
+const RUNS: u32 = 5;
+
+fn param() -> ParamGetSet {
+    ParamGetSet::Float(ValueBuilder::new(Arc::new(Atomic::new(0f32)) as _).build())
+}
+
+fn build() -> Root {
+    let root = Root::new(Some("bench".into()));
+    for c in 0..CONTAINERS {
+        let parent = root
+            .add_node(
+                Container::new(format!("c{}", c), Some("container")).unwrap(),
+                None,
+            )
+            .unwrap();
+        for l in 0..LEAFS {
+            let p = ParamGetSet::Float(
+                ValueBuilder::new(Arc::new(Atomic::new(0f32)) as _)
+                    .with_range(Range::MinMax(0.0, 1.0))
+                    .with_clip_mode(ClipMode::Both)
+                    .with_unit("gain".into())
+                    .build(),
+            );
+            root.add_node(
+                GetSet::new(format!("l{}", l), Some("leaf"), vec![p], None).unwrap(),
+                Some(parent),
+            )
+            .unwrap();
+        }
+    }
+    root
+}
+
+fn storage(spec: &PathSpec) -> ParamStorage {
+    assert_eq!(Access::ReadWrite, spec.access);
+    ParamStorage::GetSet(vec![param()], None)
+}
+
+fn time<F: FnMut()>(name: &str, mut f: F) {
+    let mut total = Duration::default();
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        f();
+        total += start.elapsed();
+    }
+    println!("{:>12}: {:?}", name, total / RUNS);
+}
+
+fn main() {
+    let root = build();
+    let json = serde_json::to_vec(&root).unwrap();
+    let data = root.export_structure();
+    println!(
+        "{} nodes, json {} bytes, snapshot {} bytes",
+        root.node_count(),
+        json.len(),
+        data.len()
+    );
+
+    time("add_node", || {
+        build();
+    });
+    time("json parse", || {
+        let _: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    });
+    time("import", || {
+        Root::import_structure(&data, storage).unwrap();
+    });
+}
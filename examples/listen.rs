@@ -1,4 +1,4 @@
-use tungstenite::connect;
+use tungstenite::{connect, Message};
 use url::Url;
 
 fn main() {
@@ -13,8 +13,19 @@ fn main() {
         println!("* {}", header);
     }
 
+    //echo latency probes, if the server sends them
+    let _ = socket.write_message(Message::Text("{\"COMMAND\":\"LATENCY_PROBES\"}".into()));
+
     loop {
         let v = socket.read_message();
         println!("{:?}", v);
+        if let Ok(Message::Text(t)) = &v {
+            if let Ok(v) = serde_json::from_str::<serde_json::Value>(t) {
+                if v["COMMAND"] == "LATENCY_PROBE" {
+                    let echo = serde_json::json!({"COMMAND": "LATENCY_ECHO", "DATA": v["DATA"]});
+                    let _ = socket.write_message(Message::Text(echo.to_string()));
+                }
+            }
+        }
     }
 }
@@ -1,14 +1,16 @@
 use ::atomic::Atomic;
+use oscquery::error::OscQueryError;
 use oscquery::func_wrap::*;
+use oscquery::node::BundleContext;
 use oscquery::param::*;
-use oscquery::root::{NodeHandle, OscQueryGraph};
+use oscquery::root::{NodeHandle, OscQueryGraph, OscWriteCallback};
 use oscquery::value::*;
 use oscquery::OscQueryServer;
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 
-fn main() -> Result<(), std::io::Error> {
+fn main() -> Result<(), OscQueryError> {
     let root = OscQueryServer::new(
         Some("example".into()),
         &SocketAddr::from_str("0.0.0.0:3000").expect("failed to bind for http"),
@@ -18,9 +20,9 @@ fn main() -> Result<(), std::io::Error> {
 
     println!(
         "http: {} osc: {} ws: {}",
-        root.http_local_addr(),
-        root.osc_local_addr(),
-        root.ws_local_addr()
+        root.http_local_addr().unwrap(),
+        root.osc_local_addr().unwrap(),
+        root.ws_local_addr().unwrap()
     );
 
     let c = oscquery::node::Container::new("foo", Some("description of foo".into()))
@@ -39,7 +41,7 @@ fn main() -> Result<(), std::io::Error> {
         Some(Box::new(OscUpdateFunc(
             move |params: &Vec<oscquery::osc::OscType>,
                   address: Option<SocketAddr>,
-                  time: Option<(u32, u32)>,
+                  time: Option<BundleContext>,
                   _handle: &NodeHandle| {
                 {
                     println!("handler got {:?} {:?} {:?}", params, address, time);
@@ -64,11 +66,11 @@ fn main() -> Result<(), std::io::Error> {
         Some(Box::new(OscUpdateFunc(
             move |params: &Vec<oscquery::osc::OscType>,
                   _address: Option<SocketAddr>,
-                  _time: Option<(u32, u32)>,
+                  _time: Option<BundleContext>,
                   _handle: &NodeHandle| {
                 {
                     if let Some(name) = params[0].clone().string() {
-                        Some(Box::new(move |r: &mut dyn OscQueryGraph| {
+                        Some(OscWriteCallback::from(move |r: &mut dyn OscQueryGraph| {
                             if let Ok(n) = oscquery::node::Get::new(
                                 name,
                                 None,
@@ -78,7 +80,7 @@ fn main() -> Result<(), std::io::Error> {
                             ) {
                                 let _ = r.add_node(n.into(), p);
                             }
-                        }) as _)
+                        }))
                     } else {
                         None
                     }
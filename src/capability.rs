@@ -0,0 +1,32 @@
+//! Fine grained capabilities, vendor extensions beyond the `EXTENSIONS` of `HOST_INFO`.
+//!
+//! Services register the capabilities they provide when they are started, clients find them
+//! under `SERVER`/`FEATURES` in `HOST_INFO` or with the `CAPABILITIES` websocket command.
+//! See `Root::capabilities`.
+
+/// The `CONTENTS_SINCE` http query.
+pub const CONTENTS_SINCE: &str = "contents-since";
+
+/// The `FILTER` http query, see `NodeFilter`.
+pub const FILTER: &str = "filter";
+
+/// The Prometheus text format statistics at `/metrics`.
+pub const METRICS: &str = "metrics";
+
+/// Namespace changes as Server-Sent Events with the `LISTEN` http query.
+pub const SSE: &str = "sse";
+
+/// The `CAPABILITIES` websocket command.
+pub const CAPABILITIES: &str = "capabilities";
+
+/// Websocket latency probes, see `WSService::set_latency_probes`.
+pub const LATENCY_PROBES: &str = "latency-probes";
+
+/// `"CURRENT":true` in websocket `LISTEN` commands.
+pub const LISTEN_CURRENT: &str = "listen-current";
+
+/// The `SERVER_SHUTDOWN` websocket notice.
+pub const SERVER_SHUTDOWN: &str = "server-shutdown";
+
+/// The version of the OSCQueryProposal that is implemented, `OSCQUERY_VERSION` in `HOST_INFO`.
+pub const OSCQUERY_VERSION: &str = "1.0";
@@ -0,0 +1,313 @@
+//! A blocking client of the HTTP service of a remote OSCQuery server.
+//!
+//! `OscQueryClient` mirrors the remote namespace in a local `Root`, see
+//! `Root::from_oscquery_json_lenient`, and reads current values with `VALUE` queries. The
+//! requests run on a runtime of the client's own, so it must not be used from within a tokio
+//! runtime, and fail once they take longer than the client's timeout.
+//!
+//! `ws` has a client of the websocket service, for streaming values.
+use crate::convert::{arg_from_json, split_type_tags};
+use crate::error::OscQueryError;
+use crate::osc::OscType;
+use crate::root::Root;
+use hyper::{client::HttpConnector, Client, StatusCode, Uri};
+use serde_json::Value;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+pub mod ws;
+
+/// How long a request may take by default, see `OscQueryClient::set_timeout`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A client of a remote server, with a mirror of its namespace.
+pub struct OscQueryClient {
+    addr: SocketAddr,
+    client: Client<HttpConnector>,
+    rt: Mutex<Runtime>,
+    timeout: Duration,
+    host_info: Value,
+    root: Root,
+    skipped: Vec<String>,
+}
+
+impl OscQueryClient {
+    /// Connect to the server at the URL, like `http://localhost:3000`, and mirror its namespace.
+    pub fn new(url: &str) -> Result<Self, OscQueryError> {
+        Self::with_timeout(url, DEFAULT_TIMEOUT)
+    }
+
+    /// Like `new` with the time each request may take, see `set_timeout`.
+    pub fn with_timeout(url: &str, timeout: Duration) -> Result<Self, OscQueryError> {
+        let url = url::Url::parse(url).map_err(|e| OscQueryError::Http(e.to_string()))?;
+        if url.scheme() != "http" {
+            return Err(OscQueryError::Http(format!(
+                "unsupported scheme {:?}",
+                url.scheme()
+            )));
+        }
+        let addr = url
+            .socket_addrs(|| None)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| OscQueryError::Http("no address for the host".to_string()))?;
+        let rt = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()?;
+        let mut client = Self {
+            addr,
+            client: Client::new(),
+            rt: Mutex::new(rt),
+            timeout,
+            host_info: Value::Null,
+            root: Root::new(None),
+            skipped: Vec::new(),
+        };
+        client.refresh()?;
+        Ok(client)
+    }
+
+    /// Fetch the namespace and `HOST_INFO` again, replacing the mirror.
+    pub fn refresh(&mut self) -> Result<(), OscQueryError> {
+        let host_info = self.get_json("/?HOST_INFO")?.unwrap_or(Value::Null);
+        let doc = self
+            .get_json("/")?
+            .ok_or_else(|| OscQueryError::InvalidDocument("empty namespace".to_string()))?;
+        let (root, skipped) = Root::from_oscquery_json_lenient(&doc)?;
+        self.root = root;
+        self.skipped = skipped;
+        self.host_info = host_info;
+        Ok(())
+    }
+
+    /// Set how long a request may take before it fails with `OscQueryError::Http`, defaults to
+    /// `DEFAULT_TIMEOUT`.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The paths of the remote nodes left out of the mirror, as of the last `refresh`, because
+    /// their `TYPE` has no local parameter, like `N` or `I`, or they are aliases of such nodes.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// The mirror of the remote namespace, as of the last `refresh`.
+    ///
+    /// Its values are those of the remote nodes when mirrored, see `get_value` for current ones.
+    pub fn root(&self) -> &Root {
+        &self.root
+    }
+
+    /// The remote `HOST_INFO`, as of the last `refresh`.
+    pub fn host_info(&self) -> &Value {
+        &self.host_info
+    }
+
+    /// The address of the remote OSC service, from `OSC_IP` and `OSC_PORT`.
+    ///
+    /// Either defaults to that of the HTTP service, like the address the server is bound to when
+    /// that is unspecified.
+    pub fn remote_osc_addr(&self) -> Option<SocketAddr> {
+        self.service_addr("OSC_IP", "OSC_PORT")
+    }
+
+    /// The address of the remote websocket service, from `WS_IP` and `WS_PORT`.
+    ///
+    /// `None` if there is neither a `WS_PORT` nor the `LISTEN` extension, which has the
+    /// websocket on the HTTP port.
+    pub fn remote_ws_addr(&self) -> Option<SocketAddr> {
+        if self.host_info["WS_PORT"].is_null() && self.host_info["EXTENSIONS"]["LISTEN"] != true {
+            return None;
+        }
+        self.service_addr("WS_IP", "WS_PORT")
+    }
+
+    /// Get the current value of the remote node at the path, with a `VALUE` query.
+    ///
+    /// The arguments are converted for the node's `TYPE` in the mirror, or queried if the node
+    /// was added since. Empty if the node has no value to read.
+    pub fn get_value(&self, path: &str) -> Result<Vec<OscType>, OscQueryError> {
+        let types = match self.root.type_string_at_path(path) {
+            Some(types) => types,
+            None => match self.get_json(&format!("{}?TYPE", path))? {
+                Some(v) => v["TYPE"]
+                    .as_str()
+                    .map(String::from)
+                    .ok_or_else(|| OscQueryError::InvalidDocument("missing TYPE".to_string()))?,
+                None => return Ok(Vec::new()),
+            },
+        };
+        let value = match self.get_json(&format!("{}?VALUE", path))? {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+        let values = value["VALUE"]
+            .as_array()
+            .ok_or_else(|| OscQueryError::TypeMismatch(types.clone()))?;
+        let tags = split_type_tags(&types);
+        if tags.len() != values.len() {
+            return Err(OscQueryError::TypeMismatch(types));
+        }
+        tags.into_iter()
+            .zip(values)
+            .map(|(t, v)| arg_from_json(t, v))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(OscQueryError::TypeMismatch(types))
+    }
+
+    fn service_addr(&self, ip: &str, port: &str) -> Option<SocketAddr> {
+        let port = match &self.host_info[port] {
+            Value::Null => self.addr.port(),
+            p => p.as_u64().filter(|p| *p <= u16::MAX as u64)? as u16,
+        };
+        let ip = match self.host_info[ip].as_str() {
+            Some(ip) => ip.parse().ok().filter(|ip: &IpAddr| !ip.is_unspecified()),
+            None => None,
+        };
+        Some(SocketAddr::new(ip.unwrap_or_else(|| self.addr.ip()), port))
+    }
+
+    //the JSON body of the response to a GET, `None` for no content
+    fn get_json(&self, path_and_query: &str) -> Result<Option<Value>, OscQueryError> {
+        let uri: Uri = format!("http://{}{}", self.addr, path_and_query)
+            .parse()
+            .map_err(|e: hyper::http::uri::InvalidUri| OscQueryError::Http(e.to_string()))?;
+        let client = self.client.clone();
+        let timeout = self.timeout;
+        let (status, body) = self
+            .rt
+            .lock()
+            .map_err(|_| OscQueryError::PoisonedLock)?
+            .block_on(async move {
+                tokio::time::timeout(timeout, async move {
+                    let rsp = client.get(uri).await?;
+                    let status = rsp.status();
+                    hyper::body::to_bytes(rsp.into_body())
+                        .await
+                        .map(|body| (status, body))
+                })
+                .await
+            })
+            .map_err(|_| OscQueryError::Http(format!("timed out after {:?}", timeout)))?
+            .map_err(|e| OscQueryError::Http(e.to_string()))?;
+        match status {
+            StatusCode::NO_CONTENT => Ok(None),
+            StatusCode::NOT_FOUND => Err(OscQueryError::NodeNotInGraph),
+            s if s.is_success() => serde_json::from_slice(&body)
+                .map(Some)
+                .map_err(|e| OscQueryError::InvalidDocument(e.to_string())),
+            s => Err(OscQueryError::Http(format!(
+                "{}: {}",
+                s,
+                String::from_utf8_lossy(&body)
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{Container, Get, GetSet};
+    use crate::param::{ParamGet, ParamGetSet};
+    use crate::value::{MutexValue, ValueBuilder};
+    use crate::OscQueryServerBuilder;
+    use ::atomic::Atomic;
+    use std::sync::Arc;
+
+    #[test]
+    fn mirror() {
+        let server = OscQueryServerBuilder::default()
+            .with_name("remote".to_string())
+            .with_http("127.0.0.1:0".parse().unwrap())
+            .with_osc("127.0.0.1:0")
+            .with_ws("127.0.0.1:0")
+            .build()
+            .expect("failed to create server");
+        let synth = server
+            .add_node(Container::new("synth", Some("a synth")).unwrap(), None)
+            .unwrap();
+        let gain = Arc::new(Atomic::new(0.5f32));
+        server
+            .add_node(
+                GetSet::new(
+                    "gain",
+                    None,
+                    vec![
+                        ParamGetSet::Float(ValueBuilder::new(gain.clone() as _).build()),
+                        ParamGetSet::String(
+                            ValueBuilder::new(Arc::new(MutexValue::new("dB".to_string())) as _)
+                                .build(),
+                        ),
+                    ],
+                    None,
+                )
+                .unwrap(),
+                Some(synth),
+            )
+            .unwrap();
+
+        let url = format!("http://{}", server.http_local_addr().unwrap());
+        let mut client = OscQueryClient::new(&url).unwrap();
+        assert_eq!(client.root().paths(), vec!["/", "/synth", "/synth/gain"]);
+        assert_eq!(client.host_info()["NAME"], "remote");
+        assert!(client.skipped().is_empty());
+        assert_eq!(
+            serde_json::to_value(client.root()).unwrap()["CONTENTS"],
+            client.get_json("/").unwrap().unwrap()["CONTENTS"]
+        );
+        assert_eq!(client.remote_osc_addr(), server.osc_local_addr());
+        assert_eq!(client.remote_ws_addr().as_ref(), server.ws_local_addr());
+
+        gain.store(0.25, ::atomic::Ordering::SeqCst);
+        assert_eq!(
+            client.get_value("/synth/gain").unwrap(),
+            vec![OscType::Float(0.25), OscType::String("dB".into())]
+        );
+        assert_eq!(client.get_value("/synth").unwrap(), Vec::new());
+        assert_matches!(
+            client.get_value("/nope"),
+            Err(OscQueryError::NodeNotInGraph)
+        );
+
+        //not mirrored yet, the type is queried
+        server
+            .add_node(
+                Get::new(
+                    "level",
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(3)) as _).build(),
+                    )],
+                )
+                .unwrap(),
+                Some(synth),
+            )
+            .unwrap();
+        assert_eq!(
+            client.get_value("/synth/level").unwrap(),
+            vec![OscType::Int(3)]
+        );
+        client.refresh().unwrap();
+        assert!(client.root().path_to_handle("/synth/level").is_some());
+
+        assert!(OscQueryClient::new("ftp://localhost").is_err());
+    }
+
+    #[test]
+    fn timeout() {
+        //accepts but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let start = std::time::Instant::now();
+        assert_matches!(
+            OscQueryClient::with_timeout(&url, Duration::from_millis(100)).map(|_| ()),
+            Err(OscQueryError::Http(e)) if e.contains("timed out")
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,336 @@
+//! A websocket client of a remote server, for the values of the paths it LISTENs to and the
+//! namespace changes.
+//!
+//! The connection is served by a thread of the client's own. When it drops the client
+//! reconnects, waiting longer after each failed attempt, and LISTENs to its paths again.
+use crate::error::OscQueryError;
+use crate::osc::{OscPacket, OscType};
+use crate::root::NamespaceChange;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tungstenite::{Message, WebSocket};
+
+//how long a read waits before the commands are checked, and the stop flag while reconnecting
+const POLL: Duration = Duration::from_millis(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_MIN: Duration = Duration::from_millis(100);
+const RECONNECT_MAX: Duration = Duration::from_secs(5);
+
+/// The values of the LISTENed paths, the OSC address and arguments of each message.
+pub type Values = Receiver<(String, Vec<OscType>)>;
+/// The namespace changes the server notifies.
+pub type Changes = Receiver<NamespaceChange>;
+
+enum Command {
+    Listen(String),
+    Ignore(String),
+}
+
+/// A connection to a remote websocket service, kept up until dropped.
+pub struct WsClient {
+    cmds: Sender<Command>,
+    listening: Arc<Mutex<BTreeSet<String>>>,
+    connected: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+struct Connection {
+    addr: SocketAddr,
+    values: Sender<(String, Vec<OscType>)>,
+    changes: Sender<NamespaceChange>,
+    cmds: Receiver<Command>,
+    listening: Arc<Mutex<BTreeSet<String>>>,
+    connected: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl WsClient {
+    /// Connect to the websocket service at the address, see `OscQueryClient::remote_ws_addr`.
+    ///
+    /// Fails if the first connection does, the connections after that are retried.
+    pub fn connect(addr: SocketAddr) -> Result<(Self, Values, Changes), OscQueryError> {
+        let socket = open(&addr)?;
+        let (cmds, cmds_rx) = channel();
+        let (values, values_rx) = channel();
+        let (changes, changes_rx) = channel();
+        let listening: Arc<Mutex<BTreeSet<String>>> = Default::default();
+        let connected = Arc::new(AtomicBool::new(true));
+        let stop = Arc::new(AtomicBool::new(false));
+        let conn = Connection {
+            addr,
+            values,
+            changes,
+            cmds: cmds_rx,
+            listening: listening.clone(),
+            connected: connected.clone(),
+            stop: stop.clone(),
+        };
+        let handle = std::thread::spawn(move || conn.run(socket));
+        Ok((
+            Self {
+                cmds,
+                listening,
+                connected,
+                stop,
+                handle: Some(handle),
+            },
+            values_rx,
+            changes_rx,
+        ))
+    }
+
+    /// LISTEN to the path, also after reconnecting.
+    pub fn listen(&self, path: &str) {
+        if let Ok(mut listening) = self.listening.lock() {
+            listening.insert(path.to_string());
+        }
+        let _ = self.cmds.send(Command::Listen(path.to_string()));
+    }
+
+    /// IGNORE the path, stop listening to it.
+    pub fn ignore(&self, path: &str) {
+        if let Ok(mut listening) = self.listening.lock() {
+            listening.remove(path);
+        }
+        let _ = self.cmds.send(Command::Ignore(path.to_string()));
+    }
+
+    /// The paths listened to, in order.
+    pub fn listening(&self) -> Vec<String> {
+        self.listening
+            .lock()
+            .map_or_else(|_| Vec::new(), |l| l.iter().cloned().collect())
+    }
+
+    /// Is the connection up, false while reconnecting.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for WsClient {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            if let Err(e) = handle.join() {
+                log::error!("error joining ws client thread {:?}", e);
+            }
+        }
+    }
+}
+
+fn open(addr: &SocketAddr) -> Result<WebSocket<TcpStream>, OscQueryError> {
+    let stream = TcpStream::connect_timeout(addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let (socket, _) = tungstenite::client(format!("ws://{}/", addr), stream)
+        .map_err(|e| OscQueryError::WebSocket(e.to_string()))?;
+    socket.get_ref().set_read_timeout(Some(POLL))?;
+    Ok(socket)
+}
+
+fn command(command: &str, path: &str) -> Message {
+    Message::Text(serde_json::json!({"COMMAND": command, "DATA": path}).to_string())
+}
+
+//the change a text frame notifies, if it is one
+fn change(text: &str) -> Option<NamespaceChange> {
+    let v: Value = serde_json::from_str(text).ok()?;
+    let path = || v["DATA"].as_str().map(String::from);
+    Some(match v["COMMAND"].as_str()? {
+        "PATH_ADDED" => NamespaceChange::PathAdded(path()?),
+        "PATH_REMOVED" => NamespaceChange::PathRemoved(path()?),
+        "PATH_CHANGED" => NamespaceChange::PathChanged(path()?),
+        "PATH_RENAMED" => NamespaceChange::PathRenamed(
+            v["DATA"]["OLD"].as_str()?.to_string(),
+            v["DATA"]["NEW"].as_str()?.to_string(),
+        ),
+        _ => return None,
+    })
+}
+
+impl Connection {
+    fn run(self, socket: WebSocket<TcpStream>) {
+        let mut socket = Some(socket);
+        let mut delay = RECONNECT_MIN;
+        while !self.stop.load(Ordering::Relaxed) {
+            let mut s = match socket.take() {
+                Some(s) => s,
+                None => match self.reconnect(&mut delay) {
+                    Some(s) => s,
+                    None => continue,
+                },
+            };
+            if self.serve(&mut s) {
+                socket = Some(s);
+            } else {
+                self.connected.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    //a new connection that listens to the paths again, `None` after waiting out the delay
+    fn reconnect(&self, delay: &mut Duration) -> Option<WebSocket<TcpStream>> {
+        match open(&self.addr) {
+            Ok(mut s) => {
+                //the commands queued while disconnected are covered by the paths
+                while self.cmds.try_recv().is_ok() {}
+                let paths = self
+                    .listening
+                    .lock()
+                    .map_or_else(|_| Vec::new(), |l| l.iter().cloned().collect::<Vec<_>>());
+                for p in paths {
+                    if s.write_message(command("LISTEN", &p)).is_err() {
+                        return None;
+                    }
+                }
+                *delay = RECONNECT_MIN;
+                self.connected.store(true, Ordering::Relaxed);
+                Some(s)
+            }
+            Err(e) => {
+                log::debug!("ws client reconnecting to {}: {}", self.addr, e);
+                let start = Instant::now();
+                while start.elapsed() < *delay && !self.stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(POLL);
+                }
+                *delay = (*delay * 2).min(RECONNECT_MAX);
+                None
+            }
+        }
+    }
+
+    //send the queued commands and handle a frame, false once the connection is gone
+    fn serve(&self, s: &mut WebSocket<TcpStream>) -> bool {
+        loop {
+            let msg = match self.cmds.try_recv() {
+                Ok(Command::Listen(p)) => command("LISTEN", &p),
+                Ok(Command::Ignore(p)) => command("IGNORE", &p),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            };
+            if s.write_message(msg).is_err() {
+                return false;
+            }
+        }
+        match s.read_message() {
+            Ok(Message::Binary(v)) => match crate::osc::decoder::decode(&v) {
+                Ok(packet) => self.deliver(packet),
+                Err(e) => log::warn!("ws client got an invalid OSC packet: {:?}", e),
+            },
+            Ok(Message::Text(t)) => {
+                if let Some(c) = change(&t) {
+                    let _ = self.changes.send(c);
+                }
+            }
+            Ok(Message::Close(..)) => return false,
+            Ok(..) => (),
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                //the ping replies, and the commands, still need to go out
+                if let Err(tungstenite::Error::Io(..)) = s.write_pending() {
+                    return false;
+                }
+            }
+            Err(..) => return false,
+        }
+        true
+    }
+
+    fn deliver(&self, packet: OscPacket) {
+        match packet {
+            OscPacket::Message(m) => {
+                let _ = self.values.send((m.addr, m.args));
+            }
+            OscPacket::Bundle(b) => {
+                for p in b.content {
+                    self.deliver(p);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::OscMessage;
+    use crate::root::RootInner;
+    use crate::service::websocket::WSService;
+    use std::sync::RwLock;
+
+    //send the message until it arrives, the subscription takes effect asynchronously
+    fn relayed(ws: &WSService, values: &Values, addr: &str, v: i32) {
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            ws.send(OscMessage {
+                addr: addr.to_string(),
+                args: vec![OscType::Int(v)],
+            });
+            //earlier sends can still arrive
+            while let Ok(got) = values.recv_timeout(Duration::from_millis(50)) {
+                if got == (addr.to_string(), vec![OscType::Int(v)]) {
+                    return;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn listen_and_reconnect() {
+        let root = Arc::new(RwLock::new(RootInner::new(None)));
+        let ws = WSService::new(root.clone(), Default::default(), "127.0.0.1:0")
+            .expect("failed to spawn ws");
+        let addr = *ws.local_addr();
+        let (client, values, changes) = WsClient::connect(addr).unwrap();
+        assert!(client.is_connected());
+        client.listen("/foo");
+        client.listen("/bar");
+        client.ignore("/bar");
+        assert_eq!(client.listening(), vec!["/foo"]);
+        relayed(&ws, &values, "/foo", 1);
+
+        root.read()
+            .unwrap()
+            .send_ns_change(NamespaceChange::PathRenamed("/a".into(), "/b".into()));
+        assert_eq!(
+            changes.recv_timeout(Duration::from_secs(5)),
+            Ok(NamespaceChange::PathRenamed("/a".into(), "/b".into()))
+        );
+
+        //the connection drops, the client listens again once the service is back
+        drop(ws);
+        let start = Instant::now();
+        while client.is_connected() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(POLL);
+        }
+        let ws = WSService::new(root, Default::default(), addr).expect("failed to respawn ws");
+        relayed(&ws, &values, "/foo", 2);
+        assert!(client.is_connected());
+    }
+
+    #[test]
+    fn changes() {
+        assert_eq!(
+            change("{\"COMMAND\":\"PATH_ADDED\",\"DATA\":\"/x\"}"),
+            Some(NamespaceChange::PathAdded("/x".into()))
+        );
+        assert_eq!(
+            change("{\"COMMAND\":\"PATH_REMOVED\",\"DATA\":\"/x\"}"),
+            Some(NamespaceChange::PathRemoved("/x".into()))
+        );
+        assert_eq!(change("{\"COMMAND\":\"SERVER_SHUTDOWN\"}"), None);
+        assert_eq!(change("not json"), None);
+    }
+}
@@ -0,0 +1,499 @@
+//! Conversions of OSC arguments, to parameter values, type tags and JSON.
+//!
+//! Every match over `OscType` lives here so the rest of the crate doesn't have to change when
+//! rosc adds a variant; unknown variants are logged and handled as unsupported.
+use crate::osc::{OscArray, OscColor, OscMidiMessage, OscType};
+use crate::param::OSCTypeStr;
+use serde::{ser::SerializeSeq, Serialize, Serializer};
+use std::convert::TryFrom;
+
+/// The value carried by an incoming OSC argument.
+pub(crate) enum ArgValue<'a> {
+    Int(i32),
+    Float(f32),
+    String(&'a str),
+    Time((u32, u32)),
+    Long(i64),
+    Double(f64),
+    Char(char),
+    Midi((u8, u8, u8, u8)),
+    Color(&'a OscColor),
+    Bool(bool),
+    Array(&'a OscArray),
+    Blob(&'a Vec<u8>),
+    /// No parameter can hold this argument, it is skipped.
+    Unsupported,
+}
+
+impl<'a> From<&'a OscType> for ArgValue<'a> {
+    fn from(arg: &'a OscType) -> Self {
+        match arg {
+            OscType::Int(v) => Self::Int(*v),
+            OscType::Float(v) => Self::Float(*v),
+            OscType::String(v) => Self::String(v),
+            OscType::Time(v) => Self::Time(*v),
+            OscType::Long(v) => Self::Long(*v),
+            OscType::Double(v) => Self::Double(*v),
+            OscType::Char(v) => Self::Char(*v),
+            OscType::Midi(v) => Self::Midi((v.port, v.status, v.data1, v.data2)),
+            OscType::Color(v) => Self::Color(v),
+            OscType::Bool(v) => Self::Bool(*v),
+            OscType::Array(v) => Self::Array(v),
+            OscType::Blob(v) => Self::Blob(v),
+            OscType::Nil | OscType::Inf => Self::Unsupported,
+            #[allow(unreachable_patterns)]
+            _ => {
+                log::warn!("unknown OSC argument {:?}", arg);
+                Self::Unsupported
+            }
+        }
+    }
+}
+
+/// The number of arguments that no parameter can hold.
+pub(crate) fn unsupported_count(args: &[OscType]) -> usize {
+    args.iter()
+        .filter(|a| matches!(ArgValue::from(*a), ArgValue::Unsupported))
+        .count()
+}
+
+/// Split an OSC type tag string into the tags of its top level arguments, arrays are kept whole.
+pub(crate) fn split_type_tags(types: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in types.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+        if depth == 0 {
+            tags.push(&types[start..i + c.len_utf8()]);
+            start = i + c.len_utf8();
+        }
+    }
+    tags
+}
+
+/// The zero value for the type tag of a single argument, `None` if no parameter can hold it.
+pub(crate) fn default_arg(tag: &str) -> Option<OscType> {
+    Some(match tag {
+        "i" => OscType::Int(0),
+        "f" => OscType::Float(0.0),
+        "s" => OscType::String(String::new()),
+        "b" => OscType::Blob(Vec::new()),
+        "t" => OscType::Time((0, 0)),
+        "h" => OscType::Long(0),
+        "d" => OscType::Double(0.0),
+        "c" => OscType::Char('\0'),
+        "m" => OscType::Midi(OscMidiMessage {
+            port: 0,
+            status: 0,
+            data1: 0,
+            data2: 0,
+        }),
+        "r" => OscType::Color(OscColor {
+            red: 0,
+            green: 0,
+            blue: 0,
+            alpha: 0,
+        }),
+        "T" | "F" => OscType::Bool(tag == "T"),
+        _ if tag.starts_with('[') && tag.ends_with(']') => OscType::Array(OscArray {
+            content: split_type_tags(&tag[1..tag.len() - 1])
+                .into_iter()
+                .map(default_arg)
+                .collect::<Option<Vec<_>>>()?,
+        }),
+        _ => return None,
+    })
+}
+
+fn non_finite(v: &str) -> Option<f64> {
+    match v {
+        "nan" => Some(f64::NAN),
+        "inf" => Some(f64::INFINITY),
+        "-inf" => Some(f64::NEG_INFINITY),
+        _ => None,
+    }
+}
+
+/// The argument for the type tag of a single argument from its JSON, the inverse of
+/// `OscTypeWrapper`.
+///
+/// `null` gives the zero value, as it is all there is for MIDI and expired values. Floats and
+/// doubles also take the strings `"nan"`, `"inf"` and `"-inf"`.
+pub(crate) fn arg_from_json(tag: &str, v: &serde_json::Value) -> Option<OscType> {
+    use serde_json::Value;
+    if v.is_null() {
+        return default_arg(tag);
+    }
+    Some(match (tag, v) {
+        ("i", v) => OscType::Int(i32::try_from(v.as_i64()?).ok()?),
+        ("f", Value::String(v)) => OscType::Float(non_finite(v)? as f32),
+        ("f", v) => OscType::Float(v.as_f64()? as f32),
+        ("s", Value::String(v)) => OscType::String(v.clone()),
+        ("b", Value::String(v)) => OscType::Blob(base64::decode(v).ok()?),
+        ("t", v) => {
+            let v = v.as_u64()?;
+            OscType::Time(((v >> 32) as u32, v as u32))
+        }
+        ("h", v) => OscType::Long(v.as_i64()?),
+        ("d", Value::String(v)) => OscType::Double(non_finite(v)?),
+        ("d", v) => OscType::Double(v.as_f64()?),
+        ("c", Value::String(v)) => {
+            let mut chars = v.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => OscType::Char(c),
+                _ => return None,
+            }
+        }
+        ("r", Value::String(v)) => {
+            let hex = v
+                .strip_prefix('#')
+                .filter(|h| h.len() == 8 && h.is_ascii())?;
+            let byte = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok();
+            OscType::Color(OscColor {
+                red: byte(0)?,
+                green: byte(1)?,
+                blue: byte(2)?,
+                alpha: byte(3)?,
+            })
+        }
+        ("T", Value::Bool(v)) | ("F", Value::Bool(v)) => OscType::Bool(*v),
+        (_, Value::Array(v)) if tag.starts_with('[') && tag.ends_with(']') => {
+            let tags = split_type_tags(&tag[1..tag.len() - 1]);
+            if tags.len() != v.len() {
+                return None;
+            }
+            OscType::Array(OscArray {
+                content: tags
+                    .into_iter()
+                    .zip(v)
+                    .map(|(t, v)| arg_from_json(t, v))
+                    .collect::<Option<Vec<_>>>()?,
+            })
+        }
+        _ => return None,
+    })
+}
+
+impl OSCTypeStr for OscType {
+    fn osc_type_str(&self) -> String {
+        match self {
+            OscType::Int(_) => "i".to_string(),
+            OscType::Float(_) => "f".to_string(),
+            OscType::String(_) => "s".to_string(),
+            OscType::Blob(_) => "b".to_string(),
+            OscType::Time(_) => "t".to_string(),
+            OscType::Long(_) => "h".to_string(),
+            OscType::Double(_) => "d".to_string(),
+            OscType::Char(_) => "c".to_string(),
+            OscType::Color(_) => "r".to_string(),
+            OscType::Midi(_) => "m".to_string(),
+            OscType::Bool(v) => if *v { "T" } else { "F" }.to_string(),
+            OscType::Array(v) => {
+                let mut s = String::from("[");
+                for i in &v.content {
+                    s.push_str(&i.osc_type_str());
+                }
+                s.push(']');
+                s
+            }
+            OscType::Nil => "N".to_string(),
+            OscType::Inf => "I".to_string(),
+            #[allow(unreachable_patterns)]
+            _ => {
+                log::warn!("no type tag for unknown OSC argument {:?}", self);
+                String::new()
+            }
+        }
+    }
+}
+
+//the second field serializes NaN and infinities as strings rather than null
+pub(crate) struct OscTypeWrapper<'a>(pub(crate) &'a OscType, pub(crate) bool);
+
+//JSON has no NaN or infinities, don't leave it up to the serializer
+fn serialize_non_finite<S: Serializer>(v: f64, strings: bool, ser: S) -> Result<S::Ok, S::Error> {
+    match (strings, v.is_nan(), v.is_sign_positive()) {
+        (false, _, _) => ser.serialize_none(),
+        (true, true, _) => ser.serialize_str("nan"),
+        (true, false, true) => ser.serialize_str("inf"),
+        (true, false, false) => ser.serialize_str("-inf"),
+    }
+}
+
+impl<'a> Serialize for OscTypeWrapper<'a> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            OscType::Int(v) => ser.serialize_i32(*v),
+            OscType::Float(v) if !v.is_finite() => serialize_non_finite(*v as f64, self.1, ser),
+            OscType::Float(v) => ser.serialize_f32(*v),
+            OscType::String(v) => ser.serialize_str(v),
+            //raw bytes aren't valid JSON
+            OscType::Blob(v) => ser.serialize_str(&base64::encode(v)),
+            OscType::Time(v) => ser.serialize_u64((v.0 as u64) << 32 | (v.1 as u64)),
+            OscType::Long(v) => ser.serialize_i64(*v),
+            OscType::Double(v) if !v.is_finite() => serialize_non_finite(*v, self.1, ser),
+            OscType::Double(v) => ser.serialize_f64(*v),
+            OscType::Char(v) => ser.serialize_char(*v),
+            OscType::Color(OscColor {
+                red,
+                green,
+                blue,
+                alpha,
+            }) => ser.serialize_str(
+                format!("#{:02X}{:02X}{:02X}{:02X}", red, green, blue, alpha).as_str(),
+            ),
+            OscType::Midi(_v) => ser.serialize_none(),
+            OscType::Bool(v) => ser.serialize_bool(*v),
+            OscType::Array(v) => {
+                let mut seq = ser.serialize_seq(Some(v.content.len()))?;
+                for i in &v.content {
+                    seq.serialize_element(&OscTypeWrapper(i, self.1))?;
+                }
+                seq.end()
+            }
+            OscType::Nil => ser.serialize_none(),
+            OscType::Inf => ser.serialize_none(),
+            #[allow(unreachable_patterns)]
+            _ => {
+                log::warn!("no JSON for unknown OSC argument {:?}", self.0);
+                ser.serialize_none()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::GetSet;
+    use crate::osc::{OscMessage, OscPacket};
+    use crate::param::ParamGetSet;
+    use crate::root::Root;
+    use crate::service::http::tests::wait_for;
+    use crate::value::{self, ValueBuilder};
+    use serde_json::json;
+    use std::net::UdpSocket;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Store<T>(Mutex<T>);
+
+    impl<T: Clone + Send> value::Get<T> for Store<T> {
+        fn get(&self) -> T {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl<T: Send> value::Set<T> for Store<T> {
+        fn set(&self, v: T) {
+            *self.0.lock().unwrap() = v;
+        }
+    }
+
+    fn store<T: Clone + Send + Default + 'static>() -> Arc<Store<T>> {
+        Arc::new(Store::default())
+    }
+
+    fn midi() -> OscMidiMessage {
+        OscMidiMessage {
+            port: 1,
+            status: 0x90,
+            data1: 60,
+            data2: 127,
+        }
+    }
+
+    fn array() -> OscArray {
+        OscArray {
+            content: vec![OscType::Int(1), OscType::Float(2.0)],
+        }
+    }
+
+    //every variant: the argument, its type tag, JSON and if a parameter can hold it
+    fn matrix() -> Vec<(OscType, &'static str, serde_json::Value, bool)> {
+        vec![
+            (OscType::Int(1), "i", json!(1), true),
+            (OscType::Float(1.5), "f", json!(1.5), true),
+            (OscType::String("x".into()), "s", json!("x"), true),
+            (OscType::Blob(vec![1, 2]), "b", json!("AQI="), true),
+            (OscType::Time((1, 2)), "t", json!(1u64 << 32 | 2), true),
+            (OscType::Long(5), "h", json!(5), true),
+            (OscType::Double(2.5), "d", json!(2.5), true),
+            (OscType::Char('c'), "c", json!("c"), true),
+            (
+                OscType::Color(OscColor {
+                    red: 1,
+                    green: 2,
+                    blue: 3,
+                    alpha: 255,
+                }),
+                "r",
+                json!("#010203FF"),
+                true,
+            ),
+            (OscType::Midi(midi()), "m", json!(null), true),
+            (OscType::Bool(true), "T", json!(true), true),
+            (OscType::Bool(false), "F", json!(false), true),
+            (OscType::Array(array()), "[if]", json!([1, 2.0]), true),
+            (OscType::Nil, "N", json!(null), false),
+            (OscType::Inf, "I", json!(null), false),
+        ]
+    }
+
+    #[test]
+    fn type_str_and_json() {
+        for (arg, tag, j, supported) in matrix() {
+            assert_eq!(tag, arg.osc_type_str());
+            assert_eq!(
+                j,
+                serde_json::to_value(OscTypeWrapper(&arg, false)).unwrap()
+            );
+            assert_eq!(
+                !supported,
+                matches!(ArgValue::from(&arg), ArgValue::Unsupported),
+                "{:?}",
+                arg
+            );
+        }
+    }
+
+    #[test]
+    fn from_json() {
+        assert_eq!(split_type_tags("i[f[ss]]T"), vec!["i", "[f[ss]]", "T"]);
+        for (arg, tag, j, supported) in matrix() {
+            if !supported {
+                assert_eq!(None, default_arg(tag));
+                continue;
+            }
+            assert!(default_arg(tag).is_some());
+            if tag != "m" {
+                assert_eq!(Some(arg), arg_from_json(tag, &j), "{}", tag);
+            }
+        }
+        //MIDI is rendered as null, so it comes back as zeros
+        assert_eq!(default_arg("m"), arg_from_json("m", &json!(null)));
+        assert_eq!(None, arg_from_json("c", &json!("ab")));
+        assert_eq!(None, arg_from_json("r", &json!("#0102FF")));
+        assert_eq!(None, arg_from_json("r", &json!("010203FF")));
+        assert_eq!(None, arg_from_json("[if]", &json!([1])));
+        assert_eq!(None, arg_from_json("i", &json!(i64::from(i32::MAX) + 1)));
+        assert_eq!(
+            Some(OscType::Array(OscArray {
+                content: vec![OscType::Int(0), OscType::String(String::new())]
+            })),
+            default_arg("[is]")
+        );
+    }
+
+    #[test]
+    fn update_and_render() {
+        let root = Root::new(None);
+        let params = vec![
+            ParamGetSet::Int(ValueBuilder::new(store::<i32>() as _).build()),
+            ParamGetSet::Float(ValueBuilder::new(store::<f32>() as _).build()),
+            ParamGetSet::String(ValueBuilder::new(store::<String>() as _).build()),
+            ParamGetSet::Blob(ValueBuilder::new(store::<Vec<u8>>() as _).build()),
+            ParamGetSet::Time(ValueBuilder::new(store::<(u32, u32)>() as _).build()),
+            ParamGetSet::Long(ValueBuilder::new(store::<i64>() as _).build()),
+            ParamGetSet::Double(ValueBuilder::new(store::<f64>() as _).build()),
+            ParamGetSet::Char(ValueBuilder::new(store::<char>() as _).build()),
+            ParamGetSet::Color(
+                ValueBuilder::new(Arc::new(Store(Mutex::new(OscColor {
+                    red: 0,
+                    green: 0,
+                    blue: 0,
+                    alpha: 0,
+                }))) as _)
+                .build(),
+            ),
+            ParamGetSet::Midi(ValueBuilder::new(store::<(u8, u8, u8, u8)>() as _).build()),
+            ParamGetSet::Bool(ValueBuilder::new(store::<bool>() as _).build()),
+            ParamGetSet::Array(
+                ValueBuilder::new(Arc::new(Store(Mutex::new(OscArray { content: vec![] }))) as _)
+                    .build(),
+            ),
+        ];
+        root.add_node(GetSet::new("all", None, params, None).unwrap(), None)
+            .unwrap();
+        let ints = (0..5)
+            .map(|_| ParamGetSet::Int(ValueBuilder::new(store::<i32>() as _).build()))
+            .collect::<Vec<_>>();
+        root.add_node(GetSet::new("ints", None, ints, None).unwrap(), None)
+            .unwrap();
+
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let send = |addr: &str, args: Vec<OscType>| {
+            let packet = OscPacket::Message(OscMessage {
+                addr: addr.into(),
+                args,
+            });
+            client
+                .send_to(
+                    &crate::osc::encoder::encode(&packet).unwrap(),
+                    osc.local_addr(),
+                )
+                .unwrap();
+        };
+
+        let args: Vec<OscType> = matrix()
+            .into_iter()
+            .filter(|(a, _, _, supported)| *supported && *a != OscType::Bool(false))
+            .map(|(a, _, _, _)| a)
+            .collect();
+        send("/all", args.clone());
+        wait_for(|| root.render_path("/all").map(|m| m.args) == Some(args.clone()));
+
+        let j = serde_json::to_value(&root).unwrap();
+        let n = &j["CONTENTS"]["all"];
+        assert_eq!(n["TYPE"], "ifsbthdcrmT[if]");
+        assert_eq!(
+            n["VALUE"],
+            json!([
+                1,
+                1.5,
+                "x",
+                "AQI=",
+                1u64 << 32 | 2,
+                5,
+                2.5,
+                "c",
+                "#010203FF",
+                null,
+                true,
+                [1, 2.0]
+            ])
+        );
+
+        //unsupported arguments are skipped, but still take their position
+        send(
+            "/ints",
+            vec![
+                OscType::Nil,
+                OscType::Int(3),
+                OscType::Nil,
+                OscType::Inf,
+                OscType::Int(4),
+            ],
+        );
+        wait_for(|| root.stats().osc_unsupported_args() == 3);
+        wait_for(|| {
+            root.render_path("/ints").map(|m| m.args)
+                == Some(vec![
+                    OscType::Int(0),
+                    OscType::Int(3),
+                    OscType::Int(0),
+                    OscType::Int(0),
+                    OscType::Int(4),
+                ])
+        });
+    }
+}
@@ -0,0 +1,138 @@
+//! The error type of the crate.
+use crate::node::Node;
+
+/// The reasons an operation on the namespace or a service failed.
+#[derive(Debug, thiserror::Error)]
+pub enum OscQueryError {
+    /// A lock was poisoned by a panic in another thread.
+    #[error("poisoned lock")]
+    PoisonedLock,
+    /// The handle belongs to a different root.
+    #[error("handle from another root")]
+    WrongRoot,
+    /// The given parent isn't in the namespace.
+    #[error("parent not in graph")]
+    ParentNotInGraph,
+    /// The node at the handle isn't in the namespace.
+    #[error("node not in graph")]
+    NodeNotInGraph,
+    /// The address is not a valid node address.
+    #[error("invalid address {0:?}")]
+    InvalidAddress(String),
+    /// The path is already taken by a node or an alias.
+    #[error("path {0:?} already in use")]
+    PathInUse(String),
+    /// The root node can't be changed.
+    #[error("the root node can't be replaced or removed")]
+    RootNode,
+    /// A node can't be moved under itself or one of its descendants.
+    #[error("can't move a node under itself")]
+    MoveUnderItself,
+    /// Only containers can have children.
+    #[error("node has children")]
+    HasChildren,
+    /// The operation needs a node with a value, containers have none.
+    #[error("containers have no value")]
+    ContainerHasNoValue,
+    /// The alias handle doesn't refer to an alias.
+    #[error("alias not found")]
+    AliasNotFound,
+    /// Containers can't be aliased.
+    #[error("alias target is a container")]
+    AliasTargetIsContainer,
+    /// Aliases can only be added under containers.
+    #[error("alias parent is not a container")]
+    AliasParentNotContainer,
+    /// The node at the path is in the way of one to be added below it, only containers can have
+    /// children.
+    #[error("{0:?} isn't a container")]
+    NotAContainer(String),
+    /// The node at the path has custom parameters, which can't be shared with another tree, see
+    /// `Root::merge`.
+    #[error("{0:?} has custom parameters and can't be shared")]
+    NotShareable(String),
+    /// The string isn't a valid `NodeFilter`.
+    #[error("invalid filter: {0}")]
+    InvalidFilter(&'static str),
+    /// The path sent by a client can't be normalized, see `node::normalize_path`.
+    #[error("invalid path: {0}")]
+    InvalidPath(&'static str),
+    /// The number isn't an `Access` value, 0 to 3.
+    #[error("invalid access {0}, expected 0 to 3")]
+    InvalidAccess(u8),
+    /// The string isn't a `ClipMode`.
+    #[error("invalid clip mode {0:?}, expected none, low, high or both")]
+    InvalidClipMode(String),
+    /// A namespace or parameter document doesn't have the expected shape.
+    #[error("invalid document: {0}")]
+    InvalidDocument(String),
+    /// The storage supplied for the path doesn't match its type.
+    #[error("storage mismatch at {0:?}")]
+    StorageMismatch(String),
+    /// The node couldn't be added, it is given back along with the reason.
+    #[error("node not added: {reason}")]
+    NodeNotAdded {
+        node: Box<Node>,
+        #[source]
+        reason: Box<OscQueryError>,
+    },
+    /// The node at the path is in, or contains, a frozen subtree, see `Root::freeze_subtree`.
+    #[error("{0:?} is frozen")]
+    Frozen(String),
+    /// The node at the path isn't a `GetSet`, or has no current value.
+    #[error("{0:?} isn't readable and writable")]
+    NotReadWrite(String),
+    /// The node at the path only has a value to read.
+    #[error("{0:?} isn't writable")]
+    ReadOnly(String),
+    /// The write to the node at the path was dropped, by the `NonFinitePolicy` or the node's
+    /// `CoercePolicy`.
+    #[error("the write to {0:?} was rejected")]
+    WriteRejected(String),
+    /// The value doesn't match any of the type strings of the node.
+    #[error("value doesn't match the type {0:?}")]
+    TypeMismatch(String),
+    /// There is no argument at the index.
+    #[error("index {index} out of range, there are {len}")]
+    IndexOutOfRange { index: usize, len: usize },
+    /// A request to a remote server failed, see `client::OscQueryClient`.
+    #[error("http: {0}")]
+    Http(String),
+    /// Connecting to, or talking with, a remote websocket service failed, see `client::ws`.
+    #[error("websocket: {0}")]
+    WebSocket(String),
+    /// The operation needs a service that the server was built without.
+    #[error("no {0} service running")]
+    ServiceNotRunning(&'static str),
+    /// Registering or unregistering an mDNS service failed.
+    #[cfg(feature = "mdns")]
+    #[error("mdns: {0}")]
+    Mdns(String),
+    /// Binding or configuring a socket failed.
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// The reasons `Root::apply_values` didn't apply all of a values snapshot.
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    /// The snapshot isn't an object of full paths to values.
+    #[error("the values snapshot isn't an object")]
+    NotAnObject,
+    /// Some entries weren't applied, the others were.
+    #[error("{applied} values applied, {} skipped", skipped.len())]
+    Skipped {
+        applied: usize,
+        /// The path of each entry that wasn't applied and why, in path order.
+        skipped: Vec<(String, OscQueryError)>,
+    },
+}
+
+impl OscQueryError {
+    pub(crate) fn node_not_added(node: Node, reason: OscQueryError) -> Self {
+        Self::NodeNotAdded {
+            node: Box::new(node),
+            reason: Box::new(reason),
+        }
+    }
+}
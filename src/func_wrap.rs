@@ -1,10 +1,12 @@
 //! Function wrappers.
-use crate::node::OscUpdate;
+use crate::node::{BundleContext, OscUpdate};
 use crate::root::{NodeHandle, OscWriteCallback};
 
 use crate::osc::OscType;
+use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 
 /// A new-type wrapper for a function that can get OSC updates and potentially modify the OSCQuery
 /// graph.
@@ -21,7 +23,7 @@ where
     F: Fn(
         &Vec<OscType>,
         Option<SocketAddr>,
-        Option<(u32, u32)>,
+        Option<BundleContext>,
         &NodeHandle,
     ) -> Option<OscWriteCallback>,
 {
@@ -29,13 +31,143 @@ where
         &self,
         args: &Vec<OscType>,
         addr: Option<SocketAddr>,
-        time: Option<(u32, u32)>,
+        time: Option<BundleContext>,
         handle: &NodeHandle,
     ) -> Option<OscWriteCallback> {
         (self.0)(args, addr, time, handle)
     }
 }
 
+/// A new-type wrapper for a function that mutates what it captures, a counter for instance.
+///
+/// Calls are serialized by a mutex, clones share the function so one can handle several nodes.
+pub struct OscUpdateFuncMut<F>(Arc<Mutex<F>>);
+
+impl<F> OscUpdateFuncMut<F> {
+    pub fn new(func: F) -> Self {
+        Self(Arc::new(Mutex::new(func)))
+    }
+}
+
+impl<F> Clone for OscUpdateFuncMut<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F> OscUpdate for OscUpdateFuncMut<F>
+where
+    F: FnMut(
+            &Vec<OscType>,
+            Option<SocketAddr>,
+            Option<BundleContext>,
+            &NodeHandle,
+        ) -> Option<OscWriteCallback>
+        + Send,
+{
+    fn osc_update(
+        &self,
+        args: &Vec<OscType>,
+        addr: Option<SocketAddr>,
+        time: Option<BundleContext>,
+        handle: &NodeHandle,
+    ) -> Option<OscWriteCallback> {
+        //a function that panicked is still called, like `OscUpdateFunc` would be
+        let mut func = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        (func)(args, addr, time, handle)
+    }
+}
+
+//a new-type wrapper for functions of a single argument, the pattern picks it from the message
+macro_rules! osc_update_func {
+    ($(#[$meta:meta])* $name:ident, $t:ty, $arg:pat => $v:expr) => {
+        $(#[$meta])*
+        ///
+        /// Called only for messages with exactly one argument of the type, others are ignored.
+        pub struct $name<F>(pub F);
+
+        impl<F> $name<F> {
+            pub fn new(func: F) -> Self {
+                Self(func)
+            }
+        }
+
+        impl<F> OscUpdate for $name<F>
+        where
+            F: Fn(
+                $t,
+                Option<SocketAddr>,
+                Option<BundleContext>,
+                &NodeHandle,
+            ) -> Option<OscWriteCallback>,
+        {
+            fn osc_update(
+                &self,
+                args: &Vec<OscType>,
+                addr: Option<SocketAddr>,
+                time: Option<BundleContext>,
+                handle: &NodeHandle,
+            ) -> Option<OscWriteCallback> {
+                match args.as_slice() {
+                    [$arg] => (self.0)($v, addr, time, handle),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+osc_update_func!(
+    /// A new-type wrapper for a function that gets a single int.
+    OscUpdateFuncInt, i32, OscType::Int(v) => *v
+);
+osc_update_func!(
+    /// A new-type wrapper for a function that gets a single float.
+    OscUpdateFuncFloat, f32, OscType::Float(v) => *v
+);
+osc_update_func!(
+    /// A new-type wrapper for a function that gets a single string.
+    OscUpdateFuncString, &str, OscType::String(v) => v.as_str()
+);
+osc_update_func!(
+    /// A new-type wrapper for a function that gets a single bool.
+    OscUpdateFuncBool, bool, OscType::Bool(v) => *v
+);
+osc_update_func!(
+    /// A new-type wrapper for a function that gets a single double.
+    OscUpdateFuncDouble, f64, OscType::Double(v) => *v
+);
+osc_update_func!(
+    /// A new-type wrapper for a function that gets a single long.
+    OscUpdateFuncLong, i64, OscType::Long(v) => *v
+);
+
+/// A new-type wrapper for a function that gets the bundle timetag as a tuple, like update
+/// functions did before `BundleContext`.
+#[deprecated(note = "use OscUpdateFunc, it gets the BundleContext")]
+pub struct OscUpdateTimetagFunc<F>(pub F);
+
+#[allow(deprecated)]
+impl<F> OscUpdate for OscUpdateTimetagFunc<F>
+where
+    F: Fn(
+        &Vec<OscType>,
+        Option<SocketAddr>,
+        Option<(u32, u32)>,
+        &NodeHandle,
+    ) -> Option<OscWriteCallback>,
+{
+    fn osc_update(
+        &self,
+        args: &Vec<OscType>,
+        addr: Option<SocketAddr>,
+        time: Option<BundleContext>,
+        handle: &NodeHandle,
+    ) -> Option<OscWriteCallback> {
+        (self.0)(args, addr, time.map(|t| t.timetag.into()), handle)
+    }
+}
+
 /// A new-type wrapper for a function that can get a value.
 ///
 /// # Remarks
@@ -58,6 +190,60 @@ pub struct GetSetFuncs<G, S, T> {
     _phantom: PhantomData<T>,
 }
 
+/// A new-type wrapper for a function that can fail to get a value, reading a file for instance.
+///
+/// Gets give the fallback, and log the error, when the function fails, see `try_get`.
+/// Also implements a no-op set.
+pub struct FallibleGetFunc<F, T, E> {
+    func: F,
+    fallback: T,
+    //a function pointer so that the error doesn't have to be `Send` or `Sync`
+    _phantom: PhantomData<fn() -> E>,
+}
+
+/// A new-type wrapper for a function that can fail to set a value.
+///
+/// Sets log the error when the function fails, see `try_set`.
+pub struct FallibleSetFunc<F, T, E> {
+    func: F,
+    _phantom: PhantomData<fn(T) -> E>,
+}
+
+impl<F, T, E> FallibleGetFunc<F, T, E>
+where
+    F: Fn() -> Result<T, E> + Send + Sync,
+{
+    pub fn new(func: F, fallback: T) -> Self {
+        Self {
+            func,
+            fallback,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Get the value, or the error of the function.
+    pub fn try_get(&self) -> Result<T, E> {
+        (self.func)()
+    }
+}
+
+impl<F, T, E> FallibleSetFunc<F, T, E>
+where
+    F: Fn(T) -> Result<(), E> + Send + Sync,
+{
+    pub fn new(func: F) -> Self {
+        Self {
+            func,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Set the value, or get the error of the function.
+    pub fn try_set(&self, value: T) -> Result<(), E> {
+        (self.func)(value)
+    }
+}
+
 impl<F, T> GetFunc<F, T>
 where
     F: Fn() -> T + Send + Sync,
@@ -115,6 +301,42 @@ where
     fn set(&self, _value: T) {}
 }
 
+impl<F, T, E> crate::value::Get<T> for FallibleGetFunc<F, T, E>
+where
+    F: Fn() -> Result<T, E> + Send + Sync,
+    T: Clone + Send + Sync,
+    E: Debug,
+{
+    fn get(&self) -> T {
+        self.try_get().unwrap_or_else(|e| {
+            log::warn!("get failed, using the fallback: {:?}", e);
+            self.fallback.clone()
+        })
+    }
+}
+
+//no op set
+impl<F, T, E> crate::value::Set<T> for FallibleGetFunc<F, T, E>
+where
+    F: Send + Sync,
+    T: Send + Sync,
+{
+    fn set(&self, _value: T) {}
+}
+
+impl<F, T, E> crate::value::Set<T> for FallibleSetFunc<F, T, E>
+where
+    F: Fn(T) -> Result<(), E> + Send + Sync,
+    T: Send + Sync,
+    E: Debug,
+{
+    fn set(&self, value: T) {
+        if let Err(e) = self.try_set(value) {
+            log::warn!("set failed: {:?}", e);
+        }
+    }
+}
+
 impl<F, T> crate::value::Set<T> for SetFunc<F, T>
 where
     F: Fn(T) -> () + Send + Sync,
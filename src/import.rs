@@ -0,0 +1,218 @@
+//! Build namespaces from parameter descriptions written for other tools.
+//!
+//! `rnbo` reads the `description.json` exported by Max/MSP RNBO, `param_list` reads a simple
+//! generic list. Both create a `GetSet` node per parameter, floats or strings restricted to a
+//! list of values, with containers for the intermediate path segments. The values live in
+//! storage supplied by the application, so it can wire them to its engine.
+//!
+//! An error stops the import, the nodes added up to that point stay in the tree.
+use crate::error::OscQueryError;
+use crate::node::{Container, GetSet};
+use crate::param::ParamGetSet;
+use crate::root::{NodeHandle, Root};
+use crate::value::{self, ClipMode, Range, ValueBuilder};
+use std::sync::Arc;
+
+pub mod param_list;
+pub mod rnbo;
+
+/// The initial value of an imported parameter, its variant gives the storage that is needed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Initial {
+    Float(f32),
+    String(String),
+}
+
+/// A parameter of an imported document, given to the storage factory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamSpec {
+    /// The full path the node will have.
+    pub path: String,
+    /// The initial value from the document, the storage should start out with it.
+    pub initial: Initial,
+}
+
+/// The storage for an imported parameter, the variant has to match the `ParamSpec` initial
+/// value.
+pub enum Storage {
+    Float(Arc<dyn value::GetSet<f32>>),
+    String(Arc<dyn value::GetSet<String>>),
+}
+
+//a parameter read from a document, relative to the parent it is imported under
+struct Param {
+    path: String,
+    description: Option<String>,
+    kind: Kind,
+}
+
+enum Kind {
+    Float {
+        range: Range<f32>,
+        unit: Option<String>,
+        initial: f32,
+    },
+    String {
+        vals: Vec<String>,
+        initial: String,
+    },
+}
+
+fn clip_mode<T>(range: &Range<T>) -> ClipMode {
+    match range {
+        Range::Min(..) => ClipMode::Low,
+        Range::Max(..) => ClipMode::High,
+        Range::MinMax(..) => ClipMode::Both,
+        Range::None | Range::Vals(..) => ClipMode::None,
+    }
+}
+
+fn invalid<S: Into<String>>(reason: S) -> OscQueryError {
+    OscQueryError::InvalidDocument(reason.into())
+}
+
+//add the parameters under the parent, in order, returns the full path and handle of each
+fn add_params<F>(
+    root: &Root,
+    parent: Option<NodeHandle>,
+    params: Vec<Param>,
+    storage_factory: F,
+) -> Result<Vec<(String, NodeHandle)>, OscQueryError>
+where
+    F: Fn(&ParamSpec) -> Storage,
+{
+    let base = match parent {
+        Some(p) => root
+            .handle_to_path(&p)
+            .ok_or(OscQueryError::ParentNotInGraph)?,
+        None => String::new(),
+    };
+    let mut added = Vec::new();
+    for param in params {
+        let segments: Vec<&str> = param.path.split('/').filter(|s| !s.is_empty()).collect();
+        let (address, containers) = segments
+            .split_last()
+            .ok_or_else(|| invalid(format!("empty path {:?}", param.path)))?;
+        let mut parent = parent;
+        let mut path = base.clone();
+        for c in containers {
+            path = format!("{}/{}", path, c);
+            parent = Some(match root.path_to_handle(&path) {
+                Some(h) if root.is_container(&h) => h,
+                Some(..) => return Err(OscQueryError::NotAContainer(path)),
+                None => root.add_node(Container::new(*c, None)?, parent)?,
+            });
+        }
+        let path = format!("{}/{}", path, address);
+        let description = param.description.as_deref();
+        let p = match param.kind {
+            Kind::Float {
+                range,
+                unit,
+                initial,
+            } => {
+                let spec = ParamSpec {
+                    path: path.clone(),
+                    initial: Initial::Float(initial),
+                };
+                match storage_factory(&spec) {
+                    Storage::Float(v) => {
+                        let mut b = ValueBuilder::new(v)
+                            .with_clip_mode(clip_mode(&range))
+                            .with_range(range);
+                        if let Some(unit) = unit {
+                            b = b.with_unit(unit);
+                        }
+                        ParamGetSet::Float(b.build())
+                    }
+                    _ => return Err(OscQueryError::StorageMismatch(path)),
+                }
+            }
+            Kind::String { vals, initial } => {
+                let spec = ParamSpec {
+                    path: path.clone(),
+                    initial: Initial::String(initial),
+                };
+                match storage_factory(&spec) {
+                    Storage::String(v) => ParamGetSet::String(
+                        ValueBuilder::new(v).with_range(Range::Vals(vals)).build(),
+                    ),
+                    _ => return Err(OscQueryError::StorageMismatch(path)),
+                }
+            }
+        };
+        let node = GetSet::new(*address, description, vec![p], None)?;
+        added.push((path, root.add_node(node, parent)?));
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    pub(crate) struct Store<T>(Mutex<T>);
+
+    impl<T: Clone + Send> value::Get<T> for Store<T> {
+        fn get(&self) -> T {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl<T: Send> value::Set<T> for Store<T> {
+        fn set(&self, v: T) {
+            *self.0.lock().unwrap() = v;
+        }
+    }
+
+    //storage starting out with the initial value
+    pub(crate) fn storage(spec: &ParamSpec) -> Storage {
+        match &spec.initial {
+            Initial::Float(v) => Storage::Float(Arc::new(Store(Mutex::new(*v)))),
+            Initial::String(v) => Storage::String(Arc::new(Store(Mutex::new(v.clone())))),
+        }
+    }
+
+    #[test]
+    fn mismatch() {
+        let root = Root::new(None);
+        let params = || {
+            vec![Param {
+                path: "a/b".into(),
+                description: None,
+                kind: Kind::String {
+                    vals: vec!["x".into()],
+                    initial: "x".into(),
+                },
+            }]
+        };
+        assert_matches!(
+            add_params(&root, None, params(), |_| Storage::Float(Arc::new(
+                ::atomic::Atomic::new(0f32)
+            ))),
+            Err(OscQueryError::StorageMismatch(p)) if p == "/a/b"
+        );
+        //the container that was created is reused
+        let added = add_params(&root, None, params(), storage).unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].0, "/a/b");
+        assert_eq!(root.node_count(), 3);
+
+        //only containers can have the parameters below them
+        let params = vec![Param {
+            path: "a/b/c".into(),
+            description: None,
+            kind: Kind::String {
+                vals: vec!["x".into()],
+                initial: "x".into(),
+            },
+        }];
+        assert_matches!(
+            add_params(&root, None, params, storage),
+            Err(OscQueryError::NotAContainer(p)) if p == "/a/b"
+        );
+        assert_eq!(root.node_count(), 3);
+    }
+}
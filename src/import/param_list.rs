@@ -0,0 +1,209 @@
+//! A simple generic parameter list import.
+//!
+//! The document is an object with a `params` array, each entry describes a parameter:
+//!
+//! ```json
+//! {
+//!   "params": [
+//!     {"path": "filter/cutoff", "min": 20, "max": 20000, "unit": "Hz", "default": 1000},
+//!     {"path": "filter/mode", "type": "string", "values": ["lp", "hp"], "description": "Mode"}
+//!   ]
+//! }
+//! ```
+//!
+//! * `path`, required, is relative to the parent, `/` separates containers.
+//! * `type`, `"float"`, the default, or `"string"`.
+//! * `description`, optional.
+//! * `min`, `max`, optional, floats are clipped to them.
+//! * `unit`, optional, for floats.
+//! * `values`, optional for floats, required for strings, the values the parameter can take.
+//! * `default`, optional, the initial value. Defaults to `min` or `0` for floats and to the first
+//!   value for strings.
+use super::*;
+use serde_json::Value;
+
+/// Add the parameters described by a parameter list document under the parent.
+///
+/// Returns the full path and handle of each parameter node, in document order. The
+/// `storage_factory` is called for every parameter.
+pub fn build<F>(
+    root: &Root,
+    parent: Option<NodeHandle>,
+    doc: &Value,
+    storage_factory: F,
+) -> Result<Vec<(String, NodeHandle)>, OscQueryError>
+where
+    F: Fn(&ParamSpec) -> Storage,
+{
+    let entries = doc["params"]
+        .as_array()
+        .ok_or_else(|| invalid("missing params"))?;
+    let params = entries
+        .iter()
+        .enumerate()
+        .map(|(i, e)| param(i, e))
+        .collect::<Result<Vec<_>, _>>()?;
+    add_params(root, parent, params, storage_factory)
+}
+
+fn param(i: usize, e: &Value) -> Result<Param, OscQueryError> {
+    let path = e["path"]
+        .as_str()
+        .ok_or_else(|| invalid(format!("param {} has no path", i)))?;
+    let wrong = |field: &str| invalid(format!("param {:?} has an invalid {}", path, field));
+    let float = |field: &str| match &e[field] {
+        Value::Null => Ok(None),
+        v => v
+            .as_f64()
+            .map(|v| Some(v as f32))
+            .ok_or_else(|| wrong(field)),
+    };
+    let kind = match e["type"].as_str() {
+        None | Some("float") => {
+            let min = float("min")?;
+            let max = float("max")?;
+            let range = match (&e["values"], min, max) {
+                (Value::Null, Some(min), Some(max)) => Range::MinMax(min, max),
+                (Value::Null, Some(min), None) => Range::Min(min),
+                (Value::Null, None, Some(max)) => Range::Max(max),
+                (Value::Null, None, None) => Range::None,
+                (Value::Array(vals), ..) => Range::Vals(
+                    vals.iter()
+                        .map(|v| v.as_f64().map(|v| v as f32))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| wrong("values"))?,
+                ),
+                _ => return Err(wrong("values")),
+            };
+            Kind::Float {
+                range,
+                unit: match &e["unit"] {
+                    Value::Null => None,
+                    v => Some(v.as_str().ok_or_else(|| wrong("unit"))?.to_string()),
+                },
+                initial: float("default")?.or(min).unwrap_or(0.0),
+            }
+        }
+        Some("string") => {
+            let vals = e["values"]
+                .as_array()
+                .and_then(|vals| {
+                    vals.iter()
+                        .map(|v| v.as_str().map(String::from))
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| wrong("values"))?;
+            let initial = match &e["default"] {
+                Value::Null => vals.first().cloned().unwrap_or_default(),
+                v => v.as_str().ok_or_else(|| wrong("default"))?.to_string(),
+            };
+            Kind::String { vals, initial }
+        }
+        Some(..) => return Err(wrong("type")),
+    };
+    Ok(Param {
+        path: path.to_string(),
+        description: match &e["description"] {
+            Value::Null => None,
+            v => Some(v.as_str().ok_or_else(|| wrong("description"))?.to_string()),
+        },
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::tests::storage;
+    use serde_json::json;
+
+    #[test]
+    fn golden() {
+        let doc = json!({
+            "params": [
+                {"path": "filter/cutoff", "min": 20, "max": 20000, "unit": "Hz", "default": 1000},
+                {
+                    "path": "filter/mode", "type": "string", "values": ["lp", "hp"],
+                    "description": "Mode"
+                },
+                {"path": "gain", "min": 0},
+                {"path": "ratio", "values": [1, 2, 4], "default": 2}
+            ]
+        });
+        let root = Root::new(None);
+        let added = build(&root, None, &doc, storage).unwrap();
+        assert_eq!(
+            added.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>(),
+            vec!["/filter/cutoff", "/filter/mode", "/gain", "/ratio"]
+        );
+
+        let j = serde_json::to_value(&root).unwrap();
+        assert_eq!(
+            j["CONTENTS"],
+            json!({
+                "filter": {
+                    "FULL_PATH": "/filter",
+                    "ACCESS": 0,
+                    "CONTENTS": {
+                        "cutoff": {
+                            "FULL_PATH": "/filter/cutoff",
+                            "ACCESS": 3,
+                            "TYPE": "f",
+                            "VALUE": [1000.0],
+                            "RANGE": [{"MIN": 20.0, "MAX": 20000.0}],
+                            "CLIPMODE": ["both"],
+                            "UNIT": ["Hz"]
+                        },
+                        "mode": {
+                            "FULL_PATH": "/filter/mode",
+                            "DESCRIPTION": "Mode",
+                            "ACCESS": 3,
+                            "TYPE": "s",
+                            "VALUE": ["lp"],
+                            "RANGE": [{"VALS": ["lp", "hp"]}],
+                            "CLIPMODE": ["none"],
+                            "UNIT": [null]
+                        }
+                    }
+                },
+                "gain": {
+                    "FULL_PATH": "/gain",
+                    "ACCESS": 3,
+                    "TYPE": "f",
+                    "VALUE": [0.0],
+                    "RANGE": [{"MIN": 0.0}],
+                    "CLIPMODE": ["low"],
+                    "UNIT": [null]
+                },
+                "ratio": {
+                    "FULL_PATH": "/ratio",
+                    "ACCESS": 3,
+                    "TYPE": "f",
+                    "VALUE": [2.0],
+                    "RANGE": [{"VALS": [1.0, 2.0, 4.0]}],
+                    "CLIPMODE": ["none"],
+                    "UNIT": [null]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_documents() {
+        let root = Root::new(None);
+        for doc in &[
+            json!({"params": {}}),
+            json!({"params": [{"min": 0}]}),
+            json!({"params": [{"path": "a", "type": "int"}]}),
+            json!({"params": [{"path": "a", "type": "string"}]}),
+            json!({"params": [{"path": "a", "min": "low"}]}),
+            json!({"params": [{"path": "a", "values": ["x"]}]}),
+        ] {
+            assert_matches!(
+                build(&root, None, doc, storage),
+                Err(OscQueryError::InvalidDocument(..))
+            );
+        }
+        assert_eq!(root.node_count(), 1);
+    }
+}
@@ -0,0 +1,234 @@
+//! Max/MSP RNBO `description.json` import.
+//!
+//! Every entry of `parameters` with the `ParameterTypeNumber` type, or without a type, becomes a
+//! node at its `paramId`, falling back to `name`, `/` separates containers. The `displayName` is
+//! the description.
+//!
+//! * Enum parameters, `isEnum` with `enumValues`, become strings restricted to the values, the
+//!   `initialValue` is the index of the initial value.
+//! * Other parameters are floats clipped to `minimum`/`maximum`, with the `unit`. With 2 or more
+//!   `steps` the range lists the evenly spaced values instead.
+use super::*;
+use serde_json::Value;
+
+const NUMBER: &str = "ParameterTypeNumber";
+
+/// Add the parameters described by an RNBO `description.json` under the parent.
+///
+/// Returns the full path and handle of each parameter node, in document order. The
+/// `storage_factory` is called for every parameter.
+pub fn build<F>(
+    root: &Root,
+    parent: Option<NodeHandle>,
+    doc: &Value,
+    storage_factory: F,
+) -> Result<Vec<(String, NodeHandle)>, OscQueryError>
+where
+    F: Fn(&ParamSpec) -> Storage,
+{
+    let entries = doc["parameters"]
+        .as_array()
+        .ok_or_else(|| invalid("missing parameters"))?;
+    let mut params = Vec::new();
+    for (i, e) in entries.iter().enumerate() {
+        match e["type"].as_str() {
+            None | Some(NUMBER) => params.push(param(i, e)?),
+            Some(..) => (),
+        }
+    }
+    add_params(root, parent, params, storage_factory)
+}
+
+fn param(i: usize, e: &Value) -> Result<Param, OscQueryError> {
+    let path = e["paramId"]
+        .as_str()
+        .or_else(|| e["name"].as_str())
+        .ok_or_else(|| invalid(format!("parameter {} has no paramId or name", i)))?;
+    let description = e["displayName"]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(String::from);
+    let initial = e["initialValue"].as_f64();
+    let kind = if e["isEnum"].as_bool().unwrap_or(false) {
+        let vals = e["enumValues"]
+            .as_array()
+            .ok_or_else(|| invalid(format!("enum parameter {:?} has no enumValues", path)))?
+            .iter()
+            .map(|v| match v {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let initial = initial
+            .and_then(|i| vals.get(i as usize))
+            .or_else(|| vals.first())
+            .cloned()
+            .unwrap_or_default();
+        Kind::String { vals, initial }
+    } else {
+        let min = e["minimum"].as_f64().map(|v| v as f32);
+        let max = e["maximum"].as_f64().map(|v| v as f32);
+        let steps = e["steps"].as_u64().unwrap_or(0);
+        let range = match (min, max) {
+            (Some(min), Some(max)) if steps >= 2 => {
+                let step = (max - min) / (steps - 1) as f32;
+                Range::Vals((0..steps).map(|s| min + step * s as f32).collect())
+            }
+            (Some(min), Some(max)) => Range::MinMax(min, max),
+            (Some(min), None) => Range::Min(min),
+            (None, Some(max)) => Range::Max(max),
+            (None, None) => Range::None,
+        };
+        Kind::Float {
+            range,
+            unit: e["unit"]
+                .as_str()
+                .filter(|s| !s.is_empty())
+                .map(String::from),
+            initial: initial.map(|v| v as f32).or(min).unwrap_or(0.0),
+        }
+    };
+    Ok(Param {
+        path: path.to_string(),
+        description,
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::tests::storage;
+    use serde_json::json;
+
+    #[test]
+    fn golden() {
+        let doc = json!({
+            "parameters": [
+                {
+                    "type": "ParameterTypeNumber",
+                    "index": 0,
+                    "name": "freq",
+                    "paramId": "osc/freq",
+                    "minimum": 20,
+                    "maximum": 2000,
+                    "exponent": 1,
+                    "steps": 0,
+                    "initialValue": 440,
+                    "isEnum": false,
+                    "enumValues": [],
+                    "displayName": "Frequency",
+                    "unit": "Hz"
+                },
+                {
+                    "type": "ParameterTypeNumber",
+                    "index": 1,
+                    "name": "wave",
+                    "paramId": "osc/wave",
+                    "minimum": 0,
+                    "maximum": 2,
+                    "steps": 3,
+                    "initialValue": 1,
+                    "isEnum": true,
+                    "enumValues": ["sine", "saw", "square"],
+                    "displayName": "",
+                    "unit": ""
+                },
+                {
+                    "type": "ParameterTypeNumber",
+                    "index": 2,
+                    "name": "mute",
+                    "paramId": "mute",
+                    "minimum": 0,
+                    "maximum": 1,
+                    "steps": 2,
+                    "initialValue": 0,
+                    "isEnum": false,
+                    "displayName": "",
+                    "unit": ""
+                },
+                {
+                    "type": "ParameterTypeSignal",
+                    "name": "in1",
+                    "paramId": "in1"
+                }
+            ]
+        });
+        let root = Root::new(None);
+        let synth = root
+            .add_node(Container::new("synth", None).unwrap(), None)
+            .unwrap();
+        let added = build(&root, Some(synth), &doc, storage).unwrap();
+        assert_eq!(
+            added.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>(),
+            vec!["/synth/osc/freq", "/synth/osc/wave", "/synth/mute"]
+        );
+
+        let j = serde_json::to_value(&root).unwrap();
+        let synth = &j["CONTENTS"]["synth"]["CONTENTS"];
+        assert_eq!(
+            synth["osc"]["CONTENTS"]["freq"],
+            json!({
+                "FULL_PATH": "/synth/osc/freq",
+                "DESCRIPTION": "Frequency",
+                "ACCESS": 3,
+                "TYPE": "f",
+                "VALUE": [440.0],
+                "RANGE": [{"MIN": 20.0, "MAX": 2000.0}],
+                "CLIPMODE": ["both"],
+                "UNIT": ["Hz"]
+            })
+        );
+        assert_eq!(
+            synth["osc"]["CONTENTS"]["wave"],
+            json!({
+                "FULL_PATH": "/synth/osc/wave",
+                "ACCESS": 3,
+                "TYPE": "s",
+                "VALUE": ["saw"],
+                "RANGE": [{"VALS": ["sine", "saw", "square"]}],
+                "CLIPMODE": ["none"],
+                "UNIT": [null]
+            })
+        );
+        assert_eq!(
+            synth["mute"],
+            json!({
+                "FULL_PATH": "/synth/mute",
+                "ACCESS": 3,
+                "TYPE": "f",
+                "VALUE": [0.0],
+                "RANGE": [{"VALS": [0.0, 1.0]}],
+                "CLIPMODE": ["none"],
+                "UNIT": [null]
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_documents() {
+        let root = Root::new(None);
+        assert_matches!(
+            build(&root, None, &json!({}), storage),
+            Err(OscQueryError::InvalidDocument(..))
+        );
+        assert_matches!(
+            build(
+                &root,
+                None,
+                &json!({"parameters": [{"minimum": 0}]}),
+                storage
+            ),
+            Err(OscQueryError::InvalidDocument(..))
+        );
+        assert_matches!(
+            build(
+                &root,
+                None,
+                &json!({"parameters": [{"name": "a", "isEnum": true}]}),
+                storage
+            ),
+            Err(OscQueryError::InvalidDocument(..))
+        );
+    }
+}
@@ -6,15 +6,23 @@
 #[macro_use]
 extern crate assert_matches;
 
+mod convert;
 mod server;
 
 /// Re-export of [rosc](https://crates.io/crates/rosc).
 pub use rosc as osc;
-pub use server::OscQueryServer;
+pub use server::{OscQueryServer, OscQueryServerBuilder};
 
+pub mod capability;
+pub mod client;
+pub mod error;
 pub mod func_wrap;
+#[cfg(feature = "import")]
+pub mod import;
 pub mod node;
 pub mod param;
 pub mod root;
 pub mod service;
+pub mod stats;
+pub mod time;
 pub mod value;
@@ -1,23 +1,40 @@
 //! OSCQuery tree items.
 use crate::{
+    convert::{ArgValue, OscTypeWrapper},
+    error::OscQueryError,
     osc::{OscMidiMessage, OscType},
     param::*,
     root::{NodeHandle, OscWriteCallback},
+    time::TimeTag,
+    value::StorageId,
 };
 use std::fmt;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
-use std::convert::From;
+use std::convert::{From, TryFrom};
 
 pub type UpdateHandler = Box<dyn OscUpdate + Send + Sync>;
 
+/// The bundle an OSC message arrived in, update handlers get None for messages outside of one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BundleContext {
+    /// The timetag of the bundle. A nested bundle with the immediate timetag takes the timetag of
+    /// the closest bundle around it that has one, the outermost when all the ones in between are
+    /// immediate.
+    pub timetag: TimeTag,
+    /// The number of bundles around the message, 1 for a message directly in a bundle.
+    pub depth: usize,
+}
+
 pub trait OscUpdate {
     fn osc_update(
         &self,
         args: &Vec<OscType>,
         addr: Option<SocketAddr>,
-        time: Option<(u32, u32)>,
+        time: Option<BundleContext>,
         handle: &NodeHandle,
     ) -> Option<OscWriteCallback>;
 }
@@ -26,17 +43,91 @@ pub trait OscRender {
     fn osc_render(&self, args: &mut Vec<OscType>);
 }
 
-pub fn address_valid(address: String) -> Result<String, &'static str> {
+//no tags rather than an empty list
+fn tags_of<I, S>(tags: I) -> Option<Vec<String>>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let tags: Vec<String> = tags.into_iter().map(Into::into).collect();
+    if tags.is_empty() {
+        None
+    } else {
+        Some(tags)
+    }
+}
+
+pub fn address_valid(address: String) -> Result<String, OscQueryError> {
     //TODO test others
     if address.contains('/') {
-        Err("invalid address")
+        Err(OscQueryError::InvalidAddress(address))
     } else {
         Ok(address)
     }
 }
 
+//whether the path is already as `normalize_path` would make it, without allocating
+pub(crate) fn is_normalized_path(path: &str) -> bool {
+    path == "/"
+        || (path.starts_with('/')
+            && !path.ends_with('/')
+            && !path.contains("//")
+            && !path.contains(['%', '\0']))
+}
+
+/// Normalize a full path sent by a client.
+///
+/// Percent-encoded characters are decoded, duplicate slashes are collapsed, a leading slash is
+/// added and a trailing slash is removed, so `foo//bar/` and `/foo/%62ar` become `/foo/bar`.
+/// Empty paths and paths with a NUL are invalid. In strict mode so are paths with whitespace or
+/// a malformed percent-encoding, which otherwise stays as is.
+pub fn normalize_path(path: &str, strict: bool) -> Result<String, OscQueryError> {
+    if path.is_empty() {
+        return Err(OscQueryError::InvalidPath("empty path"));
+    }
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = match bytes[i] {
+            b'%' => bytes
+                .get(i + 1)
+                .and_then(|h| hex(*h))
+                .and_then(|h| bytes.get(i + 2).and_then(|l| hex(*l)).map(|l| h << 4 | l)),
+            _ => None,
+        };
+        match escape {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None if strict && bytes[i] == b'%' => {
+                return Err(OscQueryError::InvalidPath("malformed percent-encoding"))
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let decoded =
+        String::from_utf8(decoded).map_err(|_| OscQueryError::InvalidPath("invalid utf-8"))?;
+    if decoded.contains('\0') {
+        return Err(OscQueryError::InvalidPath("NUL in path"));
+    }
+    if strict && decoded.contains(char::is_whitespace) {
+        return Err(OscQueryError::InvalidPath("whitespace in path"));
+    }
+    let segments: Vec<&str> = decoded.split('/').filter(|s| !s.is_empty()).collect();
+    Ok(format!("/{}", segments.join("/")))
+}
+
 /// Data access modes.
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+///
+/// Serialized as the number, see the `TryFrom<u8>` and `From<Access> for u8` conversions.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
 pub enum Access {
     NoValue = 0,
     ReadOnly = 1,
@@ -54,6 +145,11 @@ pub enum NodeQueryParam {
     Access,
     Description,
     Unit,
+    Tags,
+    Critical,
+    #[serde(rename = "EXTENDED_TYPE")]
+    ExtendedType,
+    All,
 }
 
 //types:
@@ -66,27 +162,91 @@ pub enum NodeQueryParam {
 pub struct Container {
     pub(crate) address: String,
     pub(crate) description: Option<String>,
+    pub(crate) tags: Option<Vec<String>>,
 }
 
 #[derive(Debug)]
 pub struct Get {
     address: String,
     description: Option<String>,
+    tags: Option<Vec<String>>,
+    critical: bool,
     params: Box<[ParamGet]>,
+    lazy: bool,
+    expires_after: Option<Duration>,
+    osc_address: Option<Box<OscAddress>>,
 }
 
 pub struct Set {
     address: String,
     description: Option<String>,
+    tags: Option<Vec<String>>,
+    critical: bool,
     params: Box<[ParamSet]>,
-    handler: Option<UpdateHandler>,
+    overloads: Vec<Box<[ParamSet]>>,
+    //shared with the copies of the node in other roots, see `Root::merge`
+    handler: Option<Arc<dyn OscUpdate + Send + Sync>>,
+    osc_address: Option<Box<OscAddress>>,
+    coalesce_window: Option<Duration>,
+    coerce: CoercePolicy,
+    arg_mismatch: ArgMismatch,
 }
 
 pub struct GetSet {
     address: String,
     description: Option<String>,
+    tags: Option<Vec<String>>,
+    critical: bool,
     params: Box<[ParamGetSet]>,
-    handler: Option<UpdateHandler>,
+    overloads: Vec<Box<[ParamGetSet]>>,
+    //shared with the copies of the node in other roots, see `Root::merge`
+    handler: Option<Arc<dyn OscUpdate + Send + Sync>>,
+    osc_address: Option<Box<OscAddress>>,
+    coalesce_window: Option<Duration>,
+    coerce: CoercePolicy,
+    arg_mismatch: ArgMismatch,
+}
+
+/// An OSC address for a node's outgoing messages, instead of its full path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OscAddress {
+    /// The address, for instance `/dev/7/fader`.
+    pub address: String,
+    /// Also apply incoming messages sent to the address. Ignored for read-only nodes.
+    ///
+    /// Messages sent to the full path always apply, and take precedence if another node's full
+    /// path is the same as the address.
+    pub inbound: bool,
+}
+
+/// How received OSC arguments are converted to the types of a node's parameters.
+///
+/// Only numeric and boolean arguments are converted, other types have to match.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum CoercePolicy {
+    /// No conversion, the argument type has to match the parameter.
+    #[default]
+    Strict,
+    /// Conversions that keep the value: Int to Long, Float or Double, Long to Double, Float to
+    /// Double, whole Float or Double values to Int or Long, Bool to Int or Long as 0 or 1. Also
+    /// Double and Long to Float, rounded.
+    Numeric,
+    /// `Numeric`, plus Float and Double to Int or Long rounded to the nearest and saturated, Long
+    /// to Int saturated, Int or Long to Bool, non zero being true.
+    Lenient,
+}
+
+/// What to do with a received OSC message whose arguments don't match a node's parameters,
+/// after `CoercePolicy` conversion: a different count, or an argument of the wrong type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum ArgMismatch {
+    /// Apply the arguments positionally, those that don't match their parameter are skipped,
+    /// missing ones leave their parameter as is and extra ones are ignored.
+    #[default]
+    Skip,
+    /// Drop the whole message, the update handler doesn't run. Counted in
+    /// `Stats::osc_args_rejected`.
+    Reject,
 }
 
 #[derive(Debug)]
@@ -124,19 +284,36 @@ impl std::fmt::Debug for GetSet {
 }
 
 impl Container {
-    pub fn new<A>(address: A, description: Option<&str>) -> Result<Self, &'static str>
+    pub fn new<A>(address: A, description: Option<&str>) -> Result<Self, OscQueryError>
     where
         A: ToString,
     {
         Ok(Self {
             address: address_valid(address.to_string())?,
             description: description.map(|d| d.into()),
+            tags: None,
         })
     }
+
+    /// Tag the node, for clients to group and filter by. Defaults to no tags.
+    ///
+    /// Reported as `TAGS`, left out without tags.
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags = tags_of(tags);
+        self
+    }
 }
 
 impl Get {
-    pub fn new<I, A>(address: A, description: Option<&str>, params: I) -> Result<Self, &'static str>
+    pub fn new<I, A>(
+        address: A,
+        description: Option<&str>,
+        params: I,
+    ) -> Result<Self, OscQueryError>
     where
         I: IntoIterator<Item = ParamGet>,
         A: ToString,
@@ -144,9 +321,74 @@ impl Get {
         Ok(Self {
             address: address_valid(address.to_string())?,
             description: description.map(|d| d.into()),
+            tags: None,
+            critical: false,
             params: params.into_iter().collect::<Vec<_>>().into(),
+            lazy: false,
+            expires_after: None,
+            osc_address: None,
         })
     }
+
+    /// Only compute the value when it is specifically asked for. Defaults to `false`.
+    ///
+    /// When lazy, serializations of the node or the tree it is in leave out `VALUE`, so clients
+    /// have to query `?VALUE` on the node's path. Explicit triggers still compute and send the
+    /// value. Use this for expensive values that are rarely looked at, at the cost of clients that
+    /// expect `VALUE` in the tree having to make an extra request.
+    ///
+    /// *NOTE* ignored in strict mode, where `VALUE` is always included.
+    pub fn with_lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Expire the value if it hasn't been written for the given duration. Defaults to `None`.
+    ///
+    /// Writes are OSC updates and calls to `Root::touch`, adding the node counts as a write.
+    /// While expired, `VALUE` serializes as `null`, triggers and OSC queries send nothing and the
+    /// node is listed by `Root::stale_paths`. There is no fallback value to report instead, an
+    /// expired value is always `null`.
+    pub fn with_expires_after(mut self, expires_after: Option<Duration>) -> Self {
+        self.expires_after = expires_after;
+        self
+    }
+
+    /// Tag the node, for clients to group and filter by. Defaults to no tags.
+    ///
+    /// Reported as `TAGS`, left out without tags.
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags = tags_of(tags);
+        self
+    }
+
+    /// Mark the node's delivery as needing to be reliable. Defaults to `false`.
+    ///
+    /// Reported as `CRITICAL`, clients should prefer the websocket or TCP to UDP for it.
+    /// `OscQueryServer` triggers of critical nodes only go to the websocket clients,
+    /// `OscService::trigger` sends them ahead of the queued messages.
+    pub fn with_critical(mut self, critical: bool) -> Self {
+        self.critical = critical;
+        self
+    }
+
+    /// Send outgoing messages to a different OSC address than the full path. Defaults to `None`.
+    ///
+    /// The full path is still used in the namespace, the address is reported as the vendor
+    /// attribute `OSC_ADDRESS`. Can be changed later with `Root::set_osc_address`.
+    pub fn with_osc_address(mut self, osc_address: Option<OscAddress>) -> Self {
+        self.osc_address = osc_address.map(Box::new);
+        self
+    }
+
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn params(&self) -> &[ParamGet] {
+        &self.params
+    }
 }
 
 impl Set {
@@ -155,7 +397,7 @@ impl Set {
         description: Option<&str>,
         params: I,
         handler: Option<UpdateHandler>,
-    ) -> Result<Self, &'static str>
+    ) -> Result<Self, OscQueryError>
     where
         I: IntoIterator<Item = ParamSet>,
         A: ToString,
@@ -163,10 +405,94 @@ impl Set {
         Ok(Self {
             address: address_valid(address.to_string())?,
             description: description.map(|d| d.into()),
+            tags: None,
+            critical: false,
             params: params.into_iter().collect::<Vec<_>>().into(),
-            handler,
+            overloads: Vec::new(),
+            handler: handler.map(Arc::from),
+            osc_address: None,
+            coalesce_window: None,
+            coerce: Default::default(),
+            arg_mismatch: Default::default(),
         })
     }
+
+    /// Tag the node, for clients to group and filter by. Defaults to no tags.
+    ///
+    /// Reported as `TAGS`, left out without tags.
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags = tags_of(tags);
+        self
+    }
+
+    /// Mark the node's delivery as needing to be reliable. Defaults to `false`.
+    ///
+    /// Reported as `CRITICAL`, clients should prefer the websocket or TCP to UDP for it.
+    /// `OscQueryServer` triggers of critical nodes only go to the websocket clients,
+    /// `OscService::trigger` sends them ahead of the queued messages.
+    pub fn with_critical(mut self, critical: bool) -> Self {
+        self.critical = critical;
+        self
+    }
+
+    /// Send outgoing messages to a different OSC address than the full path. Defaults to `None`.
+    ///
+    /// The full path is still used in the namespace, the address is reported as the vendor
+    /// attribute `OSC_ADDRESS`. Can be changed later with `Root::set_osc_address`.
+    pub fn with_osc_address(mut self, osc_address: Option<OscAddress>) -> Self {
+        self.osc_address = osc_address.map(Box::new);
+        self
+    }
+
+    /// Run the update handler at most once per window when writes come in bursts. Defaults to
+    /// `None`, the handler runs for every write.
+    ///
+    /// The first write runs the handler right away. Later writes within the window update the
+    /// parameters immediately, so reads are fresh, but the handler only runs once the window has
+    /// passed, with the arguments of the latest write. The deferred run happens on the OSC
+    /// receive thread, or before the next incoming packet, so its order relative to messages to
+    /// other nodes is best effort.
+    pub fn with_coalesce_window(mut self, window: Option<Duration>) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    /// Convert received arguments to the parameter types. Defaults to `CoercePolicy::Strict`.
+    pub fn with_coerce_policy(mut self, policy: CoercePolicy) -> Self {
+        self.coerce = policy;
+        self
+    }
+
+    /// Handle received arguments that don't match the parameters. Defaults to
+    /// `ArgMismatch::Skip`.
+    pub fn with_arg_mismatch(mut self, mismatch: ArgMismatch) -> Self {
+        self.arg_mismatch = mismatch;
+        self
+    }
+
+    /// Also accept messages with the types of these parameters, they are applied to them rather
+    /// than the node's parameters. Can be given more than once.
+    ///
+    /// Messages that match the node's parameters apply to those, otherwise the first overload
+    /// that the arguments match exactly is used. Messages that match none apply to the node's
+    /// parameters as before. Advertised under `OVERLOADS`, with the `TYPE` and `RANGE` of each.
+    pub fn with_overload<I>(mut self, params: I) -> Self
+    where
+        I: IntoIterator<Item = ParamSet>,
+    {
+        self.overloads
+            .push(params.into_iter().collect::<Vec<_>>().into());
+        self
+    }
+
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn params(&self) -> &[ParamSet] {
+        &self.params
+    }
 }
 
 impl GetSet {
@@ -175,7 +501,7 @@ impl GetSet {
         description: Option<&str>,
         params: I,
         handler: Option<UpdateHandler>,
-    ) -> Result<Self, &'static str>
+    ) -> Result<Self, OscQueryError>
     where
         I: IntoIterator<Item = ParamGetSet>,
         A: ToString,
@@ -183,23 +509,113 @@ impl GetSet {
         Ok(Self {
             address: address_valid(address.to_string())?,
             description: description.map(|d| d.into()),
+            tags: None,
+            critical: false,
             params: params.into_iter().collect::<Vec<_>>().into(),
-            handler,
+            overloads: Vec::new(),
+            handler: handler.map(Arc::from),
+            osc_address: None,
+            coalesce_window: None,
+            coerce: Default::default(),
+            arg_mismatch: Default::default(),
         })
     }
-}
 
-impl Serialize for Access {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    /// Tag the node, for clients to group and filter by. Defaults to no tags.
+    ///
+    /// Reported as `TAGS`, left out without tags.
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
     where
-        S: Serializer,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
     {
-        serializer.serialize_u8(match self {
-            Self::NoValue => 0,
-            Self::ReadOnly => 1,
-            Self::WriteOnly => 2,
-            Self::ReadWrite => 3,
-        })
+        self.tags = tags_of(tags);
+        self
+    }
+
+    /// Mark the node's delivery as needing to be reliable. Defaults to `false`.
+    ///
+    /// Reported as `CRITICAL`, clients should prefer the websocket or TCP to UDP for it.
+    /// `OscQueryServer` triggers of critical nodes only go to the websocket clients,
+    /// `OscService::trigger` sends them ahead of the queued messages.
+    pub fn with_critical(mut self, critical: bool) -> Self {
+        self.critical = critical;
+        self
+    }
+
+    /// Send outgoing messages to a different OSC address than the full path. Defaults to `None`.
+    ///
+    /// The full path is still used in the namespace, the address is reported as the vendor
+    /// attribute `OSC_ADDRESS`. Can be changed later with `Root::set_osc_address`.
+    pub fn with_osc_address(mut self, osc_address: Option<OscAddress>) -> Self {
+        self.osc_address = osc_address.map(Box::new);
+        self
+    }
+
+    /// Run the update handler at most once per window when writes come in bursts. Defaults to
+    /// `None`, the handler runs for every write.
+    ///
+    /// The first write runs the handler right away. Later writes within the window update the
+    /// parameters immediately, so reads are fresh, but the handler only runs once the window has
+    /// passed, with the arguments of the latest write. The deferred run happens on the OSC
+    /// receive thread, or before the next incoming packet, so its order relative to messages to
+    /// other nodes is best effort.
+    pub fn with_coalesce_window(mut self, window: Option<Duration>) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    /// Convert received arguments to the parameter types. Defaults to `CoercePolicy::Strict`.
+    pub fn with_coerce_policy(mut self, policy: CoercePolicy) -> Self {
+        self.coerce = policy;
+        self
+    }
+
+    /// Handle received arguments that don't match the parameters. Defaults to
+    /// `ArgMismatch::Skip`.
+    pub fn with_arg_mismatch(mut self, mismatch: ArgMismatch) -> Self {
+        self.arg_mismatch = mismatch;
+        self
+    }
+
+    /// Also accept messages with the types of these parameters, they are applied to them rather
+    /// than the node's parameters. Can be given more than once.
+    ///
+    /// Messages that match the node's parameters apply to those, otherwise the first overload
+    /// that the arguments match exactly is used. Messages that match none apply to the node's
+    /// parameters as before. Advertised under `OVERLOADS`, with the `TYPE` and `RANGE` of each.
+    pub fn with_overload<I>(mut self, params: I) -> Self
+    where
+        I: IntoIterator<Item = ParamGetSet>,
+    {
+        self.overloads
+            .push(params.into_iter().collect::<Vec<_>>().into());
+        self
+    }
+
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn params(&self) -> &[ParamGetSet] {
+        &self.params
+    }
+}
+
+impl From<Access> for u8 {
+    fn from(access: Access) -> Self {
+        access as u8
+    }
+}
+
+impl TryFrom<u8> for Access {
+    type Error = OscQueryError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Self::NoValue),
+            1 => Ok(Self::ReadOnly),
+            2 => Ok(Self::WriteOnly),
+            3 => Ok(Self::ReadWrite),
+            v => Err(OscQueryError::InvalidAccess(v)),
+        }
     }
 }
 
@@ -220,6 +636,46 @@ impl Node {
             Node::GetSet(n) => &n.description,
         }
     }
+    /// The tags of the node, see `Container::with_tags`.
+    pub fn tags(&self) -> Option<&[String]> {
+        match self {
+            Node::Container(n) => n.tags.as_deref(),
+            Node::Get(n) => n.tags.as_deref(),
+            Node::Set(n) => n.tags.as_deref(),
+            Node::GetSet(n) => n.tags.as_deref(),
+        }
+    }
+
+    /// Does the node need reliable delivery, see `Get::with_critical`.
+    pub fn critical(&self) -> bool {
+        match self {
+            Node::Container(..) => false,
+            Node::Get(n) => n.critical,
+            Node::Set(n) => n.critical,
+            Node::GetSet(n) => n.critical,
+        }
+    }
+
+    pub(crate) fn set_critical(&mut self, critical: bool) {
+        match self {
+            Node::Container(..) => (),
+            Node::Get(n) => n.critical = critical,
+            Node::Set(n) => n.critical = critical,
+            Node::GetSet(n) => n.critical = critical,
+        }
+    }
+
+    //replace the tags, no tags if empty
+    pub(crate) fn set_tags(&mut self, tags: Vec<String>) {
+        let tags = tags_of(tags);
+        match self {
+            Node::Container(n) => n.tags = tags,
+            Node::Get(n) => n.tags = tags,
+            Node::Set(n) => n.tags = tags,
+            Node::GetSet(n) => n.tags = tags,
+        }
+    }
+
     pub fn address(&self) -> &String {
         match self {
             Node::Container(n) => &n.address,
@@ -228,6 +684,183 @@ impl Node {
             Node::GetSet(n) => &n.address,
         }
     }
+
+    //a copy sharing the storage and update handler, for another root, `None` if there are custom
+    //parameters as those can't be copied
+    pub(crate) fn share(&self) -> Option<Node> {
+        fn params<P>(params: &[P], share: fn(&P) -> Option<P>) -> Option<Box<[P]>> {
+            params
+                .iter()
+                .map(share)
+                .collect::<Option<Vec<_>>>()
+                .map(Into::into)
+        }
+        fn overloads<P>(
+            overloads: &[Box<[P]>],
+            share: fn(&P) -> Option<P>,
+        ) -> Option<Vec<Box<[P]>>> {
+            overloads.iter().map(|o| params(o, share)).collect()
+        }
+        Some(match self {
+            Node::Container(n) => Node::Container(Container {
+                address: n.address.clone(),
+                description: n.description.clone(),
+                tags: n.tags.clone(),
+            }),
+            Node::Get(n) => Node::Get(Get {
+                address: n.address.clone(),
+                description: n.description.clone(),
+                tags: n.tags.clone(),
+                critical: n.critical,
+                params: params(&n.params, ParamGet::share)?,
+                lazy: n.lazy,
+                expires_after: n.expires_after,
+                osc_address: n.osc_address.clone(),
+            }),
+            Node::Set(n) => Node::Set(Set {
+                address: n.address.clone(),
+                description: n.description.clone(),
+                tags: n.tags.clone(),
+                critical: n.critical,
+                params: params(&n.params, ParamSet::share)?,
+                overloads: overloads(&n.overloads, ParamSet::share)?,
+                handler: n.handler.clone(),
+                osc_address: n.osc_address.clone(),
+                coalesce_window: n.coalesce_window,
+                coerce: n.coerce,
+                arg_mismatch: n.arg_mismatch,
+            }),
+            Node::GetSet(n) => Node::GetSet(GetSet {
+                address: n.address.clone(),
+                description: n.description.clone(),
+                tags: n.tags.clone(),
+                critical: n.critical,
+                params: params(&n.params, ParamGetSet::share)?,
+                overloads: overloads(&n.overloads, ParamGetSet::share)?,
+                handler: n.handler.clone(),
+                osc_address: n.osc_address.clone(),
+                coalesce_window: n.coalesce_window,
+                coerce: n.coerce,
+                arg_mismatch: n.arg_mismatch,
+            }),
+        })
+    }
+
+    //the address must already be valid, see `address_valid`
+    pub(crate) fn set_address(&mut self, address: String) {
+        match self {
+            Node::Container(n) => n.address = address,
+            Node::Get(n) => n.address = address,
+            Node::Set(n) => n.address = address,
+            Node::GetSet(n) => n.address = address,
+        }
+    }
+    /// Is the node's value only computed when specifically asked for, see `Get::with_lazy`.
+    pub fn lazy(&self) -> bool {
+        match self {
+            Node::Get(n) => n.lazy,
+            _ => false,
+        }
+    }
+    /// How long the value stays valid without a write, see `Get::with_expires_after`.
+    pub fn expires_after(&self) -> Option<Duration> {
+        match self {
+            Node::Get(n) => n.expires_after,
+            _ => None,
+        }
+    }
+    /// The update handler coalescing window, see `Set::with_coalesce_window`.
+    pub fn coalesce_window(&self) -> Option<Duration> {
+        match self {
+            Node::Set(n) => n.coalesce_window,
+            Node::GetSet(n) => n.coalesce_window,
+            _ => None,
+        }
+    }
+    /// The OSC address for outgoing messages, if it differs from the full path.
+    pub fn osc_address(&self) -> Option<&OscAddress> {
+        match self {
+            Node::Container(..) => None,
+            Node::Get(n) => n.osc_address.as_deref(),
+            Node::Set(n) => n.osc_address.as_deref(),
+            Node::GetSet(n) => n.osc_address.as_deref(),
+        }
+    }
+
+    /// Set the OSC address for outgoing messages, fails for containers.
+    pub(crate) fn set_osc_address(
+        &mut self,
+        osc_address: Option<OscAddress>,
+    ) -> Result<(), OscQueryError> {
+        let osc_address = osc_address.map(Box::new);
+        match self {
+            Node::Container(..) => return Err(OscQueryError::ContainerHasNoValue),
+            Node::Get(n) => n.osc_address = osc_address,
+            Node::Set(n) => n.osc_address = osc_address,
+            Node::GetSet(n) => n.osc_address = osc_address,
+        };
+        Ok(())
+    }
+
+    /// The storage ids of the node's parameters, in order, see `StorageId`.
+    pub(crate) fn storage_ids(&self) -> Vec<Option<StorageId>> {
+        match self {
+            Node::Container(..) => Vec::new(),
+            Node::Get(n) => n.params.iter().map(|p| p.storage_id()).collect(),
+            Node::Set(n) => n.params.iter().map(|p| p.storage_id()).collect(),
+            Node::GetSet(n) => n.params.iter().map(|p| p.storage_id()).collect(),
+        }
+    }
+    /// The defaults of the parameters, in order, as OSC arguments, `None` for the ones without,
+    /// see `ValueBuilder::with_default`.
+    pub fn param_defaults(&self) -> Vec<Option<Vec<OscType>>> {
+        match self {
+            Node::Container(..) => Vec::new(),
+            Node::Get(n) => n.params.iter().map(|p| p.default_args()).collect(),
+            Node::Set(n) => n.params.iter().map(|p| p.default_args()).collect(),
+            Node::GetSet(n) => n.params.iter().map(|p| p.default_args()).collect(),
+        }
+    }
+
+    /// The extended type of each parameter, see `ValueBuilder::with_extended_type`.
+    pub fn param_extended_types(&self) -> Vec<Option<&str>> {
+        match self {
+            Node::Container(..) => Vec::new(),
+            Node::Get(n) => n.params.iter().map(|p| p.extended_type()).collect(),
+            Node::Set(n) => n.params.iter().map(|p| p.extended_type()).collect(),
+            Node::GetSet(n) => n.params.iter().map(|p| p.extended_type()).collect(),
+        }
+    }
+
+    //the number of OSC arguments each parameter takes
+    pub(crate) fn param_arg_counts(&self) -> Vec<usize> {
+        fn count<P: OSCTypeStr>(p: &P) -> usize {
+            osc_arg_count(&p.osc_type_str())
+        }
+        match self {
+            Node::Container(..) => Vec::new(),
+            Node::Get(n) => n.params.iter().map(count).collect(),
+            Node::Set(n) => n.params.iter().map(count).collect(),
+            Node::GetSet(n) => n.params.iter().map(count).collect(),
+        }
+    }
+
+    /// The type strings of the overloads, see `Set::with_overload`.
+    pub fn overload_type_strings(&self) -> Vec<String> {
+        match self {
+            Node::Container(..) | Node::Get(..) => Vec::new(),
+            Node::Set(n) => n
+                .overloads
+                .iter()
+                .map(|o| o.iter().map(|p| p.osc_type_str()).collect())
+                .collect(),
+            Node::GetSet(n) => n
+                .overloads
+                .iter()
+                .map(|o| o.iter().map(|p| p.osc_type_str()).collect())
+                .collect(),
+        }
+    }
     pub fn type_string(&self) -> Option<String> {
         match self {
             Node::Container(..) => None,
@@ -250,7 +883,7 @@ impl Node {
     }
 }
 
-pub(crate) struct NodeValueWrapper<'a>(pub(crate) &'a Node);
+pub(crate) struct NodeValueWrapper<'a>(pub(crate) &'a Node, pub(crate) bool);
 impl<'a> Serialize for NodeValueWrapper<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -261,14 +894,14 @@ impl<'a> Serialize for NodeValueWrapper<'a> {
             Node::Get(n) => {
                 let mut seq = serializer.serialize_seq(Some(n.params.len()))?;
                 for v in n.params.iter() {
-                    seq.serialize_element(&ParamGetValueWrapper(v))?;
+                    seq.serialize_element(&ParamGetValueWrapper(v, self.1))?;
                 }
                 seq.end()
             }
             Node::GetSet(n) => {
                 let mut seq = serializer.serialize_seq(Some(n.params.len()))?;
                 for v in n.params.iter() {
-                    seq.serialize_element(&ParamGetSetValueWrapper(v))?;
+                    seq.serialize_element(&ParamGetSetValueWrapper(v, self.1))?;
                 }
                 seq.end()
             }
@@ -309,6 +942,32 @@ impl<'a> Serialize for NodeUnitWrapper<'a> {
     }
 }
 
+//the second field serializes non-finite floats as strings, like `NodeValueWrapper`
+pub(crate) struct NodeDefaultWrapper<'a>(pub(crate) &'a Node, pub(crate) bool);
+impl<'a> Serialize for NodeDefaultWrapper<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let defaults = self.0.param_defaults();
+        let mut seq = serializer.serialize_seq(Some(defaults.len()))?;
+        for d in defaults.iter() {
+            //a single entry per parameter, like `VALUE`
+            match d.as_deref() {
+                None => seq.serialize_element(&())?,
+                Some([a]) => seq.serialize_element(&OscTypeWrapper(a, self.1))?,
+                Some(args) => seq.serialize_element(
+                    &args
+                        .iter()
+                        .map(|a| OscTypeWrapper(a, self.1))
+                        .collect::<Vec<_>>(),
+                )?,
+            }
+        }
+        seq.end()
+    }
+}
+
 pub(crate) struct NodeRangeWrapper<'a>(pub(crate) &'a Node);
 impl<'a> Serialize for NodeRangeWrapper<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -375,12 +1034,58 @@ impl<'a> Serialize for NodeClipModeWrapper<'a> {
     }
 }
 
+//the TYPE of an overload and the RANGE of each of its parameters
+#[derive(Serialize)]
+struct Overload<R> {
+    #[serde(rename = "TYPE")]
+    type_string: String,
+    #[serde(rename = "RANGE")]
+    range: Vec<R>,
+}
+
+fn serialize_overloads<'b, S, P, R, F>(
+    serializer: S,
+    overloads: &'b [Box<[P]>],
+    range: F,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    P: OSCTypeStr,
+    R: Serialize,
+    F: Fn(&'b P) -> R,
+{
+    let mut seq = serializer.serialize_seq(Some(overloads.len()))?;
+    for o in overloads {
+        seq.serialize_element(&Overload {
+            type_string: o.iter().map(|p| p.osc_type_str()).collect(),
+            range: o.iter().map(&range).collect(),
+        })?;
+    }
+    seq.end()
+}
+
+pub(crate) struct NodeOverloadsWrapper<'a>(pub(crate) &'a Node);
+impl<'a> Serialize for NodeOverloadsWrapper<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            Node::Container(..) | Node::Get(..) => serializer.serialize_none(),
+            Node::Set(n) => serialize_overloads(serializer, &n.overloads, ParamSetRangeWrapper),
+            Node::GetSet(n) => {
+                serialize_overloads(serializer, &n.overloads, ParamGetSetRangeWrapper)
+            }
+        }
+    }
+}
+
 impl OscUpdate for Node {
     fn osc_update(
         &self,
         args: &Vec<OscType>,
         addr: Option<SocketAddr>,
-        time: Option<(u32, u32)>,
+        time: Option<BundleContext>,
         handle: &NodeHandle,
     ) -> Option<OscWriteCallback> {
         match self {
@@ -391,6 +1096,53 @@ impl OscUpdate for Node {
     }
 }
 
+impl Node {
+    //apply the arguments to the parameters, without running the update handler
+    pub(crate) fn update_params(&self, args: &[OscType]) {
+        match self {
+            Self::Container(..) | Self::Get(..) => (),
+            Self::Set(n) => n.update_params(args),
+            Self::GetSet(n) => n.update_params(args),
+        }
+    }
+
+    //the arguments converted by the coerce policy if any were, an error if the message is
+    //rejected
+    pub(crate) fn coerce_args(&self, args: &[OscType]) -> Result<Option<Vec<OscType>>, ()> {
+        match self {
+            Self::Container(..) | Self::Get(..) => Ok(None),
+            Self::Set(n) => n.coerce_args(args),
+            Self::GetSet(n) => n.coerce_args(args),
+        }
+    }
+
+    //the arguments with infinities clamped to the range, see `NonFinitePolicy::ClampToRange`,
+    //None if there is a NaN for a float or double parameter
+    pub(crate) fn clamp_non_finite(&self, args: &[OscType]) -> Option<Vec<OscType>> {
+        match self {
+            Self::Container(..) | Self::Get(..) => Some(args.to_vec()),
+            Self::Set(n) => n.clamp_non_finite(args),
+            Self::GetSet(n) => n.clamp_non_finite(args),
+        }
+    }
+
+    //run the update handler, without applying the arguments
+    pub(crate) fn run_handler(
+        &self,
+        args: &Vec<OscType>,
+        addr: Option<SocketAddr>,
+        time: Option<BundleContext>,
+        handle: &NodeHandle,
+    ) -> Option<OscWriteCallback> {
+        let handler = match self {
+            Self::Container(..) | Self::Get(..) => None,
+            Self::Set(n) => n.handler.as_ref(),
+            Self::GetSet(n) => n.handler.as_ref(),
+        };
+        handler.and_then(|h| h.osc_update(args, addr, time, handle))
+    }
+}
+
 impl OscRender for Node {
     fn osc_render(&self, args: &mut Vec<OscType>) {
         match self {
@@ -401,6 +1153,80 @@ impl OscRender for Node {
     }
 }
 
+//the range bound an infinity goes to, the fallback if the range has none
+fn clamp_infinite<T>(high: bool, range: &crate::value::Range<T>, fallback: T) -> T
+where
+    T: Copy + PartialOrd,
+{
+    use crate::value::Range;
+    match (range, high) {
+        (Range::Min(m), false) | (Range::Max(m), true) => Some(*m),
+        (Range::MinMax(m, _), false) | (Range::MinMax(_, m), true) => Some(*m),
+        (Range::Vals(vals), high) => vals.iter().copied().fold(None, |acc, v| match acc {
+            Some(a) if (a > v) == high => Some(a),
+            _ => Some(v),
+        }),
+        _ => None,
+    }
+    .unwrap_or(fallback)
+}
+
+//the numeric types that arguments can be coerced to
+#[derive(Copy, Clone)]
+enum Numeric {
+    Int,
+    Long,
+    Float,
+    Double,
+    Bool,
+}
+
+//the argument converted to the type, if the policy allows it
+fn coerce_arg(arg: &OscType, to: Numeric, policy: CoercePolicy) -> Option<OscType> {
+    let lenient = policy == CoercePolicy::Lenient;
+    //a float to a whole number in the bounds, rounded and saturated if lenient
+    let whole = |min: f64, max: f64| {
+        let v = match arg {
+            OscType::Float(v) => *v as f64,
+            OscType::Double(v) => *v,
+            _ => return None,
+        };
+        if !v.is_finite() {
+            None
+        } else if lenient {
+            Some(v.round().max(min).min(max))
+        } else {
+            Some(v).filter(|v| v.fract() == 0.0 && *v >= min && *v <= max)
+        }
+    };
+    match (arg, to) {
+        (_, _) if policy == CoercePolicy::Strict => None,
+        (OscType::Int(v), Numeric::Long) => Some(OscType::Long(*v as i64)),
+        (OscType::Int(v), Numeric::Float) => Some(OscType::Float(*v as f32)),
+        (OscType::Int(v), Numeric::Double) => Some(OscType::Double(*v as f64)),
+        (OscType::Long(v), Numeric::Int) => match i32::try_from(*v) {
+            Ok(v) => Some(OscType::Int(v)),
+            Err(..) if lenient => Some(OscType::Int(if *v < 0 { i32::MIN } else { i32::MAX })),
+            Err(..) => None,
+        },
+        (OscType::Long(v), Numeric::Float) => Some(OscType::Float(*v as f32)),
+        (OscType::Long(v), Numeric::Double) => Some(OscType::Double(*v as f64)),
+        (OscType::Float(v), Numeric::Double) => Some(OscType::Double(*v as f64)),
+        (OscType::Double(v), Numeric::Float) => Some(OscType::Float(*v as f32)),
+        (OscType::Float(..), Numeric::Int) | (OscType::Double(..), Numeric::Int) => {
+            whole(i32::MIN as f64, i32::MAX as f64).map(|v| OscType::Int(v as i32))
+        }
+        (OscType::Float(..), Numeric::Long) | (OscType::Double(..), Numeric::Long) => {
+            whole(i64::MIN as f64, i64::MAX as f64).map(|v| OscType::Long(v as i64))
+        }
+        (OscType::Bool(v), Numeric::Int) => Some(OscType::Int(*v as i32)),
+        (OscType::Bool(v), Numeric::Long) => Some(OscType::Long(*v as i64)),
+        (OscType::Int(v), Numeric::Bool) if lenient => Some(OscType::Bool(*v != 0)),
+        (OscType::Long(v), Numeric::Bool) if lenient => Some(OscType::Bool(*v != 0)),
+        _ => None,
+    }
+}
+
 macro_rules! impl_osc_update {
     ($t:ty, $p:ident) => {
         impl OscUpdate for $t {
@@ -408,7 +1234,7 @@ macro_rules! impl_osc_update {
                 &self,
                 args: &Vec<OscType>,
                 addr: Option<SocketAddr>,
-                time: Option<(u32, u32)>,
+                time: Option<BundleContext>,
                 handle: &NodeHandle,
             ) -> Option<OscWriteCallback> {
                 //XXX for GetSet, should we trigger if we actually did do a set?
@@ -418,62 +1244,194 @@ macro_rules! impl_osc_update {
                 if let Some(handler) = &self.handler {
                     cb = handler.osc_update(args, addr, time, handle);
                 }
-                for (p, a) in self.params.iter().zip(args) {
-                    match a {
-                        OscType::Int(v) => {
-                            if let $p::Int(s) = p {
-                                s.value().set(*v);
-                            }
-                        }
-                        OscType::Float(v) => {
-                            if let $p::Float(s) = p {
-                                s.value().set(*v);
-                            }
-                        }
-                        OscType::String(v) => {
-                            if let $p::String(s) = p {
-                                s.value().set(v.to_owned());
-                            }
+                self.update_params(args);
+                cb
+            }
+        }
+
+        impl $t {
+            //the parameters the arguments apply to, the first overload they match exactly if they
+            //don't match the node's parameters
+            fn signature(&self, args: &[OscType]) -> &[$p] {
+                if self.overloads.is_empty() || Self::fits(&self.params, args) {
+                    return &self.params;
+                }
+                self.overloads
+                    .iter()
+                    .find(|o| Self::fits(o, args))
+                    .map_or(&self.params, |o| o)
+            }
+
+            //do the arguments have the types of the parameters, one for one
+            fn fits(params: &[$p], args: &[OscType]) -> bool {
+                let mut args = args;
+                for p in params.iter() {
+                    //custom params take their arguments as is
+                    let count = match p {
+                        $p::Custom(c) => osc_arg_count(&c.osc_type_str()),
+                        _ => 1,
+                    };
+                    if count > args.len() {
+                        return false;
+                    }
+                    let (a, rest) = args.split_at(count);
+                    args = rest;
+                    if let Some(a) = a.first() {
+                        if !Self::arg_fits(a, p) {
+                            return false;
                         }
-                        OscType::Time(v) => {
-                            if let $p::Time(s) = p {
-                                s.value().set(*v);
-                            }
+                    }
+                }
+                args.is_empty()
+            }
+
+            fn arg_fits(a: &OscType, p: &$p) -> bool {
+                matches!(
+                    (ArgValue::from(a), p),
+                    (_, $p::Custom(..))
+                        | (ArgValue::Int(..), $p::Int(..))
+                        | (ArgValue::Float(..), $p::Float(..))
+                        | (ArgValue::String(..), $p::String(..))
+                        | (ArgValue::Time(..), $p::Time(..))
+                        | (ArgValue::Long(..), $p::Long(..))
+                        | (ArgValue::Double(..), $p::Double(..))
+                        | (ArgValue::Char(..), $p::Char(..))
+                        | (ArgValue::Midi(..), $p::Midi(..))
+                        | (ArgValue::Color(..), $p::Color(..))
+                        | (ArgValue::Bool(..), $p::Bool(..))
+                        | (ArgValue::Array(..), $p::Array(..))
+                        | (ArgValue::Blob(..), $p::Blob(..))
+                )
+            }
+
+            fn coerce_args(&self, args: &[OscType]) -> Result<Option<Vec<OscType>>, ()> {
+                if self.coerce == CoercePolicy::Strict && self.arg_mismatch == ArgMismatch::Skip {
+                    return Ok(None);
+                }
+                let reject = self.arg_mismatch == ArgMismatch::Reject;
+                let mut coerced: Option<Vec<OscType>> = None;
+                let mut i = 0;
+                for p in self.signature(args).iter() {
+                    //custom params take their arguments as is
+                    let count = match p {
+                        $p::Custom(c) => osc_arg_count(&c.osc_type_str()),
+                        _ => 1,
+                    };
+                    if i + count > args.len() {
+                        if reject {
+                            return Err(());
                         }
-                        OscType::Long(v) => {
-                            if let $p::Long(s) = p {
-                                s.value().set(*v);
-                            }
+                        break;
+                    }
+                    let a = &args[i];
+                    if !Self::arg_fits(a, p) {
+                        let to = match p {
+                            $p::Int(..) => Some(Numeric::Int),
+                            $p::Long(..) => Some(Numeric::Long),
+                            $p::Float(..) => Some(Numeric::Float),
+                            $p::Double(..) => Some(Numeric::Double),
+                            $p::Bool(..) => Some(Numeric::Bool),
+                            _ => None,
+                        };
+                        match to.and_then(|to| coerce_arg(a, to, self.coerce)) {
+                            Some(v) => coerced.get_or_insert_with(|| args.to_vec())[i] = v,
+                            None if reject => return Err(()),
+                            None => (),
                         }
-                        OscType::Double(v) => {
-                            if let $p::Double(s) = p {
-                                s.value().set(*v);
+                    }
+                    i += count;
+                }
+                if reject && i < args.len() {
+                    return Err(());
+                }
+                Ok(coerced)
+            }
+
+            fn clamp_non_finite(&self, args: &[OscType]) -> Option<Vec<OscType>> {
+                let mut clamped = args.to_vec();
+                let params = self.signature(args);
+                let mut args = &mut clamped[..];
+                for p in params.iter() {
+                    let count = match p {
+                        $p::Custom(c) => osc_arg_count(&c.osc_type_str()),
+                        _ => 1,
+                    };
+                    let (a, rest) = args.split_at_mut(std::cmp::min(count, args.len()));
+                    args = rest;
+                    match (a.first_mut(), p) {
+                        (Some(OscType::Float(v)), $p::Float(s)) if !v.is_finite() => {
+                            if v.is_nan() {
+                                return None;
                             }
+                            let high = v.is_sign_positive();
+                            *v = clamp_infinite(
+                                high,
+                                s.range(),
+                                if high { f32::MAX } else { f32::MIN },
+                            )
                         }
-                        OscType::Char(v) => {
-                            if let $p::Char(s) = p {
-                                s.value().set(*v);
+                        (Some(OscType::Double(v)), $p::Double(s)) if !v.is_finite() => {
+                            if v.is_nan() {
+                                return None;
                             }
+                            let high = v.is_sign_positive();
+                            *v = clamp_infinite(
+                                high,
+                                s.range(),
+                                if high { f64::MAX } else { f64::MIN },
+                            )
                         }
-                        OscType::Midi(v) => {
-                            if let $p::Midi(s) = p {
-                                s.value().set((v.port, v.status, v.data1, v.data2));
-                            }
+                        (None, _) => break,
+                        _ => (),
+                    }
+                }
+                Some(clamped)
+            }
+
+            fn update_params(&self, args: &[OscType]) {
+                let params = self.signature(args);
+                let mut args = args;
+                for p in params.iter() {
+                    //custom params might consume more than one argument
+                    if let $p::Custom(c) = p {
+                        let count = std::cmp::min(osc_arg_count(&c.osc_type_str()), args.len());
+                        let (a, rest) = args.split_at(count);
+                        c.update(a);
+                        args = rest;
+                        continue;
+                    }
+                    let a = match args.split_first() {
+                        Some((a, rest)) => {
+                            args = rest;
+                            a
                         }
-                        OscType::Bool(v) => {
-                            if let $p::Bool(s) = p {
-                                s.value().set(*v);
+                        None => break,
+                    };
+                    //clipped to the range, values the clip mode drops aren't set
+                    macro_rules! set_clipped {
+                        ($s:ident, $v:expr) => {
+                            if let Some(v) = $s.clip($v) {
+                                $s.value().set(v)
                             }
-                        }
-                        //TODO
-                        OscType::Blob(..)
-                        | OscType::Color(..)
-                        | OscType::Array(..)
-                        | OscType::Nil
-                        | OscType::Inf => unimplemented!(),
+                        };
+                    }
+                    //mismatched types and unsupported arguments are skipped
+                    match (ArgValue::from(a), p) {
+                        (ArgValue::Int(v), $p::Int(s)) => set_clipped!(s, v),
+                        (ArgValue::Float(v), $p::Float(s)) => set_clipped!(s, v),
+                        (ArgValue::String(v), $p::String(s)) => set_clipped!(s, v.to_owned()),
+                        (ArgValue::Time(v), $p::Time(s)) => set_clipped!(s, v),
+                        (ArgValue::Long(v), $p::Long(s)) => set_clipped!(s, v),
+                        (ArgValue::Double(v), $p::Double(s)) => set_clipped!(s, v),
+                        (ArgValue::Char(v), $p::Char(s)) => set_clipped!(s, v),
+                        (ArgValue::Midi(v), $p::Midi(s)) => s.value().set(v),
+                        (ArgValue::Color(v), $p::Color(s)) => s.value().set(v.clone()),
+                        (ArgValue::Bool(v), $p::Bool(s)) => set_clipped!(s, v),
+                        (ArgValue::Array(v), $p::Array(s)) => s.value().set(v.clone()),
+                        (ArgValue::Blob(v), $p::Blob(s)) => s.value().set(v.clone()),
+                        _ => (),
                     }
                 }
-                cb
             }
         }
     };
@@ -501,8 +1459,11 @@ macro_rules! impl_osc_render {
                                 data2: v.3,
                             }))
                         }
+                        $p::Color(v) => args.push(OscType::Color(v.value().get())),
                         $p::Bool(v) => args.push(OscType::Bool(v.value().get())),
                         $p::Array(v) => args.push(OscType::Array(v.value().get())),
+                        $p::Blob(v) => args.push(OscType::Blob(v.value().get())),
+                        $p::Custom(v) => v.render(args),
                     }
                 }
             }
@@ -556,7 +1517,15 @@ mod tests {
             let v = serde_json::to_value(*a);
             assert!(v.is_ok());
             assert_eq!(v.unwrap(), t.clone());
+            assert_eq!(serde_json::from_value::<Access>(t.clone()).unwrap(), *a);
+            assert_eq!(Access::try_from(u8::from(*a)).unwrap(), *a);
+        }
+        assert_matches!(Access::try_from(4), Err(OscQueryError::InvalidAccess(4)));
+        for v in &[json!(4), json!(255), json!(-1), json!(256), json!("1")] {
+            assert!(serde_json::from_value::<Access>(v.clone()).is_err());
         }
+        let e = serde_json::from_value::<Access>(json!(7)).unwrap_err();
+        assert_eq!(e.to_string(), "invalid access 7, expected 0 to 3");
     }
 
     #[test]
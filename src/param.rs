@@ -1,9 +1,11 @@
 //! Node Parameters.
 use crate::{
+    convert::OscTypeWrapper,
     osc::{OscArray, OscColor, OscMidiMessage, OscType},
     value::*,
 };
 use serde::{ser::SerializeSeq, Serialize, Serializer};
+use std::fmt;
 
 pub(crate) trait OSCTypeStr {
     fn osc_type_str(&self) -> String;
@@ -20,9 +22,11 @@ pub enum ParamGet {
     Double(ValueGet<f64>),
     Char(ValueGet<char>),
     Midi(ValueGet<(u8, u8, u8, u8)>),
+    Color(ValueGet<OscColor>),
     Bool(ValueGet<bool>),
-    //TODO Blob(ValueGet<Box<[u8]>>), //does clip mode make and range make sense?
+    Blob(ValueGet<Vec<u8>>),
     Array(ValueGet<OscArray>),
+    Custom(Box<dyn CustomParam>),
     //TODO Nil,
     //TODO Inf,
 }
@@ -38,9 +42,11 @@ pub enum ParamSet {
     Double(ValueSet<f64>),
     Char(ValueSet<char>),
     Midi(ValueSet<(u8, u8, u8, u8)>),
+    Color(ValueSet<OscColor>),
     Bool(ValueSet<bool>),
     Array(ValueSet<OscArray>),
-    //TODO Blob(ValueSet<Box<[u8]>>), //does clip mode make and range make sense?
+    Blob(ValueSet<Vec<u8>>),
+    Custom(Box<dyn CustomParam>),
 }
 
 /// read-write parameters
@@ -54,50 +60,171 @@ pub enum ParamGetSet {
     Double(ValueGetSet<f64>),
     Char(ValueGetSet<char>),
     Midi(ValueGetSet<(u8, u8, u8, u8)>),
+    Color(ValueGetSet<OscColor>),
     Bool(ValueGetSet<bool>),
     Array(ValueGetSet<OscArray>),
-    //TODO Blob(ValueGetSet<Box<[u8]>>), //does clip mode make and range make sense?
+    Blob(ValueGetSet<Vec<u8>>),
+    Custom(Box<dyn CustomParam>),
     //TODO Array(Box<[Self]>),
 }
 
-pub(crate) struct OscTypeWrapper<'a>(pub(crate) &'a OscType);
-impl<'a> Serialize for OscTypeWrapper<'a> {
-    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self.0 {
-            OscType::Int(v) => ser.serialize_i32(*v),
-            OscType::Float(v) => ser.serialize_f32(*v),
-            OscType::String(v) => ser.serialize_str(v),
-            OscType::Blob(_v) => ser.serialize_none(),
-            OscType::Time(v) => ser.serialize_u64((v.0 as u64) << 32 | (v.1 as u64)),
-            OscType::Long(v) => ser.serialize_i64(*v),
-            OscType::Double(v) => ser.serialize_f64(*v),
-            OscType::Char(v) => ser.serialize_char(*v),
-            OscType::Color(OscColor {
-                red,
-                green,
-                blue,
-                alpha,
-            }) => ser.serialize_str(
-                format!("#{:02X}{:02X}{:02X}{:02X}", red, green, blue, alpha).as_str(),
-            ),
-            OscType::Midi(_v) => ser.serialize_none(),
-            OscType::Bool(v) => ser.serialize_bool(*v),
-            OscType::Array(v) => {
-                let mut seq = ser.serialize_seq(Some(v.content.len()))?;
-                for i in &v.content {
-                    seq.serialize_element(&OscTypeWrapper(&i))?;
+/// A parameter of a user defined type, carried over OSC as one or more native OSC arguments.
+///
+/// The OSC arguments a custom parameter occupies are described by its `osc_type_str`, an array
+/// type like `[ffff]` counts as a single argument. In `TYPE` the type string is used as is, in
+/// `VALUE`, `RANGE`, `CLIPMODE`, `UNIT` and `EXTENDED_TYPE` the parameter gets a single entry,
+/// so composite types should use an array type.
+pub trait CustomParam: Send + Sync {
+    /// The OSC type tag string for this parameter, for instance `"[ffff]"`.
+    fn osc_type_str(&self) -> String;
+
+    /// Push the current value onto the OSC arguments.
+    fn render(&self, args: &mut Vec<OscType>);
+
+    /// Update the value from the OSC arguments that correspond to `osc_type_str`.
+    ///
+    /// Returns `false` if the arguments could not be converted.
+    fn update(&self, args: &[OscType]) -> bool;
+
+    /// The JSON `VALUE` entry, defaults to the rendered arguments.
+    fn serialize_value(&self) -> serde_json::Value {
+        let mut args = Vec::new();
+        self.render(&mut args);
+        let mut v: Vec<serde_json::Value> = args
+            .iter()
+            .map(|a| serde_json::to_value(OscTypeWrapper(a, false)).unwrap_or_default())
+            .collect();
+        if v.len() == 1 {
+            v.remove(0)
+        } else {
+            serde_json::Value::Array(v)
+        }
+    }
+
+    /// The JSON `RANGE` entry, defaults to `null`.
+    fn serialize_range(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// The JSON `CLIPMODE` entry, defaults to `null`.
+    fn serialize_clip_mode(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// The JSON `UNIT` entry, defaults to `null`.
+    fn serialize_unit(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// The `EXTENDED_TYPE` entry, defaults to `None`.
+    fn extended_type(&self) -> Option<&str> {
+        None
+    }
+
+    /// Push the default value onto the OSC arguments, returns `false` if there is none, the
+    /// default.
+    fn render_default(&self, _args: &mut Vec<OscType>) -> bool {
+        false
+    }
+}
+
+/// Conversion of a user defined type to and from native OSC arguments.
+///
+/// `Value`s holding an `OscConvert` type implement `CustomParam` so they can be used in
+/// `ParamGet::Custom`, `ParamSet::Custom` and `ParamGetSet::Custom`.
+pub trait OscConvert: Sized {
+    /// The OSC type tag string for this type, for instance `"[ffff]"`.
+    fn osc_type_str() -> String;
+
+    /// Push this value onto the OSC arguments.
+    fn to_osc(&self, args: &mut Vec<OscType>);
+
+    /// Convert from the OSC arguments that correspond to `osc_type_str`.
+    fn from_osc(args: &[OscType]) -> Option<Self>;
+}
+
+impl fmt::Debug for dyn CustomParam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Custom({:?})", self.osc_type_str())
+    }
+}
+
+//the number of top level arguments in an OSC type tag string, arrays count as one
+pub(crate) fn osc_arg_count(type_str: &str) -> usize {
+    let mut depth = 0usize;
+    let mut count = 0;
+    for c in type_str.chars() {
+        match c {
+            '[' => {
+                if depth == 0 {
+                    count += 1;
+                }
+                depth += 1;
+            }
+            ']' => depth = depth.saturating_sub(1),
+            _ => {
+                if depth == 0 {
+                    count += 1;
                 }
-                seq.end()
             }
-            OscType::Nil => ser.serialize_none(),
-            OscType::Inf => ser.serialize_none(),
         }
     }
+    count
 }
 
+macro_rules! impl_custom_param {
+    ($v:ident, |$s:ident, $a:ident| $render:expr, |$us:ident, $ua:ident| $update:expr) => {
+        impl<T> CustomParam for $v<T>
+        where
+            T: OscConvert + Send + Sync + 'static,
+        {
+            fn osc_type_str(&self) -> String {
+                T::osc_type_str()
+            }
+
+            fn render(&self, $a: &mut Vec<OscType>) {
+                let $s = self;
+                $render
+            }
+
+            fn update(&self, $ua: &[OscType]) -> bool {
+                let $us = self;
+                $update
+            }
+
+            fn serialize_clip_mode(&self) -> serde_json::Value {
+                serde_json::to_value(self.clip_mode()).unwrap_or_default()
+            }
+
+            fn serialize_unit(&self) -> serde_json::Value {
+                serde_json::to_value(self.unit()).unwrap_or_default()
+            }
+
+            fn extended_type(&self) -> Option<&str> {
+                Value::extended_type(self).as_deref()
+            }
+
+            fn render_default(&self, args: &mut Vec<OscType>) -> bool {
+                self.default().as_ref().map(|d| d.to_osc(args)).is_some()
+            }
+        }
+    };
+}
+
+impl_custom_param!(
+    ValueGet,
+    |v, args| v.value().get().to_osc(args),
+    |_v, _args| false
+);
+impl_custom_param!(ValueSet, |_v, _args| (), |v, args| {
+    T::from_osc(args).map(|t| v.value().set(t)).is_some()
+});
+impl_custom_param!(
+    ValueGetSet,
+    |v, args| v.value().get().to_osc(args),
+    |v, args| T::from_osc(args).map(|t| v.value().set(t)).is_some()
+);
+
 macro_rules! impl_value_ser {
     ($t:ident, $p:ident) => {
         //for serialize just the value
@@ -123,10 +250,13 @@ macro_rules! impl_value_ser {
                             data2: v.3,
                         })
                     }
+                    $p::Color(v) => OscType::Color(v.value().get()),
                     $p::Bool(v) => OscType::Bool(v.value().get()),
                     $p::Array(v) => OscType::Array(v.value().get()),
+                    $p::Blob(v) => OscType::Blob(v.value().get()),
+                    $p::Custom(v) => return v.serialize_value().serialize(serializer),
                 };
-                let w = OscTypeWrapper(&v);
+                let w = OscTypeWrapper(&v, self.1);
                 w.serialize(serializer)
             }
         }
@@ -149,12 +279,17 @@ macro_rules! impl_range_ser {
                     $p::Double(v) => serializer.serialize_some(v.range()),
                     $p::Char(v) => serializer.serialize_some(v.range()),
                     $p::Midi(..) => serializer.serialize_none(),
+                    //colors have no order, so no range
+                    $p::Color(..) => serializer.serialize_some(&Range::<()>::None),
                     $p::Bool(v) => serializer.serialize_some(v.range()),
                     $p::Array(..) => {
                         let mut seq = serializer.serialize_seq(Some(1))?;
                         seq.serialize_element(&Range::<()>::None)?;
                         seq.end()
                     }
+                    //blobs have no order, so no range
+                    $p::Blob(..) => serializer.serialize_some(&Range::<()>::None),
+                    $p::Custom(v) => v.serialize_range().serialize(serializer),
                 }
             }
         }
@@ -177,12 +312,15 @@ macro_rules! impl_clip_mode_ser {
                     $p::Double(v) => serializer.serialize_some(v.clip_mode()),
                     $p::Char(v) => serializer.serialize_some(v.clip_mode()),
                     $p::Midi(..) => serializer.serialize_none(),
+                    $p::Color(v) => serializer.serialize_some(v.clip_mode()),
                     $p::Bool(v) => serializer.serialize_some(v.clip_mode()),
                     $p::Array(..) => {
                         let mut seq = serializer.serialize_seq(Some(1))?;
                         seq.serialize_element(&ClipMode::None)?;
                         seq.end()
                     }
+                    $p::Blob(..) => serializer.serialize_some(&ClipMode::None),
+                    $p::Custom(v) => v.serialize_clip_mode().serialize(serializer),
                 }
             }
         }
@@ -205,20 +343,24 @@ macro_rules! impl_unit_ser {
                     $p::Double(v) => serializer.serialize_some(v.unit()),
                     $p::Char(v) => serializer.serialize_some(v.unit()),
                     $p::Midi(..) => serializer.serialize_none(),
+                    $p::Color(v) => serializer.serialize_some(v.unit()),
                     $p::Bool(v) => serializer.serialize_some(v.unit()),
                     $p::Array(..) => {
                         let mut seq = serializer.serialize_seq(Some(1))?;
                         seq.serialize_element(&Option::<()>::None)?;
                         seq.end()
                     }
+                    $p::Blob(v) => serializer.serialize_some(v.unit()),
+                    $p::Custom(v) => v.serialize_unit().serialize(serializer),
                 }
             }
         }
     };
 }
 
-pub(crate) struct ParamGetValueWrapper<'a>(pub(crate) &'a ParamGet);
-pub(crate) struct ParamGetSetValueWrapper<'a>(pub(crate) &'a ParamGetSet);
+//the second field serializes non-finite floats as strings, see `Root::set_non_finite_strings`
+pub(crate) struct ParamGetValueWrapper<'a>(pub(crate) &'a ParamGet, pub(crate) bool);
+pub(crate) struct ParamGetSetValueWrapper<'a>(pub(crate) &'a ParamGetSet, pub(crate) bool);
 
 impl_value_ser!(ParamGetValueWrapper, ParamGet);
 impl_value_ser!(ParamGetSetValueWrapper, ParamGetSet);
@@ -247,34 +389,6 @@ impl_unit_ser!(ParamGetUnitWrapper, ParamGet);
 impl_unit_ser!(ParamSetUnitWrapper, ParamSet);
 impl_unit_ser!(ParamGetSetUnitWrapper, ParamGetSet);
 
-impl OSCTypeStr for OscType {
-    fn osc_type_str(&self) -> String {
-        match self {
-            OscType::Int(_) => "i".to_string(),
-            OscType::Float(_) => "f".to_string(),
-            OscType::String(_) => "s".to_string(),
-            OscType::Blob(_) => "b".to_string(),
-            OscType::Time(_) => "t".to_string(),
-            OscType::Long(_) => "h".to_string(),
-            OscType::Double(_) => "d".to_string(),
-            OscType::Char(_) => "c".to_string(),
-            OscType::Color(_) => "r".to_string(),
-            OscType::Midi(_) => "m".to_string(),
-            OscType::Bool(v) => if *v { "T" } else { "F" }.to_string(),
-            OscType::Array(v) => {
-                let mut s = String::from("[");
-                for i in &v.content {
-                    s.push_str(&i.osc_type_str());
-                }
-                s.push(']');
-                s
-            }
-            OscType::Nil => "N".to_string(),
-            OscType::Inf => "I".to_string(),
-        }
-    }
-}
-
 impl OSCTypeStr for ParamGet {
     fn osc_type_str(&self) -> String {
         match self {
@@ -291,8 +405,16 @@ impl OSCTypeStr for ParamGet {
                 data1: 0,
                 data2: 0,
             }),
+            Self::Color(..) => OscType::Color(OscColor {
+                red: 0,
+                green: 0,
+                blue: 0,
+                alpha: 0,
+            }),
             Self::Bool(v) => OscType::Bool(v.value().get()),
             Self::Array(v) => OscType::Array(v.value().get()),
+            Self::Blob(..) => OscType::Blob(Vec::new()),
+            Self::Custom(v) => return v.osc_type_str(),
         }
         .osc_type_str()
     }
@@ -314,8 +436,16 @@ impl OSCTypeStr for ParamSet {
                 data1: 0,
                 data2: 0,
             }),
+            Self::Color(..) => OscType::Color(OscColor {
+                red: 0,
+                green: 0,
+                blue: 0,
+                alpha: 0,
+            }),
             Self::Bool(_) => OscType::Bool(false),
             Self::Array(_) => OscType::Array(OscArray { content: vec![] }),
+            Self::Blob(..) => OscType::Blob(Vec::new()),
+            Self::Custom(v) => return v.osc_type_str(),
         }
         .osc_type_str()
     }
@@ -337,9 +467,386 @@ impl OSCTypeStr for ParamGetSet {
                 data1: 0,
                 data2: 0,
             }),
+            Self::Color(..) => OscType::Color(OscColor {
+                red: 0,
+                green: 0,
+                blue: 0,
+                alpha: 0,
+            }),
             Self::Bool(v) => OscType::Bool(v.value().get()),
             Self::Array(v) => OscType::Array(v.value().get()),
+            Self::Blob(..) => OscType::Blob(Vec::new()),
+            Self::Custom(v) => return v.osc_type_str(),
         }
         .osc_type_str()
     }
 }
+
+macro_rules! impl_storage_id {
+    ($p:ident) => {
+        impl $p {
+            /// Get the id of the parameter's storage, `None` for custom parameters.
+            pub fn storage_id(&self) -> Option<StorageId> {
+                match self {
+                    Self::Int(v) => Some(v.storage_id()),
+                    Self::Float(v) => Some(v.storage_id()),
+                    Self::String(v) => Some(v.storage_id()),
+                    Self::Time(v) => Some(v.storage_id()),
+                    Self::Long(v) => Some(v.storage_id()),
+                    Self::Double(v) => Some(v.storage_id()),
+                    Self::Char(v) => Some(v.storage_id()),
+                    Self::Midi(v) => Some(v.storage_id()),
+                    Self::Color(v) => Some(v.storage_id()),
+                    Self::Bool(v) => Some(v.storage_id()),
+                    Self::Array(v) => Some(v.storage_id()),
+                    Self::Blob(v) => Some(v.storage_id()),
+                    Self::Custom(..) => None,
+                }
+            }
+        }
+    };
+}
+
+impl_storage_id!(ParamGet);
+impl_storage_id!(ParamSet);
+impl_storage_id!(ParamGetSet);
+
+macro_rules! impl_default_args {
+    ($p:ident) => {
+        impl $p {
+            /// Get the default as OSC arguments, `None` if there is none, see
+            /// `ValueBuilder::with_default`.
+            pub fn default_args(&self) -> Option<Vec<OscType>> {
+                let v = match self {
+                    Self::Int(v) => v.default().map(OscType::Int),
+                    Self::Float(v) => v.default().map(OscType::Float),
+                    Self::String(v) => v.default().clone().map(OscType::String),
+                    Self::Time(v) => v.default().map(OscType::Time),
+                    Self::Long(v) => v.default().map(OscType::Long),
+                    Self::Double(v) => v.default().map(OscType::Double),
+                    Self::Char(v) => v.default().map(OscType::Char),
+                    Self::Midi(v) => v.default().map(|v| {
+                        OscType::Midi(OscMidiMessage {
+                            port: v.0,
+                            status: v.1,
+                            data1: v.2,
+                            data2: v.3,
+                        })
+                    }),
+                    Self::Color(v) => v.default().clone().map(OscType::Color),
+                    Self::Bool(v) => v.default().map(OscType::Bool),
+                    Self::Array(v) => v.default().clone().map(OscType::Array),
+                    Self::Blob(v) => v.default().clone().map(OscType::Blob),
+                    Self::Custom(v) => {
+                        let mut args = Vec::new();
+                        return if v.render_default(&mut args) {
+                            Some(args)
+                        } else {
+                            None
+                        };
+                    }
+                };
+                v.map(|v| vec![v])
+            }
+        }
+    };
+}
+
+impl_default_args!(ParamGet);
+impl_default_args!(ParamSet);
+impl_default_args!(ParamGetSet);
+
+macro_rules! impl_share {
+    ($p:ident) => {
+        impl $p {
+            //the parameter with the same storage, `None` for custom parameters, they are boxed
+            pub(crate) fn share(&self) -> Option<Self> {
+                Some(match self {
+                    Self::Int(v) => Self::Int(v.clone()),
+                    Self::Float(v) => Self::Float(v.clone()),
+                    Self::String(v) => Self::String(v.clone()),
+                    Self::Time(v) => Self::Time(v.clone()),
+                    Self::Long(v) => Self::Long(v.clone()),
+                    Self::Double(v) => Self::Double(v.clone()),
+                    Self::Char(v) => Self::Char(v.clone()),
+                    Self::Midi(v) => Self::Midi(v.clone()),
+                    Self::Color(v) => Self::Color(v.clone()),
+                    Self::Bool(v) => Self::Bool(v.clone()),
+                    Self::Array(v) => Self::Array(v.clone()),
+                    Self::Blob(v) => Self::Blob(v.clone()),
+                    Self::Custom(..) => return None,
+                })
+            }
+        }
+    };
+}
+
+impl_share!(ParamGet);
+impl_share!(ParamSet);
+impl_share!(ParamGetSet);
+
+macro_rules! impl_extended_type {
+    ($p:ident) => {
+        impl $p {
+            /// Get the extended type, see `ValueBuilder::with_extended_type`.
+            pub fn extended_type(&self) -> Option<&str> {
+                match self {
+                    Self::Int(v) => v.extended_type().as_deref(),
+                    Self::Float(v) => v.extended_type().as_deref(),
+                    Self::String(v) => v.extended_type().as_deref(),
+                    Self::Time(v) => v.extended_type().as_deref(),
+                    Self::Long(v) => v.extended_type().as_deref(),
+                    Self::Double(v) => v.extended_type().as_deref(),
+                    Self::Char(v) => v.extended_type().as_deref(),
+                    Self::Midi(v) => v.extended_type().as_deref(),
+                    Self::Color(v) => v.extended_type().as_deref(),
+                    Self::Bool(v) => v.extended_type().as_deref(),
+                    Self::Array(v) => v.extended_type().as_deref(),
+                    Self::Blob(v) => v.extended_type().as_deref(),
+                    Self::Custom(v) => v.extended_type(),
+                }
+            }
+        }
+    };
+}
+
+impl_extended_type!(ParamGet);
+impl_extended_type!(ParamSet);
+impl_extended_type!(ParamGetSet);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::{OscMessage, OscPacket};
+    use crate::root::Root;
+    use crate::service::http::{tests::get, tests::wait_for, HttpService};
+    use crate::value::{Get, MutexValue, Set};
+    use serde_json::json;
+    use std::net::UdpSocket;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    struct Mat4([f32; 16]);
+
+    #[derive(Default)]
+    struct Mat4Store(Mutex<Mat4>);
+
+    impl OscConvert for Mat4 {
+        fn osc_type_str() -> String {
+            format!("[{}]", "f".repeat(16))
+        }
+
+        fn to_osc(&self, args: &mut Vec<OscType>) {
+            args.push(OscType::Array(OscArray {
+                content: self.0.iter().map(|v| OscType::Float(*v)).collect(),
+            }));
+        }
+
+        fn from_osc(args: &[OscType]) -> Option<Self> {
+            match args {
+                [OscType::Array(a)] if a.content.len() == 16 => {
+                    let mut m = Mat4::default();
+                    for (v, a) in m.0.iter_mut().zip(a.content.iter()) {
+                        match a {
+                            OscType::Float(a) => *v = *a,
+                            _ => return None,
+                        }
+                    }
+                    Some(m)
+                }
+                _ => None,
+            }
+        }
+    }
+
+    impl Get<Mat4> for Mat4Store {
+        fn get(&self) -> Mat4 {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    impl Set<Mat4> for Mat4Store {
+        fn set(&self, value: Mat4) {
+            *self.0.lock().unwrap() = value;
+        }
+    }
+
+    fn identity() -> Mat4 {
+        let mut m = Mat4::default();
+        for i in 0..4 {
+            m.0[i * 5] = 1.0;
+        }
+        m
+    }
+
+    #[test]
+    fn color() {
+        let root = Arc::new(Root::new(None));
+        let store = Arc::new(MutexValue::new(OscColor {
+            red: 0x10,
+            green: 0x20,
+            blue: 0x30,
+            alpha: 0xff,
+        }));
+        let m = crate::node::GetSet::new(
+            "tint",
+            None,
+            vec![ParamGetSet::Color(
+                ValueBuilder::new(store.clone() as _).build(),
+            )],
+            None,
+        );
+        assert!(root.add_node(m.unwrap(), None).is_ok());
+
+        let j = serde_json::to_value(root.clone()).unwrap();
+        let n = &j["CONTENTS"]["tint"];
+        assert_eq!(n["TYPE"], "r");
+        assert_eq!(n["VALUE"], json!(["#102030FF"]));
+        assert_eq!(n["RANGE"], json!([{}]));
+        assert_eq!(n["CLIPMODE"], json!(["none"]));
+
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let color = OscColor {
+            red: 1,
+            green: 2,
+            blue: 3,
+            alpha: 4,
+        };
+        let packet = OscPacket::Message(OscMessage {
+            addr: "/tint".into(),
+            args: vec![OscType::Color(color.clone())],
+        });
+        client
+            .send_to(
+                &crate::osc::encoder::encode(&packet).unwrap(),
+                osc.local_addr(),
+            )
+            .unwrap();
+        wait_for(|| store.get() == color);
+
+        osc.add_send_addr(client.local_addr().unwrap());
+        assert!(osc.trigger_path("/tint").is_some());
+        let mut buf = [0u8; 1024];
+        let (size, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(crate::osc::decoder::decode(&buf[..size]).unwrap(), packet);
+    }
+
+    #[test]
+    fn blob() {
+        let root = Root::new(None);
+        let store = Arc::new(MutexValue::new(b"hello".to_vec()));
+        let m = crate::node::GetSet::new(
+            "data",
+            None,
+            vec![ParamGetSet::Blob(
+                ValueBuilder::new(store as _)
+                    .with_clip_mode(ClipMode::Both)
+                    .build(),
+            )],
+            None,
+        );
+        assert!(root.add_node(m.unwrap(), None).is_ok());
+
+        let j = serde_json::to_value(&root).unwrap();
+        let n = &j["CONTENTS"]["data"];
+        assert_eq!(n["TYPE"], "b");
+        assert_eq!(n["VALUE"], json!(["aGVsbG8="]));
+        assert_eq!(n["RANGE"], json!([{}]));
+        //not meaningful for blobs, whatever was set
+        assert_eq!(n["CLIPMODE"], json!(["none"]));
+    }
+
+    #[test]
+    fn arg_count() {
+        assert_eq!(osc_arg_count(""), 0);
+        assert_eq!(osc_arg_count("ifs"), 3);
+        assert_eq!(osc_arg_count("[ff]"), 1);
+        assert_eq!(osc_arg_count("i[f[ii]]s"), 3);
+    }
+
+    #[test]
+    fn custom() {
+        let root = Arc::new(Root::new(None));
+        let store = Arc::new(Mat4Store::default());
+        store.set(identity());
+        let value: ValueGetSet<Mat4> = ValueBuilder::new(store.clone() as _)
+            .with_unit("transform".into())
+            .build();
+        let m = crate::node::GetSet::new(
+            "mat",
+            None,
+            vec![
+                ParamGetSet::Custom(Box::new(value)),
+                ParamGetSet::Int(
+                    ValueBuilder::new(Arc::new(::atomic::Atomic::new(0)) as _).build(),
+                ),
+            ],
+            None,
+        );
+        assert!(root.add_node(m.unwrap(), None).is_ok());
+
+        let j = serde_json::to_value(root.clone()).unwrap();
+        let n = &j["CONTENTS"]["mat"];
+        assert_eq!(n["TYPE"], "[ffffffffffffffff]i");
+        assert_eq!(n["UNIT"], json!(["transform", null]));
+        assert_eq!(n["CLIPMODE"], json!(["none", "none"]));
+        assert_eq!(n["RANGE"], json!([null, {}]));
+
+        let http =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let (status, _, body) = get(http.local_addr(), "/mat?VALUE");
+        assert_eq!(status, 200);
+        let v: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let expected: Vec<f32> = identity().0.to_vec();
+        assert_eq!(v["VALUE"], json!([expected, 0]));
+
+        //update over udp, then trigger it back out
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut m = Mat4::default();
+        for (i, v) in m.0.iter_mut().enumerate() {
+            *v = i as f32;
+        }
+        let mut args = Vec::new();
+        m.to_osc(&mut args);
+        args.push(OscType::Int(7));
+        let packet = OscPacket::Message(OscMessage {
+            addr: "/mat".into(),
+            args: args.clone(),
+        });
+        client
+            .send_to(
+                &crate::osc::encoder::encode(&packet).unwrap(),
+                osc.local_addr(),
+            )
+            .unwrap();
+        wait_for(|| store.get() == m);
+
+        //malformed values are ignored
+        assert!(!CustomParam::update(
+            &ValueBuilder::new(store.clone() as Arc<dyn crate::value::GetSet<Mat4>>).build(),
+            &[OscType::Int(1)]
+        ));
+        assert_eq!(store.get(), m);
+
+        osc.add_send_addr(client.local_addr().unwrap());
+        assert!(osc.trigger_path("/mat").is_some());
+        let mut buf = [0u8; 1024];
+        let (size, _) = client.recv_from(&mut buf).unwrap();
+        match crate::osc::decoder::decode(&buf[..size]) {
+            Ok(OscPacket::Message(msg)) => {
+                assert_eq!(msg.addr, "/mat");
+                assert_eq!(msg.args, args);
+            }
+            other => panic!("unexpected packet {:?}", other),
+        }
+    }
+}
@@ -1,22 +1,81 @@
+use crate::error::{OscQueryError, SnapshotError};
 use crate::node::*;
-use crate::osc::{OscMessage, OscPacket};
+use crate::osc::{OscMessage, OscPacket, OscType};
 use crate::service::osc::OscService;
+use crate::service::osc_tcp::{Framing, OscTcpService};
 use crate::service::websocket::WSService;
+use crate::stats::Stats;
+use crate::time::{SharedClock, TimeTag};
+use crate::value::{NonFinitePolicy, StorageId};
 
-use petgraph::stable_graph::{NodeIndex, StableGraph, WalkNeighbors};
+use ::atomic::{Atomic, Ordering};
+use petgraph::stable_graph::{NodeIndex, StableGraph};
+use petgraph::visit::{Dfs, NodeIndexable};
 use serde::{ser::SerializeMap, Serialize, Serializer};
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::net::ToSocketAddrs;
-use std::ops::DerefMut;
-use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
-use std::sync::Arc;
-use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::{ControlFlow, DerefMut};
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Weak};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+mod filter;
+pub use filter::NodeFilter;
+
+mod deserialize;
+mod merge;
+pub use merge::MergePolicy;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+#[cfg(feature = "snapshot")]
+pub use snapshot::{ParamStorage, PathSpec, StructureError};
 
 const NS_CHANGE_LEN: usize = 1024;
+const CONTENTS_JOURNAL_LEN: usize = 64;
 
 type Graph = StableGraph<NodeWrapper, ()>;
-pub type OscWriteCallback = Box<dyn FnOnce(&mut dyn OscQueryGraph)>;
+
+/// A function, returned from an OSC update handler, that is applied to the graph under the write
+/// lock after the handler returns.
+///
+/// Callbacks are applied in the order that their messages appear in the packet, bundles included.
+/// A callback that returns an error or panics doesn't stop the rest from being applied, the failure
+/// is recorded in `Stats`.
+pub struct OscWriteCallback(WriteFn);
+type WriteFn = Box<dyn FnOnce(&mut dyn OscQueryGraph) -> OscWriteResult>;
+
+/// The result of applying an `OscWriteCallback`.
+pub type OscWriteResult = Result<(), String>;
+
+impl OscWriteCallback {
+    /// Create a callback that can report an error.
+    pub fn new<F>(func: F) -> Self
+    where
+        F: FnOnce(&mut dyn OscQueryGraph) -> OscWriteResult + 'static,
+    {
+        Self(Box::new(func))
+    }
+
+    fn apply(self, graph: &mut dyn OscQueryGraph) -> OscWriteResult {
+        (self.0)(graph)
+    }
+}
+
+/// Create a callback that cannot fail.
+impl<F> From<F> for OscWriteCallback
+where
+    F: FnOnce(&mut dyn OscQueryGraph) + 'static,
+{
+    fn from(func: F) -> Self {
+        Self::new(move |graph| {
+            func(graph);
+            Ok(())
+        })
+    }
+}
 
 pub trait OscQueryGraph {
     ///add node to the graph at the root or as a child of the given parent
@@ -24,605 +83,5812 @@ pub trait OscQueryGraph {
         &mut self,
         node: Node,
         parent: Option<NodeHandle>,
-    ) -> Result<NodeHandle, (Node, &'static str)>;
+    ) -> Result<NodeHandle, OscQueryError>;
+
+    ///add node at the full path, creating the containers above it, see `Root::add_node_at_path`
+    ///
+    ///The default looks the containers up with `path_to_handle` and adds the missing ones one
+    ///at a time with `add_node`, they stay if adding the node fails.
+    fn add_node_at_path(&mut self, node: Node, path: &str) -> Result<NodeHandle, OscQueryError> {
+        let mut addresses: Vec<&str> = path.split('/').filter(|a| !a.is_empty()).collect();
+        if addresses.pop() != Some(node.address().as_str()) {
+            return Err(OscQueryError::node_not_added(
+                node,
+                OscQueryError::InvalidPath("the path doesn't end with the node's address"),
+            ));
+        }
+        let mut parent = None;
+        let mut full_path = String::new();
+        for address in addresses {
+            full_path = format!("{}/{}", full_path, address);
+            let added = match self.path_to_handle(&full_path) {
+                Some(handle) => Ok(handle),
+                None => Container::new(address, None).and_then(|c| self.add_node(c.into(), parent)),
+            };
+            parent = match added {
+                Ok(handle) => Some(handle),
+                Err(OscQueryError::NodeNotAdded { reason, .. }) => {
+                    return Err(OscQueryError::node_not_added(node, *reason))
+                }
+                Err(e) => return Err(OscQueryError::node_not_added(node, e)),
+            };
+        }
+        self.add_node(node, parent)
+    }
 
     ///Remove the node at the handle returns it and any children if found
     ///leafs come first in returned vector
-    fn rm_node(&mut self, handle: NodeHandle) -> Result<Vec<Node>, (NodeHandle, &'static str)>;
+    fn rm_node(&mut self, handle: NodeHandle) -> Result<Vec<Node>, OscQueryError>;
+
+    ///Remove the node at the full path, like `rm_node`, the leading `/` is optional
+    ///
+    ///The default removes the node that `path_to_handle` finds.
+    fn rm_node_at_path(&mut self, path: &str) -> Result<Vec<Node>, OscQueryError> {
+        match self.path_to_handle(path) {
+            Some(handle) => self.rm_node(handle),
+            None => Err(OscQueryError::NodeNotInGraph),
+        }
+    }
+
+    ///Get the handle of the node at the full path, see `Root::path_to_handle`
+    ///
+    ///The default finds nothing, implementations that keep paths should override it.
+    fn path_to_handle(&self, _path: &str) -> Option<NodeHandle> {
+        None
+    }
+
+    ///The addresses and handles of the node's immediate children, see `Root::children`
+    ///
+    ///The default finds nothing, implementations that keep the tree should override it.
+    fn children(&self, _handle: NodeHandle) -> Option<Vec<(String, NodeHandle)>> {
+        None
+    }
 }
 
 pub(crate) struct RootInner {
+    //the id in the handles of this root
+    id: u64,
     name: Option<String>,
     graph: Graph,
     root: NodeIndex,
     //for fast lookup by full path
     index_map: HashMap<String, NodeIndex>,
-    ns_change_send: Option<SyncSender<NamespaceChange>>, //TODO vec?
+    //one per receiver, dropped once the receiver is
+    ns_change_send: Mutex<Vec<SyncSender<NamespaceChange>>>,
+    //like `ns_change_send`, for the receivers that are awaited
+    ns_change_streams: Mutex<Vec<futures::channel::mpsc::Sender<NamespaceChange>>>,
+    stats: Arc<Stats>,
+    slow_handler_threshold: Option<Duration>,
+    non_finite_policy: NonFinitePolicy,
+    non_finite_strings: bool,
+    //incremented with every structural change
+    generation: u64,
+    //inbound OSC address -> node
+    osc_address_map: HashMap<String, NodeIndex>,
+    //storage -> (node, parameter index), when enabled
+    storage_index: Option<HashMap<StorageId, Vec<(NodeHandle, usize)>>>,
+    clock: SharedClock,
+    aliases: HashMap<AliasHandle, Alias>,
+    //alias full path -> alias
+    alias_paths: HashMap<String, AliasHandle>,
+    next_alias: u64,
+    //registered by the services, see `crate::capability`
+    capabilities: BTreeSet<&'static str>,
+    //nodes with a held back update handler run
+    coalescing: Mutex<HashSet<NodeIndex>>,
+    //roots of the frozen subtrees -> freeze count
+    frozen: HashMap<NodeIndex, usize>,
+    //render and send triggers even when nobody would receive them
+    force_trigger: bool,
 }
 
 /// The root of an OSCQuery tree.
 pub struct Root {
     inner: Arc<RwLock<RootInner>>,
+    stats: Arc<Stats>,
 }
 
 pub(crate) struct NodeWrapper {
     pub(crate) full_path: String,
     pub(crate) node: Node,
+    pub(crate) journal: ContentsJournal,
+    //the last time the value was written, via OSC or `Root::touch`
+    pub(crate) last_write: Atomic<Instant>,
+    //the source address of the last write, if it came from one
+    last_writer: Mutex<Option<SocketAddr>>,
+    //update handler runs held back by the node's coalesce window
+    coalesce: Mutex<Coalesce>,
+}
+
+//(args, source address, bundle)
+type PendingUpdate = (Vec<OscType>, Option<SocketAddr>, Option<BundleContext>);
+
+#[derive(Default)]
+struct Coalesce {
+    //the last time the handler ran
+    ran: Option<Instant>,
+    //the latest write since then
+    pending: Option<PendingUpdate>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum ContentsChange {
+    Added,
+    Removed,
+}
+
+/// A bounded record of the recent changes to the children of a node.
+#[derive(Default)]
+pub(crate) struct ContentsJournal {
+    //changes after this generation are all in the journal
+    horizon: u64,
+    changes: VecDeque<(u64, ContentsChange, String)>,
+}
+
+/// The net changes to the children of a node since a generation.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ContentsDiff {
+    /// The generation of the namespace when the diff was computed.
+    pub generation: u64,
+    /// The addresses of children that exist now but did not at the requested generation.
+    pub added: Vec<String>,
+    /// The addresses of children that existed at the requested generation but not now.
+    pub removed: Vec<String>,
+    /// The addresses of children that existed at both but were replaced in between.
+    pub changed: Vec<String>,
+}
+
+/// What `Root::reset_to_defaults` did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResetReport {
+    /// The full paths of the nodes that were written, in path order.
+    pub paths: Vec<String>,
+    /// The number of parameters that were set to their default.
+    pub reset: usize,
+    /// The number of writable parameters without a default, or in a node whose defaults would be
+    /// rejected like a NaN under `NonFinitePolicy::RejectWrite`, they were left as they were.
+    pub skipped: usize,
+    /// When triggered, the messages of the readable nodes that were written, rendered after the
+    /// reset, in path order.
+    pub triggered: Vec<OscMessage>,
+}
+
+/// The reasons a `ContentsDiff` could not be computed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ContentsSinceError {
+    /// There is no node at the given path.
+    NotFound,
+    /// The journal no longer reaches back to the requested generation, the current generation is
+    /// given so the client can refetch the full node.
+    TooOld(u64),
 }
 
 pub(crate) struct NodeSerializeWrapper<'a> {
     node: &'a NodeWrapper,
     graph: &'a Graph,
-    neighbors: WalkNeighbors<u32>,
+    aliases: &'a HashMap<AliasHandle, Alias>,
+    index: NodeIndex,
+    //the path of the alias being serialized, if any
+    alias_path: Option<&'a str>,
+    //only these nodes, when filtered
+    marks: Option<&'a HashSet<NodeIndex>>,
     param: Option<NodeQueryParam>,
+    //vendor extensions: leave out lazy values, include vendor attributes
+    extensions: bool,
+    non_finite_strings: bool,
+    //for expiry
+    now: Instant,
 }
 
 struct NodeSerializeContentsWrapper<'a> {
     graph: &'a Graph,
-    neighbors: WalkNeighbors<u32>,
+    aliases: &'a HashMap<AliasHandle, Alias>,
+    index: NodeIndex,
+    marks: Option<&'a HashSet<NodeIndex>>,
+    extensions: bool,
+    non_finite_strings: bool,
+    now: Instant,
 }
 
 /// A handle for a node, to be used for triggering, adding children and/or removing.
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub struct NodeHandle(NodeIndex);
+///
+/// Handles belong to the `Root` that returned them. Passing one to another root fails with
+/// `OscQueryError::WrongRoot`, or finds no node.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct NodeHandle {
+    index: NodeIndex,
+    root: u64,
+}
+
+//gives every root a different id, for its handles
+static NEXT_ROOT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// A handle for an alias, see `Root::add_alias`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AliasHandle(u64);
+
+/// Keeps a subtree frozen until dropped, see `Root::lock_subtree`.
+#[must_use = "the subtree is unfrozen when the guard is dropped"]
+pub struct SubtreeLockGuard {
+    root: Weak<RwLock<RootInner>>,
+    handle: NodeHandle,
+}
 
+impl Drop for SubtreeLockGuard {
+    fn drop(&mut self) {
+        if let Some(root) = self.root.upgrade() {
+            if let Ok(mut root) = root.write() {
+                let _ = root.unfreeze(self.handle.index);
+            }
+        }
+    }
+}
+
+//an additional path for a node
+struct Alias {
+    full_path: String,
+    parent: NodeIndex,
+    target: NodeIndex,
+    //emit at the alias path when the target is triggered
+    trigger: bool,
+}
+
+/// A change to the namespace, as the websocket service notifies its clients.
 #[derive(Clone, PartialEq, Eq, Debug)]
-pub(crate) enum NamespaceChange {
+pub enum NamespaceChange {
+    ///The node at the path was added
     PathAdded(String),
+    ///The node at the path, and those below it, were removed
     PathRemoved(String),
+    ///The attributes of the node at the path changed
+    PathChanged(String),
+    ///The old path, the new path, the paths below it move along
+    PathRenamed(String, String),
 }
 
-impl Root {
-    pub fn new(name: Option<String>) -> Self {
-        let inner = Arc::new(RwLock::new(RootInner::new(name)));
-        Self { inner }
-    }
-
-    pub fn spawn_osc<A: ToSocketAddrs>(&self, osc_addrs: A) -> Result<OscService, std::io::Error> {
-        Ok(OscService::new(self.inner.clone(), osc_addrs)?)
+impl NodeWrapper {
+    /// Render the node's current value as an OSC message at its OSC address or full path.
+    ///
+    /// Returns `None` if the value has expired.
+    pub(crate) fn osc_message(&self, now: Instant) -> Option<OscMessage> {
+        if self.expired(now) {
+            return None;
+        }
+        let mut args = Vec::new();
+        self.node.osc_render(&mut args);
+        Some(OscMessage {
+            addr: self
+                .node
+                .osc_address()
+                .map_or_else(|| self.full_path.clone(), |a| a.address.clone()),
+            args,
+        })
     }
 
-    pub fn spawn_ws<A: ToSocketAddrs>(&self, ws_addrs: A) -> Result<WSService, std::io::Error> {
-        Ok(WSService::new(self.inner.clone(), ws_addrs)?)
+    //`osc_message`, only for the nodes with a value to render
+    pub(crate) fn readable_message(&self, now: Instant) -> Option<OscMessage> {
+        match self.node {
+            Node::Get(..) | Node::GetSet(..) => self.osc_message(now),
+            _ => None,
+        }
     }
 
-    pub fn name(&self) -> Option<String> {
-        if let Ok(inner) = self.read_locked() {
-            inner.name()
-        } else {
-            None
+    /// Has the node gone without a write for longer than its `expires_after`.
+    pub(crate) fn expired(&self, now: Instant) -> bool {
+        match self.node.expires_after() {
+            Some(d) => now.saturating_duration_since(self.last_write.load(Ordering::Relaxed)) > d,
+            None => false,
         }
     }
 
-    fn write_locked(&self) -> Result<RwLockWriteGuard<RootInner>, &'static str> {
-        self.inner.write().or_else(|_| Err("poisoned lock"))
+    pub(crate) fn touch(&self, now: Instant) {
+        self.touch_from(now, None);
     }
 
-    fn read_locked(&self) -> Result<RwLockReadGuard<RootInner>, &'static str> {
-        self.inner.read().or_else(|_| Err("poisoned lock"))
+    pub(crate) fn touch_from(&self, now: Instant, addr: Option<SocketAddr>) {
+        if let Ok(mut writer) = self.last_writer.lock() {
+            *writer = addr;
+        }
+        self.last_write.store(now, Ordering::Relaxed);
     }
 
-    ///add node to the graph at the root or as a child of the given parent
-    pub fn add_node<N>(
-        &self,
-        node: N,
-        parent: Option<NodeHandle>,
-    ) -> Result<NodeHandle, (Node, &'static str)>
-    where
-        N: Into<Node>,
-    {
-        let node = node.into();
-        match self.write_locked() {
-            Ok(mut inner) => inner.add_node(node, parent),
-            Err(s) => Err((node, s)),
-        }
+    /// The source address and time of the last write, if it came from an address.
+    pub(crate) fn last_writer(&self) -> Option<(SocketAddr, Instant)> {
+        let addr = self.last_writer.lock().map_or(None, |w| *w)?;
+        Some((addr, self.last_write.load(Ordering::Relaxed)))
     }
+}
 
-    ///Remove the node at the handle returns it and any children if found
-    ///leafs come first in returned vector
-    pub fn rm_node(&self, handle: NodeHandle) -> Result<Vec<Node>, (NodeHandle, &'static str)> {
-        match self.write_locked() {
-            Ok(mut inner) => inner.rm_node(handle),
-            Err(s) => Err((handle, s)),
+impl ContentsJournal {
+    fn record(&mut self, generation: u64, change: ContentsChange, address: &str) {
+        if self.changes.len() == CONTENTS_JOURNAL_LEN {
+            if let Some((g, _, _)) = self.changes.pop_front() {
+                self.horizon = g;
+            }
         }
+        self.changes
+            .push_back((generation, change, address.to_string()));
     }
 
-    pub fn handle_to_path(&self, handle: &NodeHandle) -> Option<String> {
-        self.read_locked()
-            .expect("failed to read lock")
-            .handle_to_path(handle)
+    fn since(&self, generation: u64) -> Option<ContentsDiff> {
+        if generation < self.horizon {
+            return None;
+        }
+        //address -> (existed at generation, exists now)
+        let mut net: BTreeMap<&str, (bool, bool)> = BTreeMap::new();
+        for (_, change, address) in self.changes.iter().filter(|c| c.0 > generation) {
+            let e = net
+                .entry(address.as_str())
+                .or_insert((*change != ContentsChange::Added, false));
+            e.1 = *change != ContentsChange::Removed;
+        }
+        let mut diff = ContentsDiff::default();
+        for (address, existence) in net {
+            match existence {
+                (false, true) => diff.added.push(address.to_string()),
+                (true, false) => diff.removed.push(address.to_string()),
+                (true, true) => diff.changed.push(address.to_string()),
+                (false, false) => (),
+            }
+        }
+        Some(diff)
     }
+}
 
-    pub(crate) fn serialize_node<F, S>(
-        &self,
-        path: &str,
-        param: Option<NodeQueryParam>,
-        f: F,
-    ) -> Result<S::Ok, S::Error>
-    where
-        F: FnOnce(Option<&NodeSerializeWrapper>) -> Result<S::Ok, S::Error>,
-        S: Serializer,
-    {
-        self.read_locked()
-            .expect("failed to read lock")
-            .serialize_node::<F, S>(path, param, f)
-    }
+//does the OSC address contain any pattern characters
+fn is_pattern(addr: &str) -> bool {
+    addr.contains(&['*', '?', '[', ']', '{', '}'][..])
 }
 
-impl Serialize for Root {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let root = self.read_locked().expect("failed to read lock");
-        serializer.serialize_some(&*root)
-    }
+/// The nodes whose full paths match the OSC address pattern, in path order.
+///
+/// Supports the OSC 1.0 syntax: `?` matches any single character, `*` any sequence of
+/// characters, `[a-z]` and `[!a-z]` a character in, or not in, the list of characters and
+/// ranges, and `{foo,bar}` any of the comma separated alternatives, which can themselves be
+/// patterns. None of them match across a `/`.
+fn match_pattern(pattern: &str, paths: &HashMap<String, NodeIndex>) -> Vec<NodeIndex> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let mut matched: Vec<(&String, NodeIndex)> = paths
+        .iter()
+        .filter(|(path, _)| {
+            let path: Vec<char> = path.chars().collect();
+            pattern_matches(&pattern, &path)
+        })
+        .map(|(path, index)| (path, *index))
+        .collect();
+    matched.sort();
+    matched.into_iter().map(|(_, index)| index).collect()
 }
 
-impl OscQueryGraph for RootInner {
-    ///add node to the graph at the root or as a child of the given parent
-    fn add_node(
-        &mut self,
-        node: Node,
-        parent: Option<NodeHandle>,
-    ) -> Result<NodeHandle, (Node, &'static str)> {
-        let parent_index = match parent {
-            Some(handle) => Some(handle.0),
+fn pattern_matches(pattern: &[char], s: &[char]) -> bool {
+    //the pattern position after the last star and how far into the path it reaches, so a
+    //mismatch only ever backtracks to the last star instead of trying every split point
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut i) = (0, 0);
+    loop {
+        let next = match pattern.get(p) {
+            None if i == s.len() => return true,
             None => None,
+            Some('*') => {
+                //a run of stars matches the same as one
+                while pattern.get(p) == Some(&'*') {
+                    p += 1;
+                }
+                star = Some((p, i));
+                continue;
+            }
+            Some('?') => match s.get(i) {
+                Some(c) if *c != '/' => Some((p + 1, i + 1)),
+                _ => None,
+            },
+            Some('[') => match pattern[p..].iter().position(|c| *c == ']') {
+                Some(end) => match s.get(i) {
+                    Some(c) if *c != '/' && class_matches(&pattern[p + 1..p + end], *c) => {
+                        Some((p + end + 1, i + 1))
+                    }
+                    _ => None,
+                },
+                None => literal_matches(pattern, s, p, i),
+            },
+            Some('{') => match alternatives(&pattern[p..]) {
+                Some((alts, end)) => {
+                    let rest = &pattern[p + end + 1..];
+                    let matched = alts.into_iter().any(|alt| {
+                        let mut q = alt.to_vec();
+                        q.extend_from_slice(rest);
+                        pattern_matches(&q, &s[i..])
+                    });
+                    if matched {
+                        return true;
+                    }
+                    None
+                }
+                None => literal_matches(pattern, s, p, i),
+            },
+            Some(..) => literal_matches(pattern, s, p, i),
         };
-        let (parent_index, full_path) = if let Some(parent_index) = parent_index {
-            if let Some(parent) = self.graph.node_weight(parent_index.clone()) {
-                Ok((parent_index, parent.full_path.clone()))
-            } else {
-                return Err((node, "parent not in graph"));
+        match (next, star) {
+            (Some((np, ni)), _) => {
+                p = np;
+                i = ni;
             }
-        } else {
-            Ok((self.root, "".to_string()))
-        }?;
+            //let the last star take one more character, it never takes a '/'
+            (None, Some((sp, si))) if si < s.len() && s[si] != '/' => {
+                star = Some((sp, si + 1));
+                p = sp;
+                i = si + 1;
+            }
+            (None, _) => return false,
+        }
+    }
+}
 
-        //compute the full path
-        let full_path = format!("{}/{}", full_path, node.address());
-        let node = NodeWrapper {
-            node,
-            full_path: full_path.clone(),
-        };
+fn literal_matches(pattern: &[char], s: &[char], p: usize, i: usize) -> Option<(usize, usize)> {
+    match s.get(i) {
+        Some(c) if *c == pattern[p] => Some((p + 1, i + 1)),
+        _ => None,
+    }
+}
 
-        //actually add
-        let index = self.graph.add_node(node);
-        self.index_map.insert(full_path.clone(), index);
-        let _ = self.graph.add_edge(parent_index, index, ());
-        if let Some(ns_change_send) = &self.ns_change_send {
-            let _ = ns_change_send.try_send(NamespaceChange::PathAdded(full_path));
+//the characters between the brackets of a character class
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negate, class) = match class.first() {
+        Some('!') if class.len() > 1 => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        //a '-' at either end is literal
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
         }
-        Ok(NodeHandle(index))
     }
+    found != negate
+}
 
-    ///Remove the node at the handle returns it and any children if found
-    ///leafs come first in returned vector
-    fn rm_node(&mut self, handle: NodeHandle) -> Result<Vec<Node>, (NodeHandle, &'static str)> {
-        let index = handle.0;
-        let mut children = self.graph.neighbors(index).detach();
-        let mut v = Vec::new();
-        while let Some(index) = children.next_node(&self.graph) {
-            v.append(
-                &mut self
-                    .rm_node(NodeHandle(index))
-                    .expect("child should be in graph"),
-            );
-        }
-        match self.graph.remove_node(index) {
-            Some(node) => {
-                self.index_map.remove(&node.full_path);
-                v.push(node.node);
-                if let Some(ns_change_send) = &self.ns_change_send {
-                    let _ = ns_change_send
-                        .try_send(NamespaceChange::PathRemoved(node.full_path.clone()));
-                }
-                Ok(v)
+//split the braces at the start of the pattern into its alternatives, with the index of the
+//closing brace, nested braces are kept within their alternative
+fn alternatives(pattern: &[char]) -> Option<(Vec<&[char]>, usize)> {
+    let mut alts = Vec::new();
+    let mut depth = 0;
+    let mut start = 1;
+    for (i, c) in pattern.iter().enumerate().skip(1) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                alts.push(&pattern[start..i]);
+                return Some((alts, i));
+            }
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alts.push(&pattern[start..i]);
+                start = i + 1;
             }
-            None => Err((handle, &"node at handle not in graph")),
+            _ => (),
         }
     }
+    None
 }
 
-impl RootInner {
-    pub(crate) fn new(name: Option<String>) -> Self {
-        let mut graph = StableGraph::default();
-        let root = graph.add_node(NodeWrapper {
-            full_path: "/".to_string(),
-            node: Node::Container(Container {
-                address: "".to_string(), //invalid, but unchecked by default access
-                description: Some("root node".to_string()),
-            }),
-        });
-        let mut index_map = HashMap::new();
-        index_map.insert("/".to_string(), root);
-        Self {
-            name,
-            graph,
-            root,
-            index_map,
-            ns_change_send: None,
+//the distinct nodes in a storage index entry
+fn handles(uses: &[(NodeHandle, usize)]) -> Vec<NodeHandle> {
+    let mut handles = Vec::new();
+    for (handle, _) in uses {
+        if !handles.contains(handle) {
+            handles.push(*handle);
         }
     }
+    handles
+}
 
-    pub(crate) fn ns_change_recv(&mut self) -> Option<Receiver<NamespaceChange>> {
-        if self.ns_change_send.is_some() {
-            None
-        } else {
-            let (send, recv) = sync_channel(NS_CHANGE_LEN);
-            self.ns_change_send = Some(send);
-            Some(recv)
+impl Root {
+    pub fn new(name: Option<String>) -> Self {
+        let inner = RootInner::new(name);
+        let stats = inner.stats.clone();
+        Self {
+            inner: Arc::new(RwLock::new(inner)),
+            stats,
         }
     }
 
-    pub fn with_node_at_handle<F, R>(&self, handle: &NodeHandle, f: F) -> R
-    where
-        F: Fn(Option<&NodeWrapper>) -> R,
-    {
-        f(self.graph.node_weight(handle.0))
+    /// Apply an OSC packet to the tree, as if an OSC service received it from the address.
+    ///
+    /// Returns the replies to value queries, messages without arguments to `Get` or `GetSet`
+    /// nodes, which should be sent back to the address. Queries addressed with a pattern are
+    /// ignored, they'd let one small packet ask for a reply from every node.
+    ///
+    /// Useful for transports other than the ones in `service`.
+    pub fn handle_osc_packet(
+        &self,
+        packet: &OscPacket,
+        addr: Option<SocketAddr>,
+    ) -> Vec<OscMessage> {
+        RootInner::handle_osc_packet(&self.inner, packet, addr, None)
     }
 
-    pub fn with_node_at_path<F, R>(&self, path: &str, f: F) -> R
-    where
-        F: Fn(Option<(&NodeWrapper, &NodeIndex)>) -> R,
-    {
-        f(if let Some(index) = self.index_map.get(path) {
-            self.graph.node_weight(*index).map(|n| (n, index))
-        } else {
-            None
-        })
+    pub fn spawn_osc<A: ToSocketAddrs>(&self, osc_addrs: A) -> Result<OscService, OscQueryError> {
+        OscService::new(self.inner.clone(), self.stats.clone(), osc_addrs)
     }
 
-    pub fn handle_to_path(&self, handle: &NodeHandle) -> Option<String> {
-        self.graph
-            .node_weight(handle.0)
-            .map(|n| n.full_path.clone())
+    /// Spawn an OSC service that joins the multicast group and sends to it.
+    ///
+    /// The group, with its port, is added as a send address. For IPv4 groups the interface
+    /// selects where to join, the system picks without one, for IPv6 groups the system always
+    /// picks. The group is left when the service is dropped.
+    ///
+    /// To receive what is sent to the group bind its port, `0.0.0.0:<group port>` for instance.
+    /// The socket is bound with `SO_REUSEADDR` so other sockets on this host, other multicast
+    /// services included, can bind that port as well.
+    /// *NOTE* sends go out on the system's multicast route. They are looped back, so other
+    /// sockets on this host that joined the group receive them too.
+    pub fn spawn_osc_multicast<A: ToSocketAddrs>(
+        &self,
+        osc_addrs: A,
+        group: SocketAddr,
+        interface: Option<IpAddr>,
+    ) -> Result<OscService, OscQueryError> {
+        OscService::new_multicast(
+            self.inner.clone(),
+            self.stats.clone(),
+            osc_addrs,
+            group,
+            interface,
+        )
     }
 
-    fn handle_osc_msg(
+    /// Spawn an OSC service that accepts TCP connections, with SLIP framing.
+    pub fn spawn_osc_tcp<A: ToSocketAddrs>(
         &self,
-        msg: &OscMessage,
-        addr: Option<SocketAddr>,
-        time: Option<(u32, u32)>,
-    ) -> Option<OscWriteCallback> {
-        self.with_node_at_path(&msg.addr, |ni| {
-            if let Some((node, index)) = ni {
-                node.node
-                    .osc_update(&msg.args, addr, time, &NodeHandle(*index))
-            } else {
-                None
-            }
-        })
+        osc_addrs: A,
+    ) -> Result<OscTcpService, OscQueryError> {
+        self.spawn_osc_tcp_with_framing(osc_addrs, Framing::Slip)
     }
 
-    /// handle an osc packet, might change the graph
-    pub(crate) fn handle_osc_packet(
-        root: &Arc<RwLock<RootInner>>,
-        packet: &OscPacket,
-        addr: Option<SocketAddr>,
-        time: Option<(u32, u32)>,
-    ) {
-        let mut cb = None;
-        if let Ok(root) = root.read() {
-            cb = root.handle_osc_packet_inner(&packet, addr, time);
-        }
-        //if there was a callback returned, execute it
-        if let Some(cb) = cb {
-            if let Ok(mut root) = root.write() {
-                (cb)(root.deref_mut());
-            }
-        }
+    /// Spawn an OSC service that accepts TCP connections, with the given framing.
+    pub fn spawn_osc_tcp_with_framing<A: ToSocketAddrs>(
+        &self,
+        osc_addrs: A,
+        framing: Framing,
+    ) -> Result<OscTcpService, OscQueryError> {
+        OscTcpService::new(self.inner.clone(), self.stats.clone(), osc_addrs, framing)
     }
 
-    fn handle_osc_packet_inner(
+    pub fn spawn_ws<A: ToSocketAddrs>(&self, ws_addrs: A) -> Result<WSService, OscQueryError> {
+        WSService::new(self.inner.clone(), self.stats.clone(), ws_addrs)
+    }
+
+    /// Spawn a websocket secure (wss) service, clients do the TLS handshake with the acceptor
+    /// before upgrading to a websocket.
+    #[cfg(feature = "tls")]
+    pub fn spawn_ws_tls<A: ToSocketAddrs>(
         &self,
-        packet: &OscPacket,
-        addr: Option<SocketAddr>,
-        time: Option<(u32, u32)>,
-    ) -> Option<OscWriteCallback> {
-        match packet {
-            OscPacket::Message(msg) => self.handle_osc_msg(&msg, addr, time),
-            OscPacket::Bundle(bundle) => {
-                let mut callbacks = Vec::new();
-                for p in bundle.content.iter() {
-                    if let Some(cb) =
-                        self.handle_osc_packet_inner(p, addr.clone(), Some(bundle.timetag))
-                    {
-                        callbacks.push(cb);
-                    }
-                }
-                //handle any callbacks we might have
-                if callbacks.len() == 0 {
-                    None
-                } else {
-                    let f = Box::new(move |root: &mut dyn OscQueryGraph| {
-                        for cb in callbacks.into_iter() {
-                            (cb)(root);
-                        }
-                    });
-                    Some(f)
-                }
-            }
-        }
+        ws_addrs: A,
+        tls: tokio_rustls::TlsAcceptor,
+    ) -> Result<WSService, OscQueryError> {
+        WSService::new_tls(self.inner.clone(), self.stats.clone(), ws_addrs, tls)
     }
 
-    pub fn name(&self) -> Option<String> {
-        self.name.clone()
+    /// Create a websocket service that clients connect to in memory, for deterministic tests.
+    ///
+    /// Nothing runs until the returned future is spawned, see `service::memory`.
+    #[cfg(any(test, feature = "deterministic-test"))]
+    pub fn spawn_ws_in_memory(
+        &self,
+    ) -> Result<
+        (
+            WSService,
+            crate::service::memory::Connector,
+            impl std::future::Future<Output = ()>,
+        ),
+        OscQueryError,
+    > {
+        WSService::new_in_memory(self.inner.clone(), self.stats.clone())
     }
 
-    pub(crate) fn serialize_node<F, S>(
+    /// Create an OSC service on an in-memory socket, for deterministic tests.
+    ///
+    /// Nothing runs until the returned driver steps it, see `service::memory`.
+    #[cfg(any(test, feature = "deterministic-test"))]
+    pub fn spawn_osc_in_memory(
         &self,
-        path: &str,
-        param: Option<NodeQueryParam>,
-        f: F,
-    ) -> Result<S::Ok, S::Error>
-    where
-        F: FnOnce(Option<&NodeSerializeWrapper>) -> Result<S::Ok, S::Error>,
-        S: Serializer,
-    {
-        match self.index_map.get(path) {
-            Some(index) => match self.graph.node_weight(index.clone()) {
-                Some(node) => f(Some(&NodeSerializeWrapper {
-                    node,
-                    graph: &self.graph,
-                    neighbors: self.graph.neighbors(*index).detach(),
-                    param,
-                })),
-                None => f(None),
+        sock: crate::service::memory::MemorySocket,
+    ) -> (OscService, crate::service::osc::OscDriver) {
+        OscService::new_in_memory(self.inner.clone(), self.stats.clone(), sock)
+    }
+
+    /// Get the statistics shared by the services spawned from this root.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// Log a warning, and count it in the stats, whenever handling an incoming OSC message for a
+    /// single node takes longer than the threshold. `None`, the default, disables the warning.
+    ///
+    /// Handlers run on the thread of the service that received the message, while holding the
+    /// root's read lock, so a slow handler delays every message behind it and blocks any
+    /// structural changes to the tree.
+    /// The timing of every invocation is always available via `Stats::handler_timing`.
+    pub fn set_slow_handler_threshold(&self, threshold: Option<Duration>) {
+        if let Ok(mut inner) = self.write_locked() {
+            inner.slow_handler_threshold = threshold;
+        }
+    }
+
+    /// Set what happens to OSC messages, for `Set` and `GetSet` nodes, with a NaN or infinite
+    /// float or double argument. Defaults to `NonFinitePolicy::PassThrough`.
+    ///
+    /// Dropped messages are counted in `Stats::osc_non_finite_rejected`. Only top level
+    /// arguments are checked, array elements and custom parameters pass through.
+    /// Rendering OSC is unaffected, it sends whatever the storage holds.
+    pub fn set_non_finite_policy(&self, policy: NonFinitePolicy) {
+        if let Ok(mut inner) = self.write_locked() {
+            inner.non_finite_policy = policy;
+        }
+    }
+
+    /// Serialize NaN and infinite float and double values as the JSON strings `"nan"`, `"inf"`
+    /// and `"-inf"`, rather than `null`, the default, in the `VALUE` of the namespace.
+    ///
+    /// JSON has no such numbers, only enable this for clients that expect the strings.
+    pub fn set_non_finite_strings(&self, strings: bool) {
+        if let Ok(mut inner) = self.write_locked() {
+            inner.non_finite_strings = strings;
+        }
+    }
+
+    /// Render and send triggered messages even when no OSC send address or websocket client
+    /// would receive them, off by default. For debugging, see `OscService::trigger`.
+    pub fn set_force_trigger(&self, force: bool) {
+        if let Ok(mut inner) = self.write_locked() {
+            inner.force_trigger = force;
+        }
+    }
+
+    pub(crate) fn force_trigger(&self) -> bool {
+        self.read_locked().is_ok_and(|inner| inner.force_trigger)
+    }
+
+    /// Get the namespace generation, incremented with every structural change.
+    pub fn generation(&self) -> u64 {
+        self.read_locked().map_or(0, |inner| inner.generation)
+    }
+
+    /// Get the net changes to the children of the node at the path since the given generation.
+    pub fn contents_since(
+        &self,
+        path: &str,
+        generation: u64,
+    ) -> Result<ContentsDiff, ContentsSinceError> {
+        let inner = self
+            .read_locked()
+            .map_err(|_| ContentsSinceError::NotFound)?;
+        inner.with_node_at_path(path, |n| match n {
+            Some((node, _)) => match node.journal.since(generation) {
+                Some(mut diff) => {
+                    diff.generation = inner.generation;
+                    Ok(diff)
+                }
+                None => Err(ContentsSinceError::TooOld(inner.generation)),
             },
-            None => f(None),
+            None => Err(ContentsSinceError::NotFound),
+        })
+    }
+
+    /// Render the OSC message for the node at the given handle, if it exists.
+    pub(crate) fn render(&self, handle: &NodeHandle) -> Option<OscMessage> {
+        self.read_locked().ok().and_then(|inner| {
+            let now = inner.clock.now();
+            inner.with_node_at_handle(handle, |node| node.and_then(|n| n.osc_message(now)))
+        })
+    }
+
+    /// Render the OSC message for the node at the given path, if it exists.
+    pub(crate) fn render_path(&self, path: &str) -> Option<OscMessage> {
+        self.read_locked().ok().and_then(|inner| {
+            let now = inner.clock.now();
+            inner.with_node_at_path(path, |node| node.and_then(|(n, _)| n.osc_message(now)))
+        })
+    }
+
+    /// Like `render_path`, `None` for nodes without a value to render.
+    pub(crate) fn render_readable_path(&self, path: &str) -> Option<OscMessage> {
+        self.read_locked().ok().and_then(|inner| {
+            let now = inner.clock.now();
+            inner.with_node_at_path(path, |node| node.and_then(|(n, _)| n.readable_message(now)))
+        })
+    }
+
+    /// Record that the value of the node at the handle was written, restarting its expiry window.
+    ///
+    /// OSC writes are recorded automatically, call this after updating a value programmatically.
+    pub fn touch(&self, handle: &NodeHandle) {
+        if let Ok(inner) = self.read_locked() {
+            let now = inner.clock.now();
+            inner.with_node_at_handle(handle, |node| {
+                if let Some(node) = node {
+                    node.touch(now);
+                }
+            });
         }
     }
-}
 
-impl Serialize for RootInner {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    /// Set the time source, defaults to `SystemClock`.
+    ///
+    /// Set it before spawning services, they take the clock when they are spawned.
+    pub fn set_clock(&self, clock: SharedClock) {
+        if let Ok(mut inner) = self.write_locked() {
+            inner.clock = clock;
+        }
+    }
+
+    /// Get the time source.
+    pub fn clock(&self) -> SharedClock {
+        self.read_locked()
+            .map_or_else(|_| crate::time::system_clock(), |inner| inner.clock.clone())
+    }
+
+    /// Set, or clear, the OSC address for the outgoing messages of the node at the handle.
+    ///
+    /// See `Get::with_osc_address`. Fails if there is no node at the handle or it is a container.
+    pub fn set_osc_address(
+        &self,
+        handle: &NodeHandle,
+        osc_address: Option<OscAddress>,
+    ) -> Result<(), OscQueryError> {
+        let mut inner = self.write_locked()?;
+        let index = inner.index(handle)?;
+        inner.unmap_osc_address(index);
+        let res = match inner.graph.node_weight_mut(index) {
+            Some(n) => n.node.set_osc_address(osc_address),
+            None => Err(OscQueryError::NodeNotInGraph),
+        };
+        inner.map_osc_address(index);
+        if res.is_ok() {
+            inner.notify_changed(index);
+        }
+        res
+    }
+
+    /// Tell websocket clients that the attributes of the node at the handle, its description or
+    /// range for instance, changed with `PATH_CHANGED`, also for the paths of its aliases.
+    ///
+    /// `replace_node` and `set_osc_address` notify automatically.
+    pub fn notify_changed(&self, handle: &NodeHandle) {
+        if let Ok(inner) = self.read_locked() {
+            if let Ok(index) = inner.index(handle) {
+                inner.notify_changed(index);
+            }
+        }
+    }
+
+    /// Replace the node at the handle in place, keeping its path, children, aliases and handle.
+    /// Returns the old node.
+    ///
+    /// The new node must have the same address, a container with children or aliases under it can
+    /// only be replaced by a container and an aliased node can't become a container, the node is
+    /// given back otherwise. Clients are told with `PATH_CHANGED` and
+    /// `CONTENTS_SINCE` reports the address as changed.
+    pub fn replace_node<N>(&self, handle: &NodeHandle, node: N) -> Result<Node, OscQueryError>
     where
-        S: Serializer,
+        N: Into<Node>,
+    {
+        let node = node.into();
+        match self.write_locked() {
+            Ok(mut inner) => match inner.index(handle) {
+                Ok(index) => inner.replace_node(index, node),
+                Err(e) => Err(OscQueryError::node_not_added(node, e)),
+            },
+            Err(e) => Err(OscQueryError::node_not_added(node, e)),
+        }
+    }
+
+    /// Expose the node at the target handle at an additional path, the address as a child of the
+    /// given parent container, or the root.
+    ///
+    /// The alias shares the node: OSC messages to its path update the target and queries at its
+    /// path serialize the target with the alias `FULL_PATH`, plus an `ALIAS_OF` vendor attribute.
+    /// Removing the target, or the alias parent, removes the alias. Containers can't be aliased.
+    pub fn add_alias(
+        &self,
+        target: NodeHandle,
+        alias_parent: Option<NodeHandle>,
+        alias_address: &str,
+    ) -> Result<AliasHandle, OscQueryError> {
+        self.write_locked()?
+            .add_alias(target, alias_parent, alias_address)
+    }
+
+    /// Remove an alias, the target node is untouched.
+    pub fn rm_alias(&self, handle: AliasHandle) -> Result<(), OscQueryError> {
+        let mut inner = self.write_locked()?;
+        if let Some(parent) = inner.aliases.get(&handle).map(|a| a.parent) {
+            inner.check_frozen(parent, false)?;
+        }
+        inner.rm_alias(handle)
+    }
+
+    /// Also emit at the alias path whenever the target is triggered. Defaults to `false`.
+    pub fn set_alias_trigger(
+        &self,
+        handle: AliasHandle,
+        trigger: bool,
+    ) -> Result<(), OscQueryError> {
+        match self.write_locked()?.aliases.get_mut(&handle) {
+            Some(alias) => {
+                alias.trigger = trigger;
+                Ok(())
+            }
+            None => Err(OscQueryError::AliasNotFound),
+        }
+    }
+
+    /// Get the full path of the alias, if it exists.
+    pub fn alias_path(&self, handle: AliasHandle) -> Option<String> {
+        self.read_locked()
+            .ok()
+            .and_then(|inner| inner.aliases.get(&handle).map(|a| a.full_path.clone()))
+    }
+
+    /// Render the messages of every readable node, in path order, along with their paths and the
+    /// paths of the aliases that emit when they are triggered.
+    pub(crate) fn render_all(&self) -> Vec<(String, OscMessage, Vec<String>)> {
+        self.read_locked()
+            .map_or(Vec::new(), |inner| inner.render_all())
+    }
+
+    pub(crate) fn render_handles<I>(&self, handles: I) -> Vec<(String, OscMessage, Vec<String>)>
+    where
+        I: IntoIterator<Item = NodeHandle>,
     {
-        self.serialize_node::<_, S>(&"/", None, move |n| {
-            serializer.serialize_some(n.expect("root must be in graph"))
+        self.read_locked()
+            .map_or(Vec::new(), |inner| inner.render_handles(handles))
+    }
+
+    /// Does the node at the path need reliable delivery, see `Get::with_critical`.
+    pub(crate) fn critical_at_path(&self, path: &str) -> bool {
+        self.read_locked().is_ok_and(|inner| {
+            inner.with_node_at_path(path, |n| n.is_some_and(|(n, _)| n.node.critical()))
         })
     }
-}
 
-impl<'a> Serialize for NodeSerializeWrapper<'a> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    /// Is the node at the handle a container.
+    #[cfg(feature = "import")]
+    pub(crate) fn is_container(&self, handle: &NodeHandle) -> bool {
+        self.read_locked().is_ok_and(|inner| {
+            inner.with_node_at_handle(handle, |n| {
+                n.is_some_and(|n| matches!(n.node, Node::Container(..)))
+            })
+        })
+    }
+
+    /// The paths of the aliases that emit when the node at the path is triggered.
+    pub(crate) fn alias_triggers(&self, path: &str) -> Vec<String> {
+        self.read_locked().map_or(Vec::new(), |inner| {
+            inner.with_node_at_path(path, |n| {
+                n.map_or(Vec::new(), |(n, _)| inner.alias_triggers(&n.full_path))
+            })
+        })
+    }
+
+    /// Set the writable parameters at and below the handle, or in the whole tree with `None`, to
+    /// their defaults, see `ValueBuilder::with_default`.
+    ///
+    /// Each node gets a message with the defaults, applied like one received over OSC, so
+    /// clipping, update handlers and write callbacks all run. Parameters without a default get a
+    /// `Nil` argument, which leaves them as they were, nodes without any defaults aren't written.
+    /// The padding isn't counted in `Stats::osc_unsupported_args`. An invalid handle resets
+    /// nothing.
+    ///
+    /// With `trigger` the readable nodes that were written are rendered into
+    /// `ResetReport::triggered` for the caller to send, `OscQueryServer::reset_to_defaults` sends
+    /// them.
+    pub fn reset_to_defaults(&self, scope: Option<NodeHandle>, trigger: bool) -> ResetReport {
+        let (msgs, mut report) = match self.read_locked() {
+            Ok(inner) => {
+                let index = match scope {
+                    Some(h) => match inner.index(&h) {
+                        Ok(index) if inner.graph.contains_node(index) => index,
+                        _ => return ResetReport::default(),
+                    },
+                    None => inner.root,
+                };
+                inner.default_messages(index)
+            }
+            Err(..) => return ResetReport::default(),
+        };
+        RootInner::apply_defaults(&self.inner, &msgs);
+        if trigger {
+            report.triggered = report
+                .paths
+                .iter()
+                .filter_map(|path| self.render_readable_path(path))
+                .collect();
+        }
+        report
+    }
+
+    /// Get the defaults of the node at the path as OSC arguments, `None` unless every parameter
+    /// has one.
+    pub fn default_at_path(&self, path: &str) -> Option<Vec<OscType>> {
+        let inner = self.read_locked().ok()?;
+        let defaults =
+            inner.with_node_at_path(path, |n| n.map(|(n, _)| n.node.param_defaults()))?;
+        if defaults.is_empty() {
+            return None;
+        }
+        defaults.into_iter().try_fold(Vec::new(), |mut args, d| {
+            args.extend(d?);
+            Some(args)
+        })
+    }
+
+    //the number of OSC arguments each parameter of the node at the path takes
+    pub(crate) fn param_arg_counts_at_path(&self, path: &str) -> Option<Vec<usize>> {
+        self.read_locked()
+            .ok()?
+            .with_node_at_path(path, |n| n.map(|(n, _)| n.node.param_arg_counts()))
+    }
+
+    //the type string of the node at the path, `None` if it has no value
+    pub(crate) fn type_string_at_path(&self, path: &str) -> Option<String> {
+        self.read_locked()
+            .ok()?
+            .with_node_at_path(path, |n| n.and_then(|(n, _)| n.node.type_string()))
+    }
+
+    /// Get the argument at the index of the current value of the node at the path, `None` if
+    /// there is no such node or argument.
+    ///
+    /// The index counts OSC arguments, the same as parameters unless custom parameters take
+    /// more than one.
+    pub fn value_at_path_indexed(&self, path: &str, index: usize) -> Option<OscType> {
+        self.render_path(path)?.args.get(index).cloned()
+    }
+
+    /// Replace the argument at the index of the current value of the read/write node at the
+    /// path, see `value_at_path_indexed`.
+    ///
+    /// The whole message is applied, like one received over OSC, so clipping, update handlers
+    /// and write callbacks see all the arguments, the others unchanged.
+    /// *NOTE* a write between reading the current value and applying it is overwritten.
+    pub fn set_value_at_path_indexed(
+        &self,
+        path: &str,
+        index: usize,
+        arg: OscType,
+    ) -> Result<(), OscQueryError> {
+        let mut msg = {
+            let inner = self.read_locked()?;
+            let now = inner.clock.now();
+            inner.with_node_at_path(path, |n| match n {
+                Some((n, _)) => match (&n.node, n.osc_message(now)) {
+                    (Node::GetSet(..), Some(msg)) => Ok(msg),
+                    _ => Err(OscQueryError::NotReadWrite(path.to_string())),
+                },
+                None => Err(OscQueryError::NodeNotInGraph),
+            })?
+        };
+        let len = msg.args.len();
+        *msg.args
+            .get_mut(index)
+            .ok_or(OscQueryError::IndexOutOfRange { index, len })? = arg;
+        //the full path, the message address could be an outbound only OSC address
+        msg.addr = path.to_string();
+        RootInner::handle_osc_packet(&self.inner, &OscPacket::Message(msg), None, None);
+        Ok(())
+    }
+
+    /// Set the value of the writable node at the path from JSON, an array with an entry per
+    /// argument, as served for `VALUE`.
+    ///
+    /// The arguments are converted for the node's type string or, failing that, the first of its
+    /// overloads that they match, then applied like a message received over OSC. Fails with
+    /// `WriteRejected` if the write would be dropped, like one with a NaN under
+    /// `NonFinitePolicy::RejectWrite`.
+    pub fn set_value_at_path_json(
+        &self,
+        path: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), OscQueryError> {
+        let types = self.read_locked()?.with_node_at_path(path, |n| match n {
+            Some((n, _)) => match &n.node {
+                Node::Container(..) => Err(OscQueryError::ContainerHasNoValue),
+                Node::Get(..) => Err(OscQueryError::ReadOnly(path.to_string())),
+                node => Ok(node
+                    .type_string()
+                    .into_iter()
+                    .chain(node.overload_type_strings())
+                    .collect::<Vec<_>>()),
+            },
+            None => Err(OscQueryError::NodeNotInGraph),
+        })?;
+        let values = value
+            .as_array()
+            .ok_or_else(|| OscQueryError::TypeMismatch(types[0].clone()))?;
+        let args = types
+            .iter()
+            .find_map(|t| {
+                let tags = crate::convert::split_type_tags(t);
+                if tags.len() != values.len() {
+                    return None;
+                }
+                tags.into_iter()
+                    .zip(values)
+                    .map(|(t, v)| crate::convert::arg_from_json(t, v))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| OscQueryError::TypeMismatch(types[0].clone()))?;
+        {
+            let inner = self.read_locked()?;
+            if inner.with_node_at_path(path, |n| {
+                n.is_some_and(|(n, _)| inner.rejects_write(&n.node, &args))
+            }) {
+                return Err(OscQueryError::WriteRejected(path.to_string()));
+            }
+        }
+        let msg = OscMessage {
+            addr: path.to_string(),
+            args,
+        };
+        RootInner::handle_osc_packet(&self.inner, &OscPacket::Message(msg), None, None);
+        Ok(())
+    }
+
+    /// The current values of all the readable nodes, an object of full paths to `VALUE`
+    /// arrays, to save as a preset and restore with `apply_values`.
+    ///
+    /// NaN and infinities are kept as strings so they survive the round trip, expired values are
+    /// left out.
+    pub fn snapshot_values(&self) -> serde_json::Value {
+        let mut values = serde_json::Map::new();
+        if let Ok(inner) = self.read_locked() {
+            let now = inner.clock.now();
+            for n in inner
+                .index_map
+                .values()
+                .filter_map(|index| inner.graph.node_weight(*index))
+            {
+                if !matches!(n.node, Node::Get(..) | Node::GetSet(..)) || n.expired(now) {
+                    continue;
+                }
+                if let Ok(v) = serde_json::to_value(NodeValueWrapper(&n.node, true)) {
+                    values.insert(n.full_path.clone(), v);
+                }
+            }
+        }
+        serde_json::Value::Object(values)
+    }
+
+    /// Apply the values of a snapshot from `snapshot_values`, in path order, returns how many
+    /// were applied.
+    ///
+    /// Each value is set like with `set_value_at_path_json`, so clip modes, update handlers and
+    /// write callbacks run. Read only nodes are passed over, their values are only a record.
+    /// Entries for paths with no writable node, or with values that don't match the node's
+    /// type, are skipped and reported in `SnapshotError::Skipped`, after the rest are applied.
+    pub fn apply_values(&self, snapshot: &serde_json::Value) -> Result<usize, SnapshotError> {
+        let snapshot = snapshot.as_object().ok_or(SnapshotError::NotAnObject)?;
+        let mut paths: Vec<&String> = snapshot.keys().collect();
+        paths.sort();
+        let mut applied = 0;
+        let mut skipped = Vec::new();
+        for path in paths {
+            match self.set_value_at_path_json(path, &snapshot[path]) {
+                Ok(()) => applied += 1,
+                Err(OscQueryError::ReadOnly(..)) => (),
+                Err(e) => skipped.push((path.clone(), e)),
+            }
+        }
+        if skipped.is_empty() {
+            Ok(applied)
+        } else {
+            Err(SnapshotError::Skipped { applied, skipped })
+        }
+    }
+
+    /// Get the full paths of the nodes whose values have expired.
+    pub fn stale_paths(&self) -> Vec<String> {
+        self.read_locked().map_or(Vec::new(), |inner| {
+            let now = inner.clock.now();
+            let mut paths: Vec<String> = inner
+                .index_map
+                .values()
+                .filter_map(|index| inner.graph.node_weight(*index))
+                .filter(|n| n.expired(now))
+                .map(|n| n.full_path.clone())
+                .collect();
+            paths.sort();
+            paths
+        })
+    }
+
+    /// Start tracking which nodes share parameter storage, see `nodes_sharing_storage`.
+    ///
+    /// Nodes already in the tree are indexed immediately. Only storage addresses are kept, the
+    /// index never keeps storage alive.
+    pub fn enable_storage_index(&self) {
+        if let Ok(mut inner) = self.write_locked() {
+            if inner.storage_index.is_none() {
+                inner.storage_index = Some(HashMap::new());
+                let indices: Vec<NodeIndex> = inner.index_map.values().copied().collect();
+                for index in indices {
+                    inner.index_storage(index);
+                }
+            }
+        }
+    }
+
+    /// Get the nodes, including the given one, with a parameter backed by the same storage as
+    /// the parameter at `param_index` of the node at the handle.
+    ///
+    /// Empty unless `enable_storage_index` has been called.
+    pub fn nodes_sharing_storage(&self, handle: NodeHandle, param_index: usize) -> Vec<NodeHandle> {
+        self.read_locked().map_or(Vec::new(), |inner| {
+            let id = inner.with_node_at_handle(&handle, |node| {
+                node.and_then(|n| n.node.storage_ids().get(param_index).copied().flatten())
+            });
+            match (id, &inner.storage_index) {
+                (Some(id), Some(storage_index)) => storage_index
+                    .get(&id)
+                    .map_or(Vec::new(), |uses| handles(uses)),
+                _ => Vec::new(),
+            }
+        })
+    }
+
+    /// Get every indexed storage and the nodes that use it, ordered by id.
+    ///
+    /// Empty unless `enable_storage_index` has been called.
+    pub fn storage_map(&self) -> Vec<(StorageId, Vec<NodeHandle>)> {
+        self.read_locked().map_or(Vec::new(), |inner| {
+            let mut map: Vec<(StorageId, Vec<NodeHandle>)> = inner
+                .storage_index
+                .iter()
+                .flatten()
+                .map(|(id, uses)| (*id, handles(uses)))
+                .collect();
+            map.sort_by_key(|(id, _)| *id);
+            map
+        })
+    }
+
+    /// Visit every node with its full path, holding the read lock for at most `chunk_size` nodes
+    /// at a time so that writers aren't blocked by a long visit.
+    ///
+    /// Writers can run between chunks, so this isn't a consistent snapshot: nodes added or removed
+    /// during the visit may or may not be visited, but no node is visited twice. Aliases are not
+    /// visited. Return `ControlFlow::Break` from the visitor to stop, it is also returned.
+    pub fn visit_chunked<F>(&self, chunk_size: usize, mut visitor: F) -> ControlFlow<()>
     where
-        S: Serializer,
+        F: FnMut(&str, &Node) -> ControlFlow<()>,
     {
-        let n = &self.node.node;
-        match self.param {
-            None => {
-                let mut m = serializer.serialize_map(None)?;
-                m.serialize_entry("ACCESS".into(), &n.access())?;
-                if let Some(d) = n.description() {
-                    m.serialize_entry("DESCRIPTION".into(), d)?;
+        let chunk_size = std::cmp::max(chunk_size, 1);
+        //node indices are stable, resume after the last one visited
+        let mut cursor = 0;
+        loop {
+            let inner = match self.read_locked() {
+                Ok(inner) => inner,
+                Err(..) => return ControlFlow::Continue(()),
+            };
+            let bound = inner.graph.node_bound();
+            if cursor >= bound {
+                return ControlFlow::Continue(());
+            }
+            let mut visited = 0;
+            while cursor < bound && visited < chunk_size {
+                if let Some(n) = inner.graph.node_weight(NodeIndex::new(cursor)) {
+                    visited += 1;
+                    visitor(&n.full_path, &n.node)?;
                 }
-                m.serialize_entry("FULL_PATH".into(), &(self.node.full_path))?;
-                match n {
-                    Node::Get(..) | Node::GetSet(..) => {
-                        m.serialize_entry("VALUE".into(), &NodeValueWrapper(n))?;
-                    }
-                    _ => (),
-                };
-                match n {
-                    Node::Container(..) => {
-                        m.serialize_entry(
-                            "CONTENTS".into(),
-                            &NodeSerializeContentsWrapper {
-                                graph: self.graph,
-                                neighbors: self.neighbors.clone(),
-                            },
-                        )?;
-                    }
-                    _ => {
-                        if let Some(t) = n.type_string() {
-                            m.serialize_entry("TYPE".into(), &t)?;
-                        }
-                        m.serialize_entry("RANGE".into(), &NodeRangeWrapper(n))?;
-                        m.serialize_entry("CLIPMODE".into(), &NodeClipModeWrapper(n))?;
-                        m.serialize_entry("UNIT".into(), &NodeUnitWrapper(n))?;
-                    }
-                };
-                m.end()
+                cursor += 1;
+            }
+        }
+    }
+
+    /// Get the capabilities registered by the services of this root, sorted.
+    ///
+    /// See `crate::capability`.
+    pub fn capabilities(&self) -> Vec<String> {
+        self.read_locked()
+            .map_or(Vec::new(), |inner| inner.capabilities())
+    }
+
+    pub(crate) fn register_capability(&self, capability: &'static str) {
+        if let Ok(mut inner) = self.write_locked() {
+            inner.register_capability(capability);
+        }
+    }
+
+    //a new stream of the namespace changes, see `RootInner::ns_change_stream`
+    pub(crate) fn ns_change_stream(
+        &self,
+    ) -> Result<futures::channel::mpsc::Receiver<NamespaceChange>, OscQueryError> {
+        Ok(self.write_locked()?.ns_change_stream())
+    }
+
+    /// The full paths of all the nodes, including `"/"` for the root, in no particular order.
+    /// Aliases aren't included.
+    pub fn iter_paths(&self) -> Vec<String> {
+        self.read_locked()
+            .map_or_else(|_| Vec::new(), |inner| inner.all_paths())
+    }
+
+    /// Like `iter_paths` but depth first like `for_each_node`, parents come before their
+    /// children and siblings are in address order.
+    pub fn iter_paths_depth_first(&self) -> Vec<String> {
+        self.read_locked()
+            .map_or_else(|_| Vec::new(), |inner| inner.all_paths_depth_first())
+    }
+
+    /// The full paths of all the nodes, like `iter_paths` but sorted.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = self.iter_paths();
+        paths.sort();
+        paths
+    }
+
+    /// Call `f` with the full path, node and handle of every node, depth first from the root,
+    /// parents before their children and siblings in address order. Aliases aren't visited.
+    ///
+    /// The tree is locked for reading for the whole walk so `f` must not call back into the
+    /// root, or its server, that can deadlock. Collect what's needed, the handles for instance,
+    /// and use it after the walk.
+    pub fn for_each_node<F>(&self, f: F)
+    where
+        F: FnMut(&str, &Node, NodeHandle),
+    {
+        if let Ok(inner) = self.read_locked() {
+            inner.for_each_node(f);
+        }
+    }
+
+    //does any node satisfy the predicate, aliases aren't considered
+    pub(crate) fn any_node<F>(&self, f: F) -> bool
+    where
+        F: Fn(&Node) -> bool,
+    {
+        self.read_locked().is_ok_and(|inner| {
+            inner
+                .index_map
+                .values()
+                .filter_map(|index| inner.graph.node_weight(*index))
+                .any(|n| f(&n.node))
+        })
+    }
+
+    /// The full paths and handles of all the nodes, `"/"` included, depth first like
+    /// `for_each_node`.
+    pub fn depth_first_iter(&self) -> Vec<(String, NodeHandle)> {
+        let mut nodes = Vec::new();
+        self.for_each_node(|path, _, handle| nodes.push((path.to_string(), handle)));
+        nodes
+    }
+
+    /// The full paths and handles of all the nodes, `"/"` included, breadth first: by depth,
+    /// then by parent, siblings in address order.
+    pub fn breadth_first_iter(&self) -> Vec<(String, NodeHandle)> {
+        self.read_locked()
+            .map_or_else(|_| Vec::new(), |inner| inner.breadth_first())
+    }
+
+    /// Check that the internal bookkeeping of the tree is consistent, a debugging and testing aid.
+    ///
+    /// Aliases are checked too: each has a container parent and a live target, and no path is
+    /// both a node and an alias. All the problems found are described, not just the first.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let problems = self
+            .read_locked()
+            .map_or_else(|e| vec![e.to_string()], |inner| inner.validate());
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Get the number of nodes in the tree, including the root node.
+    pub fn node_count(&self) -> usize {
+        self.read_locked().map_or(0, |inner| inner.index_map.len())
+    }
+
+    pub fn name(&self) -> Option<String> {
+        if let Ok(inner) = self.read_locked() {
+            inner.name()
+        } else {
+            None
+        }
+    }
+
+    /// Freeze the structure of the subtree at the handle: adding, removing, moving or replacing
+    /// nodes in it, or aliases under it, fails with `OscQueryError::Frozen`, added nodes are given
+    /// back.
+    /// Values are still written and triggered as usual.
+    ///
+    /// Freezes are counted, the subtree stays frozen until each freeze is undone with
+    /// `unfreeze_subtree`. Prefer `lock_subtree`, which undoes the freeze when dropped.
+    pub fn freeze_subtree(&self, handle: &NodeHandle) -> Result<(), OscQueryError> {
+        let mut inner = self.write_locked()?;
+        let index = inner.index(handle)?;
+        inner.freeze(index)
+    }
+
+    /// Undo a freeze made with `freeze_subtree`.
+    pub fn unfreeze_subtree(&self, handle: &NodeHandle) -> Result<(), OscQueryError> {
+        let mut inner = self.write_locked()?;
+        let index = inner.index(handle)?;
+        inner.unfreeze(index)
+    }
+
+    /// Freeze the subtree at the handle, see `freeze_subtree`, until the returned guard is dropped.
+    pub fn lock_subtree(&self, handle: NodeHandle) -> Result<SubtreeLockGuard, OscQueryError> {
+        self.freeze_subtree(&handle)?;
+        Ok(SubtreeLockGuard {
+            root: Arc::downgrade(&self.inner),
+            handle,
+        })
+    }
+
+    fn write_locked(&self) -> Result<RwLockWriteGuard<RootInner>, OscQueryError> {
+        self.inner.write().map_err(|_| OscQueryError::PoisonedLock)
+    }
+
+    fn read_locked(&self) -> Result<RwLockReadGuard<RootInner>, OscQueryError> {
+        self.inner.read().map_err(|_| OscQueryError::PoisonedLock)
+    }
+
+    ///add node to the graph at the root or as a child of the given parent
+    pub fn add_node<N>(
+        &self,
+        node: N,
+        parent: Option<NodeHandle>,
+    ) -> Result<NodeHandle, OscQueryError>
+    where
+        N: Into<Node>,
+    {
+        let node = node.into();
+        match self.write_locked() {
+            Ok(mut inner) => inner.add_node(node, parent),
+            Err(e) => Err(OscQueryError::node_not_added(node, e)),
+        }
+    }
+
+    /// Add node at the full path, like `/synth/osc1/freq` for a node with the address `freq`,
+    /// creating the containers above it that don't exist yet.
+    ///
+    /// The leading `/` is optional. Fails if the path doesn't end with the node's address, with
+    /// `NotAContainer` if a node above it isn't a container and with `PathInUse` if the path is
+    /// taken. Nothing is created when it fails, and the node is given back, see
+    /// `OscQueryError::NodeNotAdded`.
+    pub fn add_node_at_path<N>(&self, node: N, path: &str) -> Result<NodeHandle, OscQueryError>
+    where
+        N: Into<Node>,
+    {
+        let node = node.into();
+        match self.write_locked() {
+            Ok(mut inner) => inner.add_node_at_path(node, path),
+            Err(e) => Err(OscQueryError::node_not_added(node, e)),
+        }
+    }
+
+    ///Remove the node at the handle returns it and any children if found
+    ///leafs come first in returned vector
+    pub fn rm_node(&self, handle: NodeHandle) -> Result<Vec<Node>, OscQueryError> {
+        self.write_locked()?.rm_node(handle)
+    }
+
+    /// Remove the node at the full path, like `rm_node`, for when only the path is known.
+    ///
+    /// The leading `/` is optional. Fails with `NodeNotInGraph` if there is no node at the path,
+    /// aliases aren't resolved, and with `RootNode` for `/`.
+    pub fn rm_node_at_path(&self, path: &str) -> Result<Vec<Node>, OscQueryError> {
+        self.write_locked()?.rm_node_at_path(path)
+    }
+
+    /// The addresses and handles of the immediate children of the node at the handle, sorted by
+    /// address. Empty for a leaf, `None` if there is no node at the handle.
+    pub fn children(&self, handle: NodeHandle) -> Option<Vec<(String, NodeHandle)>> {
+        self.read_locked().ok()?.children(handle)
+    }
+
+    /// Move the node at the handle, along with its children, under the new parent, or the root.
+    /// The handles stay valid.
+    ///
+    /// Clients are told with a single `PATH_RENAMED` from the old path to the new one, the paths
+    /// below it move along. The paths of aliases under the moved node change with it. Fails if
+    /// the new path is in use, the new parent isn't a container or it is the node itself or one
+    /// of its descendants.
+    pub fn move_node(
+        &self,
+        handle: NodeHandle,
+        new_parent: Option<NodeHandle>,
+    ) -> Result<NodeHandle, OscQueryError> {
+        let mut inner = self.write_locked()?;
+        let index = inner.index(&handle)?;
+        let parent = match new_parent {
+            Some(p) => inner.index(&p)?,
+            None => inner.root,
+        };
+        inner.move_node(index, parent)?;
+        Ok(handle)
+    }
+
+    /// Give the node at the handle a new address, its children and the aliases under it follow.
+    /// The handles stay valid. Returns the old and the new full path.
+    ///
+    /// Clients are told with a single `PATH_RENAMED`, like for `move_node`. Fails if the address
+    /// isn't valid or a sibling already has it.
+    pub fn rename_node(
+        &self,
+        handle: NodeHandle,
+        new_address: String,
+    ) -> Result<(String, String), OscQueryError> {
+        let mut inner = self.write_locked()?;
+        let index = inner.index(&handle)?;
+        inner.rename_node(index, new_address)
+    }
+
+    pub fn handle_to_path(&self, handle: &NodeHandle) -> Option<String> {
+        self.read_locked().ok()?.handle_to_path(handle)
+    }
+
+    /// Get the handle of the node at the full path, the inverse of `handle_to_path`.
+    ///
+    /// The leading `/` is optional. Aliases aren't resolved, they have no handle of their own.
+    pub fn path_to_handle(&self, path: &str) -> Option<NodeHandle> {
+        self.read_locked().ok()?.path_to_handle(path)
+    }
+
+    pub(crate) fn serialize_node<F, S>(
+        &self,
+        path: &str,
+        param: Option<NodeQueryParam>,
+        extensions: bool,
+        f: F,
+    ) -> Result<S::Ok, S::Error>
+    where
+        F: FnOnce(Option<&NodeSerializeWrapper>) -> Result<S::Ok, S::Error>,
+        S: Serializer,
+    {
+        self.read_locked()
+            .map_err(serde::ser::Error::custom)?
+            .serialize_node::<F, S>(path, param, extensions, f)
+    }
+
+    /// Serialize the container at the path with only the leaves below it that match the filter,
+    /// and the containers leading to them. `None` is given if there is no container at the path.
+    pub(crate) fn serialize_filtered<F, S>(
+        &self,
+        path: &str,
+        filter: &NodeFilter,
+        extensions: bool,
+        f: F,
+    ) -> Result<S::Ok, S::Error>
+    where
+        F: FnOnce(Option<&NodeSerializeWrapper>) -> Result<S::Ok, S::Error>,
+        S: Serializer,
+    {
+        self.read_locked()
+            .map_err(serde::ser::Error::custom)?
+            .serialize_filtered::<F, S>(path, filter, extensions, f)
+    }
+}
+
+impl Serialize for Root {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let root = self.read_locked().map_err(serde::ser::Error::custom)?;
+        serializer.serialize_some(&*root)
+    }
+}
+
+impl OscQueryGraph for RootInner {
+    ///add node to the graph at the root or as a child of the given parent
+    fn add_node(
+        &mut self,
+        node: Node,
+        parent: Option<NodeHandle>,
+    ) -> Result<NodeHandle, OscQueryError> {
+        let parent_index = match parent.map(|p| self.index(&p)).transpose() {
+            Ok(index) => index,
+            Err(e) => return Err(OscQueryError::node_not_added(node, e)),
+        };
+        let (parent_index, full_path) = if let Some(parent_index) = parent_index {
+            if let Some(parent) = self.graph.node_weight(parent_index.clone()) {
+                (parent_index, parent.full_path.clone())
+            } else {
+                return Err(OscQueryError::node_not_added(
+                    node,
+                    OscQueryError::ParentNotInGraph,
+                ));
+            }
+        } else {
+            (self.root, "".to_string())
+        };
+        if let Err(e) = self.check_frozen(parent_index, false) {
+            return Err(OscQueryError::node_not_added(node, e));
+        }
+
+        //compute the full path, the root's is "/"
+        let full_path = if parent_index == self.root {
+            format!("/{}", node.address())
+        } else {
+            format!("{}/{}", full_path, node.address())
+        };
+        if self.index_map.contains_key(&full_path) || self.alias_paths.contains_key(&full_path) {
+            return Err(OscQueryError::node_not_added(
+                node,
+                OscQueryError::PathInUse(full_path),
+            ));
+        }
+        self.generation += 1;
+        if let Some(parent) = self.graph.node_weight_mut(parent_index) {
+            parent
+                .journal
+                .record(self.generation, ContentsChange::Added, node.address());
+        }
+        let node = NodeWrapper {
+            node,
+            full_path: full_path.clone(),
+            journal: Default::default(),
+            last_write: Atomic::new(self.clock.now()),
+            last_writer: Default::default(),
+            coalesce: Default::default(),
+        };
+
+        //actually add
+        let index = self.graph.add_node(node);
+        self.index_map.insert(full_path.clone(), index);
+        let _ = self.graph.add_edge(parent_index, index, ());
+        self.index_storage(index);
+        self.map_osc_address(index);
+        self.send_ns_change(NamespaceChange::PathAdded(full_path));
+        Ok(self.handle(index))
+    }
+
+    fn add_node_at_path(&mut self, node: Node, path: &str) -> Result<NodeHandle, OscQueryError> {
+        let mut addresses: Vec<&str> = path.split('/').filter(|a| !a.is_empty()).collect();
+        if addresses.pop() != Some(node.address().as_str()) {
+            return Err(OscQueryError::node_not_added(
+                node,
+                OscQueryError::InvalidPath("the path doesn't end with the node's address"),
+            ));
+        }
+
+        //find the deepest container that exists, so everything is checked before adding
+        let mut parent = self.root;
+        let mut full_path = String::new();
+        let mut existing = 0;
+        for address in &addresses {
+            full_path = format!("{}/{}", full_path, address);
+            match self.index_map.get(&full_path) {
+                Some(index) if matches!(self.graph[*index].node, Node::Container(..)) => {
+                    parent = *index;
+                    existing += 1;
+                }
+                Some(..) => {
+                    return Err(OscQueryError::node_not_added(
+                        node,
+                        OscQueryError::NotAContainer(full_path),
+                    ))
+                }
+                None => break,
+            }
+        }
+        let in_use = if existing == addresses.len() {
+            Some(format!("{}/{}", full_path, node.address()))
+        } else {
+            Some(full_path)
+        }
+        .filter(|p| self.index_map.contains_key(p) || self.alias_paths.contains_key(p));
+        let checked = match in_use {
+            Some(p) => Err(OscQueryError::PathInUse(p)),
+            None => self.check_frozen(parent, false),
+        };
+        if let Err(e) = checked {
+            return Err(OscQueryError::node_not_added(node, e));
+        }
+
+        let mut parent = if parent == self.root {
+            None
+        } else {
+            Some(self.handle(parent))
+        };
+        for address in &addresses[existing..] {
+            let added = Container::new(address, None).and_then(|c| self.add_node(c.into(), parent));
+            parent = match added {
+                Ok(handle) => Some(handle),
+                Err(OscQueryError::NodeNotAdded { reason, .. }) => {
+                    return Err(OscQueryError::node_not_added(node, *reason))
+                }
+                Err(e) => return Err(OscQueryError::node_not_added(node, e)),
+            };
+        }
+        self.add_node(node, parent)
+    }
+
+    ///Remove the node at the handle returns it and any children if found
+    ///leafs come first in returned vector
+    fn rm_node(&mut self, handle: NodeHandle) -> Result<Vec<Node>, OscQueryError> {
+        let index = self.index(&handle)?;
+        if index == self.root {
+            return Err(OscQueryError::RootNode);
+        }
+        self.check_frozen(index, true)?;
+        let mut children = self.graph.neighbors(index).detach();
+        let mut v = Vec::new();
+        while let Some(index) = children.next_node(&self.graph) {
+            v.append(
+                &mut self
+                    .rm_node(self.handle(index))
+                    .expect("child should be in graph"),
+            );
+        }
+        let parent = self
+            .graph
+            .neighbors_directed(index, petgraph::Direction::Incoming)
+            .next();
+        let mut aliases: Vec<(&String, AliasHandle)> = self
+            .aliases
+            .iter()
+            .filter(|(_, a)| a.target == index || a.parent == index)
+            .map(|(h, a)| (&a.full_path, *h))
+            .collect();
+        aliases.sort_by(|a, b| a.0.cmp(b.0));
+        let aliases: Vec<AliasHandle> = aliases.into_iter().map(|(_, h)| h).collect();
+        for alias in aliases {
+            let _ = self.rm_alias(alias);
+        }
+        self.unindex_storage(index);
+        self.unmap_osc_address(index);
+        match self.graph.remove_node(index) {
+            Some(node) => {
+                self.generation += 1;
+                let generation = self.generation;
+                if let Some(parent) = parent.and_then(|p| self.graph.node_weight_mut(p)) {
+                    parent
+                        .journal
+                        .record(generation, ContentsChange::Removed, node.node.address());
+                }
+                self.index_map.remove(&node.full_path);
+                v.push(node.node);
+                self.send_ns_change(NamespaceChange::PathRemoved(node.full_path.clone()));
+                Ok(v)
+            }
+            None => Err(OscQueryError::NodeNotInGraph),
+        }
+    }
+
+    fn rm_node_at_path(&mut self, path: &str) -> Result<Vec<Node>, OscQueryError> {
+        let handle = self
+            .path_to_handle(path)
+            .ok_or(OscQueryError::NodeNotInGraph)?;
+        self.rm_node(handle)
+    }
+
+    fn path_to_handle(&self, path: &str) -> Option<NodeHandle> {
+        let index = if path.starts_with('/') {
+            self.index_map.get(path)
+        } else {
+            self.index_map.get(&format!("/{}", path))
+        };
+        index.map(|i| self.handle(*i))
+    }
+
+    fn children(&self, handle: NodeHandle) -> Option<Vec<(String, NodeHandle)>> {
+        let index = self.index(&handle).ok()?;
+        self.graph.node_weight(index)?;
+        Some(
+            self.children_sorted(index)
+                .into_iter()
+                .map(|c| (self.graph[c].node.address().to_string(), self.handle(c)))
+                .collect(),
+        )
+    }
+}
+
+impl RootInner {
+    pub(crate) fn new(name: Option<String>) -> Self {
+        let mut graph = StableGraph::default();
+        let root = graph.add_node(NodeWrapper {
+            full_path: "/".to_string(),
+            node: Node::Container(Container {
+                address: "".to_string(), //invalid, but unchecked by default access
+                description: Some("root node".to_string()),
+                tags: None,
+            }),
+            journal: Default::default(),
+            last_write: Atomic::new(Instant::now()),
+            last_writer: Default::default(),
+            coalesce: Default::default(),
+        });
+        let mut index_map = HashMap::new();
+        index_map.insert("/".to_string(), root);
+        Self {
+            id: NEXT_ROOT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            name,
+            graph,
+            root,
+            index_map,
+            ns_change_send: Default::default(),
+            ns_change_streams: Default::default(),
+            stats: Default::default(),
+            slow_handler_threshold: None,
+            non_finite_policy: Default::default(),
+            non_finite_strings: false,
+            force_trigger: false,
+            generation: 0,
+            osc_address_map: HashMap::new(),
+            storage_index: None,
+            clock: crate::time::system_clock(),
+            aliases: HashMap::new(),
+            alias_paths: HashMap::new(),
+            next_alias: 0,
+            capabilities: BTreeSet::new(),
+            coalescing: Default::default(),
+            frozen: HashMap::new(),
+        }
+    }
+
+    fn add_alias(
+        &mut self,
+        target: NodeHandle,
+        parent: Option<NodeHandle>,
+        address: &str,
+    ) -> Result<AliasHandle, OscQueryError> {
+        let address = address_valid(address.to_string())?;
+        let target = self.index(&target)?;
+        let parent = parent.map(|p| self.index(&p)).transpose()?;
+        match self.graph.node_weight(target).map(|n| &n.node) {
+            Some(Node::Container(..)) => return Err(OscQueryError::AliasTargetIsContainer),
+            Some(..) => (),
+            None => return Err(OscQueryError::NodeNotInGraph),
+        }
+        let parent = parent.unwrap_or(self.root);
+        self.check_frozen(parent, false)?;
+        let full_path = match self.graph.node_weight(parent) {
+            Some(n) => match n.node {
+                Node::Container(..) if parent == self.root => format!("/{}", address),
+                Node::Container(..) => format!("{}/{}", n.full_path, address),
+                _ => return Err(OscQueryError::AliasParentNotContainer),
+            },
+            None => return Err(OscQueryError::ParentNotInGraph),
+        };
+        if self.index_map.contains_key(&full_path) || self.alias_paths.contains_key(&full_path) {
+            return Err(OscQueryError::PathInUse(full_path));
+        }
+
+        self.generation += 1;
+        let generation = self.generation;
+        if let Some(parent) = self.graph.node_weight_mut(parent) {
+            parent
+                .journal
+                .record(generation, ContentsChange::Added, &address);
+        }
+        let handle = AliasHandle(self.next_alias);
+        self.next_alias += 1;
+        self.alias_paths.insert(full_path.clone(), handle);
+        self.aliases.insert(
+            handle,
+            Alias {
+                full_path: full_path.clone(),
+                parent,
+                target,
+                trigger: false,
+            },
+        );
+        self.send_ns_change(NamespaceChange::PathAdded(full_path));
+        Ok(handle)
+    }
+
+    fn replace_node(&mut self, index: NodeIndex, node: Node) -> Result<Node, OscQueryError> {
+        match self.graph.node_weight(index) {
+            Some(..) if index == self.root => {
+                return Err(OscQueryError::node_not_added(node, OscQueryError::RootNode))
+            }
+            Some(n) if n.node.address() != node.address() => {
+                let address = node.address().clone();
+                return Err(OscQueryError::node_not_added(
+                    node,
+                    OscQueryError::InvalidAddress(address),
+                ));
+            }
+            Some(..)
+                if !matches!(node, Node::Container(..))
+                    && self
+                        .graph
+                        .neighbors_directed(index, petgraph::Direction::Outgoing)
+                        .next()
+                        .is_some() =>
+            {
+                return Err(OscQueryError::node_not_added(
+                    node,
+                    OscQueryError::HasChildren,
+                ));
+            }
+            //the aliases under and of the node have to stay valid
+            Some(..)
+                if !matches!(node, Node::Container(..))
+                    && self.aliases.values().any(|a| a.parent == index) =>
+            {
+                return Err(OscQueryError::node_not_added(
+                    node,
+                    OscQueryError::AliasParentNotContainer,
+                ));
+            }
+            Some(..)
+                if matches!(node, Node::Container(..))
+                    && self.aliases.values().any(|a| a.target == index) =>
+            {
+                return Err(OscQueryError::node_not_added(
+                    node,
+                    OscQueryError::AliasTargetIsContainer,
+                ));
+            }
+            Some(..) => (),
+            None => {
+                return Err(OscQueryError::node_not_added(
+                    node,
+                    OscQueryError::NodeNotInGraph,
+                ))
+            }
+        }
+        if let Err(e) = self.check_frozen(index, false) {
+            return Err(OscQueryError::node_not_added(node, e));
+        }
+        self.unindex_storage(index);
+        self.unmap_osc_address(index);
+        let now = self.clock.now();
+        let old = match self.graph.node_weight_mut(index) {
+            Some(n) => {
+                n.touch(now);
+                //a held back run belongs to the old handler
+                n.coalesce = Default::default();
+                std::mem::replace(&mut n.node, node)
+            }
+            None => unreachable!("checked above"),
+        };
+        self.index_storage(index);
+        self.map_osc_address(index);
+
+        //removed and added in the same generation, reported as changed
+        self.generation += 1;
+        let generation = self.generation;
+        let parent = self
+            .graph
+            .neighbors_directed(index, petgraph::Direction::Incoming)
+            .next();
+        if let Some(parent) = parent.and_then(|p| self.graph.node_weight_mut(p)) {
+            parent
+                .journal
+                .record(generation, ContentsChange::Removed, old.address());
+            parent
+                .journal
+                .record(generation, ContentsChange::Added, old.address());
+        }
+        self.notify_changed(index);
+        Ok(old)
+    }
+
+    fn freeze(&mut self, index: NodeIndex) -> Result<(), OscQueryError> {
+        if !self.graph.contains_node(index) {
+            return Err(OscQueryError::NodeNotInGraph);
+        }
+        *self.frozen.entry(index).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn unfreeze(&mut self, index: NodeIndex) -> Result<(), OscQueryError> {
+        match self.frozen.get_mut(&index) {
+            Some(count) if *count > 1 => *count -= 1,
+            Some(..) => {
+                self.frozen.remove(&index);
+            }
+            None => return Err(OscQueryError::NodeNotInGraph),
+        }
+        Ok(())
+    }
+
+    fn parent(&self, index: NodeIndex) -> Option<NodeIndex> {
+        self.graph
+            .neighbors_directed(index, petgraph::Direction::Incoming)
+            .next()
+    }
+
+    //is the node at the index in a frozen subtree, or, when removing, does it contain one
+    fn check_frozen(&self, index: NodeIndex, removing: bool) -> Result<(), OscQueryError> {
+        if self.frozen.is_empty() {
+            return Ok(());
+        }
+        let ancestors = |index| std::iter::successors(Some(index), move |&i| self.parent(i));
+        let frozen = || ancestors(index).any(|i| self.frozen.contains_key(&i));
+        let contains = || {
+            self.frozen
+                .keys()
+                .any(|&f| ancestors(f).any(|i| i == index))
+        };
+        if frozen() || (removing && contains()) {
+            let path = self
+                .graph
+                .node_weight(index)
+                .map(|n| n.full_path.clone())
+                .unwrap_or_default();
+            Err(OscQueryError::Frozen(path))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn move_node(&mut self, index: NodeIndex, parent: NodeIndex) -> Result<(), OscQueryError> {
+        if index == self.root {
+            return Err(OscQueryError::RootNode);
+        }
+        let address = match self.graph.node_weight(index) {
+            Some(n) => n.node.address().clone(),
+            None => return Err(OscQueryError::NodeNotInGraph),
+        };
+        let full_path = match self.graph.node_weight(parent) {
+            Some(..) if parent == self.root => format!("/{}", address),
+            Some(n) if matches!(n.node, Node::Container(..)) => {
+                format!("{}/{}", n.full_path, address)
+            }
+            Some(n) => return Err(OscQueryError::NotAContainer(n.full_path.clone())),
+            None => return Err(OscQueryError::ParentNotInGraph),
+        };
+        if std::iter::successors(Some(parent), |&i| self.parent(i)).any(|i| i == index) {
+            return Err(OscQueryError::MoveUnderItself);
+        }
+        let old_parent = self.parent(index);
+        if old_parent == Some(parent) {
+            return Ok(());
+        }
+        self.check_frozen(index, true)?;
+        self.check_frozen(parent, false)?;
+        if self.index_map.contains_key(&full_path) || self.alias_paths.contains_key(&full_path) {
+            return Err(OscQueryError::PathInUse(full_path));
+        }
+
+        let old_path = self.rename_subtree(index, &full_path);
+        if let Some(old_parent) = old_parent {
+            if let Some(edge) = self.graph.find_edge(old_parent, index) {
+                self.graph.remove_edge(edge);
+            }
+        }
+        self.graph.add_edge(parent, index, ());
+        self.generation += 1;
+        let generation = self.generation;
+        if let Some(old_parent) = old_parent.and_then(|p| self.graph.node_weight_mut(p)) {
+            old_parent
+                .journal
+                .record(generation, ContentsChange::Removed, &address);
+        }
+        self.graph[parent]
+            .journal
+            .record(generation, ContentsChange::Added, &address);
+
+        self.send_ns_change(NamespaceChange::PathRenamed(old_path, full_path));
+        Ok(())
+    }
+
+    fn rename_node(
+        &mut self,
+        index: NodeIndex,
+        address: String,
+    ) -> Result<(String, String), OscQueryError> {
+        if index == self.root {
+            return Err(OscQueryError::RootNode);
+        }
+        let address = crate::node::address_valid(address)?;
+        let (old_address, old_path) = match self.graph.node_weight(index) {
+            Some(n) => (n.node.address().clone(), n.full_path.clone()),
+            None => return Err(OscQueryError::NodeNotInGraph),
+        };
+        if old_address == address {
+            return Ok((old_path.clone(), old_path));
+        }
+        let parent = self.parent(index).ok_or(OscQueryError::NodeNotInGraph)?;
+        let full_path = match self.graph.node_weight(parent) {
+            Some(..) if parent == self.root => format!("/{}", address),
+            Some(n) => format!("{}/{}", n.full_path, address),
+            None => return Err(OscQueryError::ParentNotInGraph),
+        };
+        self.check_frozen(index, true)?;
+        if self.index_map.contains_key(&full_path) || self.alias_paths.contains_key(&full_path) {
+            return Err(OscQueryError::PathInUse(full_path));
+        }
+
+        self.rename_subtree(index, &full_path);
+        self.graph[index].node.set_address(address.clone());
+        self.generation += 1;
+        let generation = self.generation;
+        let journal = &mut self.graph[parent].journal;
+        journal.record(generation, ContentsChange::Removed, &old_address);
+        journal.record(generation, ContentsChange::Added, &address);
+
+        self.send_ns_change(NamespaceChange::PathRenamed(
+            old_path.clone(),
+            full_path.clone(),
+        ));
+        Ok((old_path, full_path))
+    }
+
+    //give the node at the index the full path, the nodes and aliases below it follow, parents
+    //first, returns the old path
+    fn rename_subtree(&mut self, index: NodeIndex, full_path: &str) -> String {
+        let old_path = self.graph[index].full_path.clone();
+        let rename = |path: &str| format!("{}{}", full_path, &path[old_path.len()..]);
+        let mut dfs = Dfs::new(&self.graph, index);
+        while let Some(i) = dfs.next(&self.graph) {
+            let node = &mut self.graph[i];
+            let path = rename(&node.full_path);
+            let old = std::mem::replace(&mut node.full_path, path.clone());
+            self.index_map.remove(&old);
+            self.index_map.insert(path, i);
+        }
+        for (handle, alias) in self.aliases.iter_mut() {
+            if alias.full_path.starts_with(&old_path)
+                && alias.full_path[old_path.len()..].starts_with('/')
+            {
+                let path = rename(&alias.full_path);
+                let old = std::mem::replace(&mut alias.full_path, path.clone());
+                self.alias_paths.remove(&old);
+                self.alias_paths.insert(path, *handle);
+            }
+        }
+        old_path
+    }
+
+    fn notify_changed(&self, index: NodeIndex) {
+        if let Some(node) = self.graph.node_weight(index) {
+            self.send_ns_change(NamespaceChange::PathChanged(node.full_path.clone()));
+            let mut aliases: Vec<&String> = self
+                .aliases
+                .values()
+                .filter(|a| a.target == index)
+                .map(|a| &a.full_path)
+                .collect();
+            aliases.sort();
+            for path in aliases {
+                self.send_ns_change(NamespaceChange::PathChanged(path.clone()));
+            }
+        }
+    }
+
+    fn rm_alias(&mut self, handle: AliasHandle) -> Result<(), OscQueryError> {
+        let alias = self
+            .aliases
+            .remove(&handle)
+            .ok_or(OscQueryError::AliasNotFound)?;
+        self.alias_paths.remove(&alias.full_path);
+        self.generation += 1;
+        let generation = self.generation;
+        if let Some(parent) = self.graph.node_weight_mut(alias.parent) {
+            let address = alias.full_path.rsplit('/').next().unwrap_or("");
+            parent
+                .journal
+                .record(generation, ContentsChange::Removed, address);
+        }
+        self.send_ns_change(NamespaceChange::PathRemoved(alias.full_path));
+        Ok(())
+    }
+
+    pub(crate) fn all_paths(&self) -> Vec<String> {
+        self.index_map.keys().cloned().collect()
+    }
+
+    pub(crate) fn all_paths_depth_first(&self) -> Vec<String> {
+        let mut paths = Vec::with_capacity(self.index_map.len());
+        self.for_each_node(|path, _, _| paths.push(path.to_string()));
+        paths
+    }
+
+    pub(crate) fn for_each_node<F>(&self, mut f: F)
+    where
+        F: FnMut(&str, &Node, NodeHandle),
+    {
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            if let Some(node) = self.graph.node_weight(index) {
+                f(&node.full_path, &node.node, self.handle(index));
+            }
+            //reversed so that the first address is popped first
+            stack.extend(self.children_sorted(index).into_iter().rev());
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut paths: HashMap<NodeIndex, &String> = HashMap::new();
+        for (path, index) in &self.index_map {
+            match self.graph.node_weight(*index) {
+                Some(n) if &n.full_path != path => problems.push(format!(
+                    "{:?} is indexed at {:?} but has the path {:?}",
+                    index, path, n.full_path
+                )),
+                Some(..) => (),
+                None => problems.push(format!(
+                    "{:?} is indexed at {:?} but not in the graph",
+                    index, path
+                )),
+            }
+            if let Some(other) = paths.insert(*index, path) {
+                problems.push(format!(
+                    "{:?} is indexed at both {:?} and {:?}",
+                    index, other, path
+                ));
+            }
+        }
+        for index in self.graph.node_indices() {
+            let parents: Vec<NodeIndex> = self
+                .graph
+                .neighbors_directed(index, petgraph::Direction::Incoming)
+                .collect();
+            let path = &self.graph[index].full_path;
+            if index == self.root {
+                if !parents.is_empty() {
+                    problems.push("the root has a parent".to_string());
+                }
+                continue;
+            }
+            match parents.as_slice() {
+                [parent] => {
+                    let expected = format!(
+                        "{}/{}",
+                        self.graph[*parent].full_path.trim_end_matches('/'),
+                        self.graph[index].node.address()
+                    );
+                    if &expected != path {
+                        problems.push(format!("{:?} should have the path {:?}", path, expected));
+                    }
+                }
+                parents => problems.push(format!("{:?} has {} parents", path, parents.len())),
+            }
+            if !paths.contains_key(&index) {
+                problems.push(format!("{:?} isn't indexed", path));
+            }
+        }
+        let mut reachable = 0;
+        let mut dfs = Dfs::new(&self.graph, self.root);
+        while dfs.next(&self.graph).is_some() {
+            reachable += 1;
+        }
+        if reachable != self.graph.node_count() {
+            problems.push(format!(
+                "{} of the {} nodes can't be reached from the root",
+                self.graph.node_count() - reachable,
+                self.graph.node_count()
+            ));
+        }
+        for (path, alias) in &self.alias_paths {
+            match self.aliases.get(alias) {
+                Some(a) if &a.full_path != path => problems.push(format!(
+                    "the alias at {:?} has the path {:?}",
+                    path, a.full_path
+                )),
+                Some(a) if !self.graph.contains_node(a.target) => {
+                    problems.push(format!("the target of the alias at {:?} is gone", path))
+                }
+                Some(a) => match self.graph.node_weight(a.parent) {
+                    Some(NodeWrapper {
+                        node: Node::Container(..),
+                        ..
+                    }) => (),
+                    Some(..) => problems.push(format!(
+                        "the parent of the alias at {:?} isn't a container",
+                        path
+                    )),
+                    None => problems.push(format!("the parent of the alias at {:?} is gone", path)),
+                },
+                None => problems.push(format!("the alias at {:?} is gone", path)),
+            }
+            if self.index_map.contains_key(path) {
+                problems.push(format!("{:?} is both a node and an alias", path));
+            }
+        }
+        problems
+    }
+
+    pub(crate) fn breadth_first(&self) -> Vec<(String, NodeHandle)> {
+        let mut nodes = Vec::with_capacity(self.index_map.len());
+        let mut queue = VecDeque::from(vec![self.root]);
+        while let Some(index) = queue.pop_front() {
+            if let Some(node) = self.graph.node_weight(index) {
+                nodes.push((node.full_path.clone(), self.handle(index)));
+            }
+            queue.extend(self.children_sorted(index));
+        }
+        nodes
+    }
+
+    //the children of the node at the index, in address order
+    fn children_sorted(&self, index: NodeIndex) -> Vec<NodeIndex> {
+        let mut children: Vec<(&String, NodeIndex)> = self
+            .graph
+            .neighbors(index)
+            .filter_map(|c| self.graph.node_weight(c).map(|n| (n.node.address(), c)))
+            .collect();
+        children.sort_by(|a, b| a.0.cmp(b.0));
+        children.into_iter().map(|(_, c)| c).collect()
+    }
+
+    //the messages that reset the writable nodes in the subtree at the index to their defaults,
+    //in path order
+    fn default_messages(&self, index: NodeIndex) -> (Vec<OscMessage>, ResetReport) {
+        let mut nodes = Vec::new();
+        let mut dfs = Dfs::new(&self.graph, index);
+        while let Some(i) = dfs.next(&self.graph) {
+            if let Some(n) = self.graph.node_weight(i) {
+                if let Node::Set(..) | Node::GetSet(..) = n.node {
+                    nodes.push(n);
+                }
+            }
+        }
+        nodes.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+        let mut report = ResetReport::default();
+        let mut msgs = Vec::new();
+        for n in nodes {
+            let defaults = n.node.param_defaults();
+            let with = defaults.iter().filter(|d| d.is_some()).count();
+            report.skipped += defaults.len() - with;
+            if with == 0 {
+                continue;
+            }
+            //the parameters after the last default don't need an argument
+            let last = defaults.iter().rposition(Option::is_some).unwrap_or(0);
+            let mut args = Vec::new();
+            for (d, count) in defaults
+                .into_iter()
+                .zip(n.node.param_arg_counts())
+                .take(last + 1)
+            {
+                match d {
+                    Some(d) => args.extend(d),
+                    None => args.resize(args.len() + count, OscType::Nil),
+                }
+            }
+            //a write that would be dropped leaves the values as they were
+            if self.rejects_write(&n.node, &args) {
+                report.skipped += with;
+                continue;
+            }
+            report.reset += with;
+            report.paths.push(n.full_path.clone());
+            msgs.push(OscMessage {
+                addr: n.full_path.clone(),
+                args,
+            });
+        }
+        (msgs, report)
+    }
+
+    //the messages of every readable node, in path order, with their paths and trigger aliases
+    pub(crate) fn render_all(&self) -> Vec<(String, OscMessage, Vec<String>)> {
+        self.current_messages("/", usize::MAX)
+            .into_iter()
+            .map(|(path, msg)| {
+                let aliases = self.alias_triggers(&path);
+                (path, msg, aliases)
+            })
+            .collect()
+    }
+
+    //like `render_all` for the nodes at the handles, in their order, skipping the invalid
+    //handles and the nodes that don't render
+    pub(crate) fn render_handles<I>(&self, handles: I) -> Vec<(String, OscMessage, Vec<String>)>
+    where
+        I: IntoIterator<Item = NodeHandle>,
+    {
+        let now = self.clock.now();
+        handles
+            .into_iter()
+            .filter_map(|handle| {
+                self.with_node_at_handle(&handle, |node| {
+                    let node = node?;
+                    let msg = node.osc_message(now)?;
+                    Some((
+                        node.full_path.clone(),
+                        msg,
+                        self.alias_triggers(&node.full_path),
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    //the paths of the aliases of the node at the path that emit when it is triggered
+    pub(crate) fn alias_triggers(&self, path: &str) -> Vec<String> {
+        let index = match self.index_map.get(path) {
+            Some(index) => *index,
+            None => return Vec::new(),
+        };
+        let mut paths: Vec<String> = self
+            .aliases
+            .values()
+            .filter(|a| a.target == index && a.trigger)
+            .map(|a| a.full_path.clone())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn map_osc_address(&mut self, index: NodeIndex) {
+        if let Some(node) = self.graph.node_weight(index) {
+            match (&node.node, node.node.osc_address()) {
+                (Node::Set(..), Some(a)) | (Node::GetSet(..), Some(a)) if a.inbound => {
+                    if let Some(other) = self.osc_address_map.insert(a.address.clone(), index) {
+                        if other != index {
+                            log::warn!("osc address {} was used by another node", a.address);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn unmap_osc_address(&mut self, index: NodeIndex) {
+        if let Some(a) = self
+            .graph
+            .node_weight(index)
+            .and_then(|n| n.node.osc_address())
+        {
+            if self.osc_address_map.get(&a.address) == Some(&index) {
+                self.osc_address_map.remove(&a.address);
+            }
+        }
+    }
+
+    fn index_storage(&mut self, index: NodeIndex) {
+        let handle = self.handle(index);
+        if let (Some(storage_index), Some(node)) =
+            (&mut self.storage_index, self.graph.node_weight(index))
+        {
+            for (i, id) in node.node.storage_ids().into_iter().enumerate() {
+                if let Some(id) = id {
+                    storage_index
+                        .entry(id)
+                        .or_insert_with(Vec::new)
+                        .push((handle, i));
+                }
+            }
+        }
+    }
+
+    fn unindex_storage(&mut self, index: NodeIndex) {
+        if let (Some(storage_index), Some(node)) =
+            (&mut self.storage_index, self.graph.node_weight(index))
+        {
+            for id in node.node.storage_ids().into_iter().flatten() {
+                if let Some(uses) = storage_index.get_mut(&id) {
+                    uses.retain(|(h, _)| h.index != index);
+                    if uses.is_empty() {
+                        storage_index.remove(&id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A new receiver of the namespace changes, each receiver sees every change.
+    pub(crate) fn ns_change_recv(&mut self) -> Receiver<NamespaceChange> {
+        let (send, recv) = sync_channel(NS_CHANGE_LEN);
+        if let Ok(senders) = self.ns_change_send.get_mut() {
+            senders.push(send);
+        }
+        recv
+    }
+
+    /// Like `ns_change_recv` but a stream, for async tasks to await the changes.
+    pub(crate) fn ns_change_stream(&mut self) -> futures::channel::mpsc::Receiver<NamespaceChange> {
+        let (send, recv) = futures::channel::mpsc::channel(NS_CHANGE_LEN);
+        if let Ok(senders) = self.ns_change_streams.get_mut() {
+            senders.push(send);
+        }
+        recv
+    }
+
+    pub(crate) fn send_ns_change(&self, change: NamespaceChange) {
+        if let Ok(mut senders) = self.ns_change_send.lock() {
+            //a full channel drops the change, a dropped receiver drops the sender
+            senders.retain(|s| {
+                !matches!(
+                    s.try_send(change.clone()),
+                    Err(TrySendError::Disconnected(..))
+                )
+            });
+        }
+        if let Ok(mut senders) = self.ns_change_streams.lock() {
+            for s in senders.iter_mut() {
+                let _ = s.try_send(change.clone());
+            }
+            senders.retain(|s| !s.is_closed());
+        }
+    }
+
+    pub(crate) fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    //the OSC addresses messages are sent at: the paths, those of the aliases and the OSC addresses
+    //of the nodes
+    pub(crate) fn send_addresses(&self) -> HashSet<&str> {
+        self.index_map
+            .keys()
+            .chain(self.alias_paths.keys())
+            .map(String::as_str)
+            .chain(
+                self.index_map
+                    .values()
+                    .filter_map(|i| self.graph.node_weight(*i)?.node.osc_address())
+                    .map(|a| a.address.as_str()),
+            )
+            .collect()
+    }
+
+    pub(crate) fn force_trigger(&self) -> bool {
+        self.force_trigger
+    }
+
+    pub(crate) fn register_capability(&mut self, capability: &'static str) {
+        self.capabilities.insert(capability);
+    }
+
+    pub(crate) fn unregister_capability(&mut self, capability: &'static str) {
+        self.capabilities.remove(capability);
+    }
+
+    pub(crate) fn capabilities(&self) -> Vec<String> {
+        self.capabilities.iter().map(|c| c.to_string()).collect()
+    }
+
+    pub fn with_node_at_handle<F, R>(&self, handle: &NodeHandle, f: F) -> R
+    where
+        F: Fn(Option<&NodeWrapper>) -> R,
+    {
+        f(self
+            .index(handle)
+            .ok()
+            .and_then(|index| self.graph.node_weight(index)))
+    }
+
+    pub fn with_node_at_path<F, R>(&self, path: &str, f: F) -> R
+    where
+        F: Fn(Option<(&NodeWrapper, &NodeIndex)>) -> R,
+    {
+        let index = self.index_map.get(path).or_else(|| {
+            self.alias_paths
+                .get(path)
+                .and_then(|a| self.aliases.get(a))
+                .map(|a| &a.target)
+        });
+        f(if let Some(index) = index {
+            self.graph.node_weight(*index).map(|n| (n, index))
+        } else {
+            None
+        })
+    }
+
+    /// The source address and time of the last write to the node that messages with the OSC
+    /// address are sent for, if the write came from an address.
+    pub(crate) fn last_writer(&self, addr: &str) -> Option<(SocketAddr, Instant)> {
+        match self.osc_address_map.get(addr) {
+            Some(index) if !self.index_map.contains_key(addr) => {
+                self.graph.node_weight(*index)?.last_writer()
+            }
+            _ => self.with_node_at_path(addr, |n| n.and_then(|(n, _)| n.last_writer())),
+        }
+    }
+
+    /// Render the current values at the path, the node itself or, for a container, the readable
+    /// nodes below it in path order, at most `max`. Returns the paths and messages.
+    pub(crate) fn current_messages(&self, path: &str, max: usize) -> Vec<(String, OscMessage)> {
+        let now = self.clock.now();
+        let container = self.with_node_at_path(path, |n| match n {
+            Some((n, _)) => match n.node {
+                Node::Container(..) => Some(n.full_path.clone()),
+                _ => None,
+            },
+            None => None,
+        });
+        match container {
+            Some(full_path) => {
+                let prefix = if full_path == "/" {
+                    full_path
+                } else {
+                    format!("{}/", full_path)
+                };
+                let mut paths: Vec<&String> = self
+                    .index_map
+                    .keys()
+                    .filter(|p| p.starts_with(&prefix))
+                    .collect();
+                paths.sort();
+                paths
+                    .into_iter()
+                    .filter_map(|p| {
+                        self.with_node_at_path(p, |n| {
+                            n.and_then(|(n, _)| n.readable_message(now))
+                                .map(|m| (p.clone(), m))
+                        })
+                    })
+                    .take(max)
+                    .collect()
+            }
+            None => self.with_node_at_path(path, |n| {
+                n.and_then(|(n, _)| n.readable_message(now))
+                    .map(|m| vec![(path.to_string(), m)])
+                    .unwrap_or_default()
+            }),
+        }
+    }
+
+    pub fn handle_to_path(&self, handle: &NodeHandle) -> Option<String> {
+        self.index(handle)
+            .ok()
+            .and_then(|index| self.graph.node_weight(index))
+            .map(|n| n.full_path.clone())
+    }
+
+    //the handle of the node at the index
+    pub(crate) fn handle(&self, index: NodeIndex) -> NodeHandle {
+        NodeHandle {
+            index,
+            root: self.id,
+        }
+    }
+
+    //the index of the node at the handle, if the handle belongs to this root
+    pub(crate) fn index(&self, handle: &NodeHandle) -> Result<NodeIndex, OscQueryError> {
+        if handle.root == self.id {
+            Ok(handle.index)
+        } else {
+            Err(OscQueryError::WrongRoot)
+        }
+    }
+
+    fn handle_osc_msg(
+        &self,
+        msg: &OscMessage,
+        addr: Option<SocketAddr>,
+        time: Option<BundleContext>,
+        callbacks: &mut Vec<(String, OscWriteCallback)>,
+        replies: &mut Vec<OscMessage>,
+    ) {
+        //full paths take precedence over inbound OSC addresses
+        let path = match self.osc_address_map.get(&msg.addr) {
+            Some(index)
+                if !self.index_map.contains_key(&msg.addr)
+                    && !self.alias_paths.contains_key(&msg.addr) =>
+            {
+                self.graph.node_weight(*index).map(|n| n.full_path.as_str())
+            }
+            _ => None,
+        };
+        //the reply to a value query, or the callback of an update
+        let found = self.with_node_at_path(path.unwrap_or(&msg.addr), |ni| {
+            ni.map(|(node, index)| match self.osc_query(node, msg) {
+                Some(reply) => (reply, None),
+                None => (None, self.osc_update(node, *index, msg, addr, time, true)),
+            })
+        });
+        if let Some((reply, cb)) = found {
+            replies.extend(reply);
+            if let Some(cb) = cb {
+                callbacks.push((msg.addr.clone(), cb));
+            }
+        } else if is_pattern(&msg.addr) {
+            //addresses that exist are never patterns, even if they contain pattern characters
+            for index in match_pattern(&msg.addr, &self.index_map) {
+                if let Some(node) = self.graph.node_weight(index) {
+                    //only exact addresses are answered, so one small query can't fan out into a
+                    //reply for every node in the tree
+                    if self.osc_query(node, msg).is_some() {
+                        continue;
+                    }
+                    if let Some(cb) = self.osc_update(node, index, msg, addr, time, true) {
+                        callbacks.push((node.full_path.clone(), cb));
+                    }
+                }
+            }
+        }
+    }
+
+    //a message without arguments to a readable node queries its value, `Some` with the reply,
+    //if it hasn't expired
+    fn osc_query(&self, node: &NodeWrapper, msg: &OscMessage) -> Option<Option<OscMessage>> {
+        match node.node {
+            Node::Get(..) | Node::GetSet(..) if msg.args.is_empty() => {
+                Some(node.osc_message(self.clock.now()))
+            }
+            _ => None,
+        }
+    }
+
+    //apply the non-finite policy, the replacement arguments if clamped, an error if rejected
+    fn non_finite(&self, node: &Node, args: &[OscType]) -> Result<Option<Vec<OscType>>, ()> {
+        let found = args.iter().any(|a| match a {
+            OscType::Float(v) => !v.is_finite(),
+            OscType::Double(v) => !v.is_finite(),
+            _ => false,
+        });
+        match (found, self.non_finite_policy) {
+            (false, _) | (true, NonFinitePolicy::PassThrough) => Ok(None),
+            (true, NonFinitePolicy::RejectWrite) => Err(()),
+            (true, NonFinitePolicy::ClampToRange) => {
+                node.clamp_non_finite(args).map(Some).ok_or(())
+            }
+        }
+    }
+
+    //would `osc_update` drop a write of the arguments
+    fn rejects_write(&self, node: &Node, args: &[OscType]) -> bool {
+        match self.non_finite(node, args) {
+            Err(()) => true,
+            Ok(Some(args)) => node.coerce_args(&args).is_err(),
+            Ok(None) => node.coerce_args(args).is_err(),
+        }
+    }
+
+    //`received` is false for writes that didn't arrive as OSC, their unsupported arguments aren't
+    //counted
+    fn osc_update(
+        &self,
+        node: &NodeWrapper,
+        index: NodeIndex,
+        msg: &OscMessage,
+        addr: Option<SocketAddr>,
+        time: Option<BundleContext>,
+        received: bool,
+    ) -> Option<OscWriteCallback> {
+        match node.node {
+            Node::Set(..) | Node::GetSet(..) => {
+                let clamped;
+                let args = match self.non_finite(&node.node, &msg.args) {
+                    Ok(None) => &msg.args,
+                    Ok(Some(args)) => {
+                        clamped = args;
+                        &clamped
+                    }
+                    Err(()) => {
+                        self.stats.inc_osc_non_finite_rejected();
+                        return None;
+                    }
+                };
+                let coerced;
+                let args = match node.node.coerce_args(args) {
+                    Ok(None) => args,
+                    Ok(Some(args)) => {
+                        coerced = args;
+                        &coerced
+                    }
+                    Err(()) => {
+                        self.stats.inc_osc_args_rejected();
+                        return None;
+                    }
+                };
+                let unsupported = crate::convert::unsupported_count(args);
+                if received && unsupported > 0 {
+                    self.stats.add_osc_unsupported_args(unsupported);
+                }
+                let now = self.clock.now();
+                node.touch_from(now, addr);
+                if let Some(window) = node.node.coalesce_window() {
+                    node.node.update_params(args);
+                    let mut c = node.coalesce.lock().expect("failed to lock");
+                    match c.ran {
+                        Some(ran) if now.saturating_duration_since(ran) < window => {
+                            c.pending = Some((args.clone(), addr, time));
+                            if let Ok(mut coalescing) = self.coalescing.lock() {
+                                coalescing.insert(index);
+                            }
+                            return None;
+                        }
+                        _ => {
+                            c.ran = Some(now);
+                            c.pending = None;
+                        }
+                    }
+                    drop(c);
+                    self.run_handler(node, index, args, addr, time)
+                } else {
+                    let start = Instant::now();
+                    let cb = node.node.osc_update(args, addr, time, &self.handle(index));
+                    self.record_handler_time(&node.full_path, start.elapsed());
+                    cb
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn run_handler(
+        &self,
+        node: &NodeWrapper,
+        index: NodeIndex,
+        args: &Vec<OscType>,
+        addr: Option<SocketAddr>,
+        time: Option<BundleContext>,
+    ) -> Option<OscWriteCallback> {
+        let start = Instant::now();
+        let cb = node.node.run_handler(args, addr, time, &self.handle(index));
+        self.record_handler_time(&node.full_path, start.elapsed());
+        cb
+    }
+
+    //run the held back update handlers whose coalesce window has passed
+    fn run_coalesced(&self, callbacks: &mut Vec<(String, OscWriteCallback)>) {
+        let mut coalescing = match self.coalescing.lock() {
+            Ok(c) if !c.is_empty() => c,
+            _ => return,
+        };
+        let now = self.clock.now();
+        let mut due: Vec<(&NodeWrapper, NodeIndex)> = Vec::new();
+        coalescing.retain(|index| {
+            let node = match self.graph.node_weight(*index) {
+                Some(node) => node,
+                None => return false,
+            };
+            let c = node.coalesce.lock().expect("failed to lock");
+            match (c.pending.is_some(), c.ran, node.node.coalesce_window()) {
+                (false, _, _) => false,
+                (true, Some(ran), Some(window)) if now.saturating_duration_since(ran) < window => {
+                    true
+                }
+                _ => {
+                    due.push((node, *index));
+                    false
+                }
+            }
+        });
+        drop(coalescing);
+        //in path order, so it doesn't depend on the hashing
+        due.sort_by(|a, b| a.0.full_path.cmp(&b.0.full_path));
+        for (node, index) in due {
+            let pending = {
+                let mut c = node.coalesce.lock().expect("failed to lock");
+                c.ran = Some(now);
+                c.pending.take()
+            };
+            if let Some((args, addr, time)) = pending {
+                if let Some(cb) = self.run_handler(node, index, &args, addr, time) {
+                    callbacks.push((node.full_path.clone(), cb));
+                }
+            }
+        }
+    }
+
+    /// Run the held back update handlers whose coalesce window has passed.
+    pub(crate) fn flush_coalesced(root: &Arc<RwLock<RootInner>>) {
+        let mut callbacks = Vec::new();
+        let stats = if let Ok(root) = root.read() {
+            root.run_coalesced(&mut callbacks);
+            root.stats.clone()
+        } else {
+            return;
+        };
+        Self::apply_callbacks(root, &stats, callbacks);
+    }
+
+    fn record_handler_time(&self, path: &str, elapsed: Duration) {
+        self.stats.record_handler_time(path, elapsed);
+        if let Some(threshold) = self.slow_handler_threshold {
+            if elapsed > threshold {
+                self.stats.inc_slow_handlers();
+                log::warn!(
+                    "osc handler for {} took {:?}, longer than {:?}",
+                    path,
+                    elapsed,
+                    threshold
+                );
+            }
+        }
+    }
+
+    /// handle an osc packet, might change the graph
+    pub(crate) fn handle_osc_packet(
+        root: &Arc<RwLock<RootInner>>,
+        packet: &OscPacket,
+        addr: Option<SocketAddr>,
+        time: Option<BundleContext>,
+    ) -> Vec<OscMessage> {
+        let mut callbacks = Vec::new();
+        let mut replies = Vec::new();
+        let stats = if let Ok(root) = root.read() {
+            //held back runs that are due go first
+            root.run_coalesced(&mut callbacks);
+            root.handle_osc_packet_inner(packet, addr, time, &mut callbacks, &mut replies);
+            root.stats.clone()
+        } else {
+            return replies;
+        };
+        Self::apply_callbacks(root, &stats, callbacks);
+        replies
+    }
+
+    //write the messages of `default_messages` like OSC updates to their full paths
+    fn apply_defaults(root: &Arc<RwLock<RootInner>>, msgs: &[OscMessage]) {
+        let mut callbacks = Vec::new();
+        let stats = if let Ok(root) = root.read() {
+            root.run_coalesced(&mut callbacks);
+            for msg in msgs {
+                let cb = root.with_node_at_path(&msg.addr, |ni| {
+                    ni.and_then(|(node, index)| {
+                        root.osc_update(node, *index, msg, None, None, false)
+                    })
+                });
+                if let Some(cb) = cb {
+                    callbacks.push((msg.addr.clone(), cb));
+                }
+            }
+            root.stats.clone()
+        } else {
+            return;
+        };
+        Self::apply_callbacks(root, &stats, callbacks);
+    }
+
+    fn apply_callbacks(
+        root: &Arc<RwLock<RootInner>>,
+        stats: &Stats,
+        callbacks: Vec<(String, OscWriteCallback)>,
+    ) {
+        if callbacks.is_empty() {
+            return;
+        }
+        //execute any callbacks, in order, isolating failures so the rest still apply
+        if let Ok(mut root) = root.write() {
+            for (path, cb) in callbacks {
+                let res = std::panic::catch_unwind(AssertUnwindSafe(|| cb.apply(root.deref_mut())));
+                let message = match res {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(e)) => e,
+                    Err(payload) => {
+                        if let Some(s) = payload.downcast_ref::<&str>() {
+                            format!("panicked: {}", s)
+                        } else if let Some(s) = payload.downcast_ref::<String>() {
+                            format!("panicked: {}", s)
+                        } else {
+                            "panicked".to_string()
+                        }
+                    }
+                };
+                log::warn!("osc write callback for {} failed: {}", path, message);
+                stats.record_callback_failure(path, message);
+            }
+        }
+    }
+
+    fn handle_osc_packet_inner(
+        &self,
+        packet: &OscPacket,
+        addr: Option<SocketAddr>,
+        time: Option<BundleContext>,
+        callbacks: &mut Vec<(String, OscWriteCallback)>,
+        replies: &mut Vec<OscMessage>,
+    ) {
+        match packet {
+            OscPacket::Message(msg) => self.handle_osc_msg(msg, addr, time, callbacks, replies),
+            OscPacket::Bundle(bundle) => {
+                let timetag = TimeTag::from(bundle.timetag);
+                let bundle_time = BundleContext {
+                    //an immediate bundle happens at the time of the one around it
+                    timetag: match time {
+                        Some(outer) if timetag.is_immediate() => outer.timetag,
+                        _ => timetag,
+                    },
+                    depth: time.map_or(1, |outer| outer.depth + 1),
+                };
+                for p in bundle.content.iter() {
+                    self.handle_osc_packet_inner(p, addr, Some(bundle_time), callbacks, replies);
+                }
+            }
+        }
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    pub(crate) fn serialize_node<F, S>(
+        &self,
+        path: &str,
+        param: Option<NodeQueryParam>,
+        extensions: bool,
+        f: F,
+    ) -> Result<S::Ok, S::Error>
+    where
+        F: FnOnce(Option<&NodeSerializeWrapper>) -> Result<S::Ok, S::Error>,
+        S: Serializer,
+    {
+        self.serialize_node_marked::<F, S>(path, param, extensions, None, f)
+    }
+
+    pub(crate) fn serialize_filtered<F, S>(
+        &self,
+        path: &str,
+        filter: &NodeFilter,
+        extensions: bool,
+        f: F,
+    ) -> Result<S::Ok, S::Error>
+    where
+        F: FnOnce(Option<&NodeSerializeWrapper>) -> Result<S::Ok, S::Error>,
+        S: Serializer,
+    {
+        let index = self.index_map.get(path).copied().filter(|i| {
+            matches!(
+                self.graph.node_weight(*i).map(|n| &n.node),
+                Some(Node::Container(..))
+            )
+        });
+        match index {
+            Some(index) => {
+                let marks = self.filter_marks(index, filter);
+                self.serialize_node_marked::<F, S>(path, None, extensions, Some(&marks), f)
+            }
+            None => f(None),
+        }
+    }
+
+    fn serialize_node_marked<F, S>(
+        &self,
+        path: &str,
+        param: Option<NodeQueryParam>,
+        extensions: bool,
+        marks: Option<&HashSet<NodeIndex>>,
+        f: F,
+    ) -> Result<S::Ok, S::Error>
+    where
+        F: FnOnce(Option<&NodeSerializeWrapper>) -> Result<S::Ok, S::Error>,
+        S: Serializer,
+    {
+        let (index, alias_path) = match self.index_map.get(path) {
+            Some(index) => (Some(*index), None),
+            None => match self.alias_paths.get_key_value(path) {
+                Some((alias_path, alias)) => (
+                    self.aliases.get(alias).map(|a| a.target),
+                    Some(alias_path.as_str()),
+                ),
+                None => (None, None),
+            },
+        };
+        match index.and_then(|index| self.graph.node_weight(index).map(|n| (index, n))) {
+            Some((index, node)) => f(Some(&NodeSerializeWrapper {
+                node,
+                graph: &self.graph,
+                aliases: &self.aliases,
+                index,
+                alias_path,
+                marks,
+                param,
+                extensions,
+                non_finite_strings: self.non_finite_strings,
+                now: self.clock.now(),
+            })),
+            None => f(None),
+        }
+    }
+}
+
+impl Serialize for RootInner {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.serialize_node::<_, S>("/", None, true, move |n| match n {
+            Some(n) => serializer.serialize_some(n),
+            None => Err(serde::ser::Error::custom("root not in graph")),
+        })
+    }
+}
+
+impl<'a> Serialize for NodeSerializeWrapper<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let n = &self.node.node;
+        match self.param {
+            None | Some(NodeQueryParam::All) => {
+                let mut m = serializer.serialize_map(None)?;
+                m.serialize_entry("ACCESS".into(), &n.access())?;
+                if let Some(d) = n.description() {
+                    m.serialize_entry("DESCRIPTION".into(), d)?;
+                }
+                m.serialize_entry("FULL_PATH", self.alias_path.unwrap_or(&self.node.full_path))?;
+                if let Some(tags) = n.tags() {
+                    m.serialize_entry("TAGS", tags)?;
+                }
+                if n.critical() {
+                    m.serialize_entry("CRITICAL", &true)?;
+                }
+                if let (true, Some(a)) = (self.extensions, n.osc_address()) {
+                    m.serialize_entry("OSC_ADDRESS", &a.address)?;
+                }
+                if let (true, Some(_)) = (self.extensions, self.alias_path) {
+                    m.serialize_entry("ALIAS_OF", &self.node.full_path)?;
+                }
+                match n {
+                    Node::Get(..) | Node::GetSet(..) if !(self.extensions && n.lazy()) => {
+                        if self.node.expired(self.now) {
+                            m.serialize_entry("VALUE", &())?;
+                        } else {
+                            m.serialize_entry(
+                                "VALUE",
+                                &NodeValueWrapper(n, self.non_finite_strings),
+                            )?;
+                        }
+                    }
+                    _ => (),
+                };
+                match n {
+                    Node::Container(..) => {
+                        m.serialize_entry(
+                            "CONTENTS".into(),
+                            &NodeSerializeContentsWrapper {
+                                graph: self.graph,
+                                aliases: self.aliases,
+                                index: self.index,
+                                marks: self.marks,
+                                extensions: self.extensions,
+                                non_finite_strings: self.non_finite_strings,
+                                now: self.now,
+                            },
+                        )?;
+                    }
+                    _ => {
+                        if let Some(t) = n.type_string() {
+                            m.serialize_entry("TYPE".into(), &t)?;
+                        }
+                        m.serialize_entry("RANGE".into(), &NodeRangeWrapper(n))?;
+                        m.serialize_entry("CLIPMODE".into(), &NodeClipModeWrapper(n))?;
+                        m.serialize_entry("UNIT".into(), &NodeUnitWrapper(n))?;
+                        let extended_types = n.param_extended_types();
+                        if extended_types.iter().any(Option::is_some) {
+                            m.serialize_entry("EXTENDED_TYPE", &extended_types)?;
+                        }
+                        if n.param_defaults().iter().any(Option::is_some) {
+                            m.serialize_entry(
+                                "DEFAULT",
+                                &NodeDefaultWrapper(n, self.non_finite_strings),
+                            )?;
+                        }
+                        if !n.overload_type_strings().is_empty() {
+                            m.serialize_entry("OVERLOADS", &NodeOverloadsWrapper(n))?;
+                        }
+                    }
+                };
+                m.end()
+            }
+            Some(NodeQueryParam::Access) => {
+                let mut m = serializer.serialize_map(None)?;
+                m.serialize_entry("ACCESS".into(), &n.access())?;
+                m.end()
+            }
+            Some(NodeQueryParam::Description) => {
+                let mut m = serializer.serialize_map(None)?;
+                m.serialize_entry("DESCRIPTION".into(), n.description())?;
+                m.end()
+            }
+            Some(NodeQueryParam::Value) => match n {
+                Node::Get(..) | Node::GetSet(..) => {
+                    let mut m = serializer.serialize_map(None)?;
+                    if self.node.expired(self.now) {
+                        m.serialize_entry("VALUE", &())?;
+                    } else {
+                        m.serialize_entry("VALUE", &NodeValueWrapper(n, self.non_finite_strings))?;
+                    }
+                    m.end()
+                }
+                _ => serializer.serialize_none(),
+            },
+            Some(NodeQueryParam::Range) => match n {
+                Node::Container(..) => serializer.serialize_none(),
+                _ => {
+                    let mut m = serializer.serialize_map(None)?;
+                    m.serialize_entry("RANGE".into(), &NodeRangeWrapper(n))?;
+                    m.end()
+                }
+            },
+            Some(NodeQueryParam::ClipMode) => match n {
+                Node::Container(..) => serializer.serialize_none(),
+                _ => {
+                    let mut m = serializer.serialize_map(None)?;
+                    m.serialize_entry("CLIPMODE".into(), &NodeClipModeWrapper(n))?;
+                    m.end()
+                }
+            },
+            Some(NodeQueryParam::Type) => match n {
+                Node::Container(..) => serializer.serialize_none(),
+                _ => {
+                    let mut m = serializer.serialize_map(None)?;
+                    m.serialize_entry("TYPE".into(), &n.type_string())?;
+
+                    m.end()
+                }
+            },
+            Some(NodeQueryParam::Tags) => match n.tags() {
+                Some(tags) => {
+                    let mut m = serializer.serialize_map(None)?;
+                    m.serialize_entry("TAGS", tags)?;
+                    m.end()
+                }
+                None => serializer.serialize_none(),
+            },
+            Some(NodeQueryParam::ExtendedType) => match n {
+                Node::Container(..) => serializer.serialize_none(),
+                _ => {
+                    let mut m = serializer.serialize_map(None)?;
+                    m.serialize_entry("EXTENDED_TYPE", &n.param_extended_types())?;
+                    m.end()
+                }
+            },
+            Some(NodeQueryParam::Critical) => match n {
+                Node::Container(..) => serializer.serialize_none(),
+                _ => {
+                    let mut m = serializer.serialize_map(None)?;
+                    m.serialize_entry("CRITICAL", &n.critical())?;
+                    m.end()
+                }
+            },
+            Some(NodeQueryParam::Unit) => match n {
+                Node::Container(..) => serializer.serialize_none(),
+                _ => {
+                    let mut m = serializer.serialize_map(None)?;
+                    m.serialize_entry("UNIT".into(), &NodeUnitWrapper(n))?;
+                    m.end()
+                }
+            },
+        }
+    }
+}
+
+impl<'a> Serialize for NodeSerializeContentsWrapper<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut m = serializer.serialize_map(None)?;
+        let mut neighbors = self.graph.neighbors(self.index).detach();
+        while let Some(index) = neighbors.next_node(self.graph) {
+            if matches!(self.marks, Some(m) if !m.contains(&index)) {
+                continue;
+            }
+            if let Some(node) = self.graph.node_weight(index) {
+                let w = NodeSerializeWrapper {
+                    node: &node,
+                    graph: self.graph,
+                    aliases: self.aliases,
+                    index,
+                    alias_path: None,
+                    marks: self.marks,
+                    param: None,
+                    extensions: self.extensions,
+                    non_finite_strings: self.non_finite_strings,
+                    now: self.now,
+                };
+                m.serialize_entry(&node.node.address(), &w)?;
+            }
+        }
+        //aliases appear under their parent, at their own address, but not in filtered queries
+        let mut aliases: Vec<&Alias> = self
+            .aliases
+            .values()
+            .filter(|a| a.parent == self.index && self.marks.is_none())
+            .collect();
+        aliases.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+        for alias in aliases {
+            if let Some(node) = self.graph.node_weight(alias.target) {
+                let w = NodeSerializeWrapper {
+                    node,
+                    graph: self.graph,
+                    aliases: self.aliases,
+                    index: alias.target,
+                    alias_path: Some(&alias.full_path),
+                    marks: None,
+                    param: None,
+                    extensions: self.extensions,
+                    non_finite_strings: self.non_finite_strings,
+                    now: self.now,
+                };
+                let address = alias.full_path.rsplit('/').next().unwrap_or("");
+                m.serialize_entry(address, &w)?;
+            }
+        }
+        m.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::param::*;
+
+    use crate::value::*;
+    use ::atomic::Atomic;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn basic_expectations() {
+        let root = Root::new(Some("test".into()));
+
+        let c = Container::new("foo", Some("description of foo".into()));
+        assert!(c.is_ok());
+
+        let res = root.add_node(c.unwrap(), None);
+        assert!(res.is_ok());
+
+        let chandle = res.unwrap();
+        assert_eq!(Some("/foo".to_string()), root.handle_to_path(&chandle));
+
+        let c = Container::new("bar", Some("description of foo".into()));
+        assert!(c.is_ok());
+
+        let res = root.add_node(c.unwrap(), Some(chandle));
+        assert!(res.is_ok());
+        assert_eq!(
+            Some("/foo/bar".to_string()),
+            root.handle_to_path(&res.unwrap())
+        );
+
+        let a = Arc::new(Atomic::new(2084i32));
+        let m = crate::node::Get::new(
+            "baz",
+            None,
+            vec![ParamGet::Int(ValueBuilder::new(a.clone() as _).build())],
+        );
+
+        //can add a method
+        let res = root.add_node(m.unwrap(), Some(chandle));
+        assert!(res.is_ok());
+
+        let mhandle = res.unwrap();
+        assert_eq!(Some("/foo/baz".to_string()), root.handle_to_path(&mhandle));
+
+        //okay to add method to method
+        let m = crate::node::GetSet::new(
+            &"biz",
+            None,
+            vec![ParamGetSet::Int(ValueBuilder::new(a.clone() as _).build())],
+            None,
+        );
+
+        let res = root.add_node(m.unwrap(), Some(mhandle));
+        assert!(res.is_ok());
+
+        //can remove a method
+        let handle = res.unwrap();
+        let res = root.rm_node(handle.clone());
+        assert!(res.is_ok());
+        let v = res.unwrap();
+        assert_eq!(1, v.len());
+        //second attempt gives error
+        assert_matches!(
+            root.rm_node(handle.clone()),
+            Err(OscQueryError::NodeNotInGraph)
+        );
+
+        //can remove the top
+        let res = root.rm_node(chandle);
+        assert!(res.is_ok());
+        let v = res.unwrap();
+        assert_eq!(3, v.len());
+
+        //come out with leaf first
+        assert_eq!(&"baz", v[0].address());
+        assert_eq!(&"bar", v[1].address());
+        assert_eq!(&"foo", v[2].address());
+
+        //adding under a removed parent gives the node back
+        let c = Container::new("bar", None).unwrap();
+        match root.add_node(c, Some(chandle)) {
+            Err(OscQueryError::NodeNotAdded { node, reason }) => {
+                assert_eq!(&"bar", node.address());
+                assert_matches!(*reason, OscQueryError::ParentNotInGraph);
+            }
+            _ => panic!("expected NodeNotAdded"),
+        }
+        assert_matches!(
+            Container::new("a/b", None),
+            Err(OscQueryError::InvalidAddress(a)) if a == "a/b"
+        );
+    }
+
+    #[test]
+    fn is_send_and_sync() {
+        let root = Arc::new(Root::new(None));
+
+        let c = Container::new("foo", Some("description of foo"));
+        assert!(c.is_ok());
+
+        let a = Arc::new(Atomic::new(2084i32));
+        let m = crate::node::Set::new(
+            "baz",
+            None,
+            vec![ParamSet::Int(ValueBuilder::new(a.clone() as _).build())],
+            None,
+        );
+
+        let r = root.clone();
+        let h = thread::spawn(move || {
+            let res = r.add_node(c.unwrap(), None);
+            assert!(res.is_ok());
+
+            let c = Container::new("bar", None);
+            assert!(c.is_ok());
+            let res = r.add_node(c.unwrap(), Some(res.unwrap()));
+            assert!(res.is_ok());
+
+            let res = r.add_node(m.unwrap(), Some(res.unwrap()));
+            assert!(res.is_ok());
+        });
+        let c = Container::new("bar", None);
+        assert!(c.is_ok());
+        let res = root.add_node(c.unwrap(), None);
+        assert!(res.is_ok());
+
+        assert!(h.join().is_ok());
+    }
+
+    use serde_json::json;
+
+    #[test]
+    fn non_finite() {
+        use crate::service::http::tests::wait_for;
+        use std::net::UdpSocket;
+        use std::sync::atomic::{AtomicI32, Ordering};
+
+        let root = Root::new(None);
+        let f = Arc::new(Atomic::new(0.5f32));
+        let d = Arc::new(Atomic::new(0f64));
+        let m = Arc::new(Atomic::new(0i32));
+        let node = |name: &str, p: ParamGetSet| {
+            root.add_node(
+                crate::node::GetSet::new(name, None, vec![p], None).unwrap(),
+                None,
+            )
+            .unwrap()
+        };
+        node(
+            "f",
+            ParamGetSet::Float(
+                ValueBuilder::new(f.clone() as _)
+                    .with_range(Range::MinMax(0.0, 1.0))
+                    .build(),
+            ),
+        );
+        node(
+            "d",
+            ParamGetSet::Double(ValueBuilder::new(d.clone() as _).build()),
+        );
+        node(
+            "m",
+            ParamGetSet::Int(ValueBuilder::new(m.clone() as _).build()),
+        );
+
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let marker = AtomicI32::new(0);
+        //send, then wait for a marker sent after it, to know it was handled
+        let send = |addr: &str, arg: OscType| {
+            let n = marker.fetch_add(1, Ordering::SeqCst) + 1;
+            for (addr, arg) in &[(addr, arg), ("/m", OscType::Int(n))] {
+                let packet = OscPacket::Message(OscMessage {
+                    addr: addr.to_string(),
+                    args: vec![arg.clone()],
+                });
+                client
+                    .send_to(
+                        &crate::osc::encoder::encode(&packet).unwrap(),
+                        osc.local_addr(),
+                    )
+                    .unwrap();
+            }
+            wait_for(|| m.load(::atomic::Ordering::SeqCst) == n);
+        };
+        let value = |path: &str| {
+            let j = serde_json::to_value(&root).unwrap();
+            j["CONTENTS"][path]["VALUE"].clone()
+        };
+        let stored = || f.load(::atomic::Ordering::SeqCst);
+
+        //pass through, the default
+        send("/f", OscType::Float(f32::NAN));
+        assert!(stored().is_nan());
+        assert_eq!(value("f"), json!([null]));
+        root.set_non_finite_strings(true);
+        assert_eq!(value("f"), json!(["nan"]));
+        send("/f", OscType::Float(f32::INFINITY));
+        assert_eq!(stored(), f32::INFINITY);
+        assert_eq!(value("f"), json!(["inf"]));
+        //rendering sends what is stored
+        assert_eq!(
+            root.render_path("/f").unwrap().args,
+            vec![OscType::Float(f32::INFINITY)]
+        );
+        send("/d", OscType::Double(f64::NEG_INFINITY));
+        assert_eq!(value("d"), json!(["-inf"]));
+        root.set_non_finite_strings(false);
+        assert_eq!(value("d"), json!([null]));
+        assert_eq!(root.stats().osc_non_finite_rejected(), 0);
+
+        root.set_non_finite_policy(NonFinitePolicy::RejectWrite);
+        f.store(0.5, ::atomic::Ordering::SeqCst);
+        send("/f", OscType::Float(f32::NAN));
+        assert_eq!(stored(), 0.5);
+        send("/f", OscType::Float(f32::INFINITY));
+        assert_eq!(stored(), 0.5);
+        assert_eq!(root.stats().osc_non_finite_rejected(), 2);
+        send("/f", OscType::Float(0.25));
+        assert_eq!(stored(), 0.25);
+
+        root.set_non_finite_policy(NonFinitePolicy::ClampToRange);
+        send("/f", OscType::Float(f32::NAN));
+        assert_eq!(stored(), 0.25);
+        assert_eq!(root.stats().osc_non_finite_rejected(), 3);
+        send("/f", OscType::Float(f32::INFINITY));
+        assert_eq!(stored(), 1.0);
+        send("/f", OscType::Float(f32::NEG_INFINITY));
+        assert_eq!(stored(), 0.0);
+        //no range, the largest finite value
+        send("/d", OscType::Double(f64::INFINITY));
+        assert_eq!(d.load(::atomic::Ordering::SeqCst), f64::MAX);
+        assert_eq!(value("d"), json!([f64::MAX]));
+        assert_eq!(root.stats().osc_non_finite_rejected(), 3);
+    }
+
+    #[test]
+    fn update_func_mut() {
+        let root = Root::new(None);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut count = 0u32;
+        let counter = crate::node::Set::new(
+            "counter",
+            None,
+            vec![ParamSet::Int(ValueBuilder::new(Arc::new(()) as _).build())],
+            Some(Box::new(crate::func_wrap::OscUpdateFuncMut::new(
+                move |_: &Vec<crate::osc::OscType>,
+                      _: Option<SocketAddr>,
+                      _: Option<BundleContext>,
+                      _: &NodeHandle| {
+                    count += 1;
+                    let _ = tx.send(count);
+                    None
+                },
+            ))),
+        );
+        assert!(root.add_node(counter.unwrap(), None).is_ok());
+
+        for _ in 0..3 {
+            let packet = OscPacket::Message(OscMessage {
+                addr: "/counter".to_string(),
+                args: vec![crate::osc::OscType::Int(1)],
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<u32>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn update_func_typed() {
+        use crate::func_wrap::{OscUpdateFuncInt, OscUpdateFuncString};
+        use crate::osc::OscType;
+
+        let root = Root::new(None);
+        let got = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let g = got.clone();
+        root.add_node(
+            crate::node::Set::new(
+                "int",
+                None,
+                vec![ParamSet::Int(ValueBuilder::new(Arc::new(()) as _).build())],
+                Some(Box::new(OscUpdateFuncInt::new(
+                    move |v: i32,
+                          _: Option<SocketAddr>,
+                          _: Option<BundleContext>,
+                          _: &NodeHandle| {
+                        g.lock().unwrap().push(v.to_string());
+                        None
+                    },
+                ))),
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let g = got.clone();
+        root.add_node(
+            crate::node::Set::new(
+                "string",
+                None,
+                vec![ParamSet::String(
+                    ValueBuilder::new(Arc::new(()) as _).build(),
+                )],
+                Some(Box::new(OscUpdateFuncString::new(
+                    move |v: &str,
+                          _: Option<SocketAddr>,
+                          _: Option<BundleContext>,
+                          _: &NodeHandle| {
+                        g.lock().unwrap().push(v.to_string());
+                        None
+                    },
+                ))),
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+
+        for (addr, args) in [
+            ("/int", vec![OscType::Int(3)]),
+            ("/int", vec![OscType::Float(3.0)]),
+            ("/int", vec![OscType::Int(4), OscType::Int(5)]),
+            ("/string", vec![OscType::String("saw".into())]),
+            ("/string", vec![OscType::Int(6)]),
+        ] {
+            let packet = OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args,
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        }
+        assert_eq!(*got.lock().unwrap(), vec!["3", "saw"]);
+    }
+
+    #[test]
+    fn fallible_funcs() {
+        use crate::func_wrap::{FallibleGetFunc, FallibleSetFunc};
+        use crate::osc::OscType;
+        use serde_json::json;
+        use std::sync::atomic::AtomicBool;
+
+        let root = Root::new(None);
+        let fail = Arc::new(AtomicBool::new(false));
+        let f = fail.clone();
+        let temp = Arc::new(FallibleGetFunc::new(
+            move || {
+                if f.load(Ordering::SeqCst) {
+                    Err("no sensor")
+                } else {
+                    Ok(42)
+                }
+            },
+            -1,
+        ));
+        assert_eq!(temp.try_get(), Ok(42));
+        root.add_node(
+            crate::node::Get::new(
+                "temp",
+                None,
+                vec![ParamGet::Int(ValueBuilder::new(temp.clone() as _).build())],
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let set = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let s = set.clone();
+        root.add_node(
+            crate::node::Set::new(
+                "fan",
+                None,
+                vec![ParamSet::Int(
+                    ValueBuilder::new(Arc::new(FallibleSetFunc::new(move |v: i32| {
+                        if v < 0 {
+                            return Err("negative speed");
+                        }
+                        s.lock().unwrap().push(v);
+                        Ok(())
+                    })) as _)
+                    .build(),
+                )],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(root.snapshot_values()["/temp"], json!([42]));
+        fail.store(true, Ordering::SeqCst);
+        assert_eq!(temp.try_get(), Err("no sensor"));
+        assert_eq!(root.snapshot_values()["/temp"], json!([-1]));
+
+        for v in &[3, -2, 5] {
+            let packet = OscPacket::Message(OscMessage {
+                addr: "/fan".to_string(),
+                args: vec![OscType::Int(*v)],
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        }
+        assert_eq!(*set.lock().unwrap(), vec![3, 5]);
+    }
+
+    #[test]
+    fn slow_handler() {
+        let root = Root::new(None);
+        root.set_slow_handler_threshold(Some(Duration::from_millis(5)));
+
+        let slow = crate::node::Set::new(
+            "slow",
+            None,
+            vec![ParamSet::Int(ValueBuilder::new(Arc::new(()) as _).build())],
+            Some(Box::new(crate::func_wrap::OscUpdateFunc(
+                |_: &Vec<crate::osc::OscType>,
+                 _: Option<SocketAddr>,
+                 _: Option<BundleContext>,
+                 _: &NodeHandle| {
+                    thread::sleep(Duration::from_millis(20));
+                    None
+                },
+            ))),
+        );
+        assert!(root.add_node(slow.unwrap(), None).is_ok());
+        let fast = crate::node::Set::new(
+            "fast",
+            None,
+            vec![ParamSet::Int(ValueBuilder::new(Arc::new(()) as _).build())],
+            None,
+        );
+        assert!(root.add_node(fast.unwrap(), None).is_ok());
+
+        for addr in &["/slow", "/fast", "/fast"] {
+            let packet = OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![crate::osc::OscType::Int(1)],
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        }
+
+        let stats = root.stats();
+        assert_eq!(stats.slow_handlers(), 1);
+        let t = stats.handler_timing("/slow").expect("to have timing");
+        assert_eq!(t.count, 1);
+        assert!(t.max >= Duration::from_millis(20));
+        assert_eq!(t.mean(), t.max);
+        let t = stats.handler_timing("/fast").expect("to have timing");
+        assert_eq!(t.count, 2);
+        assert!(stats.handler_timing("/nothing").is_none());
+    }
+
+    #[test]
+    fn value_json() {
+        use serde_json::json;
+
+        let root = Root::new(None);
+        let level = Arc::new(Atomic::new(0f32));
+        let name = Arc::new(MutexValue::new(String::new()));
+        let voice = crate::node::Set::new(
+            "voice",
+            None,
+            vec![ParamSet::Float(
+                ValueBuilder::new(level.clone() as _).build(),
+            )],
+            None,
+        )
+        .unwrap()
+        .with_overload(vec![ParamSet::String(
+            ValueBuilder::new(name.clone() as _).build(),
+        )]);
+        root.add_node(voice, None).unwrap();
+
+        root.set_value_at_path_json("/voice", &json!([0.5]))
+            .unwrap();
+        assert_eq!(level.load(Ordering::SeqCst), 0.5);
+        root.set_value_at_path_json("/voice", &json!(["lead"]))
+            .unwrap();
+        assert_eq!(crate::value::Get::get(&*name), "lead");
+        assert_matches!(
+            root.set_value_at_path_json("/voice", &json!([0.5, 1])),
+            Err(OscQueryError::TypeMismatch(t)) if t == "f"
+        );
+        assert_matches!(
+            root.set_value_at_path_json("/voice", &json!(0.5)),
+            Err(OscQueryError::TypeMismatch(..))
+        );
+        assert_eq!(level.load(Ordering::SeqCst), 0.5);
+
+        root.add_node(
+            crate::node::Get::new(
+                "get",
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(1)) as _).build(),
+                )],
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        assert_matches!(
+            root.set_value_at_path_json("/get", &json!([2])),
+            Err(OscQueryError::ReadOnly(p)) if p == "/get"
+        );
+        assert_matches!(
+            root.set_value_at_path_json("/", &json!([2])),
+            Err(OscQueryError::ContainerHasNoValue)
+        );
+        assert_matches!(
+            root.set_value_at_path_json("/nope", &json!([2])),
+            Err(OscQueryError::NodeNotInGraph)
+        );
+    }
+
+    #[test]
+    fn snapshot_values() {
+        use crate::osc::{OscArray, OscType};
+        use serde_json::json;
+
+        let tree = || {
+            let root = Root::new(None);
+            let synth = root
+                .add_node(Container::new("synth", None).unwrap(), None)
+                .unwrap();
+            let add = |address: &str, param: ParamGetSet| {
+                root.add_node(
+                    crate::node::GetSet::new(address, None, vec![param], None).unwrap(),
+                    Some(synth),
+                )
+                .unwrap();
+            };
+            add(
+                "int",
+                ParamGetSet::Int(ValueBuilder::new(Arc::new(Atomic::new(0)) as _).build()),
+            );
+            add(
+                "float",
+                ParamGetSet::Float(
+                    ValueBuilder::new(Arc::new(Atomic::new(0f32)) as _)
+                        .with_range(crate::value::Range::MinMax(0.0, 1.0))
+                        .with_clip_mode(crate::value::ClipMode::Both)
+                        .build(),
+                ),
+            );
+            add(
+                "string",
+                ParamGetSet::String(
+                    ValueBuilder::new(Arc::new(MutexValue::new(String::new())) as _).build(),
+                ),
+            );
+            add(
+                "bool",
+                ParamGetSet::Bool(ValueBuilder::new(Arc::new(Atomic::new(false)) as _).build()),
+            );
+            add(
+                "array",
+                ParamGetSet::Array(
+                    ValueBuilder::new(Arc::new(MutexValue::new(OscArray {
+                        content: vec![OscType::Int(0), OscType::Float(0.0)],
+                    })) as _)
+                    .build(),
+                ),
+            );
+            root.add_node(
+                crate::node::Get::new(
+                    "meter",
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(7)) as _).build(),
+                    )],
+                )
+                .unwrap(),
+                Some(synth),
+            )
+            .unwrap();
+            root
+        };
+
+        let root = tree();
+        let values = json!({
+            "/synth/int": [3],
+            "/synth/float": [0.25],
+            "/synth/string": ["saw"],
+            "/synth/bool": [true],
+            "/synth/array": [[2, 0.5]],
+        });
+        assert_eq!(root.apply_values(&values).unwrap(), 5);
+        let snapshot = root.snapshot_values();
+        let mut expected = values.clone();
+        expected["/synth/meter"] = json!([7]);
+        assert_eq!(snapshot, expected);
+
+        //onto a fresh tree, the read only meter is passed over
+        let other = tree();
+        assert_eq!(other.apply_values(&snapshot).unwrap(), 5);
+        assert_eq!(other.snapshot_values(), snapshot);
+
+        //clipped like a message received over OSC
+        let err = other
+            .apply_values(&json!({
+                "/synth/float": [2.0],
+                "/synth/nope": [1],
+                "/synth/int": ["x"],
+            }))
+            .unwrap_err();
+        match err {
+            SnapshotError::Skipped { applied, skipped } => {
+                assert_eq!(applied, 1);
+                assert_eq!(skipped.len(), 2);
+                assert_eq!(skipped[0].0, "/synth/int");
+                assert_matches!(skipped[0].1, OscQueryError::TypeMismatch(..));
+                assert_eq!(skipped[1].0, "/synth/nope");
+                assert_matches!(skipped[1].1, OscQueryError::NodeNotInGraph);
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        assert_eq!(other.snapshot_values()["/synth/float"], json!([1.0]));
+        assert_matches!(
+            other.apply_values(&json!([1])),
+            Err(SnapshotError::NotAnObject)
+        );
+    }
+
+    #[test]
+    fn value_indexed() {
+        use crate::osc::OscType;
+
+        let root = Root::new(None);
+        let got = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let g = got.clone();
+        let node = crate::node::GetSet::new(
+            "ifs",
+            None,
+            vec![
+                ParamGetSet::Int(ValueBuilder::new(Arc::new(Atomic::new(1)) as _).build()),
+                ParamGetSet::Float(ValueBuilder::new(Arc::new(Atomic::new(2.5f32)) as _).build()),
+                ParamGetSet::String(
+                    ValueBuilder::new(Arc::new(MutexValue::new("x".to_string())) as _).build(),
+                ),
+            ],
+            Some(Box::new(crate::func_wrap::OscUpdateFunc(
+                move |args: &Vec<OscType>,
+                      _: Option<SocketAddr>,
+                      _: Option<BundleContext>,
+                      _: &NodeHandle| {
+                    g.lock().unwrap().push(args.clone());
+                    None
+                },
+            ))),
+        );
+        root.add_node(node.unwrap(), None).unwrap();
+        root.add_node(
+            crate::node::Get::new(
+                "get",
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(1)) as _).build(),
+                )],
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(root.value_at_path_indexed("/ifs", 0), Some(OscType::Int(1)));
+        assert_eq!(
+            root.value_at_path_indexed("/ifs", 2),
+            Some(OscType::String("x".into()))
+        );
+        assert_eq!(root.value_at_path_indexed("/ifs", 3), None);
+        assert_eq!(root.value_at_path_indexed("/nope", 0), None);
+
+        root.set_value_at_path_indexed("/ifs", 1, OscType::Float(4.0))
+            .unwrap();
+        root.set_value_at_path_indexed("/ifs", 2, OscType::String("y".into()))
+            .unwrap();
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![
+                vec![
+                    OscType::Int(1),
+                    OscType::Float(4.0),
+                    OscType::String("x".into())
+                ],
+                vec![
+                    OscType::Int(1),
+                    OscType::Float(4.0),
+                    OscType::String("y".into())
+                ],
+            ]
+        );
+        assert_eq!(
+            root.value_at_path_indexed("/ifs", 1),
+            Some(OscType::Float(4.0))
+        );
+
+        assert_matches!(
+            root.set_value_at_path_indexed("/ifs", 3, OscType::Int(0)),
+            Err(OscQueryError::IndexOutOfRange { index: 3, len: 3 })
+        );
+        assert_matches!(
+            root.set_value_at_path_indexed("/get", 0, OscType::Int(0)),
+            Err(OscQueryError::NotReadWrite(p)) if p == "/get"
+        );
+        assert_matches!(
+            root.set_value_at_path_indexed("/nope", 0, OscType::Int(0)),
+            Err(OscQueryError::NodeNotInGraph)
+        );
+        assert_eq!(got.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn bundle_context() {
+        use crate::time::TimeTag;
+
+        let root = Root::new(None);
+        let got = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let g = got.clone();
+        let m = crate::node::Set::new(
+            "a",
+            None,
+            vec![ParamSet::Int(ValueBuilder::new(Arc::new(()) as _).build())],
+            Some(Box::new(crate::func_wrap::OscUpdateFunc(
+                move |_: &Vec<crate::osc::OscType>,
+                      _: Option<SocketAddr>,
+                      time: Option<BundleContext>,
+                      _: &NodeHandle| {
+                    g.lock().unwrap().push(time);
+                    None
+                },
+            ))),
+        );
+        assert!(root.add_node(m.unwrap(), None).is_ok());
+
+        let msg = || {
+            OscPacket::Message(OscMessage {
+                addr: "/a".to_string(),
+                args: vec![crate::osc::OscType::Int(1)],
+            })
+        };
+        let bundle =
+            |timetag, content| OscPacket::Bundle(crate::osc::OscBundle { timetag, content });
+        let ctx = |timetag: (u32, u32), depth| {
+            Some(BundleContext {
+                timetag: timetag.into(),
+                depth,
+            })
+        };
+        let packets = vec![
+            msg(),
+            bundle((0, 1), vec![msg()]),
+            bundle((5, 6), vec![msg()]),
+            //immediate inner bundles take the time of the closest bundle with one
+            bundle(
+                (5, 6),
+                vec![
+                    bundle((0, 1), vec![msg(), bundle((0, 1), vec![msg()])]),
+                    bundle((7, 8), vec![bundle((0, 1), vec![msg()])]),
+                ],
+            ),
+            //an immediate outer bundle doesn't hide a real inner time
+            bundle((0, 1), vec![bundle((7, 8), vec![msg()])]),
+        ];
+        for p in &packets {
+            root.handle_osc_packet(p, None);
+        }
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![
+                None,
+                ctx((0, 1), 1),
+                ctx((5, 6), 1),
+                ctx((5, 6), 2),
+                ctx((5, 6), 3),
+                ctx((7, 8), 3),
+                ctx((7, 8), 2),
+            ]
+        );
+        assert!(got.lock().unwrap()[1].unwrap().timetag.is_immediate());
+        assert_eq!(got.lock().unwrap()[2].unwrap().timetag, TimeTag(5, 6));
+    }
+
+    #[test]
+    fn callback_failures() {
+        let root = Root::new(None);
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for name in &["a", "b", "c", "d"] {
+            let name = name.to_string();
+            let order = order.clone();
+            let m = crate::node::Set::new(
+                name.clone(),
+                None,
+                vec![ParamSet::Int(ValueBuilder::new(Arc::new(()) as _).build())],
+                Some(Box::new(crate::func_wrap::OscUpdateFunc(
+                    move |_: &Vec<crate::osc::OscType>,
+                          _: Option<SocketAddr>,
+                          _: Option<BundleContext>,
+                          _: &NodeHandle| {
+                        let name = name.clone();
+                        let order = order.clone();
+                        Some(OscWriteCallback::new(move |graph| {
+                            order.lock().unwrap().push(name.clone());
+                            match name.as_str() {
+                                "b" => panic!("b failed"),
+                                "d" => Err("d failed".to_string()),
+                                _ => graph
+                                    .add_node(
+                                        Container::new(format!("{}{}", name, name), None)
+                                            .unwrap()
+                                            .into(),
+                                        None,
+                                    )
+                                    .map(|_| ())
+                                    .map_err(|e| e.to_string()),
+                            }
+                        }))
+                    },
+                ))),
+            );
+            assert!(root.add_node(m.unwrap(), None).is_ok());
+        }
+
+        let msg = |addr: &str| {
+            OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![crate::osc::OscType::Int(1)],
+            })
+        };
+        let bundle = |content| {
+            OscPacket::Bundle(crate::osc::OscBundle {
+                timetag: (0, 1),
+                content,
+            })
+        };
+        let packet = bundle(vec![
+            msg("/a"),
+            bundle(vec![msg("/b"), msg("/d")]),
+            msg("/c"),
+        ]);
+        RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+
+        assert_eq!(*order.lock().unwrap(), vec!["a", "b", "d", "c"]);
+        let json = serde_json::to_value(&root).unwrap();
+        assert!(json["CONTENTS"]["aa"].is_object());
+        assert!(json["CONTENTS"]["cc"].is_object());
+
+        let stats = root.stats();
+        assert_eq!(stats.callback_failures(), 2);
+        let failures = stats.recent_callback_failures();
+        assert_eq!(failures[0].path, "/b");
+        assert_eq!(failures[0].message, "panicked: b failed");
+        assert_eq!(failures[1].path, "/d");
+        assert_eq!(failures[1].message, "d failed");
+
+        //the lock is still usable
+        assert!(!root.inner.is_poisoned());
+        assert!(root
+            .add_node(Container::new("e", None).unwrap(), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn pattern() {
+        let paths: HashMap<String, NodeIndex> = [
+            "/foo",
+            "/foo/bar",
+            "/foo/baz",
+            "/foo/bat",
+            "/foo/b",
+            "/foo/bar/x",
+            "/fob",
+            "/mix/ch1/gain",
+            "/mix/ch2/gain",
+            "/mix/ch10/gain",
+            "/mix/ch2/pan",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p.to_string(), NodeIndex::new(i)))
+        .collect();
+        let index: HashMap<NodeIndex, &String> = paths.iter().map(|(p, i)| (*i, p)).collect();
+        let matched = |pattern: &str| -> Vec<&str> {
+            match_pattern(pattern, &paths)
+                .into_iter()
+                .map(|i| index[&i].as_str())
+                .collect()
+        };
+
+        assert_eq!(matched("/foo/bar"), vec!["/foo/bar"]);
+        assert_eq!(
+            matched("/foo/ba?"),
+            vec!["/foo/bar", "/foo/bat", "/foo/baz"]
+        );
+        assert_eq!(matched("/fo?"), vec!["/fob", "/foo"]);
+        assert!(matched("/foo/b?").is_empty());
+        //stars don't cross path parts
+        assert_eq!(
+            matched("/foo/*"),
+            vec!["/foo/b", "/foo/bar", "/foo/bat", "/foo/baz"]
+        );
+        assert_eq!(matched("/foo/b*"), matched("/foo/*"));
+        assert_eq!(matched("/*/*/x"), vec!["/foo/bar/x"]);
+        assert_eq!(matched("/foo/*r"), vec!["/foo/bar"]);
+        assert_eq!(matched("/foo/**"), matched("/foo/*"));
+        assert_eq!(
+            matched("/mix/*/gain"),
+            vec!["/mix/ch1/gain", "/mix/ch10/gain", "/mix/ch2/gain"]
+        );
+        //classes, ranges and negation
+        assert_eq!(matched("/foo/ba[rz]"), vec!["/foo/bar", "/foo/baz"]);
+        assert_eq!(matched("/foo/ba[a-t]"), vec!["/foo/bar", "/foo/bat"]);
+        assert_eq!(matched("/foo/ba[!a-s]"), vec!["/foo/bat", "/foo/baz"]);
+        assert_eq!(matched("/foo/ba[!rtz]"), Vec::<&str>::new());
+        assert_eq!(
+            matched("/mix/ch[0-9]/*"),
+            vec!["/mix/ch1/gain", "/mix/ch2/gain", "/mix/ch2/pan"]
+        );
+        assert_eq!(matched("/foo/ba[t-]"), vec!["/foo/bat"]);
+        //alternatives, nested and with patterns inside
+        assert_eq!(matched("/foo/{bar,baz}"), vec!["/foo/bar", "/foo/baz"]);
+        assert_eq!(matched("/{foo,fob}"), vec!["/fob", "/foo"]);
+        assert_eq!(
+            matched("/foo/{b,ba{r,t}}"),
+            vec!["/foo/b", "/foo/bar", "/foo/bat"]
+        );
+        assert_eq!(
+            matched("/mix/ch{1,1?}/gain"),
+            vec!["/mix/ch1/gain", "/mix/ch10/gain"]
+        );
+        assert_eq!(matched("/foo/{}"), Vec::<&str>::new());
+        assert_eq!(matched("/foo/ba{,r}"), vec!["/foo/bar"]);
+        //unclosed brackets and braces are literal
+        assert!(matched("/foo/ba[r").is_empty());
+        assert!(matched("/foo/{bar").is_empty());
+    }
+
+    #[test]
+    fn pattern_star_run() {
+        //a deep tree of long path parts
+        let mut path = String::new();
+        let mut paths = HashMap::new();
+        for i in 0..16 {
+            path.push_str(&format!("/{}", "a".repeat(32)));
+            paths.insert(path.clone(), NodeIndex::new(i));
+        }
+        let parts = |part: &str| format!("/{}", part).repeat(16);
+
+        //these all try every split point for every star when matched recursively
+        let start = std::time::Instant::now();
+        assert_eq!(match_pattern(&parts(&"*".repeat(64)), &paths).len(), 1);
+        assert_eq!(match_pattern(&parts(&"*a".repeat(32)), &paths).len(), 1);
+        assert!(match_pattern(&parts(&format!("{}b", "*a".repeat(16))), &paths).is_empty());
+        assert!(match_pattern(&format!("{}b", parts("*")), &paths).is_empty());
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn osc_pattern() {
+        let root = Root::new(None);
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mix = root
+            .add_node(Container::new("mix", None).unwrap(), None)
+            .unwrap();
+        let mut values = Vec::new();
+        for ch in &["ch1", "ch2", "ch*"] {
+            let c = root
+                .add_node(Container::new(*ch, None).unwrap(), Some(mix))
+                .unwrap();
+            let value = Arc::new(Atomic::new(0i32));
+            values.push(value.clone());
+            let order = order.clone();
+            let path = format!("/mix/{}/gain", ch);
+            let gain = crate::node::Set::new(
+                "gain",
+                None,
+                vec![ParamSet::Int(ValueBuilder::new(value as _).build())],
+                Some(Box::new(crate::func_wrap::OscUpdateFunc(
+                    move |_: &Vec<crate::osc::OscType>,
+                          _: Option<SocketAddr>,
+                          _: Option<BundleContext>,
+                          _: &NodeHandle| {
+                        let path = path.clone();
+                        let order = order.clone();
+                        Some(OscWriteCallback::from(move |_: &mut dyn OscQueryGraph| {
+                            order.lock().unwrap().push(path)
+                        }))
+                    },
+                ))),
+            );
+            root.add_node(gain.unwrap(), Some(c)).unwrap();
+        }
+        let send = |addr: &str, v: i32| {
+            let packet = OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![crate::osc::OscType::Int(v)],
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        };
+        let current = || {
+            values
+                .iter()
+                .map(|v| v.load(Ordering::Relaxed))
+                .collect::<Vec<_>>()
+        };
+
+        //every match is updated and their callbacks are applied in path order
+        send("/mix/*/gain", 3);
+        assert_eq!(current(), vec![3, 3, 3]);
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["/mix/ch*/gain", "/mix/ch1/gain", "/mix/ch2/gain"]
+        );
+
+        //an existing path is used as is
+        order.lock().unwrap().clear();
+        send("/mix/ch*/gain", 4);
+        assert_eq!(current(), vec![3, 3, 4]);
+        assert_eq!(*order.lock().unwrap(), vec!["/mix/ch*/gain"]);
+
+        send("/mix/ch{1,2}/gain", 5);
+        assert_eq!(current(), vec![5, 5, 4]);
+        send("/mix/ch[!1]/gain", 6);
+        assert_eq!(current(), vec![5, 6, 6]);
+        //containers are skipped
+        send("/mix/*", 7);
+        send("/nothing/*", 7);
+        assert_eq!(current(), vec![5, 6, 6]);
+    }
+
+    #[test]
+    fn coalesce() {
+        use crate::osc::OscType;
+        use crate::time::ManualClock;
+
+        let clock = Arc::new(ManualClock::new());
+        let root = Root::new(None);
+        root.set_clock(clock.clone());
+        let window = Duration::from_millis(50);
+        let value = Arc::new(Atomic::new(0i32));
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let c = calls.clone();
+        let m = crate::node::Set::new(
+            "fader",
+            None,
+            vec![ParamSet::Int(ValueBuilder::new(value.clone() as _).build())],
+            Some(Box::new(crate::func_wrap::OscUpdateFunc(
+                move |args: &Vec<OscType>,
+                      _: Option<SocketAddr>,
+                      _: Option<BundleContext>,
+                      _: &NodeHandle| {
+                    c.lock().unwrap().push(args.clone());
+                    None
+                },
+            ))),
+        )
+        .unwrap()
+        .with_coalesce_window(Some(window));
+        root.add_node(m, None).unwrap();
+
+        for i in 1..=100 {
+            let packet = OscPacket::Message(OscMessage {
+                addr: "/fader".to_string(),
+                args: vec![OscType::Int(i)],
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        }
+        //the value is current, the handler only saw the first write
+        assert_eq!(value.load(Ordering::Relaxed), 100);
+        assert_eq!(*calls.lock().unwrap(), vec![vec![OscType::Int(1)]]);
+
+        //nothing is due yet
+        RootInner::flush_coalesced(&root.inner);
+        assert_eq!(calls.lock().unwrap().len(), 1);
+
+        //the held back run gets the latest arguments, once
+        clock.advance(window);
+        RootInner::flush_coalesced(&root.inner);
+        RootInner::flush_coalesced(&root.inner);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![vec![OscType::Int(1)], vec![OscType::Int(100)]]
+        );
+    }
+
+    #[test]
+    fn clip() {
+        use crate::osc::OscType;
+
+        let root = Root::new(None);
+        let int = Arc::new(Atomic::new(0i32));
+        let long = Arc::new(Atomic::new(0i64));
+        let float = Arc::new(Atomic::new(0f32));
+        let double = Arc::new(Atomic::new(0f64));
+        let c = Arc::new(Atomic::new('a'));
+        let m = crate::node::Set::new(
+            "clipped",
+            None,
+            vec![
+                ParamSet::Int(
+                    ValueBuilder::new(int.clone() as _)
+                        .with_range(Range::MinMax(0, 127))
+                        .with_clip_mode(ClipMode::Both)
+                        .build(),
+                ),
+                ParamSet::Long(
+                    ValueBuilder::new(long.clone() as _)
+                        .with_range(Range::MinMax(0, 10))
+                        .with_clip_mode(ClipMode::Low)
+                        .build(),
+                ),
+                ParamSet::Float(
+                    ValueBuilder::new(float.clone() as _)
+                        .with_range(Range::MinMax(0.0, 1.0))
+                        .with_clip_mode(ClipMode::High)
+                        .build(),
+                ),
+                ParamSet::Double(
+                    ValueBuilder::new(double.clone() as _)
+                        .with_range(Range::MinMax(0.0, 1.0))
+                        .with_clip_mode(ClipMode::None)
+                        .build(),
+                ),
+                ParamSet::Char(
+                    ValueBuilder::new(c.clone() as _)
+                        .with_range(Range::Vals(vec!['a', 'm', 'z']))
+                        .with_clip_mode(ClipMode::Both)
+                        .build(),
+                ),
+            ],
+            None,
+        )
+        .unwrap();
+        root.add_node(m, None).unwrap();
+        let send = |args: Vec<OscType>| {
+            let packet = OscPacket::Message(OscMessage {
+                addr: "/clipped".to_string(),
+                args,
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        };
+        let current = || {
+            (
+                int.load(Ordering::SeqCst),
+                long.load(Ordering::SeqCst),
+                float.load(Ordering::SeqCst),
+                double.load(Ordering::SeqCst),
+                c.load(Ordering::SeqCst),
+            )
+        };
+
+        send(vec![
+            OscType::Int(200),
+            OscType::Long(-5),
+            OscType::Float(2.0),
+            OscType::Double(2.0),
+            OscType::Char('k'),
+        ]);
+        assert_eq!(current(), (127, 0, 1.0, 2.0, 'm'));
+        send(vec![
+            OscType::Int(-1),
+            OscType::Long(20),
+            OscType::Float(-2.0),
+            OscType::Double(-2.0),
+            OscType::Char('y'),
+        ]);
+        assert_eq!(current(), (0, 20, -2.0, -2.0, 'z'));
+        send(vec![
+            OscType::Int(64),
+            OscType::Long(5),
+            OscType::Float(0.5),
+            OscType::Double(0.5),
+            OscType::Char('a'),
+        ]);
+        assert_eq!(current(), (64, 5, 0.5, 0.5, 'a'));
+    }
+
+    #[test]
+    fn clip_modes() {
+        use crate::osc::OscType;
+
+        //sent below, in and above the range, for every clip mode
+        for (mode, expected) in &[
+            (ClipMode::None, [(-5, -0.5), (5, 0.5), (15, 1.5)]),
+            (ClipMode::Low, [(0, 0.0), (5, 0.5), (15, 1.5)]),
+            (ClipMode::High, [(-5, -0.5), (5, 0.5), (10, 1.0)]),
+            (ClipMode::Both, [(0, 0.0), (5, 0.5), (10, 1.0)]),
+        ] {
+            let root = Root::new(None);
+            let int = Arc::new(Atomic::new(0i32));
+            let float = Arc::new(Atomic::new(0f32));
+            let m = crate::node::Set::new(
+                "clipped",
+                None,
+                vec![
+                    ParamSet::Int(
+                        ValueBuilder::new(int.clone() as _)
+                            .with_range(Range::MinMax(0, 10))
+                            .with_clip_mode(*mode)
+                            .build(),
+                    ),
+                    ParamSet::Float(
+                        ValueBuilder::new(float.clone() as _)
+                            .with_range(Range::MinMax(0.0, 1.0))
+                            .with_clip_mode(*mode)
+                            .build(),
+                    ),
+                ],
+                None,
+            )
+            .unwrap();
+            root.add_node(m, None).unwrap();
+            for ((i, f), expected) in [(-5, -0.5), (5, 0.5), (15, 1.5)].iter().zip(expected) {
+                let packet = OscPacket::Message(OscMessage {
+                    addr: "/clipped".to_string(),
+                    args: vec![OscType::Int(*i), OscType::Float(*f)],
+                });
+                RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+                assert_eq!(
+                    (int.load(Ordering::SeqCst), float.load(Ordering::SeqCst)),
+                    *expected,
+                    "{:?} {}",
+                    mode,
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn coerce() {
+        use crate::osc::OscType;
+
+        let send = |coerce: CoercePolicy, mismatch: ArgMismatch, args: Vec<OscType>| {
+            let root = Root::new(None);
+            let int = Arc::new(Atomic::new(0i32));
+            let double = Arc::new(Atomic::new(0f64));
+            let m = crate::node::Set::new(
+                "a",
+                None,
+                vec![
+                    ParamSet::Int(ValueBuilder::new(int.clone() as _).build()),
+                    ParamSet::Double(ValueBuilder::new(double.clone() as _).build()),
+                ],
+                None,
+            )
+            .unwrap()
+            .with_coerce_policy(coerce)
+            .with_arg_mismatch(mismatch);
+            root.add_node(m, None).unwrap();
+            let packet = OscPacket::Message(OscMessage {
+                addr: "/a".to_string(),
+                args,
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+            (
+                int.load(Ordering::SeqCst),
+                double.load(Ordering::SeqCst),
+                root.stats().osc_args_rejected(),
+            )
+        };
+        use ArgMismatch::*;
+        use CoercePolicy::*;
+        use OscType::{Bool, Double, Float, Int, Long, String};
+        for (coerce, mismatch, args, expected) in vec![
+            //mismatched types are skipped by default
+            (Strict, Skip, vec![Float(1.0), Int(2)], (0, 0.0, 0)),
+            (Strict, Skip, vec![Int(1), Double(2.5)], (1, 2.5, 0)),
+            (Numeric, Skip, vec![Float(1.0), Int(2)], (1, 2.0, 0)),
+            (Numeric, Skip, vec![Double(-3.0), Float(0.5)], (-3, 0.5, 0)),
+            (Numeric, Skip, vec![Float(1.5), Long(2)], (0, 2.0, 0)),
+            (Numeric, Skip, vec![Bool(true), Bool(true)], (1, 0.0, 0)),
+            (Numeric, Skip, vec![Long(1 << 40), Int(2)], (0, 2.0, 0)),
+            (Lenient, Skip, vec![Float(1.5), Int(2)], (2, 2.0, 0)),
+            (
+                Lenient,
+                Skip,
+                vec![Double(1e12), Int(2)],
+                (i32::MAX, 2.0, 0),
+            ),
+            (
+                Lenient,
+                Skip,
+                vec![Long(-(1 << 40)), Int(2)],
+                (i32::MIN, 2.0, 0),
+            ),
+            (Lenient, Skip, vec![Float(f32::NAN), Int(2)], (0, 2.0, 0)),
+            (Lenient, Skip, vec![String("1".into()), Int(2)], (0, 2.0, 0)),
+            //excess and missing arguments
+            (Strict, Skip, vec![Int(1), Double(2.0), Int(3)], (1, 2.0, 0)),
+            (Strict, Skip, vec![Int(1)], (1, 0.0, 0)),
+            (
+                Strict,
+                Reject,
+                vec![Int(1), Double(2.0), Int(3)],
+                (0, 0.0, 1),
+            ),
+            (Strict, Reject, vec![Int(1)], (0, 0.0, 1)),
+            (Strict, Reject, vec![Float(1.0), Double(2.0)], (0, 0.0, 1)),
+            (Strict, Reject, vec![Int(1), Double(2.0)], (1, 2.0, 0)),
+            (Numeric, Reject, vec![Float(1.0), Float(2.0)], (1, 2.0, 0)),
+            (Numeric, Reject, vec![Float(1.5), Float(2.0)], (0, 0.0, 1)),
+        ] {
+            assert_eq!(
+                send(coerce, mismatch, args.clone()),
+                expected,
+                "{:?} {:?} {:?}",
+                coerce,
+                mismatch,
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn overloads() {
+        use crate::osc::OscType;
+        use crate::value::Range;
+
+        let root = Root::new(None);
+        let level = Arc::new(Atomic::new(0f32));
+        let index = Arc::new(Atomic::new(0i32));
+        let indexed = Arc::new(Atomic::new(0f32));
+        let name = Arc::new(MutexValue::new(String::new()));
+        let m = crate::node::Set::new(
+            "voice",
+            None,
+            vec![ParamSet::Float(
+                ValueBuilder::new(level.clone() as _).build(),
+            )],
+            None,
+        )
+        .unwrap()
+        .with_overload(vec![
+            ParamSet::Int(
+                ValueBuilder::new(index.clone() as _)
+                    .with_range(Range::MinMax(0, 7))
+                    .build(),
+            ),
+            ParamSet::Float(ValueBuilder::new(indexed.clone() as _).build()),
+        ])
+        .with_overload(vec![ParamSet::String(
+            ValueBuilder::new(name.clone() as _).build(),
+        )]);
+        root.add_node(m, None).unwrap();
+
+        let json = serde_json::to_value(&root).unwrap();
+        let voice = &json["CONTENTS"]["voice"];
+        assert_eq!(voice["TYPE"], "f");
+        assert_eq!(voice["OVERLOADS"][0]["TYPE"], "if");
+        assert_eq!(voice["OVERLOADS"][0]["RANGE"][0]["MIN"], 0);
+        assert_eq!(voice["OVERLOADS"][0]["RANGE"][0]["MAX"], 7);
+        assert_eq!(voice["OVERLOADS"][1]["TYPE"], "s");
+        assert_eq!(voice["OVERLOADS"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            root.read_locked()
+                .unwrap()
+                .with_node_at_path("/voice", |n| n.unwrap().0.node.overload_type_strings()),
+            vec!["if".to_string(), "s".to_string()]
+        );
+
+        let send = |args: Vec<OscType>| {
+            let packet = OscPacket::Message(OscMessage {
+                addr: "/voice".to_string(),
+                args,
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        };
+        send(vec![OscType::Float(0.5)]);
+        assert_eq!(level.load(Ordering::SeqCst), 0.5);
+        send(vec![OscType::Int(3), OscType::Float(0.25)]);
+        assert_eq!(index.load(Ordering::SeqCst), 3);
+        assert_eq!(indexed.load(Ordering::SeqCst), 0.25);
+        assert_eq!(level.load(Ordering::SeqCst), 0.5);
+        send(vec![OscType::String("lead".into())]);
+        assert_eq!(crate::value::Get::get(&*name), "lead");
+        //no exact match, applied to the node's parameters, mismatches skipped
+        send(vec![OscType::Float(0.75), OscType::Float(0.1)]);
+        assert_eq!(level.load(Ordering::SeqCst), 0.75);
+        assert_eq!(indexed.load(Ordering::SeqCst), 0.25);
+        send(vec![OscType::Int(5)]);
+        assert_eq!(index.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn defaults() {
+        use crate::osc::OscType;
+        use std::sync::Mutex;
+
+        struct SetOnly(Mutex<Vec<i32>>);
+        impl crate::value::Set<i32> for SetOnly {
+            fn set(&self, v: i32) {
+                self.0.lock().unwrap().push(v);
+            }
+        }
+
+        let root = Root::new(None);
+        let a = root
+            .add_node(Container::new("a", None).unwrap(), None)
+            .unwrap();
+        let gain = Arc::new(Atomic::new(0.2f32));
+        let pan = Arc::new(Atomic::new(0.3f32));
+        let level = Arc::new(Atomic::new(4));
+        let set_only = Arc::new(SetOnly(Mutex::new(Vec::new())));
+        root.add_node(
+            crate::node::GetSet::new(
+                "mix",
+                None,
+                vec![
+                    ParamGetSet::Float(
+                        ValueBuilder::new(gain.clone() as _)
+                            .with_range(Range::MinMax(0.0, 0.5))
+                            .with_clip_mode(ClipMode::Both)
+                            .with_default(0.75)
+                            .build(),
+                    ),
+                    ParamGetSet::Float(ValueBuilder::new(pan.clone() as _).build()),
+                ],
+                None,
+            )
+            .unwrap(),
+            Some(a),
+        )
+        .unwrap();
+        root.add_node(
+            crate::node::Set::new(
+                "trig",
+                None,
+                vec![ParamSet::Int(
+                    ValueBuilder::new(set_only.clone() as _)
+                        .with_default(7)
+                        .build(),
+                )],
+                None,
+            )
+            .unwrap(),
+            Some(a),
+        )
+        .unwrap();
+        root.add_node(
+            crate::node::GetSet::new(
+                "level",
+                None,
+                vec![ParamGetSet::Int(
+                    ValueBuilder::new(level.clone() as _)
+                        .with_default(1)
+                        .build(),
+                )],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        //the first parameter is padded with a `Nil`
+        let second = Arc::new(Atomic::new(0));
+        root.add_node(
+            crate::node::GetSet::new(
+                "pair",
+                None,
+                vec![
+                    ParamGetSet::Int(ValueBuilder::new(Arc::new(Atomic::new(0)) as _).build()),
+                    ParamGetSet::Int(
+                        ValueBuilder::new(second.clone() as _)
+                            .with_default(2)
+                            .build(),
+                    ),
+                ],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(&root).unwrap();
+        assert_eq!(
+            json["CONTENTS"]["a"]["CONTENTS"]["mix"]["DEFAULT"],
+            json!([0.75, null])
+        );
+        assert_eq!(
+            json["CONTENTS"]["a"]["CONTENTS"]["trig"]["DEFAULT"],
+            json!([7])
+        );
+        assert_eq!(json["CONTENTS"]["a"].get("DEFAULT"), None);
+        assert_eq!(root.default_at_path("/level"), Some(vec![OscType::Int(1)]));
+        assert_eq!(root.default_at_path("/a/mix"), None);
+        assert_eq!(root.default_at_path("/a"), None);
+
+        //only the subtree, through the set path so the default is clipped
+        let report = root.reset_to_defaults(Some(a), false);
+        assert_eq!(
+            report,
+            ResetReport {
+                paths: vec!["/a/mix".to_string(), "/a/trig".to_string()],
+                reset: 2,
+                skipped: 1,
+                triggered: Vec::new(),
+            }
+        );
+        assert_eq!(gain.load(Ordering::SeqCst), 0.5);
+        assert_eq!(pan.load(Ordering::SeqCst), 0.3);
+        assert_eq!(*set_only.0.lock().unwrap(), vec![7]);
+        assert_eq!(level.load(Ordering::SeqCst), 4);
+
+        //the readable nodes that were written are rendered, the set only one isn't
+        level.store(4, Ordering::SeqCst);
+        let report = root.reset_to_defaults(None, true);
+        assert_eq!(report.paths.len(), 4);
+        assert_eq!(report.reset, 4);
+        assert_eq!(level.load(Ordering::SeqCst), 1);
+        assert_eq!(second.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            report.triggered,
+            vec![
+                OscMessage {
+                    addr: "/a/mix".to_string(),
+                    args: vec![OscType::Float(0.5), OscType::Float(0.3)],
+                },
+                OscMessage {
+                    addr: "/level".to_string(),
+                    args: vec![OscType::Int(1)],
+                },
+                OscMessage {
+                    addr: "/pair".to_string(),
+                    args: vec![OscType::Int(0), OscType::Int(2)],
+                },
+            ]
+        );
+        //the padding of the parameter without a default isn't an unsupported argument
+        assert_eq!(root.stats().osc_unsupported_args(), 0);
+
+        //defaults that would be rejected are reported as skipped
+        let bad = Arc::new(Atomic::new(0.5f32));
+        root.add_node(
+            crate::node::GetSet::new(
+                "bad",
+                None,
+                vec![ParamGetSet::Float(
+                    ValueBuilder::new(bad.clone() as _)
+                        .with_default(f32::NAN)
+                        .build(),
+                )],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        root.set_non_finite_policy(NonFinitePolicy::RejectWrite);
+        let report = root.reset_to_defaults(None, false);
+        assert_eq!(report.paths.len(), 4);
+        assert_eq!(report.reset, 4);
+        assert_eq!(report.skipped, 3);
+        assert_eq!(bad.load(Ordering::SeqCst), 0.5);
+    }
+
+    #[test]
+    fn replace_node() {
+        let root = Root::new(None);
+        let c = root
+            .add_node(Container::new("c", None).unwrap(), None)
+            .unwrap();
+        let leaf = || {
+            crate::node::Get::new(
+                "c",
+                Some("leaf"),
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(1i32)) as _).build(),
+                )],
+            )
+            .unwrap()
+        };
+
+        //childless containers can become leafs and back
+        assert_matches!(root.replace_node(&c, leaf()), Ok(Node::Container(..)));
+        assert_matches!(
+            root.replace_node(&c, Container::new("c", None).unwrap()),
+            Ok(Node::Get(..))
+        );
+
+        //children are kept, so the container has to stay one
+        let child = root
+            .add_node(Container::new("child", None).unwrap(), Some(c))
+            .unwrap();
+        assert_matches!(
+            root.replace_node(&c, leaf()),
+            Err(OscQueryError::NodeNotAdded { node, reason })
+                if matches!(*node, Node::Get(..)) && matches!(*reason, OscQueryError::HasChildren)
+        );
+        assert_matches!(
+            root.replace_node(&c, Container::new("c", Some("described")).unwrap()),
+            Ok(Node::Container(..))
+        );
+        assert_eq!(root.handle_to_path(&child), Some("/c/child".to_string()));
+        let j = serde_json::to_value(&root).unwrap();
+        assert_eq!(j["CONTENTS"]["c"]["DESCRIPTION"], "described");
+        assert!(j["CONTENTS"]["c"]["CONTENTS"]["child"].is_object());
+
+        //aliases keep a container parent and a leaf target
+        let e = root
+            .add_node(Container::new("e", None).unwrap(), None)
+            .unwrap();
+        let d = root.add_node(leaf(), Some(child)).unwrap();
+        let alias = root.add_alias(d, Some(e), "alias").unwrap();
+        let leaf_e = crate::node::Get::new(
+            "e",
+            None,
+            vec![ParamGet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(1i32)) as _).build(),
+            )],
+        )
+        .unwrap();
+        assert_matches!(
+            root.replace_node(&e, leaf_e),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(*reason, OscQueryError::AliasParentNotContainer)
+        );
+        assert_matches!(
+            root.replace_node(&d, Container::new("c", None).unwrap()),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(*reason, OscQueryError::AliasTargetIsContainer)
+        );
+        root.rm_alias(alias).unwrap();
+        assert_matches!(
+            root.replace_node(&d, Container::new("c", None).unwrap()),
+            Ok(Node::Get(..))
+        );
+        assert_eq!(root.validate(), Ok(()));
+
+        let r = root.path_to_handle("/").unwrap();
+        assert_matches!(
+            root.replace_node(&r, Container::new("c", None).unwrap()),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(*reason, OscQueryError::RootNode)
+        );
+    }
+
+    #[test]
+    fn frozen() {
+        let root = Root::new(None);
+        let container = |name: &str| Container::new(name, None).unwrap();
+        let show = root.add_node(container("show"), None).unwrap();
+        let sub = root.add_node(container("sub"), Some(show)).unwrap();
+        let other = root.add_node(container("other"), None).unwrap();
+        let value = Arc::new(Atomic::new(0i32));
+        let m = crate::node::Set::new(
+            "a",
+            None,
+            vec![ParamSet::Int(ValueBuilder::new(value.clone() as _).build())],
+            Some(Box::new(crate::func_wrap::OscUpdateFunc(
+                move |_: &Vec<crate::osc::OscType>,
+                      _: Option<SocketAddr>,
+                      _: Option<BundleContext>,
+                      _: &NodeHandle| {
+                    Some(OscWriteCallback::new(move |graph| {
+                        graph
+                            .add_node(Container::new("added", None).unwrap().into(), Some(show))
+                            .map(|_| ())
+                            .map_err(|e| e.to_string())
+                    }))
+                },
+            ))),
+        )
+        .unwrap();
+        let a = root.add_node(m, Some(sub)).unwrap();
+        let send = |v: i32| {
+            let packet = OscPacket::Message(OscMessage {
+                addr: "/show/sub/a".to_string(),
+                args: vec![crate::osc::OscType::Int(v)],
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        };
+
+        let guard = root.lock_subtree(show).unwrap();
+        assert_matches!(
+            root.add_node(container("b"), Some(sub)),
+            Err(OscQueryError::NodeNotAdded { node, reason })
+                if node.address() == "b"
+                    && matches!(*reason, OscQueryError::Frozen(ref p) if p == "/show/sub")
+        );
+        assert_matches!(
+            root.add_node(container("b"), Some(show)),
+            Err(OscQueryError::NodeNotAdded { .. })
+        );
+        assert_matches!(root.rm_node(a), Err(OscQueryError::Frozen(p)) if p == "/show/sub/a");
+        assert_matches!(root.rm_node(show), Err(OscQueryError::Frozen(..)));
+        assert_matches!(
+            root.replace_node(&sub, container("sub")),
+            Err(OscQueryError::NodeNotAdded { .. })
+        );
+        assert_matches!(
+            root.add_alias(a, Some(sub), "alias"),
+            Err(OscQueryError::Frozen(..))
+        );
+        //outside of the subtree is fine, the target of an alias isn't changed
+        let alias = root.add_alias(a, Some(other), "alias").unwrap();
+        root.rm_alias(alias).unwrap();
+        root.add_node(container("b"), Some(other)).unwrap();
+
+        //values still flow, the callback fails
+        send(3);
+        assert_eq!(value.load(Ordering::SeqCst), 3);
+        assert!(root.path_to_handle("/show/added").is_none());
+        let failures = root.stats().recent_callback_failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].message, "node not added: \"/show\" is frozen");
+
+        //freezing within a frozen subtree, the container can't be removed while the inner one holds
+        //but is open again otherwise
+        root.freeze_subtree(&sub).unwrap();
+        drop(guard);
+        assert_matches!(root.rm_node(show), Err(OscQueryError::Frozen(p)) if p == "/show");
+        send(4);
+        assert!(root.path_to_handle("/show/added").is_some());
+        assert_eq!(value.load(Ordering::SeqCst), 4);
+        root.add_node(container("b"), Some(show)).unwrap();
+
+        root.unfreeze_subtree(&sub).unwrap();
+        assert_matches!(
+            root.unfreeze_subtree(&sub),
+            Err(OscQueryError::NodeNotInGraph)
+        );
+        root.rm_node(a).unwrap();
+        assert_eq!(root.rm_node(show).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn add_node_path_in_use() {
+        let root = Root::new(None);
+        let set = |address: &str| {
+            crate::node::Set::new(
+                address,
+                None,
+                vec![ParamSet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _).build(),
+                )],
+                None,
+            )
+            .unwrap()
+        };
+        let a = root.add_node(set("a"), None).unwrap();
+        root.add_alias(a, None, "b").unwrap();
+        assert_matches!(
+            root.add_node(set("b"), None),
+            Err(OscQueryError::NodeNotAdded { node, reason })
+                if node.address() == "b"
+                    && matches!(&*reason, OscQueryError::PathInUse(p) if p == "/b")
+        );
+        assert_matches!(
+            root.add_node(set("a"), None),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(&*reason, OscQueryError::PathInUse(p) if p == "/a")
+        );
+        //the root as the parent, like none
+        let top = root.path_to_handle("/").unwrap();
+        assert_matches!(
+            root.add_node(set("b"), Some(top)),
+            Err(OscQueryError::NodeNotAdded { .. })
+        );
+        let c = root.add_node(set("c"), Some(top)).unwrap();
+        assert_eq!(root.handle_to_path(&c), Some("/c".to_string()));
+
+        let json = serde_json::to_value(&root).unwrap();
+        assert_eq!(json["CONTENTS"].as_object().unwrap().len(), 3);
+        assert_eq!(root.validate(), Ok(()));
+    }
+
+    #[test]
+    fn ns_change_receivers() {
+        let root = Root::new(None);
+        let (a, b) = {
+            let mut inner = root.inner.write().unwrap();
+            (inner.ns_change_recv(), inner.ns_change_recv())
+        };
+        let foo = root
+            .add_node(Container::new("foo", None).unwrap(), None)
+            .unwrap();
+        root.add_node(Container::new("bar", None).unwrap(), Some(foo))
+            .unwrap();
+        root.rm_node(foo).unwrap();
+        let expected = vec![
+            NamespaceChange::PathAdded("/foo".into()),
+            NamespaceChange::PathAdded("/foo/bar".into()),
+            NamespaceChange::PathRemoved("/foo/bar".into()),
+            NamespaceChange::PathRemoved("/foo".into()),
+        ];
+        assert_eq!(a.try_iter().collect::<Vec<_>>(), expected);
+        assert_eq!(b.try_iter().collect::<Vec<_>>(), expected);
+
+        //the sender of a dropped receiver is removed with the next change
+        drop(a);
+        root.add_node(Container::new("baz", None).unwrap(), None)
+            .unwrap();
+        assert_eq!(
+            b.try_iter().collect::<Vec<_>>(),
+            vec![NamespaceChange::PathAdded("/baz".into())]
+        );
+        assert_eq!(
+            root.inner
+                .read()
+                .unwrap()
+                .ns_change_send
+                .lock()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn iter_paths() {
+        let root = Root::new(None);
+        assert_eq!(root.iter_paths(), vec!["/"]);
+        let container = |name: &str| Container::new(name, None).unwrap();
+        let a = root.add_node(container("a"), None).unwrap();
+        let b = root.add_node(container("b"), None).unwrap();
+        root.add_node(container("c"), Some(a)).unwrap();
+        let d = root.add_node(container("d"), Some(b)).unwrap();
+        root.add_node(container("e"), Some(d)).unwrap();
+
+        let mut paths = root.iter_paths();
+        paths.sort();
+        assert_eq!(paths, vec!["/", "/a", "/a/c", "/b", "/b/d", "/b/d/e"]);
+
+        assert_eq!(
+            root.iter_paths_depth_first(),
+            vec!["/", "/a", "/a/c", "/b", "/b/d", "/b/d/e"]
+        );
+    }
+
+    #[test]
+    fn for_each_node() {
+        let root = Root::new(None);
+        let container = |name: &str| Container::new(name, None).unwrap();
+        let b = root.add_node(container("b"), None).unwrap();
+        let a = root.add_node(container("a"), None).unwrap();
+        root.add_node(container("e"), Some(b)).unwrap();
+        let d = root.add_node(container("d"), Some(b)).unwrap();
+        root.add_node(container("c"), Some(a)).unwrap();
+        root.add_node(container("f"), Some(d)).unwrap();
+
+        let mut visited = Vec::new();
+        root.for_each_node(|path, node, handle| {
+            visited.push((path.to_string(), node.address().to_string(), handle));
+        });
+        let paths: Vec<&str> = visited.iter().map(|(p, _, _)| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["/", "/a", "/a/c", "/b", "/b/d", "/b/d/f", "/b/e"]
+        );
+        assert_eq!(visited[1], ("/a".to_string(), "a".to_string(), a));
+        assert_eq!(visited[4].2, d);
+        for (path, _, handle) in &visited {
+            assert_eq!(root.path_to_handle(path), Some(*handle));
+        }
+
+        assert_eq!(
+            root.paths(),
+            vec!["/", "/a", "/a/c", "/b", "/b/d", "/b/d/f", "/b/e"]
+        );
+
+        let depth_first = root.depth_first_iter();
+        assert_eq!(
+            depth_first,
+            visited
+                .into_iter()
+                .map(|(p, _, h)| (p, h))
+                .collect::<Vec<_>>()
+        );
+        let paths = |nodes: Vec<(String, NodeHandle)>| -> Vec<String> {
+            nodes.into_iter().map(|(p, _)| p).collect()
+        };
+        assert_eq!(
+            paths(root.breadth_first_iter()),
+            vec!["/", "/a", "/b", "/a/c", "/b/d", "/b/e", "/b/d/f"]
+        );
+        assert_eq!(root.breadth_first_iter()[2], ("/b".to_string(), b));
+    }
+
+    #[test]
+    fn validate() {
+        let root = Root::new(None);
+        let container = |name: &str| Container::new(name, None).unwrap();
+        let a = root.add_node(container("a"), None).unwrap();
+        let b = root.add_node(container("b"), Some(a)).unwrap();
+        root.add_node(container("c"), Some(b)).unwrap();
+        let d = root.add_node(container("d"), None).unwrap();
+        let g = crate::node::Get::new(
+            "g",
+            None,
+            vec![ParamGet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _).build(),
+            )],
+        )
+        .unwrap();
+        let g = root.add_node(g, None).unwrap();
+        root.add_alias(g, Some(d), "alias").unwrap();
+        assert_eq!(root.validate(), Ok(()));
+        root.rm_node(b).unwrap();
+        assert_eq!(root.validate(), Ok(()));
+
+        {
+            let mut inner = root.inner.write().unwrap();
+            let d = inner.index(&d).unwrap();
+            inner.index_map.insert("/e".to_string(), d);
+            let a = inner.index(&a).unwrap();
+            inner.graph[a].full_path = "/x".to_string();
+            let g = inner.index(&g).unwrap();
+            let alias = inner.alias_paths["/d/alias"];
+            inner.aliases.get_mut(&alias).unwrap().parent = g;
+            inner.alias_paths.insert("/g".to_string(), alias);
+        }
+        let problems = root.validate().unwrap_err();
+        assert_eq!(problems.len(), 7, "{:?}", problems);
+        for problem in &[
+            "is indexed at \"/a\" but has the path \"/x\"",
+            "is indexed at \"/e\" but has the path \"/d\"",
+            "is indexed at both",
+            "\"/x\" should have the path \"/a\"",
+            "the alias at \"/g\" has the path \"/d/alias\"",
+            "\"/g\" is both a node and an alias",
+            "the parent of the alias at \"/d/alias\" isn't a container",
+        ] {
+            assert!(problems.iter().any(|p| p.contains(problem)), "{}", problem);
+        }
+    }
+
+    #[test]
+    fn rm_node_at_path() {
+        let root = Root::new(None);
+        let container = |name: &str| Container::new(name, None).unwrap();
+        let a = root.add_node(container("a"), None).unwrap();
+        let c = root.add_node(container("c"), Some(a)).unwrap();
+        let b = root.add_node(container("b"), Some(a)).unwrap();
+        let d = root.add_node(container("d"), Some(b)).unwrap();
+
+        assert_eq!(
+            root.children(a),
+            Some(vec![("b".to_string(), b), ("c".to_string(), c)])
+        );
+        assert_eq!(root.children(d), Some(Vec::new()));
+        let top = root.path_to_handle("/").unwrap();
+        assert_eq!(root.children(top), Some(vec![("a".to_string(), a)]));
+
+        assert_matches!(root.rm_node_at_path("/"), Err(OscQueryError::RootNode));
+        assert_matches!(root.rm_node(top), Err(OscQueryError::RootNode));
+        assert_matches!(
+            root.rm_node_at_path("/a/nope"),
+            Err(OscQueryError::NodeNotInGraph)
+        );
+
+        //leafs first
+        let removed: Vec<String> = root
+            .rm_node_at_path("a/b")
+            .unwrap()
+            .iter()
+            .map(|n| n.address().to_string())
+            .collect();
+        assert_eq!(removed, vec!["d", "b"]);
+        assert_eq!(root.iter_paths().len(), 3);
+        assert_eq!(root.children(a), Some(vec![("c".to_string(), c)]));
+        assert_eq!(root.children(b), None);
+
+        assert_eq!(root.rm_node_at_path("/a").unwrap().len(), 2);
+        assert_eq!(root.iter_paths(), vec!["/"]);
+        assert_eq!(root.children(top), Some(Vec::new()));
+    }
+
+    #[test]
+    fn add_node_at_path() {
+        let root = Root::new(None);
+        let freq = || {
+            crate::node::Set::new(
+                "freq",
+                None,
+                vec![ParamSet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _).build(),
+                )],
+                None,
+            )
+            .unwrap()
+        };
+        let synth = root
+            .add_node(Container::new("synth", Some("a synth")).unwrap(), None)
+            .unwrap();
+        let generation = root.generation();
+        let ns = root.inner.write().unwrap().ns_change_recv();
+
+        let f = root.add_node_at_path(freq(), "/synth/osc1/freq").unwrap();
+        assert_eq!(root.path_to_handle("/synth/osc1/freq"), Some(f));
+        assert_eq!(
+            root.paths(),
+            vec!["/", "/synth", "/synth/osc1", "/synth/osc1/freq"]
+        );
+        let osc1 = root.path_to_handle("/synth/osc1").unwrap();
+        assert_eq!(root.children(synth), Some(vec![("osc1".to_string(), osc1)]));
+        assert_eq!(
+            ns.try_iter().collect::<Vec<_>>(),
+            vec![
+                NamespaceChange::PathAdded("/synth/osc1".into()),
+                NamespaceChange::PathAdded("/synth/osc1/freq".into())
+            ]
+        );
+        let diff = root.contents_since("/synth", generation).unwrap();
+        assert_eq!(diff.added, vec!["osc1"]);
+
+        //the existing containers are used, the leading slash is optional
+        let f2 = root.add_node_at_path(freq(), "synth/osc2/freq").unwrap();
+        assert_eq!(root.path_to_handle("/synth/osc2/freq"), Some(f2));
+        root.add_node_at_path(freq(), "/freq").unwrap();
+
+        let paths = root.paths();
+        assert_matches!(
+            root.add_node_at_path(freq(), "/synth/osc1/freq"),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(&*reason, OscQueryError::PathInUse(p) if p == "/synth/osc1/freq")
+        );
+        assert_matches!(
+            root.add_node_at_path(freq(), "/synth/osc1/freq/more/freq"),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(&*reason, OscQueryError::NotAContainer(p) if p == "/synth/osc1/freq")
+        );
+        assert_matches!(
+            root.add_node_at_path(freq(), "/synth/osc3/gain"),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(*reason, OscQueryError::InvalidPath(..))
+        );
+        root.add_alias(f, Some(synth), "alias").unwrap();
+        assert_matches!(
+            root.add_node_at_path(freq(), "/synth/alias/x/freq"),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(&*reason, OscQueryError::PathInUse(p) if p == "/synth/alias")
+        );
+        let guard = root.lock_subtree(synth).unwrap();
+        assert_matches!(
+            root.add_node_at_path(freq(), "/synth/osc3/freq"),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(*reason, OscQueryError::Frozen(..))
+        );
+        drop(guard);
+        //nothing was created by the failures
+        assert_eq!(root.paths(), paths);
+        assert_eq!(root.validate(), Ok(()));
+    }
+
+    #[test]
+    fn graph_defaults() {
+        //a graph with only the required methods and a path lookup
+        struct Minimal<'a>(&'a mut RootInner);
+        impl OscQueryGraph for Minimal<'_> {
+            fn add_node(
+                &mut self,
+                node: Node,
+                parent: Option<NodeHandle>,
+            ) -> Result<NodeHandle, OscQueryError> {
+                self.0.add_node(node, parent)
             }
-            Some(NodeQueryParam::Access) => {
-                let mut m = serializer.serialize_map(None)?;
-                m.serialize_entry("ACCESS".into(), &n.access())?;
-                m.end()
+            fn rm_node(&mut self, handle: NodeHandle) -> Result<Vec<Node>, OscQueryError> {
+                self.0.rm_node(handle)
             }
-            Some(NodeQueryParam::Description) => {
-                let mut m = serializer.serialize_map(None)?;
-                m.serialize_entry("DESCRIPTION".into(), n.description())?;
-                m.end()
+            fn path_to_handle(&self, path: &str) -> Option<NodeHandle> {
+                self.0.path_to_handle(path)
             }
-            Some(NodeQueryParam::Value) => match n {
-                Node::Get(..) | Node::GetSet(..) => {
-                    let mut m = serializer.serialize_map(None)?;
-                    m.serialize_entry("VALUE".into(), &NodeValueWrapper(n))?;
-                    m.end()
-                }
-                _ => serializer.serialize_none(),
-            },
-            Some(NodeQueryParam::Range) => match n {
-                Node::Container(..) => serializer.serialize_none(),
-                _ => {
-                    let mut m = serializer.serialize_map(None)?;
-                    m.serialize_entry("RANGE".into(), &NodeRangeWrapper(n))?;
-                    m.end()
-                }
-            },
-            Some(NodeQueryParam::ClipMode) => match n {
-                Node::Container(..) => serializer.serialize_none(),
-                _ => {
-                    let mut m = serializer.serialize_map(None)?;
-                    m.serialize_entry("CLIPMODE".into(), &NodeClipModeWrapper(n))?;
-                    m.end()
-                }
-            },
-            Some(NodeQueryParam::Type) => match n {
-                Node::Container(..) => serializer.serialize_none(),
-                _ => {
-                    let mut m = serializer.serialize_map(None)?;
-                    m.serialize_entry("TYPE".into(), &n.type_string())?;
-
-                    m.end()
-                }
-            },
-            Some(NodeQueryParam::Unit) => match n {
-                Node::Container(..) => serializer.serialize_none(),
-                _ => {
-                    let mut m = serializer.serialize_map(None)?;
-                    m.serialize_entry("UNIT".into(), &NodeUnitWrapper(n))?;
-                    m.end()
-                }
-            },
         }
+        let root = Root::new(None);
+        let mut inner = root.inner.write().unwrap();
+        let mut graph = Minimal(&mut inner);
+        let freq = crate::node::Set::new(
+            "freq",
+            None,
+            vec![ParamSet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _).build(),
+            )],
+            None,
+        )
+        .unwrap();
+
+        let f = graph
+            .add_node_at_path(freq.into(), "/synth/osc1/freq")
+            .unwrap();
+        assert_eq!(graph.path_to_handle("/synth/osc1/freq"), Some(f));
+        let synth = graph.path_to_handle("/synth").unwrap();
+        assert_eq!(graph.children(synth), None);
+        assert_eq!(graph.rm_node_at_path("/synth/osc1").unwrap().len(), 2);
+        assert_matches!(
+            graph.rm_node_at_path("/synth/osc1"),
+            Err(OscQueryError::NodeNotInGraph)
+        );
+        drop(inner);
+        assert_eq!(root.paths(), vec!["/", "/synth"]);
     }
-}
 
-impl<'a> Serialize for NodeSerializeContentsWrapper<'a> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut m = serializer.serialize_map(None)?;
-        let mut neighbors = self.neighbors.clone();
-        while let Some(index) = neighbors.next_node(self.graph) {
-            if let Some(node) = self.graph.node_weight(index) {
-                let w = NodeSerializeWrapper {
-                    node: &node,
-                    graph: self.graph,
-                    neighbors: self.graph.neighbors(index).detach(),
-                    param: None,
-                };
-                m.serialize_entry(&node.node.address(), &w)?;
-            }
+    #[test]
+    fn path_to_handle() {
+        let root = Root::new(None);
+        let foo = root
+            .add_node(Container::new("foo", None).unwrap(), None)
+            .unwrap();
+        let bar = root
+            .add_node(Container::new("bar", None).unwrap(), Some(foo))
+            .unwrap();
+        for (path, handle) in &[
+            ("/foo", Some(foo)),
+            ("/foo/bar", Some(bar)),
+            ("foo/bar", Some(bar)),
+            ("foo", Some(foo)),
+            ("/foo/baz", None),
+            ("bar", None),
+        ] {
+            assert_eq!(root.path_to_handle(path), *handle, "{}", path);
         }
-        m.end()
+        assert_eq!(
+            root.path_to_handle("/")
+                .and_then(|h| root.handle_to_path(&h)),
+            Some("/".to_string())
+        );
+        let path = root.handle_to_path(&bar).unwrap();
+        assert_eq!(root.path_to_handle(&path), Some(bar));
+
+        root.rm_node(bar).unwrap();
+        assert_eq!(root.path_to_handle("/foo/bar"), None);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn wrong_root() {
+        let (a, b) = (Root::new(None), Root::new(None));
+        let get = |name: &str| {
+            crate::node::Get::new(
+                name,
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(0)) as _).build(),
+                )],
+            )
+            .unwrap()
+        };
+        //the same index in both graphs
+        let ha = a.add_node(get("a"), None).unwrap();
+        let hb = b.add_node(get("b"), None).unwrap();
+        assert_ne!(ha, hb);
 
-    use crate::param::*;
+        assert_matches!(
+            b.add_node(Container::new("c", None).unwrap(), Some(ha)),
+            Err(OscQueryError::NodeNotAdded { node, reason })
+                if node.address() == "c" && matches!(*reason, OscQueryError::WrongRoot)
+        );
+        assert_matches!(
+            b.replace_node(&ha, get("b")),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(*reason, OscQueryError::WrongRoot)
+        );
+        assert_matches!(b.rm_node(ha), Err(OscQueryError::WrongRoot));
+        assert_matches!(b.set_osc_address(&ha, None), Err(OscQueryError::WrongRoot));
+        assert_matches!(
+            b.add_alias(ha, None, "alias"),
+            Err(OscQueryError::WrongRoot)
+        );
+        assert_matches!(b.add_alias(hb, Some(ha), "alias"), Err(..));
+        assert_matches!(b.freeze_subtree(&ha), Err(OscQueryError::WrongRoot));
+        assert_matches!(b.lock_subtree(ha).err(), Some(OscQueryError::WrongRoot));
+        assert_eq!(b.handle_to_path(&ha), None);
+        assert_eq!(b.render(&ha), None);
+        b.touch(&ha);
+        b.notify_changed(&ha);
+        b.enable_storage_index();
+        assert!(b.nodes_sharing_storage(ha, 0).is_empty());
+        let osc = b.spawn_osc("127.0.0.1:0").unwrap();
+        b.set_force_trigger(true);
+        assert!(osc.trigger(ha).is_none());
+        assert!(osc.trigger(hb).is_some());
 
-    use crate::value::*;
-    use ::atomic::Atomic;
-    use std::sync::Arc;
-    use std::thread;
+        //nothing changed in either root, the handles still work with their own
+        assert_eq!(b.handle_to_path(&hb), Some("/b".to_string()));
+        assert_eq!(b.path_to_handle("/b"), Some(hb));
+        assert_eq!(b.nodes_sharing_storage(hb, 0), vec![hb]);
+        assert_eq!(a.handle_to_path(&ha), Some("/a".to_string()));
+        assert_eq!(a.rm_node(ha).unwrap().len(), 1);
+        assert_eq!(b.rm_node(hb).unwrap().len(), 1);
+    }
 
     #[test]
-    fn basic_expectations() {
-        let root = Root::new(Some("test".into()));
+    fn move_node() {
+        let root = Root::new(None);
+        let container = |name: &str| Container::new(name, None).unwrap();
+        let mixer = root.add_node(container("mixer"), None).unwrap();
+        let channel = root.add_node(container("channel1"), Some(mixer)).unwrap();
+        let gain = Arc::new(Atomic::new(0i32));
+        let g = root
+            .add_node(
+                crate::node::Set::new(
+                    "gain",
+                    None,
+                    vec![ParamSet::Int(ValueBuilder::new(gain.clone() as _).build())],
+                    None,
+                )
+                .unwrap(),
+                Some(channel),
+            )
+            .unwrap();
+        let outputs = root.add_node(container("outputs"), None).unwrap();
+        let alias = root.add_alias(g, Some(channel), "level").unwrap();
+        let generation = root.generation();
+        let ns = root.inner.write().unwrap().ns_change_recv();
 
-        let c = Container::new("foo", Some("description of foo".into()));
-        assert!(c.is_ok());
+        assert_eq!(root.move_node(channel, Some(outputs)).unwrap(), channel);
+        let mut paths = root.iter_paths();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "/",
+                "/mixer",
+                "/outputs",
+                "/outputs/channel1",
+                "/outputs/channel1/gain"
+            ]
+        );
+        assert_eq!(
+            root.handle_to_path(&g),
+            Some("/outputs/channel1/gain".to_string())
+        );
+        assert_eq!(
+            root.alias_path(alias),
+            Some("/outputs/channel1/level".to_string())
+        );
+        assert_eq!(
+            ns.try_iter().collect::<Vec<_>>(),
+            vec![NamespaceChange::PathRenamed(
+                "/mixer/channel1".into(),
+                "/outputs/channel1".into()
+            )]
+        );
+        let diff = root.contents_since("/mixer", generation).unwrap();
+        assert_eq!(diff.removed, vec!["channel1"]);
+        let diff = root.contents_since("/outputs", generation).unwrap();
+        assert_eq!(diff.added, vec!["channel1"]);
 
-        let res = root.add_node(c.unwrap(), None);
-        assert!(res.is_ok());
+        //OSC follows the move
+        let send = |addr: &str, v: i32| {
+            let packet = OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![crate::osc::OscType::Int(v)],
+            });
+            RootInner::handle_osc_packet(&root.inner, &packet, None, None);
+        };
+        send("/mixer/channel1/gain", 1);
+        assert_eq!(gain.load(Ordering::SeqCst), 0);
+        send("/outputs/channel1/gain", 2);
+        assert_eq!(gain.load(Ordering::SeqCst), 2);
+        send("/outputs/channel1/level", 3);
+        assert_eq!(gain.load(Ordering::SeqCst), 3);
+        let json = serde_json::to_value(&root).unwrap();
+        assert_eq!(
+            json["CONTENTS"]["outputs"]["CONTENTS"]["channel1"]["CONTENTS"]["gain"]["FULL_PATH"],
+            "/outputs/channel1/gain"
+        );
 
-        let chandle = res.unwrap();
-        assert_eq!(Some("/foo".to_string()), root.handle_to_path(&chandle));
+        //to the root, and back
+        root.move_node(g, None).unwrap();
+        assert_eq!(root.path_to_handle("/gain"), Some(g));
+        root.move_node(g, Some(channel)).unwrap();
+        assert_eq!(root.path_to_handle("/outputs/channel1/gain"), Some(g));
 
-        let c = Container::new("bar", Some("description of foo".into()));
-        assert!(c.is_ok());
+        assert_matches!(
+            root.move_node(outputs, Some(channel)),
+            Err(OscQueryError::MoveUnderItself)
+        );
+        assert_matches!(
+            root.move_node(channel, Some(channel)),
+            Err(OscQueryError::MoveUnderItself)
+        );
+        root.add_node(container("channel1"), Some(mixer)).unwrap();
+        assert_matches!(
+            root.move_node(channel, Some(mixer)),
+            Err(OscQueryError::PathInUse(p)) if p == "/mixer/channel1"
+        );
+        let guard = root.lock_subtree(mixer).unwrap();
+        assert_matches!(
+            root.move_node(outputs, Some(mixer)),
+            Err(OscQueryError::Frozen(..))
+        );
+        drop(guard);
+        root.move_node(outputs, Some(mixer)).unwrap();
+        assert_eq!(
+            root.handle_to_path(&g),
+            Some("/mixer/outputs/channel1/gain".to_string())
+        );
+
+        //only containers take children
+        assert_matches!(
+            root.move_node(mixer, Some(g)),
+            Err(OscQueryError::NotAContainer(p)) if p == "/mixer/outputs/channel1/gain"
+        );
+        assert_eq!(root.path_to_handle("/mixer"), Some(mixer));
+    }
+
+    #[test]
+    fn rename_node() {
+        let root = Root::new(None);
+        let container = |name: &str| Container::new(name, None).unwrap();
+        let mixer = root.add_node(container("mixer"), None).unwrap();
+        let channel = root.add_node(container("channel1"), Some(mixer)).unwrap();
+        let gain = root
+            .add_node(
+                crate::node::Set::new(
+                    "gain",
+                    None,
+                    vec![ParamSet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _).build(),
+                    )],
+                    None,
+                )
+                .unwrap(),
+                Some(channel),
+            )
+            .unwrap();
+        let alias = root.add_alias(gain, Some(channel), "level").unwrap();
+        root.add_node(container("channel2"), Some(mixer)).unwrap();
+        let generation = root.generation();
+        let ns = root.inner.write().unwrap().ns_change_recv();
 
-        let res = root.add_node(c.unwrap(), Some(chandle));
-        assert!(res.is_ok());
         assert_eq!(
-            Some("/foo/bar".to_string()),
-            root.handle_to_path(&res.unwrap())
+            root.rename_node(channel, "left".to_string()).unwrap(),
+            ("/mixer/channel1".to_string(), "/mixer/left".to_string())
+        );
+        assert_eq!(
+            root.paths(),
+            vec![
+                "/",
+                "/mixer",
+                "/mixer/channel2",
+                "/mixer/left",
+                "/mixer/left/gain"
+            ]
+        );
+        assert_eq!(root.path_to_handle("/mixer/left"), Some(channel));
+        assert_eq!(root.path_to_handle("/mixer/left/gain"), Some(gain));
+        assert_eq!(root.path_to_handle("/mixer/channel1/gain"), None);
+        assert_eq!(
+            root.alias_path(alias),
+            Some("/mixer/left/level".to_string())
+        );
+        assert_eq!(
+            ns.try_iter().collect::<Vec<_>>(),
+            vec![NamespaceChange::PathRenamed(
+                "/mixer/channel1".into(),
+                "/mixer/left".into()
+            )]
+        );
+        let diff = root.contents_since("/mixer", generation).unwrap();
+        assert_eq!(diff.removed, vec!["channel1"]);
+        assert_eq!(diff.added, vec!["left"]);
+        let json = serde_json::to_value(&root).unwrap();
+        assert_eq!(
+            json["CONTENTS"]["mixer"]["CONTENTS"]["left"]["CONTENTS"]["gain"]["FULL_PATH"],
+            "/mixer/left/gain"
         );
+        assert_eq!(root.validate(), Ok(()));
 
-        let a = Arc::new(Atomic::new(2084i32));
+        //the same address changes nothing
+        let generation = root.generation();
+        root.rename_node(channel, "left".to_string()).unwrap();
+        assert_eq!(root.generation(), generation);
+        assert!(ns.try_recv().is_err());
+
+        assert_matches!(
+            root.rename_node(channel, "channel2".to_string()),
+            Err(OscQueryError::PathInUse(p)) if p == "/mixer/channel2"
+        );
+        assert_matches!(
+            root.rename_node(gain, "level".to_string()),
+            Err(OscQueryError::PathInUse(p)) if p == "/mixer/left/level"
+        );
+        assert_matches!(
+            root.rename_node(channel, "a/b".to_string()),
+            Err(OscQueryError::InvalidAddress(..))
+        );
+        assert_matches!(
+            root.rename_node(root.path_to_handle("/").unwrap(), "x".to_string()),
+            Err(OscQueryError::RootNode)
+        );
+        assert_eq!(root.path_to_handle("/mixer/left"), Some(channel));
+    }
+
+    #[test]
+    fn storage_index() {
+        let root = Root::new(None);
+        let shared = Arc::new(Atomic::new(1i32));
+        let other = Arc::new(Atomic::new(2i32));
+
+        let get = root
+            .add_node(
+                crate::node::Get::new(
+                    "get",
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(shared.clone() as _).build(),
+                    )],
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        assert!(root.nodes_sharing_storage(get, 0).is_empty());
+
+        //existing nodes are indexed when enabled
+        root.enable_storage_index();
+        let getset = root
+            .add_node(
+                crate::node::GetSet::new(
+                    "getset",
+                    None,
+                    vec![
+                        ParamGetSet::Int(ValueBuilder::new(other.clone() as _).build()),
+                        ParamGetSet::Int(ValueBuilder::new(shared.clone() as _).build()),
+                    ],
+                    None,
+                )
+                .unwrap(),
+                Some(get),
+            )
+            .unwrap();
+
+        assert_eq!(vec![get, getset], root.nodes_sharing_storage(get, 0));
+        assert_eq!(vec![get, getset], root.nodes_sharing_storage(getset, 1));
+        assert_eq!(vec![getset], root.nodes_sharing_storage(getset, 0));
+        assert!(root.nodes_sharing_storage(getset, 2).is_empty());
+
+        let map = root.storage_map();
+        assert_eq!(2, map.len());
+        assert!(map.contains(&(StorageId::of(&shared), vec![get, getset])));
+        assert!(map.contains(&(StorageId::of(&other), vec![getset])));
+
+        assert!(root.rm_node(getset).is_ok());
+        assert_eq!(vec![get], root.nodes_sharing_storage(get, 0));
+        assert_eq!(
+            vec![(StorageId::of(&shared), vec![get])],
+            root.storage_map()
+        );
+
+        //the index doesn't keep the storage alive
+        assert!(root.rm_node(get).is_ok());
+        assert!(root.storage_map().is_empty());
+        assert_eq!(1, Arc::strong_count(&shared));
+        assert_eq!(1, Arc::strong_count(&other));
+    }
+
+    #[test]
+    fn expiry() {
+        let root = Root::new(None);
+        let a = Arc::new(Atomic::new(5i32));
         let m = crate::node::Get::new(
-            "baz",
+            "sensor",
             None,
             vec![ParamGet::Int(ValueBuilder::new(a.clone() as _).build())],
-        );
+        )
+        .unwrap()
+        .with_expires_after(Some(Duration::from_millis(50)));
+        let handle = root.add_node(m, None).unwrap();
+        let value = || serde_json::to_value(&root).unwrap()["CONTENTS"]["sensor"]["VALUE"].clone();
 
-        //can add a method
-        let res = root.add_node(m.unwrap(), Some(chandle));
-        assert!(res.is_ok());
+        assert_eq!(value(), json!([5]));
+        assert!(root.render(&handle).is_some());
+        assert!(root.stale_paths().is_empty());
 
-        let mhandle = res.unwrap();
-        assert_eq!(Some("/foo/baz".to_string()), root.handle_to_path(&mhandle));
+        thread::sleep(Duration::from_millis(80));
+        assert_eq!(value(), serde_json::Value::Null);
+        assert!(root.render(&handle).is_none());
+        assert!(root.render_path("/sensor").is_none());
+        assert_eq!(root.stale_paths(), vec!["/sensor".to_string()]);
 
-        //okay to add method to method
-        let m = crate::node::GetSet::new(
-            &"biz",
-            None,
-            vec![ParamGetSet::Int(ValueBuilder::new(a.clone() as _).build())],
-            None,
+        a.store(6, ::atomic::Ordering::SeqCst);
+        root.touch(&handle);
+        assert_eq!(value(), json!([6]));
+        assert_eq!(
+            root.render(&handle).map(|m| m.args),
+            Some(vec![crate::osc::OscType::Int(6)])
         );
+        assert!(root.stale_paths().is_empty());
+    }
 
-        let res = root.add_node(m.unwrap(), Some(mhandle));
-        assert!(res.is_ok());
+    #[test]
+    fn expiry_clock() {
+        use crate::time::{Clock, ManualClock};
+        use std::time::SystemTime;
 
-        //can remove a method
-        let handle = res.unwrap();
-        let res = root.rm_node(handle.clone());
-        assert!(res.is_ok());
-        let v = res.unwrap();
-        assert_eq!(1, v.len());
-        //second attempt gives error
-        assert!(root.rm_node(handle.clone()).is_err());
+        let clock = Arc::new(ManualClock::new());
+        let root = Root::new(None);
+        root.set_clock(clock.clone());
+        let m = crate::node::Get::new(
+            "sensor",
+            None,
+            vec![ParamGet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(5i32)) as _).build(),
+            )],
+        )
+        .unwrap()
+        .with_expires_after(Some(Duration::from_secs(10)));
+        let handle = root.add_node(m, None).unwrap();
 
-        //can remove the top
-        let res = root.rm_node(chandle);
-        assert!(res.is_ok());
-        let v = res.unwrap();
-        assert_eq!(3, v.len());
+        //wall clock jumps don't matter
+        clock.set_wall(SystemTime::UNIX_EPOCH);
+        assert!(root.render(&handle).is_some());
+        clock.set_wall(clock.wall() + Duration::from_secs(3600));
+        assert!(root.render(&handle).is_some());
+        assert!(root.stale_paths().is_empty());
 
-        //come out with leaf first
-        assert_eq!(&"baz", v[0].address());
-        assert_eq!(&"bar", v[1].address());
-        assert_eq!(&"foo", v[2].address());
+        clock.advance(Duration::from_secs(11));
+        assert!(root.render(&handle).is_none());
+        assert_eq!(root.stale_paths(), vec!["/sensor".to_string()]);
+        root.touch(&handle);
+        assert!(root.render(&handle).is_some());
     }
 
     #[test]
-    fn is_send_and_sync() {
+    fn visit_chunked() {
+        use std::collections::HashSet;
+        use std::sync::atomic::AtomicUsize;
+
         let root = Arc::new(Root::new(None));
+        let leaf = |name: String| {
+            crate::node::Get::new(
+                name,
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _).build(),
+                )],
+            )
+            .unwrap()
+        };
+        for i in 0..2000 {
+            root.add_node(leaf(format!("n{}", i)), None).unwrap();
+        }
 
-        let c = Container::new("foo", Some("description of foo"));
-        assert!(c.is_ok());
+        //a writer started during the visit gets in between two chunks, it records how many
+        //nodes had been visited while it holds the lock
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut writer = None;
+        let mut visited = HashSet::new();
+        let res = root.visit_chunked(32, |path, _| {
+            assert!(visited.insert(path.to_string()), "{} visited twice", path);
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if writer.is_none() {
+                let root = root.clone();
+                let calls = calls.clone();
+                writer = Some(thread::spawn(move || {
+                    let _inner = root.inner.write().unwrap();
+                    calls.load(std::sync::atomic::Ordering::SeqCst)
+                }));
+            }
+            thread::sleep(Duration::from_micros(20));
+            ControlFlow::Continue(())
+        });
+        let written_at = writer.unwrap().join().unwrap();
 
-        let a = Arc::new(Atomic::new(2084i32));
-        let m = crate::node::Set::new(
-            "baz",
-            None,
-            vec![ParamSet::Int(ValueBuilder::new(a.clone() as _).build())],
-            None,
+        assert_eq!(res, ControlFlow::Continue(()));
+        assert!(visited.contains("/"));
+        assert!((0..2000).all(|i| visited.contains(&format!("/n{}", i))));
+        //writers only wait for a chunk, not the whole visit
+        assert_eq!(written_at % 32, 0, "written after {} nodes", written_at);
+        assert!(
+            written_at < visited.len(),
+            "the writer waited for the whole visit"
         );
 
-        let r = root.clone();
-        let h = thread::spawn(move || {
-            let res = r.add_node(c.unwrap(), None);
-            assert!(res.is_ok());
-
-            let c = Container::new("bar", None);
-            assert!(c.is_ok());
-            let res = r.add_node(c.unwrap(), Some(res.unwrap()));
-            assert!(res.is_ok());
-
-            let res = r.add_node(m.unwrap(), Some(res.unwrap()));
-            assert!(res.is_ok());
+        let mut count = 0;
+        let res = root.visit_chunked(32, |_, _| {
+            count += 1;
+            if count == 10 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
         });
-        let c = Container::new("bar", None);
-        assert!(c.is_ok());
-        let res = root.add_node(c.unwrap(), None);
-        assert!(res.is_ok());
-
-        assert!(h.join().is_ok());
+        assert_eq!(res, ControlFlow::Break(()));
+        assert_eq!(count, 10);
     }
 
-    use serde_json::json;
-
     #[test]
     fn serialize() {
         let root = Arc::new(Root::new(Some("test".into())));
@@ -0,0 +1,567 @@
+//! Rebuilding a tree from its JSON namespace, as served by the HTTP service.
+use super::*;
+use crate::convert::{arg_from_json, default_arg, split_type_tags};
+use crate::osc::OscType;
+use crate::param::*;
+use crate::value::{ClipMode, MutexValue, Range, Value, ValueBuilder};
+use serde::de::{Deserialize, Deserializer, Error as _};
+use serde_json::{Map, Value as Json};
+
+//an alias found in CONTENTS, added once all the nodes are in place
+struct AliasRecord {
+    path: String,
+    parent: Option<NodeHandle>,
+    address: String,
+    target: String,
+}
+
+fn invalid(path: &str, what: &str) -> OscQueryError {
+    OscQueryError::InvalidDocument(format!("{} at {:?}", what, path))
+}
+
+//the entry for the parameter at the index of a per parameter attribute, like RANGE
+fn attribute<'a>(node: &'a Json, key: &str, index: usize) -> &'a Json {
+    node[key].get(index).unwrap_or(&Json::Null)
+}
+
+fn range<T, F>(path: &str, tag: &str, range: &Json, extract: &F) -> Result<Range<T>, OscQueryError>
+where
+    F: Fn(OscType) -> Option<T>,
+{
+    let bound = |key: &str| -> Result<Option<T>, OscQueryError> {
+        match &range[key] {
+            Json::Null => Ok(None),
+            v => arg_from_json(tag, v)
+                .and_then(extract)
+                .map(Some)
+                .ok_or_else(|| invalid(path, "invalid RANGE")),
+        }
+    };
+    if let Some(vals) = range["VALS"].as_array() {
+        return vals
+            .iter()
+            .map(|v| arg_from_json(tag, v).and_then(extract))
+            .collect::<Option<Vec<_>>>()
+            .map(Range::Vals)
+            .ok_or_else(|| invalid(path, "invalid RANGE"));
+    }
+    Ok(match (bound("MIN")?, bound("MAX")?) {
+        (Some(min), Some(max)) => Range::MinMax(min, max),
+        (Some(min), None) => Range::Min(min),
+        (None, Some(max)) => Range::Max(max),
+        (None, None) => Range::None,
+    })
+}
+
+//a value with the range, clip mode and unit of the parameter at the index
+fn value<V, T, F>(
+    storage: V,
+    path: &str,
+    tag: &str,
+    node: &Json,
+    index: usize,
+    extract: F,
+) -> Result<Value<V, T>, OscQueryError>
+where
+    F: Fn(OscType) -> Option<T>,
+{
+    let mut b = ValueBuilder::new(storage).with_range(range(
+        path,
+        tag,
+        attribute(node, "RANGE", index),
+        &extract,
+    )?);
+    match attribute(node, "CLIPMODE", index) {
+        Json::Null => (),
+        c => {
+            b = b.with_clip_mode(
+                serde_json::from_value::<ClipMode>(c.clone())
+                    .map_err(|_| invalid(path, "invalid CLIPMODE"))?,
+            )
+        }
+    }
+    if let Some(unit) = attribute(node, "UNIT", index).as_str() {
+        b = b.with_unit(unit.to_string());
+    }
+    if let Some(t) = attribute(node, "EXTENDED_TYPE", index).as_str() {
+        b = b.with_extended_type(t.to_string());
+    }
+    Ok(b.build())
+}
+
+//the parameters of the leaf node, with atomic storage or a mutex for the types that aren't Copy
+macro_rules! params {
+    ($p:ident, $path:expr, $node:expr) => {{
+        let (path, node): (&str, &Json) = ($path, $node);
+        let types = node["TYPE"]
+            .as_str()
+            .ok_or_else(|| invalid(path, "missing TYPE"))?;
+        let mut params = Vec::new();
+        for (i, tag) in split_type_tags(types).into_iter().enumerate() {
+            if default_arg(tag).is_none() {
+                return Err(invalid(path, &format!("unsupported TYPE {:?}", tag)));
+            }
+            let arg = arg_from_json(tag, attribute(node, "VALUE", i))
+                .ok_or_else(|| invalid(path, "invalid VALUE"))?;
+            params.push(match arg {
+                OscType::Int(v) => $p::Int(value(
+                    Arc::new(Atomic::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Int(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                OscType::Float(v) => $p::Float(value(
+                    Arc::new(Atomic::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Float(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                OscType::String(v) => $p::String(value(
+                    Arc::new(MutexValue::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::String(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                OscType::Time(v) => $p::Time(value(
+                    Arc::new(Atomic::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Time(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                OscType::Long(v) => $p::Long(value(
+                    Arc::new(Atomic::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Long(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                OscType::Double(v) => $p::Double(value(
+                    Arc::new(Atomic::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Double(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                OscType::Char(v) => $p::Char(value(
+                    Arc::new(Atomic::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Char(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                OscType::Bool(v) => $p::Bool(value(
+                    Arc::new(Atomic::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Bool(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                //serialized without range, clip mode or unit
+                OscType::Midi(v) => $p::Midi(
+                    ValueBuilder::new(
+                        Arc::new(Atomic::new((v.port, v.status, v.data1, v.data2))) as _
+                    )
+                    .build(),
+                ),
+                OscType::Color(v) => $p::Color(value(
+                    Arc::new(MutexValue::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Color(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                OscType::Array(v) => {
+                    $p::Array(ValueBuilder::new(Arc::new(MutexValue::new(v)) as _).build())
+                }
+                OscType::Blob(v) => $p::Blob(value(
+                    Arc::new(MutexValue::new(v)) as _,
+                    path,
+                    tag,
+                    node,
+                    i,
+                    |a| match a {
+                        OscType::Blob(v) => Some(v),
+                        _ => None,
+                    },
+                )?),
+                _ => return Err(invalid(path, "unsupported TYPE")),
+            });
+        }
+        params
+    }};
+}
+
+impl Root {
+    fn add_json_contents(
+        &self,
+        parent: Option<NodeHandle>,
+        parent_path: &str,
+        contents: &Map<String, Json>,
+        aliases: &mut Vec<AliasRecord>,
+        skipped: &mut Option<Vec<String>>,
+    ) -> Result<(), OscQueryError> {
+        for (address, node) in contents {
+            let path = format!("{}/{}", parent_path, address);
+            let path = path.as_str();
+            if let Some(target) = node["ALIAS_OF"].as_str() {
+                aliases.push(AliasRecord {
+                    path: path.to_string(),
+                    parent,
+                    address: address.clone(),
+                    target: target.to_string(),
+                });
+                continue;
+            }
+            if let (Some(skipped), Some(types)) = (skipped.as_mut(), node["TYPE"].as_str()) {
+                if split_type_tags(types)
+                    .into_iter()
+                    .any(|t| default_arg(t).is_none())
+                {
+                    skipped.push(path.to_string());
+                    continue;
+                }
+            }
+            let description = node["DESCRIPTION"].as_str();
+            let access = node["ACCESS"].as_u64().unwrap_or(0);
+            let mut n: Node = match access {
+                0 => Container::new(address.as_str(), description)?.into(),
+                1 => Get::new(address.as_str(), description, params!(ParamGet, path, node))?
+                    //the value is only left out of lazy nodes
+                    .with_lazy(node.get("VALUE").is_none())
+                    .into(),
+                2 => Set::new(
+                    address.as_str(),
+                    description,
+                    params!(ParamSet, path, node),
+                    None,
+                )?
+                .into(),
+                3 => GetSet::new(
+                    address.as_str(),
+                    description,
+                    params!(ParamGetSet, path, node),
+                    None,
+                )?
+                .into(),
+                _ => return Err(invalid(path, "invalid ACCESS")),
+            };
+            if let Some(tags) = node["TAGS"].as_array() {
+                n.set_tags(
+                    tags.iter()
+                        .filter_map(|t| t.as_str().map(String::from))
+                        .collect(),
+                );
+            }
+            n.set_critical(node["CRITICAL"].as_bool().unwrap_or(false));
+            let handle = self.add_node(n, parent)?;
+            if let Some(address) = node["OSC_ADDRESS"].as_str() {
+                self.set_osc_address(
+                    &handle,
+                    Some(OscAddress {
+                        address: address.to_string(),
+                        inbound: false,
+                    }),
+                )?;
+            }
+            if access == 0 {
+                if let Some(contents) = node["CONTENTS"].as_object() {
+                    self.add_json_contents(Some(handle), path, contents, aliases, skipped)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Build a tree from the JSON namespace of its root, like deserializing a `Root` but without
+    /// taking the document and with the error, `InvalidDocument` names the path that isn't valid.
+    pub fn from_oscquery_json(doc: &Json) -> Result<Root, OscQueryError> {
+        Self::from_json_doc(doc, &mut None)
+    }
+
+    /// Like `from_oscquery_json` but leave out the nodes with a `TYPE` that no parameter can
+    /// hold, like `N` or `I`, and the aliases of those, instead of failing. Returns their paths
+    /// along with the tree.
+    pub fn from_oscquery_json_lenient(doc: &Json) -> Result<(Root, Vec<String>), OscQueryError> {
+        let mut skipped = Some(Vec::new());
+        let root = Self::from_json_doc(doc, &mut skipped)?;
+        Ok((root, skipped.unwrap_or_default()))
+    }
+
+    //the tree of the document, skipping the unsupported nodes if there is a list for them
+    fn from_json_doc(doc: &Json, skipped: &mut Option<Vec<String>>) -> Result<Root, OscQueryError> {
+        let root = Root::new(None);
+        let mut aliases = Vec::new();
+        if let Some(contents) = doc["CONTENTS"].as_object() {
+            root.add_json_contents(None, "", contents, &mut aliases, skipped)?;
+        }
+        for a in aliases {
+            let target = match (root.path_to_handle(&a.target), skipped.as_mut()) {
+                (Some(target), _) => target,
+                (None, Some(skipped)) if skipped.contains(&a.target) => {
+                    skipped.push(a.path);
+                    continue;
+                }
+                (None, _) => return Err(invalid(&a.target, "missing ALIAS_OF target")),
+            };
+            root.add_alias(target, a.parent, &a.address)?;
+        }
+        Ok(root)
+    }
+}
+
+/// Build a tree from the JSON namespace of its root, with `FULL_PATH`, `ACCESS`, `TYPE`,
+/// `VALUE`, `CONTENTS`, `RANGE`, `CLIPMODE`, `UNIT`, `EXTENDED_TYPE`, `DESCRIPTION`,
+/// `TAGS` and `CRITICAL`.
+///
+/// Values are stored in `Atomic`s, strings and arrays in a `MutexValue`, and there are no update
+/// handlers. Nodes without a `VALUE` are lazy or write only, their values start out at zero.
+/// Aliases and the `OSC_ADDRESS` of nodes are restored, custom parameters become arrays or
+/// plain parameters of their type string. The name of the root isn't part of the namespace.
+impl<'de> Deserialize<'de> for Root {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let doc = Json::deserialize(deserializer)?;
+        Root::from_oscquery_json(&doc).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::{OscArray, OscColor};
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let root = Root::new(None);
+        let c = root
+            .add_node(
+                Container::new("c", Some("a container"))
+                    .unwrap()
+                    .with_tags(vec!["group"]),
+                None,
+            )
+            .unwrap();
+        let g = root
+            .add_node(
+                Get::new(
+                    "get",
+                    Some("ints"),
+                    vec![
+                        ParamGet::Int(
+                            ValueBuilder::new(Arc::new(Atomic::new(5i32)) as _)
+                                .with_range(Range::MinMax(0, 127))
+                                .with_clip_mode(ClipMode::Both)
+                                .with_unit("midi".into())
+                                .with_extended_type("midi.note".into())
+                                .build(),
+                        ),
+                        ParamGet::Char(
+                            ValueBuilder::new(Arc::new(Atomic::new('x')) as _)
+                                .with_range(Range::Vals(vec!['x', 'y']))
+                                .build(),
+                        ),
+                    ],
+                )
+                .unwrap()
+                .with_critical(true),
+                Some(c),
+            )
+            .unwrap();
+        root.add_node(
+            Set::new(
+                "set",
+                None,
+                vec![ParamSet::Float(
+                    ValueBuilder::new(Arc::new(()) as _)
+                        .with_range(Range::Min(-1.0))
+                        .with_clip_mode(ClipMode::Low)
+                        .build(),
+                )],
+                None,
+            )
+            .unwrap(),
+            Some(c),
+        )
+        .unwrap();
+        root.add_node(
+            GetSet::new(
+                "all",
+                None,
+                vec![
+                    ParamGetSet::String(
+                        ValueBuilder::new(Arc::new(MutexValue::new("hi".to_string())) as _)
+                            .with_range(Range::Vals(vec!["hi".into(), "bye".into()]))
+                            .build(),
+                    ),
+                    ParamGetSet::Time(
+                        ValueBuilder::new(Arc::new(Atomic::new((1u32, 2u32))) as _).build(),
+                    ),
+                    ParamGetSet::Long(ValueBuilder::new(Arc::new(Atomic::new(-3i64)) as _).build()),
+                    ParamGetSet::Double(
+                        ValueBuilder::new(Arc::new(Atomic::new(0.5f64)) as _)
+                            .with_range(Range::Max(1.0))
+                            .build(),
+                    ),
+                    ParamGetSet::Midi(
+                        ValueBuilder::new(Arc::new(Atomic::new((0u8, 0u8, 0u8, 0u8))) as _).build(),
+                    ),
+                    ParamGetSet::Bool(ValueBuilder::new(Arc::new(Atomic::new(true)) as _).build()),
+                    ParamGetSet::Color(
+                        ValueBuilder::new(Arc::new(MutexValue::new(OscColor {
+                            red: 255,
+                            green: 128,
+                            blue: 0,
+                            alpha: 255,
+                        })) as _)
+                        .with_unit("rgba".into())
+                        .build(),
+                    ),
+                    ParamGetSet::Blob(
+                        ValueBuilder::new(Arc::new(MutexValue::new(vec![0u8, 1, 255])) as _)
+                            .build(),
+                    ),
+                    ParamGetSet::Array(
+                        ValueBuilder::new(Arc::new(MutexValue::new(OscArray {
+                            content: vec![OscType::Int(1), OscType::String("a".into())],
+                        })) as _)
+                        .build(),
+                    ),
+                ],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let lazy = Get::new(
+            "lazy",
+            None,
+            vec![ParamGet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(1i32)) as _).build(),
+            )],
+        )
+        .unwrap()
+        .with_lazy(true);
+        let lazy = root.add_node(lazy, None).unwrap();
+        root.set_osc_address(
+            &lazy,
+            Some(OscAddress {
+                address: "/dev/lazy".into(),
+                inbound: false,
+            }),
+        )
+        .unwrap();
+        root.add_alias(g, None, "shortcut").unwrap();
+
+        let j = serde_json::to_value(&root).unwrap();
+        let copy: Root = serde_json::from_value(j.clone()).unwrap();
+        assert_eq!(j, serde_json::to_value(&copy).unwrap());
+        assert_eq!(root.node_count(), copy.node_count());
+        assert_eq!(j["CONTENTS"]["all"]["TYPE"], "sthdmTrb[is]");
+        assert_eq!(j["CONTENTS"]["shortcut"]["ALIAS_OF"], "/c/get");
+        assert_eq!(j["CONTENTS"]["c"]["TAGS"], serde_json::json!(["group"]));
+        assert_eq!(j["CONTENTS"]["c"]["CONTENTS"]["get"]["CRITICAL"], true);
+        assert_eq!(
+            j["CONTENTS"]["c"]["CONTENTS"]["get"]["EXTENDED_TYPE"],
+            json!(["midi.note", null])
+        );
+        assert!(j["CONTENTS"]["set"].get("EXTENDED_TYPE").is_none());
+    }
+
+    #[test]
+    fn invalid_documents() {
+        for doc in &[
+            json!({"CONTENTS": {"a": {"ACCESS": 1}}}),
+            json!({"CONTENTS": {"a": {"ACCESS": 1, "TYPE": "N", "VALUE": [null]}}}),
+            json!({"CONTENTS": {"a": {"ACCESS": 1, "TYPE": "b", "VALUE": ["!"]}}}),
+            json!({"CONTENTS": {"a": {"ACCESS": 1, "TYPE": "i", "VALUE": ["x"]}}}),
+            json!({"CONTENTS": {"a": {"ACCESS": 7, "TYPE": "i"}}}),
+            json!({"CONTENTS": {"a": {"ACCESS": 3, "TYPE": "i", "RANGE": [{"MIN": "x"}]}}}),
+            json!({"CONTENTS": {"a": {"ACCESS": 3, "TYPE": "i", "CLIPMODE": ["sideways"]}}}),
+            json!({"CONTENTS": {"a": {"ALIAS_OF": "/nothing"}}}),
+        ] {
+            assert!(
+                serde_json::from_value::<Root>(doc.clone()).is_err(),
+                "{}",
+                doc
+            );
+        }
+        let root: Root = serde_json::from_value(json!({"CONTENTS": {}})).unwrap();
+        assert_eq!(root.node_count(), 1);
+
+        let doc = json!({"CONTENTS": {"a": {"ACCESS": 0, "CONTENTS": {
+            "b": {"ACCESS": 3, "TYPE": "iz", "VALUE": [1, 2]}
+        }}}});
+        match Root::from_oscquery_json(&doc) {
+            Err(OscQueryError::InvalidDocument(e)) => {
+                assert_eq!(e, "unsupported TYPE \"z\" at \"/a/b\"")
+            }
+            r => panic!("unexpected {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn lenient() {
+        let doc = json!({"CONTENTS": {"a": {"ACCESS": 0, "CONTENTS": {
+            "b": {"ACCESS": 3, "TYPE": "iz", "VALUE": [1, 2]},
+            "c": {"ACCESS": 1, "TYPE": "I", "VALUE": [null]},
+            "d": {"ACCESS": 1, "TYPE": "i", "VALUE": [3]},
+            "e": {"ALIAS_OF": "/a/c"},
+            "f": {"ALIAS_OF": "/a/d"}
+        }}}});
+        let (root, skipped) = Root::from_oscquery_json_lenient(&doc).unwrap();
+        assert_eq!(skipped, vec!["/a/b", "/a/c", "/a/e"]);
+        assert_eq!(root.paths(), vec!["/", "/a", "/a/d"]);
+        assert_eq!(root.value_at_path_indexed("/a/f", 0), Some(OscType::Int(3)));
+
+        //other problems still fail
+        let doc = json!({"CONTENTS": {"a": {"ACCESS": 1, "TYPE": "i", "VALUE": ["x"]}}});
+        assert_matches!(
+            Root::from_oscquery_json_lenient(&doc).map(|(_, skipped)| skipped),
+            Err(OscQueryError::InvalidDocument(..))
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Serialization of only the nodes matching a filter, the `FILTER` http query.
+use super::*;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Selects the leaves of a filtered query, see `Root::serialize_filtered`.
+///
+/// Parsed from `HAS_<ATTR>`, where the attribute is one of `DESCRIPTION`, `RANGE`, `CLIPMODE`,
+/// `UNIT`, `EXTENDED_TYPE`, `TAGS` or `CRITICAL`, from `ACCESS:<n>` or from `TAG:<tag>`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NodeFilter {
+    /// The node has the attribute set, for at least one of its parameters.
+    Has(NodeQueryParam),
+    /// The node has the access.
+    Access(Access),
+    /// The node has the tag.
+    Tag(String),
+}
+
+impl FromStr for NodeFilter {
+    type Err = OscQueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(attr) = s.strip_prefix("HAS_") {
+            match serde_json::from_value(serde_json::Value::String(attr.to_string())) {
+                Ok(p @ NodeQueryParam::Description)
+                | Ok(p @ NodeQueryParam::Range)
+                | Ok(p @ NodeQueryParam::ClipMode)
+                | Ok(p @ NodeQueryParam::Unit)
+                | Ok(p @ NodeQueryParam::ExtendedType)
+                | Ok(p @ NodeQueryParam::Tags)
+                | Ok(p @ NodeQueryParam::Critical) => Ok(Self::Has(p)),
+                _ => Err(OscQueryError::InvalidFilter("unsupported attribute")),
+            }
+        } else if let Some(access) = s.strip_prefix("ACCESS:") {
+            access
+                .parse::<u8>()
+                .ok()
+                .and_then(|a| Access::try_from(a).ok())
+                .map(Self::Access)
+                .ok_or(OscQueryError::InvalidFilter("invalid access"))
+        } else if let Some(tag) = s.strip_prefix("TAG:") {
+            if tag.is_empty() {
+                Err(OscQueryError::InvalidFilter("empty tag"))
+            } else {
+                Ok(Self::Tag(tag.to_string()))
+            }
+        } else {
+            Err(OscQueryError::InvalidFilter("unknown filter"))
+        }
+    }
+}
+
+//is any part of the serialized attribute set
+fn present(v: &serde_json::Value) -> bool {
+    use serde_json::Value;
+    match v {
+        Value::Null => false,
+        Value::Object(m) => !m.is_empty(),
+        Value::Array(a) => a.iter().any(present),
+        //the clip mode default
+        Value::String(s) => s != "none",
+        _ => true,
+    }
+}
+
+impl NodeFilter {
+    /// Does the leaf match, containers never do.
+    pub fn matches(&self, node: &Node) -> bool {
+        if let Node::Container(..) = node {
+            return false;
+        }
+        let attr = |v: Result<serde_json::Value, _>| matches!(v, Ok(v) if present(&v));
+        match self {
+            Self::Has(NodeQueryParam::Description) => node.description().is_some(),
+            Self::Has(NodeQueryParam::Range) => attr(serde_json::to_value(NodeRangeWrapper(node))),
+            Self::Has(NodeQueryParam::ClipMode) => {
+                attr(serde_json::to_value(NodeClipModeWrapper(node)))
+            }
+            Self::Has(NodeQueryParam::Unit) => attr(serde_json::to_value(NodeUnitWrapper(node))),
+            Self::Has(NodeQueryParam::ExtendedType) => {
+                node.param_extended_types().iter().any(Option::is_some)
+            }
+            Self::Has(NodeQueryParam::Tags) => node.tags().is_some(),
+            Self::Has(NodeQueryParam::Critical) => node.critical(),
+            Self::Has(..) => false,
+            Self::Access(a) => node.access() == *a,
+            Self::Tag(tag) => node.tags().is_some_and(|t| t.contains(tag)),
+        }
+    }
+}
+
+impl RootInner {
+    //the matching leaves below the index and their ancestors, in one walk
+    pub(super) fn filter_marks(&self, index: NodeIndex, filter: &NodeFilter) -> HashSet<NodeIndex> {
+        let mut marks = HashSet::new();
+        self.mark(index, filter, &mut marks);
+        marks
+    }
+
+    //returns true if the node, or any below it, matched
+    fn mark(&self, index: NodeIndex, filter: &NodeFilter, marks: &mut HashSet<NodeIndex>) -> bool {
+        let node = match self.graph.node_weight(index) {
+            Some(n) => &n.node,
+            None => return false,
+        };
+        let mut matched = filter.matches(node);
+        let mut children = self.graph.neighbors(index).detach();
+        while let Some(child) = children.next_node(&self.graph) {
+            matched |= self.mark(child, filter, marks);
+        }
+        if matched {
+            marks.insert(index);
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_matches!(
+            "HAS_UNIT".parse(),
+            Ok(NodeFilter::Has(NodeQueryParam::Unit))
+        );
+        assert_matches!(
+            "ACCESS:2".parse(),
+            Ok(NodeFilter::Access(Access::WriteOnly))
+        );
+        assert_matches!(
+            "TAG:artnet".parse(),
+            Ok(NodeFilter::Tag(t)) if t == "artnet"
+        );
+        for s in &["HAS_VALUE", "ACCESS:4", "TAG:", "UNIT"] {
+            assert_matches!(
+                s.parse::<NodeFilter>(),
+                Err(OscQueryError::InvalidFilter(..))
+            );
+        }
+    }
+}
@@ -0,0 +1,433 @@
+//! Merging the nodes of another tree into this one, for composing a namespace from subsystems.
+use super::*;
+
+/// What `Root::merge` does with a node whose path is already taken, by a node or an alias.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MergePolicy {
+    /// Keep what is there. The nodes below are still merged if it is a container, skipped
+    /// otherwise.
+    Skip,
+    /// Replace the node in place, see `Root::replace_node`, or the alias. A container with
+    /// children can only be replaced by a container.
+    Replace,
+    /// Fail with `PathInUse`.
+    Fail,
+}
+
+//a change to the tree, all of them are checked before any is made
+enum Step {
+    Add {
+        parent: String,
+        node: Node,
+        //the alias at the path, removed first
+        alias: Option<AliasHandle>,
+        //false for the containers of the prefix
+        merged: bool,
+    },
+    Replace {
+        index: NodeIndex,
+        node: Node,
+    },
+}
+
+fn parent_path(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+impl Root {
+    /// Merge the nodes of the other tree into this one, under the prefix path, or the root. With
+    /// the prefix `/audio` the other tree's `/foo/bar` becomes `/audio/foo/bar`, the containers of
+    /// the prefix are created if needed.
+    ///
+    /// The nodes share the storage and update handlers with those in the other tree, as they are
+    /// behind `Arc`s. Nodes with custom parameters can't be merged, see `NotShareable`, and
+    /// aliases aren't. What happens when a path is already taken depends on the policy.
+    ///
+    /// Returns the handles of the merged nodes, depth first, those replaced in place included.
+    /// Nothing is changed when it fails.
+    pub fn merge(
+        &self,
+        other: &Root,
+        prefix: Option<&str>,
+        policy: MergePolicy,
+    ) -> Result<Vec<NodeHandle>, OscQueryError> {
+        //copied before locking this tree, so a tree can be merged into itself
+        let mut nodes = Vec::new();
+        let mut unshareable = None;
+        other.read_locked()?.for_each_node(|path, node, _| {
+            if path == "/" || unshareable.is_some() {
+                return;
+            }
+            match node.share() {
+                Some(node) => nodes.push((path.to_string(), node)),
+                None => unshareable = Some(path.to_string()),
+            }
+        });
+        if let Some(path) = unshareable {
+            return Err(OscQueryError::NotShareable(path));
+        }
+        let mut inner = self.write_locked()?;
+        let steps = inner.merge_steps(nodes, prefix.unwrap_or(""), policy)?;
+        Ok(inner.apply_merge(steps))
+    }
+}
+
+impl RootInner {
+    fn merge_steps(
+        &self,
+        nodes: Vec<(String, Node)>,
+        prefix: &str,
+        policy: MergePolicy,
+    ) -> Result<Vec<Step>, OscQueryError> {
+        let mut steps = Vec::new();
+        //the paths added, or replaced, by the steps
+        let mut planned: HashSet<String> = HashSet::new();
+        //the paths whose nodes, and those below, are skipped
+        let mut skipped: HashSet<String> = HashSet::new();
+
+        let mut prefix_path = String::new();
+        for address in prefix.split('/').filter(|a| !a.is_empty()) {
+            let parent = if prefix_path.is_empty() {
+                "/".to_string()
+            } else {
+                prefix_path.clone()
+            };
+            prefix_path = format!("{}/{}", prefix_path, address);
+            match self.index_map.get(&prefix_path) {
+                Some(index) if matches!(self.graph[*index].node, Node::Container(..)) => (),
+                Some(..) => return Err(OscQueryError::NotAContainer(prefix_path)),
+                None if self.alias_paths.contains_key(&prefix_path) => {
+                    return Err(OscQueryError::PathInUse(prefix_path))
+                }
+                None => {
+                    if !planned.contains(&parent) {
+                        self.check_frozen(self.index_map[&parent], false)?;
+                    }
+                    steps.push(Step::Add {
+                        parent,
+                        node: Container::new(address, None)?.into(),
+                        alias: None,
+                        merged: false,
+                    });
+                    planned.insert(prefix_path.clone());
+                }
+            }
+        }
+
+        for (path, node) in nodes {
+            let path = format!("{}{}", prefix_path, path);
+            let parent = parent_path(&path).to_string();
+            if skipped.contains(&parent) {
+                skipped.insert(path);
+                continue;
+            }
+            //the parent is a container, it was merged or skipped otherwise
+            let parent_index = if planned.contains(&parent) {
+                None
+            } else {
+                Some(self.index_map[&parent])
+            };
+            let existing = self.index_map.get(&path).copied();
+            let alias = self.alias_paths.get(&path).copied();
+            if existing.is_none() && alias.is_none() {
+                if let Some(index) = parent_index {
+                    self.check_frozen(index, false)?;
+                }
+                steps.push(Step::Add {
+                    parent,
+                    node,
+                    alias: None,
+                    merged: true,
+                });
+                planned.insert(path);
+                continue;
+            }
+            match policy {
+                MergePolicy::Fail => return Err(OscQueryError::PathInUse(path)),
+                MergePolicy::Skip => {
+                    if !existing.is_some_and(|i| matches!(self.graph[i].node, Node::Container(..)))
+                    {
+                        skipped.insert(path);
+                    }
+                }
+                MergePolicy::Replace => match existing {
+                    Some(index) => {
+                        if !matches!(node, Node::Container(..))
+                            && self.graph.neighbors(index).next().is_some()
+                        {
+                            return Err(OscQueryError::node_not_added(
+                                node,
+                                OscQueryError::HasChildren,
+                            ));
+                        }
+                        self.check_frozen(index, true)?;
+                        steps.push(Step::Replace { index, node });
+                        planned.insert(path);
+                    }
+                    None => {
+                        if let Some(index) = parent_index {
+                            self.check_frozen(index, false)?;
+                        }
+                        steps.push(Step::Add {
+                            parent,
+                            node,
+                            alias,
+                            merged: true,
+                        });
+                        planned.insert(path);
+                    }
+                },
+            }
+        }
+        Ok(steps)
+    }
+
+    fn apply_merge(&mut self, steps: Vec<Step>) -> Vec<NodeHandle> {
+        let mut handles = Vec::new();
+        for step in steps {
+            match step {
+                Step::Add {
+                    parent,
+                    node,
+                    alias,
+                    merged,
+                } => {
+                    if let Some(alias) = alias {
+                        let _ = self.rm_alias(alias);
+                    }
+                    let parent = Some(self.index_map[&parent])
+                        .filter(|i| *i != self.root)
+                        .map(|i| self.handle(i));
+                    let handle = self.add_node(node, parent).expect("merge step checked");
+                    if merged {
+                        handles.push(handle);
+                    }
+                }
+                Step::Replace { index, node } => {
+                    self.replace_node(index, node).expect("merge step checked");
+                    handles.push(self.handle(index));
+                }
+            }
+        }
+        handles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::param::{ParamGetSet, ParamSet};
+    use crate::value::ValueBuilder;
+    use serde_json::json;
+
+    fn container(address: &str) -> Container {
+        Container::new(address, None).unwrap()
+    }
+
+    fn gain(value: Arc<Atomic<f32>>) -> GetSet {
+        GetSet::new(
+            "gain",
+            None,
+            vec![ParamGetSet::Float(ValueBuilder::new(value as _).build())],
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn merge() {
+        let audio = Root::new(None);
+        let engine = audio.add_node(container("engine"), None).unwrap();
+        let value = Arc::new(Atomic::new(0.5f32));
+        audio.add_node(gain(value.clone()), Some(engine)).unwrap();
+        audio.add_node(container("meters"), None).unwrap();
+
+        let root = Root::new(None);
+        let handles = root
+            .merge(&audio, Some("/audio"), MergePolicy::Fail)
+            .unwrap();
+        assert_eq!(
+            root.paths(),
+            vec![
+                "/",
+                "/audio",
+                "/audio/engine",
+                "/audio/engine/gain",
+                "/audio/meters"
+            ]
+        );
+        //the prefix isn't merged
+        let expected: Vec<NodeHandle> = ["/audio/engine", "/audio/engine/gain", "/audio/meters"]
+            .iter()
+            .map(|p| root.path_to_handle(p).unwrap())
+            .collect();
+        assert_eq!(handles, expected);
+        assert_eq!(root.validate(), Ok(()));
+
+        //the storage is shared
+        root.set_value_at_path_json("/audio/engine/gain", &json!([0.25]))
+            .unwrap();
+        assert_eq!(value.load(Ordering::SeqCst), 0.25);
+        value.store(0.75, Ordering::SeqCst);
+        assert_eq!(
+            root.value_at_path_indexed("/audio/engine/gain", 0),
+            Some(OscType::Float(0.75))
+        );
+
+        //into the root, alongside what is there
+        let ui = Root::new(None);
+        let page = ui.add_node(container("page"), None).unwrap();
+        ui.add_node(gain(Arc::new(Atomic::new(0f32))), Some(page))
+            .unwrap();
+        assert_eq!(root.merge(&ui, None, MergePolicy::Fail).unwrap().len(), 2);
+        assert!(root.path_to_handle("/page/gain").is_some());
+        //and into itself
+        assert_eq!(
+            root.merge(&root, Some("copy"), MergePolicy::Fail)
+                .unwrap()
+                .len(),
+            6
+        );
+        assert!(root.path_to_handle("/copy/audio/engine/gain").is_some());
+        assert_eq!(root.validate(), Ok(()));
+    }
+
+    #[test]
+    fn policies() {
+        let other = Root::new(None);
+        let engine = other.add_node(container("engine"), None).unwrap();
+        let theirs = Arc::new(Atomic::new(1f32));
+        other.add_node(gain(theirs.clone()), Some(engine)).unwrap();
+        let mute = other.add_node(container("mute"), None).unwrap();
+        other.add_node(container("all"), Some(mute)).unwrap();
+
+        let root = Root::new(None);
+        let engine = root.add_node(container("engine"), None).unwrap();
+        let ours = Arc::new(Atomic::new(0f32));
+        let g = root.add_node(gain(ours.clone()), Some(engine)).unwrap();
+        //a leaf where the other tree has a container
+        root.add_node(
+            crate::node::Set::new(
+                "mute",
+                None,
+                vec![ParamSet::Bool(
+                    ValueBuilder::new(Arc::new(Atomic::new(false)) as _).build(),
+                )],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let paths = root.paths();
+
+        assert_matches!(
+            root.merge(&other, None, MergePolicy::Fail),
+            Err(OscQueryError::PathInUse(p)) if p == "/engine"
+        );
+        assert_matches!(
+            root.merge(&other, Some("/engine/gain"), MergePolicy::Fail),
+            Err(OscQueryError::NotAContainer(p)) if p == "/engine/gain"
+        );
+        let guard = root.lock_subtree(engine).unwrap();
+        assert_matches!(
+            root.merge(&other, None, MergePolicy::Replace),
+            Err(OscQueryError::Frozen(..))
+        );
+        drop(guard);
+        assert_eq!(root.paths(), paths);
+
+        //mute and what is below are skipped, the engine is merged into
+        assert_eq!(
+            root.merge(&other, None, MergePolicy::Skip).unwrap(),
+            Vec::new()
+        );
+        assert_eq!(root.paths(), paths);
+        root.set_value_at_path_json("/engine/gain", &json!([0.5]))
+            .unwrap();
+        assert_eq!(ours.load(Ordering::SeqCst), 0.5);
+
+        let handles = root.merge(&other, None, MergePolicy::Replace).unwrap();
+        assert_eq!(handles.len(), 4);
+        //replaced in place
+        assert_eq!(root.path_to_handle("/engine/gain"), Some(g));
+        assert!(root.path_to_handle("/mute/all").is_some());
+        root.set_value_at_path_json("/engine/gain", &json!([0.25]))
+            .unwrap();
+        assert_eq!(theirs.load(Ordering::SeqCst), 0.25);
+        assert_eq!(ours.load(Ordering::SeqCst), 0.5);
+
+        //a container with children isn't replaced by a leaf
+        let leaf = Root::new(None);
+        leaf.add_node(gain(Arc::new(Atomic::new(0f32))), None)
+            .unwrap();
+        root.add_node(container("gain"), None).unwrap();
+        root.add_node(container("x"), root.path_to_handle("/gain"))
+            .unwrap();
+        assert_matches!(
+            root.merge(&leaf, None, MergePolicy::Replace),
+            Err(OscQueryError::NodeNotAdded { reason, .. })
+                if matches!(*reason, OscQueryError::HasChildren)
+        );
+        assert_eq!(root.validate(), Ok(()));
+    }
+
+    struct One;
+
+    impl crate::param::CustomParam for One {
+        fn osc_type_str(&self) -> String {
+            "i".to_string()
+        }
+
+        fn render(&self, args: &mut Vec<OscType>) {
+            args.push(OscType::Int(1));
+        }
+
+        fn update(&self, _args: &[OscType]) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn aliases_and_custom() {
+        let other = Root::new(None);
+        other.add_node(container("level"), None).unwrap();
+        let root = Root::new(None);
+        let target = root
+            .add_node(gain(Arc::new(Atomic::new(0f32))), None)
+            .unwrap();
+        let alias = root.add_alias(target, None, "level").unwrap();
+
+        assert!(root
+            .merge(&other, None, MergePolicy::Skip)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            root.merge(&other, None, MergePolicy::Replace)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(root.alias_path(alias), None);
+        assert!(root.path_to_handle("/level").is_some());
+
+        other
+            .add_node(
+                crate::node::Get::new(
+                    "custom",
+                    None,
+                    vec![crate::param::ParamGet::Custom(Box::new(One))],
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        assert_matches!(
+            root.merge(&other, None, MergePolicy::Replace),
+            Err(OscQueryError::NotShareable(p)) if p == "/custom"
+        );
+    }
+}
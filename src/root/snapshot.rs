@@ -0,0 +1,560 @@
+//! Binary export and import of the tree structure.
+use super::*;
+use crate::param::*;
+use crate::value::{ClipMode, Range, Value};
+use serde::Deserialize;
+
+//identifies structure snapshots, followed by the little endian format version
+const MAGIC: &[u8; 4] = b"OSQS";
+const VERSION: u16 = 1;
+
+/// A leaf of an imported tree, given to the storage factory of `Root::import_structure`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathSpec {
+    /// The full path of the node.
+    pub path: String,
+    /// The access of the node, never `Access::NoValue`.
+    pub access: Access,
+    /// The OSC type tag string of the node at export time, as reported in `TYPE`.
+    pub type_string: String,
+}
+
+/// The parameters, and handler, for a leaf of an imported tree.
+///
+/// The variant has to match the `PathSpec` access and the parameters its type string. Ranges,
+/// clip modes and units are taken from the snapshot, so the parameters can be built with the
+/// `ValueBuilder` defaults.
+pub enum ParamStorage {
+    Get(Vec<ParamGet>),
+    Set(Vec<ParamSet>, Option<UpdateHandler>),
+    GetSet(Vec<ParamGetSet>, Option<UpdateHandler>),
+}
+
+/// The reasons a structure snapshot could not be imported.
+#[derive(Debug)]
+pub enum StructureError {
+    /// The data isn't a structure snapshot.
+    NotASnapshot,
+    /// The snapshot was written with an unsupported format version.
+    Version(u16),
+    /// The snapshot couldn't be decoded.
+    Decode(String),
+    /// The storage returned for the path doesn't match the snapshot.
+    Storage(String),
+    /// The node at the path couldn't be added.
+    Node(String, OscQueryError),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    name: Option<String>,
+    //parents before children, children in insertion order
+    nodes: Vec<NodeRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NodeRecord {
+    //index of the parent in the node list, `None` for children of the root
+    parent: Option<usize>,
+    address: String,
+    description: Option<String>,
+    kind: KindRecord,
+}
+
+#[derive(Serialize, Deserialize)]
+enum KindRecord {
+    Container,
+    Get {
+        type_string: String,
+        params: Vec<ParamRecord>,
+        lazy: bool,
+        expires_after: Option<Duration>,
+    },
+    Set {
+        type_string: String,
+        params: Vec<ParamRecord>,
+    },
+    GetSet {
+        type_string: String,
+        params: Vec<ParamRecord>,
+    },
+}
+
+//the value attributes, array attributes aren't reported so they aren't recorded
+#[derive(Serialize, Deserialize)]
+enum ParamRecord {
+    Int(Attributes<i32>),
+    Float(Attributes<f32>),
+    String(Attributes<String>),
+    Time(Attributes<(u32, u32)>),
+    Long(Attributes<i64>),
+    Double(Attributes<f64>),
+    Char(Attributes<char>),
+    Midi(Attributes<(u8, u8, u8, u8)>),
+    Bool(Attributes<bool>),
+    Array,
+    Custom(String),
+    //colors have no range
+    Color(ClipMode, Option<String>),
+    //blobs have neither a range nor a clip mode
+    Blob(Option<String>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Attributes<T> {
+    clip_mode: ClipMode,
+    range: RangeRecord<T>,
+    unit: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum RangeRecord<T> {
+    None,
+    Min(T),
+    Max(T),
+    MinMax(T, T),
+    Vals(Vec<T>),
+}
+
+impl<T: Clone> Attributes<T> {
+    fn new<V>(value: &Value<V, T>) -> Self {
+        Self {
+            clip_mode: value.clip_mode,
+            range: match &value.range {
+                Range::None => RangeRecord::None,
+                Range::Min(v) => RangeRecord::Min(v.clone()),
+                Range::Max(v) => RangeRecord::Max(v.clone()),
+                Range::MinMax(min, max) => RangeRecord::MinMax(min.clone(), max.clone()),
+                Range::Vals(v) => RangeRecord::Vals(v.clone()),
+            },
+            unit: value.unit.clone(),
+        }
+    }
+
+    fn apply<V>(self, value: &mut Value<V, T>) -> bool {
+        value.clip_mode = self.clip_mode;
+        value.range = match self.range {
+            RangeRecord::None => Range::None,
+            RangeRecord::Min(v) => Range::Min(v),
+            RangeRecord::Max(v) => Range::Max(v),
+            RangeRecord::MinMax(min, max) => Range::MinMax(min, max),
+            RangeRecord::Vals(v) => Range::Vals(v),
+        };
+        value.unit = self.unit;
+        true
+    }
+}
+
+macro_rules! impl_param_record {
+    ($record:ident, $apply:ident, $p:ident) => {
+        fn $record(p: &$p) -> ParamRecord {
+            match p {
+                $p::Int(v) => ParamRecord::Int(Attributes::new(v)),
+                $p::Float(v) => ParamRecord::Float(Attributes::new(v)),
+                $p::String(v) => ParamRecord::String(Attributes::new(v)),
+                $p::Time(v) => ParamRecord::Time(Attributes::new(v)),
+                $p::Long(v) => ParamRecord::Long(Attributes::new(v)),
+                $p::Double(v) => ParamRecord::Double(Attributes::new(v)),
+                $p::Char(v) => ParamRecord::Char(Attributes::new(v)),
+                $p::Midi(v) => ParamRecord::Midi(Attributes::new(v)),
+                $p::Bool(v) => ParamRecord::Bool(Attributes::new(v)),
+                $p::Color(v) => ParamRecord::Color(v.clip_mode, v.unit.clone()),
+                $p::Array(..) => ParamRecord::Array,
+                $p::Blob(v) => ParamRecord::Blob(v.unit.clone()),
+                $p::Custom(v) => ParamRecord::Custom(v.osc_type_str()),
+            }
+        }
+
+        //apply the recorded attributes, false if the kinds don't match
+        fn $apply(r: ParamRecord, p: &mut $p) -> bool {
+            match (r, p) {
+                (ParamRecord::Int(a), $p::Int(v)) => a.apply(v),
+                (ParamRecord::Float(a), $p::Float(v)) => a.apply(v),
+                (ParamRecord::String(a), $p::String(v)) => a.apply(v),
+                (ParamRecord::Time(a), $p::Time(v)) => a.apply(v),
+                (ParamRecord::Long(a), $p::Long(v)) => a.apply(v),
+                (ParamRecord::Double(a), $p::Double(v)) => a.apply(v),
+                (ParamRecord::Char(a), $p::Char(v)) => a.apply(v),
+                (ParamRecord::Midi(a), $p::Midi(v)) => a.apply(v),
+                (ParamRecord::Bool(a), $p::Bool(v)) => a.apply(v),
+                (ParamRecord::Color(clip_mode, unit), $p::Color(v)) => {
+                    v.clip_mode = clip_mode;
+                    v.unit = unit;
+                    true
+                }
+                (ParamRecord::Array, $p::Array(..)) => true,
+                (ParamRecord::Blob(unit), $p::Blob(v)) => {
+                    v.unit = unit;
+                    true
+                }
+                (ParamRecord::Custom(t), $p::Custom(v)) => v.osc_type_str() == t,
+                _ => false,
+            }
+        }
+    };
+}
+
+impl_param_record!(record_get, apply_get, ParamGet);
+impl_param_record!(record_set, apply_set, ParamSet);
+impl_param_record!(record_get_set, apply_get_set, ParamGetSet);
+
+fn apply_all<P>(
+    records: Vec<ParamRecord>,
+    params: &mut [P],
+    apply: fn(ParamRecord, &mut P) -> bool,
+) -> bool {
+    records.len() == params.len()
+        && records
+            .into_iter()
+            .zip(params.iter_mut())
+            .all(|(r, p)| apply(r, p))
+}
+
+impl NodeRecord {
+    fn new(parent: Option<usize>, node: &Node) -> Self {
+        let type_string = node.type_string().unwrap_or_default();
+        let kind = match node {
+            Node::Container(..) => KindRecord::Container,
+            Node::Get(n) => KindRecord::Get {
+                type_string,
+                params: n.params().iter().map(record_get).collect(),
+                lazy: node.lazy(),
+                expires_after: node.expires_after(),
+            },
+            Node::Set(n) => KindRecord::Set {
+                type_string,
+                params: n.params().iter().map(record_set).collect(),
+            },
+            Node::GetSet(n) => KindRecord::GetSet {
+                type_string,
+                params: n.params().iter().map(record_get_set).collect(),
+            },
+        };
+        Self {
+            parent,
+            address: node.address().clone(),
+            description: node.description().clone(),
+            kind,
+        }
+    }
+
+    fn into_node<F>(self, path: String, storage_factory: &F) -> Result<Node, StructureError>
+    where
+        F: Fn(&PathSpec) -> ParamStorage,
+    {
+        let description = self.description.as_deref();
+        let mismatch = |path: String| StructureError::Storage(path);
+        let invalid = |path: String, e| StructureError::Node(path, e);
+        let spec = |access, type_string| PathSpec {
+            path: path.clone(),
+            access,
+            type_string,
+        };
+        Ok(match self.kind {
+            KindRecord::Container => Container::new(self.address, description)
+                .map_err(|e| invalid(path, e))?
+                .into(),
+            KindRecord::Get {
+                type_string,
+                params,
+                lazy,
+                expires_after,
+            } => match storage_factory(&spec(Access::ReadOnly, type_string)) {
+                ParamStorage::Get(mut p) => {
+                    if !apply_all(params, &mut p, apply_get) {
+                        return Err(mismatch(path));
+                    }
+                    Get::new(self.address, description, p)
+                        .map_err(|e| invalid(path, e))?
+                        .with_lazy(lazy)
+                        .with_expires_after(expires_after)
+                        .into()
+                }
+                _ => return Err(mismatch(path)),
+            },
+            KindRecord::Set {
+                type_string,
+                params,
+            } => match storage_factory(&spec(Access::WriteOnly, type_string)) {
+                ParamStorage::Set(mut p, h) => {
+                    if !apply_all(params, &mut p, apply_set) {
+                        return Err(mismatch(path));
+                    }
+                    Set::new(self.address, description, p, h)
+                        .map_err(|e| invalid(path, e))?
+                        .into()
+                }
+                _ => return Err(mismatch(path)),
+            },
+            KindRecord::GetSet {
+                type_string,
+                params,
+            } => match storage_factory(&spec(Access::ReadWrite, type_string)) {
+                ParamStorage::GetSet(mut p, h) => {
+                    if !apply_all(params, &mut p, apply_get_set) {
+                        return Err(mismatch(path));
+                    }
+                    GetSet::new(self.address, description, p, h)
+                        .map_err(|e| invalid(path, e))?
+                        .into()
+                }
+                _ => return Err(mismatch(path)),
+            },
+        })
+    }
+}
+
+impl Root {
+    /// Export the structure of the tree, without values, in a compact binary format.
+    ///
+    /// Paths, node kinds, types, ranges, clip modes, units, descriptions and the order of
+    /// children are captured. Custom parameters only record their type string.
+    pub fn export_structure(&self) -> Vec<u8> {
+        let inner = self.read_locked().expect("failed to read lock");
+        let mut snapshot = Snapshot {
+            name: inner.name(),
+            nodes: Vec::new(),
+        };
+        //(parent record, node), children are visited in insertion order
+        let mut stack: Vec<(Option<usize>, NodeIndex)> = Vec::new();
+        let children = |index| {
+            let mut c: Vec<NodeIndex> = inner.graph.neighbors(index).collect();
+            //neighbors are newest first
+            c.reverse();
+            c
+        };
+        for index in children(inner.root).into_iter().rev() {
+            stack.push((None, index));
+        }
+        while let Some((parent, index)) = stack.pop() {
+            if let Some(node) = inner.graph.node_weight(index) {
+                let record = snapshot.nodes.len();
+                snapshot.nodes.push(NodeRecord::new(parent, &node.node));
+                for child in children(index).into_iter().rev() {
+                    stack.push((Some(record), child));
+                }
+            }
+        }
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        bincode::serialize_into(&mut data, &snapshot).expect("failed to encode structure");
+        data
+    }
+
+    /// Create a tree from the output of `export_structure`.
+    ///
+    /// The `storage_factory` is called for every node that has a value, and supplies the
+    /// parameters and handler that connect it to the application.
+    pub fn import_structure<F>(data: &[u8], storage_factory: F) -> Result<Root, StructureError>
+    where
+        F: Fn(&PathSpec) -> ParamStorage,
+    {
+        if data.len() < MAGIC.len() + 2 || &data[..MAGIC.len()] != MAGIC {
+            return Err(StructureError::NotASnapshot);
+        }
+        let version = u16::from_le_bytes([data[MAGIC.len()], data[MAGIC.len() + 1]]);
+        if version != VERSION {
+            return Err(StructureError::Version(version));
+        }
+        let snapshot: Snapshot = bincode::deserialize(&data[MAGIC.len() + 2..])
+            .map_err(|e| StructureError::Decode(e.to_string()))?;
+
+        let root = Root::new(snapshot.name);
+        {
+            let mut inner = root.write_locked().expect("failed to write lock");
+            let mut handles: Vec<NodeHandle> = Vec::with_capacity(snapshot.nodes.len());
+            for record in snapshot.nodes {
+                let parent = match record.parent {
+                    Some(i) => match handles.get(i) {
+                        Some(h) => Some(*h),
+                        None => return Err(StructureError::Decode("invalid parent".into())),
+                    },
+                    None => None,
+                };
+                let parent_path = match &parent {
+                    Some(h) => inner.handle_to_path(h).unwrap_or_default(),
+                    None => String::new(),
+                };
+                let path = format!("{}/{}", parent_path, record.address);
+                let node = record.into_node(path.clone(), &storage_factory)?;
+                let handle = inner
+                    .add_node(node, parent)
+                    .map_err(|e| StructureError::Node(path, e))?;
+                handles.push(handle);
+            }
+        }
+        Ok(root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::ValueBuilder;
+    use ::atomic::Atomic;
+
+    //a tree with every kind of node and parameter attribute
+    fn generate(root: &Root, width: usize) {
+        for i in 0..width {
+            let c = root
+                .add_node(
+                    Container::new(format!("c{}", i), Some("container")).unwrap(),
+                    None,
+                )
+                .unwrap();
+            let g = root
+                .add_node(
+                    Get::new(
+                        "get",
+                        Some("read only"),
+                        vec![
+                            ParamGet::Int(
+                                ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _)
+                                    .with_range(Range::MinMax(-1, 1))
+                                    .with_clip_mode(ClipMode::Both)
+                                    .build(),
+                            ),
+                            ParamGet::Float(
+                                ValueBuilder::new(Arc::new(Atomic::new(0f32)) as _)
+                                    .with_unit("distance.m".into())
+                                    .build(),
+                            ),
+                        ],
+                    )
+                    .unwrap()
+                    .with_expires_after(Some(Duration::from_secs(60))),
+                    Some(c),
+                )
+                .unwrap();
+            root.add_node(
+                Get::new(
+                    "lazy",
+                    None,
+                    vec![ParamGet::Double(
+                        ValueBuilder::new(Arc::new(Atomic::new(0f64)) as _).build(),
+                    )],
+                )
+                .unwrap()
+                .with_lazy(true),
+                Some(g),
+            )
+            .unwrap();
+            root.add_node(
+                Set::new(
+                    "set",
+                    None,
+                    vec![ParamSet::Long(
+                        ValueBuilder::new(Arc::new(Atomic::new(0i64)) as _)
+                            .with_range(Range::Min(4))
+                            .build(),
+                    )],
+                    None,
+                )
+                .unwrap(),
+                Some(c),
+            )
+            .unwrap();
+            root.add_node(
+                GetSet::new(
+                    "getset",
+                    Some("read write"),
+                    vec![
+                        ParamGetSet::Bool(
+                            ValueBuilder::new(Arc::new(Atomic::new(false)) as _).build(),
+                        ),
+                        ParamGetSet::Char(
+                            ValueBuilder::new(Arc::new(Atomic::new('a')) as _)
+                                .with_range(Range::Vals(vec!['a', 'b']))
+                                .build(),
+                        ),
+                    ],
+                    None,
+                )
+                .unwrap(),
+                Some(c),
+            )
+            .unwrap();
+        }
+    }
+
+    fn atomic_storage(spec: &PathSpec) -> ParamStorage {
+        macro_rules! params {
+            ($p:ident) => {
+                spec.type_string
+                    .chars()
+                    .map(|t| match t {
+                        'i' => $p::Int(ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _).build()),
+                        'f' => {
+                            $p::Float(ValueBuilder::new(Arc::new(Atomic::new(0f32)) as _).build())
+                        }
+                        'h' => {
+                            $p::Long(ValueBuilder::new(Arc::new(Atomic::new(0i64)) as _).build())
+                        }
+                        'd' => {
+                            $p::Double(ValueBuilder::new(Arc::new(Atomic::new(0f64)) as _).build())
+                        }
+                        'c' => $p::Char(ValueBuilder::new(Arc::new(Atomic::new('a')) as _).build()),
+                        'T' | 'F' => {
+                            $p::Bool(ValueBuilder::new(Arc::new(Atomic::new(false)) as _).build())
+                        }
+                        t => panic!("unexpected type {}", t),
+                    })
+                    .collect()
+            };
+        }
+        match spec.access {
+            Access::ReadOnly => ParamStorage::Get(params!(ParamGet)),
+            Access::WriteOnly => ParamStorage::Set(params!(ParamSet), None),
+            _ => ParamStorage::GetSet(params!(ParamGetSet), None),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let root = Root::new(Some("snapshot".into()));
+        generate(&root, 10);
+        let data = root.export_structure();
+
+        let imported = Root::import_structure(&data, atomic_storage);
+        assert!(imported.is_ok());
+        let imported = imported.unwrap();
+        assert_eq!(root.node_count(), imported.node_count());
+        assert_eq!(
+            serde_json::to_string(&root).unwrap(),
+            serde_json::to_string(&imported).unwrap()
+        );
+        assert_eq!(
+            Some(Duration::from_secs(60)),
+            imported
+                .read_locked()
+                .unwrap()
+                .with_node_at_path("/c3/get", |n| n.unwrap().0.node.expires_after())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let root = Root::new(None);
+        generate(&root, 1);
+        let mut data = root.export_structure();
+
+        assert_matches!(
+            Root::import_structure(b"{}", atomic_storage).err(),
+            Some(StructureError::NotASnapshot)
+        );
+        assert_matches!(
+            Root::import_structure(&data[..data.len() - 4], atomic_storage).err(),
+            Some(StructureError::Decode(..))
+        );
+        assert_matches!(
+            Root::import_structure(&data, |_: &PathSpec| ParamStorage::Get(Vec::new())).err(),
+            Some(StructureError::Storage(p)) if p == "/c0/get"
+        );
+        data[4] = 2;
+        assert_matches!(
+            Root::import_structure(&data, atomic_storage).err(),
+            Some(StructureError::Version(2))
+        );
+    }
+}
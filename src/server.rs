@@ -1,40 +1,183 @@
-use crate::node::Node;
-use crate::root::{NodeHandle, Root};
+use crate::error::{OscQueryError, SnapshotError};
+use crate::node::{Node, OscAddress};
+use crate::osc::{OscBundle, OscMessage, OscPacket, OscType};
+use crate::root::{AliasHandle, MergePolicy, NodeHandle, ResetReport, Root, SubtreeLockGuard};
 use crate::service::{http, osc, websocket};
+use crate::stats::Stats;
+use crate::time::TimeTag;
 use std::net::{SocketAddr, ToSocketAddrs};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
+
+const SHUTDOWN_ADDR: &str = "/oscquery/shutdown";
 
 /// A batteries included ease of use wrapper for the various services that make osc query.
 pub struct OscQueryServer {
+    //first, so the services are unregistered before they stop
+    #[cfg(feature = "mdns")]
+    mdns: Option<crate::service::mdns::MdnsService>,
     root: Arc<Root>,
-    osc: osc::OscService,
-    ws: websocket::WSService,
-    http: http::HttpService,
+    osc: RwLock<Vec<osc::OscService>>,
+    ws: Option<websocket::WSService>,
+    http: Option<http::HttpService>,
+}
+
+/// Build an `OscQueryServer`.
+///
+/// `new` starts out with all the services, `default` with none of them, add the ones to spawn
+/// with `with_http`, `with_osc` and `with_ws`.
+#[derive(Default)]
+pub struct OscQueryServerBuilder {
+    name: Option<String>,
+    http_addr: Option<SocketAddr>,
+    http_config: http::HttpConfig,
+    osc_addrs: Vec<Result<Vec<SocketAddr>, std::io::Error>>,
+    ws_addrs: Option<Result<Vec<SocketAddr>, std::io::Error>>,
+    //spawn OSC and websocket services on ephemeral ports unless addresses are given
+    ephemeral: bool,
+    #[cfg(feature = "mdns")]
+    mdns: bool,
+    #[cfg(feature = "tls")]
+    ws_tls: Option<tokio_rustls::TlsAcceptor>,
+}
+
+impl OscQueryServerBuilder {
+    /// Start building a server with all the services, the http service binds to the given
+    /// address.
+    pub fn new(http_addr: SocketAddr) -> Self {
+        Self {
+            http_addr: Some(http_addr),
+            ephemeral: true,
+            ..Default::default()
+        }
+    }
+
+    /// Set the server name, advertised in `HOST_INFO`.
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Set the address for the http service to bind to.
+    pub fn with_http(mut self, addr: SocketAddr) -> Self {
+        self.http_addr = Some(addr);
+        self
+    }
+
+    /// Set the http service configuration. Defaults to `HttpConfig::default()`.
+    pub fn with_http_config(mut self, config: http::HttpConfig) -> Self {
+        self.http_config = config;
+        self
+    }
+
+    /// Add an OSC service bound to the given address, can be called more than once.
+    ///
+    /// All OSC services feed the same tree and triggers are sent via all of them.
+    /// With `new`, defaults to a single service on an ephemeral port of the http address' ip.
+    pub fn with_osc<A: ToSocketAddrs>(mut self, addr: A) -> Self {
+        self.osc_addrs
+            .push(addr.to_socket_addrs().map(|a| a.collect()));
+        self
+    }
+
+    /// Set the address for the websocket service to bind to.
+    ///
+    /// With `new`, defaults to an ephemeral port of the http address' ip.
+    pub fn with_ws<A: ToSocketAddrs>(mut self, addr: A) -> Self {
+        self.ws_addrs = Some(addr.to_socket_addrs().map(|a| a.collect()));
+        self
+    }
+
+    /// Serve the websocket as websocket secure (wss), with the acceptor's TLS configuration.
+    ///
+    /// Defaults to `None`, plain websockets.
+    #[cfg(feature = "tls")]
+    pub fn with_ws_tls(mut self, tls: Option<tokio_rustls::TlsAcceptor>) -> Self {
+        self.ws_tls = tls;
+        self
+    }
+
+    /// Advertise the server via mDNS, the http service as `_oscjson._tcp` and the OSC services
+    /// as `_osc._udp`, with the server name, or `oscquery`, as the instance name.
+    ///
+    /// Defaults to false, ignored without the http service.
+    #[cfg(feature = "mdns")]
+    pub fn with_mdns(mut self, mdns: bool) -> Self {
+        self.mdns = mdns;
+        self
+    }
+
+    /// Bind and start the services.
+    pub fn build(self) -> Result<OscQueryServer, OscQueryError> {
+        #[cfg(feature = "mdns")]
+        let mdns_name = self.name.clone().unwrap_or_else(|| "oscquery".to_string());
+        let root = Arc::new(Root::new(self.name));
+        let mut osc_addrs = self.osc_addrs;
+        let mut ws_addrs = self.ws_addrs;
+        if let (true, Some(http_addr)) = (self.ephemeral, self.http_addr) {
+            let ephemeral = vec![SocketAddr::new(http_addr.ip(), 0)];
+            if osc_addrs.is_empty() {
+                osc_addrs.push(Ok(ephemeral.clone()));
+            }
+            ws_addrs.get_or_insert(Ok(ephemeral));
+        }
+        let osc = osc_addrs
+            .into_iter()
+            .map(|addrs| root.spawn_osc(&addrs?[..]))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ws = match ws_addrs {
+            #[cfg(feature = "tls")]
+            Some(addrs) if self.ws_tls.is_some() => {
+                Some(root.spawn_ws_tls(&addrs?[..], self.ws_tls.unwrap())?)
+            }
+            Some(addrs) => Some(root.spawn_ws(&addrs?[..])?),
+            None => None,
+        };
+        let http = match self.http_addr {
+            Some(addr) => Some(http::HttpService::new_with_config(
+                root.clone(),
+                &addr,
+                osc.iter().map(|o| *o.local_addr()).collect(),
+                ws.as_ref().map(|ws| *ws.local_addr()),
+                self.http_config,
+            )?),
+            None => None,
+        };
+
+        #[cfg(feature = "mdns")]
+        let mdns = match (&http, self.mdns) {
+            (Some(http), true) => Some(crate::service::mdns::MdnsService::new(
+                &mdns_name,
+                http.local_addr(),
+                &osc.iter().map(|o| *o.local_addr()).collect::<Vec<_>>(),
+            )?),
+            _ => None,
+        };
+
+        Ok(OscQueryServer {
+            #[cfg(feature = "mdns")]
+            mdns,
+            root,
+            osc: RwLock::new(osc),
+            ws,
+            http,
+        })
+    }
 }
 
 impl OscQueryServer {
+    ///Bind and start all the services, see `OscQueryServerBuilder` to pick which ones.
     pub fn new<OA: ToSocketAddrs, WA: ToSocketAddrs>(
         server_name: Option<String>,
         http_addr: &SocketAddr,
         osc_addr: OA,
         ws_addr: WA,
-    ) -> Result<Self, std::io::Error> {
-        let root = Arc::new(Root::new(server_name));
-        let osc = root.spawn_osc(osc_addr)?;
-        let ws = root.spawn_ws(ws_addr)?;
-        let http = http::HttpService::new(
-            root.clone(),
-            http_addr,
-            Some(osc.local_addr().clone()),
-            Some(ws.local_addr().clone()),
-        );
-
-        Ok(Self {
-            root,
-            osc,
-            ws,
-            http,
-        })
+    ) -> Result<Self, OscQueryError> {
+        let mut builder = OscQueryServerBuilder::new(*http_addr)
+            .with_osc(osc_addr)
+            .with_ws(ws_addr);
+        builder.name = server_name;
+        builder.build()
     }
 
     ///Add node to the graph at the root or as a child of the given parent
@@ -42,61 +185,1330 @@ impl OscQueryServer {
         &self,
         node: N,
         parent: Option<NodeHandle>,
-    ) -> Result<NodeHandle, (Node, &'static str)>
+    ) -> Result<NodeHandle, OscQueryError>
     where
         N: Into<Node>,
     {
         self.root.add_node(node.into(), parent)
     }
 
+    ///Add node at the full path, creating the containers above it, see `Root::add_node_at_path`.
+    pub fn add_node_at_path<N>(&self, node: N, path: &str) -> Result<NodeHandle, OscQueryError>
+    where
+        N: Into<Node>,
+    {
+        self.root.add_node_at_path(node, path)
+    }
+
+    ///Merge the nodes of another tree into this one, see `Root::merge`.
+    pub fn merge(
+        &self,
+        other: &Root,
+        prefix: Option<&str>,
+        policy: MergePolicy,
+    ) -> Result<Vec<NodeHandle>, OscQueryError> {
+        self.root.merge(other, prefix, policy)
+    }
+
     ///Remove the node at the handle returns it and any children if found.
     ///
     ///Leaves come first in returned vector.
-    pub fn rm_node(&self, handle: NodeHandle) -> Result<Vec<Node>, (NodeHandle, &'static str)> {
+    pub fn rm_node(&self, handle: NodeHandle) -> Result<Vec<Node>, OscQueryError> {
         self.root.rm_node(handle)
     }
 
+    ///Remove the node at the full path, see `Root::rm_node_at_path`.
+    pub fn rm_node_at_path(&self, path: &str) -> Result<Vec<Node>, OscQueryError> {
+        self.root.rm_node_at_path(path)
+    }
+
+    ///The addresses and handles of the node's immediate children, see `Root::children`.
+    pub fn children(&self, handle: NodeHandle) -> Option<Vec<(String, NodeHandle)>> {
+        self.root.children(handle)
+    }
+
+    ///Record that the value of the node at the handle was written, see `Root::touch`.
+    pub fn touch(&self, handle: &NodeHandle) {
+        self.root.touch(handle)
+    }
+
+    ///Set the parameters at and below the handle, or in the whole tree, to their defaults, see
+    ///`Root::reset_to_defaults`.
+    ///
+    ///With `trigger` the readable nodes that were written are triggered afterwards, see
+    ///`trigger_path`, `ResetReport::triggered` has the messages that were sent.
+    pub fn reset_to_defaults(&self, scope: Option<NodeHandle>, trigger: bool) -> ResetReport {
+        let mut report = self.root.reset_to_defaults(scope, false);
+        if trigger {
+            report.triggered = report
+                .paths
+                .iter()
+                .filter_map(|path| {
+                    self.send_rendered(path, || self.root.render_readable_path(path))
+                })
+                .collect();
+        }
+        report
+    }
+
+    ///Get the defaults of the node at the path, see `Root::default_at_path`.
+    pub fn default_at_path(&self, path: &str) -> Option<Vec<OscType>> {
+        self.root.default_at_path(path)
+    }
+
+    ///Get an argument of the current value of the node at the path, see
+    ///`Root::value_at_path_indexed`.
+    pub fn value_at_path_indexed(&self, path: &str, index: usize) -> Option<OscType> {
+        self.root.value_at_path_indexed(path, index)
+    }
+
+    ///Replace an argument of the current value of the node at the path, see
+    ///`Root::set_value_at_path_indexed`.
+    pub fn set_value_at_path_indexed(
+        &self,
+        path: &str,
+        index: usize,
+        arg: OscType,
+    ) -> Result<(), OscQueryError> {
+        self.root.set_value_at_path_indexed(path, index, arg)
+    }
+
+    ///Set the value of the writable node at the path from JSON, see
+    ///`Root::set_value_at_path_json`.
+    pub fn set_value_at_path_json(
+        &self,
+        path: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), OscQueryError> {
+        self.root.set_value_at_path_json(path, value)
+    }
+
+    ///The current values of all the readable nodes, see `Root::snapshot_values`.
+    pub fn snapshot_values(&self) -> serde_json::Value {
+        self.root.snapshot_values()
+    }
+
+    ///Apply the values of a snapshot, see `Root::apply_values`.
+    pub fn apply_values(&self, snapshot: &serde_json::Value) -> Result<usize, SnapshotError> {
+        self.root.apply_values(snapshot)
+    }
+
+    ///Set, or clear, the OSC address for the outgoing messages of the node at the handle, see
+    ///`Root::set_osc_address`.
+    pub fn set_osc_address(
+        &self,
+        handle: &NodeHandle,
+        osc_address: Option<OscAddress>,
+    ) -> Result<(), OscQueryError> {
+        self.root.set_osc_address(handle, osc_address)
+    }
+
+    ///Tell websocket clients that the attributes of the node at the handle changed, see
+    ///`Root::notify_changed`.
+    pub fn notify_changed(&self, handle: &NodeHandle) {
+        self.root.notify_changed(handle)
+    }
+
+    ///Replace the node at the handle in place, returns the old node, see `Root::replace_node`.
+    pub fn replace_node<N>(&self, handle: &NodeHandle, node: N) -> Result<Node, OscQueryError>
+    where
+        N: Into<Node>,
+    {
+        self.root.replace_node(handle, node)
+    }
+
+    ///Expose the node at the target handle at an additional path, see `Root::add_alias`.
+    pub fn add_alias(
+        &self,
+        target: NodeHandle,
+        alias_parent: Option<NodeHandle>,
+        alias_address: &str,
+    ) -> Result<AliasHandle, OscQueryError> {
+        self.root.add_alias(target, alias_parent, alias_address)
+    }
+
+    ///Remove an alias, the target node is untouched.
+    pub fn rm_alias(&self, handle: AliasHandle) -> Result<(), OscQueryError> {
+        self.root.rm_alias(handle)
+    }
+
+    ///Also emit at the alias path whenever the target is triggered, see `Root::set_alias_trigger`.
+    pub fn set_alias_trigger(
+        &self,
+        handle: AliasHandle,
+        trigger: bool,
+    ) -> Result<(), OscQueryError> {
+        self.root.set_alias_trigger(handle, trigger)
+    }
+
+    ///Freeze the structure of the subtree at the handle, see `Root::freeze_subtree`.
+    pub fn freeze_subtree(&self, handle: &NodeHandle) -> Result<(), OscQueryError> {
+        self.root.freeze_subtree(handle)
+    }
+
+    ///Undo a freeze made with `freeze_subtree`.
+    pub fn unfreeze_subtree(&self, handle: &NodeHandle) -> Result<(), OscQueryError> {
+        self.root.unfreeze_subtree(handle)
+    }
+
+    ///Freeze the subtree at the handle until the returned guard is dropped, see
+    ///`Root::lock_subtree`.
+    pub fn lock_subtree(&self, handle: NodeHandle) -> Result<SubtreeLockGuard, OscQueryError> {
+        self.root.lock_subtree(handle)
+    }
+
+    ///Move the node at the handle under the new parent, or the root, see `Root::move_node`.
+    pub fn move_node(
+        &self,
+        handle: NodeHandle,
+        new_parent: Option<NodeHandle>,
+    ) -> Result<NodeHandle, OscQueryError> {
+        self.root.move_node(handle, new_parent)
+    }
+
+    ///Give the node at the handle a new address, see `Root::rename_node`.
+    pub fn rename_node(
+        &self,
+        handle: NodeHandle,
+        new_address: String,
+    ) -> Result<(String, String), OscQueryError> {
+        self.root.rename_node(handle, new_address)
+    }
+
     /// Get the full path that a handle represents, if it exists.
     pub fn handle_to_path(&self, handle: &NodeHandle) -> Option<String> {
         self.root.handle_to_path(handle)
     }
 
-    ///Get the Http service's bound address.
-    pub fn http_local_addr(&self) -> &SocketAddr {
-        self.http.local_addr()
+    /// Get the handle of the node at the full path, see `Root::path_to_handle`.
+    pub fn path_to_handle(&self, path: &str) -> Option<NodeHandle> {
+        self.root.path_to_handle(path)
+    }
+
+    ///Get the Http service's bound address, if it is running.
+    pub fn http_local_addr(&self) -> Option<&SocketAddr> {
+        self.http.as_ref().map(|http| http.local_addr())
+    }
+
+    ///Get the first OSC service's bound address, if there is one.
+    pub fn osc_local_addr(&self) -> Option<SocketAddr> {
+        self.osc_services().first().map(|o| *o.local_addr())
+    }
+
+    ///Get the OSC services.
+    ///
+    ///*NOTE* `rebind_osc` blocks while the returned guard is held.
+    pub fn osc_services(&self) -> RwLockReadGuard<'_, Vec<osc::OscService>> {
+        self.osc.read().expect("failed to get read lock")
+    }
+
+    ///Move the first OSC service to a new address, returns the address it bound to.
+    ///
+    ///The new service takes over the send addresses, send interval and queued messages of the
+    ///old one, which is stopped once it is swapped out. `HOST_INFO` advertises the new address
+    ///right away. On error the old service keeps running, failing to update the mDNS
+    ///announcement is only logged.
+    pub fn rebind_osc<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr, OscQueryError> {
+        if self.osc_services().is_empty() {
+            return Err(OscQueryError::ServiceNotRunning("osc"));
+        }
+        let service = self.root.spawn_osc(addr)?;
+        let local_addr = *service.local_addr();
+        let old = {
+            let mut osc = self.osc.write().expect("failed to get write lock");
+            service.migrate_from(&osc[0]);
+            let old = std::mem::replace(&mut osc[0], service);
+            let addrs: Vec<SocketAddr> = osc.iter().map(|o| *o.local_addr()).collect();
+            if let Some(http) = &self.http {
+                http.set_osc_addrs(addrs.clone());
+            }
+            //the swap is done, a failed announcement doesn't undo it
+            #[cfg(feature = "mdns")]
+            {
+                if let Some(mdns) = &self.mdns {
+                    if let Err(e) = mdns.set_osc_addrs(&addrs) {
+                        log::warn!("failed to announce the osc address {}: {}", local_addr, e);
+                    }
+                }
+            }
+            old
+        };
+        //joins the service thread, so outside of the lock
+        drop(old);
+        Ok(local_addr)
+    }
+
+    ///Get the websocket service's bound address, if it is running.
+    pub fn ws_local_addr(&self) -> Option<&SocketAddr> {
+        self.ws.as_ref().map(|ws| ws.local_addr())
+    }
+
+    ///Add an address for every OSC service to send all outgoing OSC messages to.
+    ///
+    ///Use `osc_services` to add an address to a single service.
+    pub fn add_osc_send_addr(&self, addr: SocketAddr) {
+        for osc in self.osc_services().iter() {
+            osc.add_send_addr(addr);
+        }
+    }
+
+    ///Stop sending outgoing OSC messages to an address from every OSC service.
+    ///
+    ///Returns false if no service was sending to it, see `OscService::remove_send_addr`.
+    pub fn remove_osc_send_addr(&self, addr: SocketAddr) -> bool {
+        let mut removed = false;
+        for osc in self.osc_services().iter() {
+            removed |= osc.remove_send_addr(addr);
+        }
+        removed
+    }
+
+    ///Render and send triggered messages even when nobody would receive them, see
+    ///`Root::set_force_trigger`.
+    pub fn set_force_trigger(&self, force: bool) {
+        self.root.set_force_trigger(force)
+    }
+
+    ///Set the policy that decides what each websocket client may LISTEN to, receive and write.
+    ///
+    ///Does nothing without the websocket service.
+    pub fn set_ws_policy(&self, policy: Box<dyn websocket::WsPolicy>) {
+        if let Some(ws) = &self.ws {
+            ws.set_policy(policy)
+        }
+    }
+
+    ///The number of websocket clients that LISTEN to the path, see `WSService::listeners`.
+    ///
+    ///Zero without the websocket service.
+    pub fn ws_listeners(&self, path: &str) -> usize {
+        self.ws.as_ref().map_or(0, |ws| ws.listeners(path))
+    }
+
+    ///The paths websocket clients LISTEN to, see `WSService::listened_paths`.
+    ///
+    ///Empty without the websocket service.
+    pub fn ws_listened_paths(&self) -> Vec<String> {
+        self.ws
+            .as_ref()
+            .map_or_else(Vec::new, |ws| ws.listened_paths())
     }
 
-    ///Get the OSC service's bound address.
-    pub fn osc_local_addr(&self) -> &SocketAddr {
-        self.osc.local_addr()
+    ///Call the hook when websocket clients start or stop listening to a path, see
+    ///`WSService::on_listen`.
+    ///
+    ///Does nothing without the websocket service.
+    pub fn on_ws_listen<F>(&self, hook: F)
+    where
+        F: Fn(&str, bool) + Send + Sync + 'static,
+    {
+        if let Some(ws) = &self.ws {
+            ws.on_listen(hook)
+        }
     }
 
-    ///Get the websocket service's bound address.
-    pub fn ws_local_addr(&self) -> &SocketAddr {
-        self.ws.local_addr()
+    ///Get the statistics shared by the services.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.root.stats()
+    }
+
+    ///Tell clients that the server is going away, keep serving for the grace period and then stop.
+    ///
+    ///Websocket clients, including any that connect during the grace period, get a
+    ///`{"COMMAND":"SERVER_SHUTDOWN","DATA":{"GRACE_MS":...}}` text message, the OSC send
+    ///addresses get a `/oscquery/shutdown` message with the grace in milliseconds as an int
+    ///argument and `HOST_INFO` includes `SHUTDOWN_AT`, in milliseconds since the unix epoch.
+    ///
+    ///*NOTE* this blocks the calling thread for the grace period.
+    pub fn announce_shutdown(self, grace: Duration) {
+        if let Some(http) = &self.http {
+            http.announce_shutdown(self.root.clock().wall() + grace);
+        }
+        if let Some(ws) = &self.ws {
+            ws.announce_shutdown(grace);
+        }
+        self.send_osc(&OscMessage {
+            addr: SHUTDOWN_ADDR.to_string(),
+            args: vec![OscType::Int(grace.as_millis() as i32)],
+        });
+        std::thread::sleep(grace);
     }
 
     ///Trigger a send (if possible) for the node at the given handle.
     ///
-    ///Returns true if there was a node at the handle that could be and was triggered.
+    ///The message is sent via every OSC service and to the websocket clients listening to it,
+    ///only to the latter for critical nodes, see `Get::with_critical`. Returns true if there
+    ///was a node at the handle that could be and was triggered, false also when nobody would
+    ///receive it: no OSC service has a send address and no websocket client LISTENs to it or to
+    ///an alias that emits on trigger. Then nothing is rendered, unless `set_force_trigger`.
     pub fn trigger(&self, handle: NodeHandle) -> bool {
-        if let Some(msg) = self.osc.trigger(handle) {
-            self.ws.send(msg);
-            true
-        } else {
-            false
+        let path = match self.root.handle_to_path(&handle) {
+            Some(path) => path,
+            None => return false,
+        };
+        let critical = self.root.critical_at_path(&path);
+        if !self.trigger_wanted(&path, critical) {
+            return false;
+        }
+        match self.root.render(&handle) {
+            Some(msg) => {
+                self.send_alias_triggers(&path, &msg, critical);
+                self.send_osc_ws(path, msg, critical)
+            }
+            None => false,
         }
     }
 
     ///Trigger a send (if possible) for the node at the given path.
     ///
-    ///Returns true if there was a node at the path that could be and was triggered.
+    ///The message is sent via every OSC service and to the websocket clients listening to it.
+    ///Returns true if there was a node at the path that could be and was triggered, false also
+    ///when nobody would receive it, see `trigger`.
     pub fn trigger_path(&self, path: &str) -> bool {
-        if let Some(msg) = self.osc.trigger_path(path) {
-            self.ws.send(msg);
-            true
+        self.send_rendered(path, || self.root.render_path(path))
+            .is_some()
+    }
+
+    //`trigger_path` with the rendering, returns the message that was sent
+    fn send_rendered<F>(&self, path: &str, render: F) -> Option<OscMessage>
+    where
+        F: FnOnce() -> Option<OscMessage>,
+    {
+        let critical = self.root.critical_at_path(path);
+        if !self.trigger_wanted(path, critical) {
+            return None;
+        }
+        let msg = render()?;
+        self.send_alias_triggers(path, &msg, critical);
+        if self.send_osc_ws(path.to_string(), msg.clone(), critical) {
+            Some(msg)
         } else {
-            false
+            None
+        }
+    }
+
+    ///Trigger a send for every readable node in the tree, in path order.
+    ///
+    ///See `trigger`, returns the messages of the nodes that were sent.
+    pub fn trigger_all(&self) -> Vec<OscMessage> {
+        self.root
+            .render_all()
+            .into_iter()
+            .filter_map(|(path, msg, _)| {
+                let critical = self.root.critical_at_path(&path);
+                self.send_alias_triggers(&path, &msg, critical);
+                if self.send_osc_ws(path, msg.clone(), critical) {
+                    Some(msg)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    ///Like `trigger_all` but the OSC services send all the messages in a single bundle, timed
+    ///with the current wall clock time. Critical nodes are left out of the bundle.
+    ///
+    ///Websocket clients get the messages they listen to one by one. Returns the messages of the
+    ///nodes that were sent, nothing is sent if there are none.
+    ///*NOTE* the bundle of a large tree may not fit in a UDP datagram.
+    pub fn trigger_all_bundle(&self) -> Vec<OscMessage> {
+        let rendered = self.root.render_all();
+        let timetag = TimeTag::from_system_time(self.root.clock().wall());
+        if rendered.is_empty() || !self.send_bundle_osc_ws(&rendered, timetag) {
+            return Vec::new();
+        }
+        rendered.into_iter().map(|(_, msg, _)| msg).collect()
+    }
+
+    ///Trigger the nodes at the handles together, see `OscService::trigger_bundle`.
+    ///
+    ///The OSC services send the messages in a single bundle with the timetag, immediate if
+    ///`None`, critical nodes are left out of it. Websocket clients get the messages they listen
+    ///to one by one. Invalid handles are skipped, as are nodes nobody would receive, see
+    ///`trigger`. Returns the bundle of all the messages that were sent, `None` if there are none.
+    pub fn trigger_bundle<I>(&self, handles: I, timetag: Option<(u32, u32)>) -> Option<OscBundle>
+    where
+        I: IntoIterator<Item = NodeHandle>,
+    {
+        let handles: Vec<NodeHandle> = handles
+            .into_iter()
+            .filter(|handle| {
+                self.root.handle_to_path(handle).is_some_and(|path| {
+                    self.trigger_wanted(&path, self.root.critical_at_path(&path))
+                })
+            })
+            .collect();
+        let rendered = self.root.render_handles(handles);
+        let timetag = timetag.map_or(TimeTag::IMMEDIATE, TimeTag::from);
+        if rendered.is_empty() || !self.send_bundle_osc_ws(&rendered, timetag) {
+            return None;
+        }
+        Some(osc::trigger_bundle_at(&rendered, timetag))
+    }
+
+    //the OSC services send the rendered messages in a bundle, critical ones left out, the
+    //websocket clients get them one by one. False if encoding failed or no service is running
+    fn send_bundle_osc_ws(
+        &self,
+        rendered: &[(String, OscMessage, Vec<String>)],
+        timetag: TimeTag,
+    ) -> bool {
+        let osc = !self.osc_services().is_empty();
+        //critical nodes only go to the websocket clients
+        let udp: Vec<_> = rendered
+            .iter()
+            .filter(|(path, ..)| !self.root.critical_at_path(path))
+            .cloned()
+            .collect();
+        if osc && !udp.is_empty() {
+            let bundle = osc::trigger_bundle_at(&udp, timetag);
+            match crate::osc::encoder::encode(&OscPacket::Bundle(bundle.clone())) {
+                Ok(buf) => {
+                    for osc in self.osc_services().iter() {
+                        osc.send_bundle(&bundle, &buf);
+                    }
+                }
+                Err(..) => {
+                    eprintln!("error encoding");
+                    return false;
+                }
+            }
+        }
+        match &self.ws {
+            //skip the normalization and queuing without clients
+            Some(ws) if ws.client_count() > 0 => {
+                for (path, msg, aliases) in rendered {
+                    for alias in aliases {
+                        let msg = OscMessage {
+                            addr: alias.clone(),
+                            args: msg.args.clone(),
+                        };
+                        ws.send_for(alias.clone(), msg);
+                    }
+                    ws.send_for(path.clone(), msg.clone());
+                }
+                true
+            }
+            Some(..) => true,
+            None => osc,
+        }
+    }
+
+    //also send at the paths of the aliases that emit on trigger
+    fn send_alias_triggers(&self, path: &str, msg: &OscMessage, critical: bool) {
+        for alias in self.root.alias_triggers(path) {
+            let msg = OscMessage {
+                addr: alias.clone(),
+                args: msg.args.clone(),
+            };
+            self.send_osc_ws(alias, msg, critical);
+        }
+    }
+
+    //whether a triggered message of the path would reach anyone, an OSC send address or a
+    //websocket client listening to it or to one of its trigger aliases
+    fn trigger_wanted(&self, path: &str, critical: bool) -> bool {
+        if !critical && self.osc_services().iter().any(|osc| osc.has_send_addrs()) {
+            return true;
+        }
+        let ws = match &self.ws {
+            Some(ws) if ws.client_count() > 0 => {
+                ws.has_listeners(path)
+                    || self
+                        .root
+                        .alias_triggers(path)
+                        .iter()
+                        .any(|a| ws.has_listeners(a))
+            }
+            _ => false,
+        };
+        ws || self.root.force_trigger()
+    }
+
+    //encode once, send via each OSC service
+    fn send_osc(&self, msg: &OscMessage) -> bool {
+        match crate::osc::encoder::encode(&OscPacket::Message(msg.clone())) {
+            Ok(buf) => {
+                for osc in self.osc_services().iter() {
+                    osc.send(msg, &buf);
+                }
+                true
+            }
+            Err(..) => {
+                eprintln!("error encoding");
+                false
+            }
+        }
+    }
+
+    //ws clients LISTEN to the path, which can differ from the message address, critical
+    //messages skip the OSC services
+    fn send_osc_ws(&self, path: String, msg: OscMessage, critical: bool) -> bool {
+        let osc = !critical && !self.osc_services().is_empty();
+        if osc && !self.send_osc(&msg) {
+            return false;
+        }
+        match &self.ws {
+            Some(ws) => {
+                //skip the normalization and queuing without clients
+                if ws.client_count() > 0 {
+                    ws.send_for(path, msg);
+                }
+                true
+            }
+            None => osc,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::http::tests::{get, get_host, wait_for};
+    use crate::service::websocket::tests::{connect, listen, read_osc, read_text};
+    use std::net::{TcpStream, UdpSocket};
+
+    #[test]
+    fn announce_shutdown() {
+        let server = OscQueryServer::new(
+            None,
+            &"127.0.0.1:0".parse().unwrap(),
+            "127.0.0.1:0",
+            "127.0.0.1:0",
+        )
+        .expect("failed to create server");
+        let http_addr = *server.http_local_addr().unwrap();
+        let ws_addr = *server.ws_local_addr().unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        let mut client = connect(&ws_addr);
+
+        let grace = Duration::from_millis(500);
+        let handle = std::thread::spawn(move || server.announce_shutdown(grace));
+
+        let cmd = read_text(&mut client);
+        assert_eq!(cmd["COMMAND"], "SERVER_SHUTDOWN");
+        assert!(cmd["DATA"]["GRACE_MS"].as_u64().unwrap() <= 500);
+
+        let mut buf = [0u8; 1024];
+        let (size, _) = udp.recv_from(&mut buf).expect("no osc shutdown message");
+        match crate::osc::decoder::decode(&buf[..size]) {
+            Ok(crate::osc::OscPacket::Message(m)) => {
+                assert_eq!(m.addr, SHUTDOWN_ADDR);
+                assert_eq!(m.args, vec![OscType::Int(500)]);
+            }
+            other => panic!("unexpected packet {:?}", other),
+        }
+
+        //still serving, and late clients still get told
+        let (status, _, body) = get(&http_addr, "/?HOST_INFO");
+        assert_eq!(status, 200);
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(info["SHUTDOWN_AT"].as_u64().is_some());
+        let mut late = connect(&ws_addr);
+        assert_eq!(read_text(&mut late)["COMMAND"], "SERVER_SHUTDOWN");
+
+        assert!(handle.join().is_ok());
+        wait_for(|| TcpStream::connect(http_addr).is_err());
+        assert!(TcpStream::connect(ws_addr).is_err());
+    }
+
+    #[test]
+    fn multiple_osc() {
+        let server = OscQueryServerBuilder::new("127.0.0.1:0".parse().unwrap())
+            .with_osc("127.0.0.1:0")
+            .with_osc("127.0.0.2:0")
+            .build()
+            .expect("failed to create server");
+        let a = Arc::new(::atomic::Atomic::new(0i32));
+        let m = crate::node::GetSet::new(
+            "foo",
+            None,
+            vec![crate::param::ParamGetSet::Int(
+                crate::value::ValueBuilder::new(a.clone() as _).build(),
+            )],
+            None,
+        );
+        let handle = server.add_node(m.unwrap(), None).unwrap();
+        let services: Vec<SocketAddr> = server
+            .osc_services()
+            .iter()
+            .map(|o| *o.local_addr())
+            .collect();
+        assert_eq!(services.len(), 2);
+        assert_eq!(server.osc_local_addr().unwrap(), services[0]);
+
+        //HOST_INFO advertises the service on the ip the request was addressed to
+        let http_addr = *server.http_local_addr().unwrap();
+        for (host, expected) in &[
+            (format!("127.0.0.2:{}", http_addr.port()), services[1]),
+            (format!("127.0.0.1:{}", http_addr.port()), services[0]),
+            ("localhost".to_string(), services[0]),
+        ] {
+            let (status, _, body) = get_host(&http_addr, host, "/?HOST_INFO");
+            assert_eq!(status, 200);
+            let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(info["OSC_IP"], expected.ip().to_string());
+            assert_eq!(info["OSC_PORT"], expected.port());
+            assert_eq!(info["OSC_SERVICES"].as_array().unwrap().len(), 2);
+        }
+
+        let listeners: Vec<UdpSocket> = services
+            .iter()
+            .map(|s| {
+                let l = UdpSocket::bind(SocketAddr::new(s.ip(), 0)).unwrap();
+                l.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                l
+            })
+            .collect();
+        for (service, l) in server.osc_services().iter().zip(listeners.iter()) {
+            service.add_send_addr(l.local_addr().unwrap());
+        }
+
+        //write via the second, trigger to both
+        let packet = OscPacket::Message(OscMessage {
+            addr: "/foo".into(),
+            args: vec![OscType::Int(42)],
+        });
+        listeners[1]
+            .send_to(&crate::osc::encoder::encode(&packet).unwrap(), services[1])
+            .unwrap();
+        wait_for(|| a.load(::atomic::Ordering::SeqCst) == 42);
+        assert!(server.trigger(handle));
+
+        for (l, service) in listeners.iter().zip(services.iter()) {
+            let mut buf = [0u8; 1024];
+            let (size, from) = l.recv_from(&mut buf).expect("no message");
+            assert_eq!(&from, service);
+            assert_eq!(crate::osc::decoder::decode(&buf[..size]).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn optional_services() {
+        let int_node = |a: &Arc<::atomic::Atomic<i32>>| {
+            crate::node::GetSet::new(
+                "foo",
+                None,
+                vec![crate::param::ParamGetSet::Int(
+                    crate::value::ValueBuilder::new(a.clone() as _).build(),
+                )],
+                None,
+            )
+            .unwrap()
+        };
+        let host_info = |server: &OscQueryServer| {
+            let (status, _, body) = get(server.http_local_addr().unwrap(), "/?HOST_INFO");
+            assert_eq!(status, 200);
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()
+        };
+
+        //http and osc, no websocket
+        let server = OscQueryServerBuilder::default()
+            .with_http("127.0.0.1:0".parse().unwrap())
+            .with_osc("127.0.0.1:0")
+            .build()
+            .expect("failed to create server");
+        assert!(server.ws_local_addr().is_none());
+        let osc_addr = server.osc_local_addr().unwrap();
+        let info = host_info(&server);
+        assert_eq!(info["OSC_PORT"], osc_addr.port());
+        assert!(info.get("WS_IP").is_none());
+        assert!(info.get("WS_PORT").is_none());
+        assert_eq!(info["EXTENSIONS"]["LISTEN"], false);
+        assert_eq!(info["EXTENSIONS"]["PATH_ADDED"], false);
+        assert_eq!(info["EXTENSIONS"]["PATH_RENAMED"], false);
+        assert_eq!(info["EXTENSIONS"]["OVERLOADS"], true);
+        assert_eq!(info["EXTENSIONS"]["DEFAULT"], true);
+        let a = Arc::new(::atomic::Atomic::new(3i32));
+        let handle = server.add_node(int_node(&a), None).unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        assert!(server.trigger(handle));
+        let mut buf = [0u8; 1024];
+        let (_, from) = udp.recv_from(&mut buf).expect("no message");
+        assert_eq!(from, osc_addr);
+
+        //http and websocket, no osc
+        let server = OscQueryServerBuilder::default()
+            .with_http("127.0.0.1:0".parse().unwrap())
+            .with_ws("127.0.0.1:0")
+            .build()
+            .expect("failed to create server");
+        assert!(server.osc_local_addr().is_none());
+        assert!(server.osc_services().is_empty());
+        assert_matches!(
+            server.rebind_osc("127.0.0.1:0"),
+            Err(OscQueryError::ServiceNotRunning(_))
+        );
+        let info = host_info(&server);
+        assert!(info.get("OSC_IP").is_none());
+        assert!(info.get("OSC_PORT").is_none());
+        assert_eq!(info["WS_PORT"], server.ws_local_addr().unwrap().port());
+        assert_eq!(info["EXTENSIONS"]["LISTEN"], true);
+        assert_eq!(info["EXTENSIONS"]["PATH_RENAMED"], true);
+        let mut client = connect(server.ws_local_addr().unwrap());
+        server.add_node(int_node(&a), None).unwrap();
+        assert_eq!(read_text(&mut client)["COMMAND"], "PATH_ADDED");
+        listen(&mut client, server.ws.as_ref().unwrap(), "/foo");
+        assert!(server.trigger_path("/foo"));
+        let msg = read_osc(&mut client);
+        assert_eq!(msg.addr, "/foo");
+        assert_eq!(msg.args, vec![OscType::Int(3)]);
+
+        //nothing to trigger to
+        let server = OscQueryServerBuilder::default()
+            .build()
+            .expect("failed to create server");
+        assert!(server.http_local_addr().is_none());
+        let handle = server.add_node(int_node(&a), None).unwrap();
+        assert!(!server.trigger(handle));
+    }
+
+    #[test]
+    fn rebind_osc() {
+        let server = Arc::new(
+            OscQueryServer::new(
+                None,
+                &"127.0.0.1:0".parse().unwrap(),
+                "127.0.0.1:0",
+                "127.0.0.1:0",
+            )
+            .expect("failed to create server"),
+        );
+        let a = Arc::new(::atomic::Atomic::new(0i32));
+        let handle = server
+            .add_node(
+                crate::node::GetSet::new(
+                    "foo",
+                    None,
+                    vec![crate::param::ParamGetSet::Int(
+                        crate::value::ValueBuilder::new(a.clone() as _).build(),
+                    )],
+                    None,
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        let old = server.osc_local_addr().unwrap();
+
+        let count = 200;
+        let triggers = {
+            let server = server.clone();
+            std::thread::spawn(move || {
+                for _ in 0..count {
+                    assert!(server.trigger(handle));
+                    std::thread::sleep(Duration::from_micros(100));
+                }
+            })
+        };
+        std::thread::sleep(Duration::from_millis(5));
+        let new = server.rebind_osc("127.0.0.1:0").unwrap();
+        assert_ne!(new, old);
+        assert_eq!(server.osc_local_addr().unwrap(), new);
+        triggers.join().unwrap();
+
+        //no trigger is lost, the later ones come from the new port
+        let mut buf = [0u8; 1024];
+        let mut from = Vec::new();
+        for _ in 0..count {
+            let (_, addr) = udp.recv_from(&mut buf).expect("trigger lost");
+            from.push(addr);
+        }
+        assert_eq!(from.last(), Some(&new));
+        assert!(from.iter().all(|f| f == &old || f == &new));
+
+        let (status, _, body) = get(server.http_local_addr().unwrap(), "/?HOST_INFO");
+        assert_eq!(status, 200);
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(info["OSC_PORT"], new.port());
+
+        //the new port receives, the old socket is closed
+        let packet = OscPacket::Message(OscMessage {
+            addr: "/foo".into(),
+            args: vec![OscType::Int(7)],
+        });
+        udp.send_to(&crate::osc::encoder::encode(&packet).unwrap(), new)
+            .unwrap();
+        wait_for(|| a.load(::atomic::Ordering::SeqCst) == 7);
+        assert!(UdpSocket::bind(old).is_ok());
+    }
+
+    #[cfg(feature = "mdns")]
+    #[test]
+    fn mdns() {
+        use crate::service::mdns::{tests::browse, OSCJSON_TYPE, OSC_UDP_TYPE};
+        let server = OscQueryServerBuilder::new("127.0.0.1:0".parse().unwrap())
+            .with_name("mdns server".to_string())
+            .with_mdns(true)
+            .build()
+            .expect("failed to create server");
+        assert_eq!(
+            browse(OSCJSON_TYPE, "mdns server"),
+            Some(server.http_local_addr().unwrap().port())
+        );
+        let new = server.rebind_osc("127.0.0.1:0").unwrap();
+        assert_eq!(browse(OSC_UDP_TYPE, "mdns server"), Some(new.port()));
+    }
+
+    #[test]
+    fn osc_address() {
+        let server = OscQueryServer::new(
+            None,
+            &"127.0.0.1:0".parse().unwrap(),
+            "127.0.0.1:0",
+            "127.0.0.1:0",
+        )
+        .expect("failed to create server");
+        let fader = Arc::new(::atomic::Atomic::new(0i32));
+        let other = Arc::new(::atomic::Atomic::new(0i32));
+        let node = |name: &str, a: &Arc<::atomic::Atomic<i32>>| {
+            crate::node::GetSet::new(
+                name,
+                None,
+                vec![crate::param::ParamGetSet::Int(
+                    crate::value::ValueBuilder::new(a.clone() as _).build(),
+                )],
+                None,
+            )
+            .unwrap()
+        };
+        //connect first, so the path additions are seen before listening
+        let mut client = connect(server.ws_local_addr().unwrap());
+        let desks = server
+            .add_node(crate::node::Container::new("desks", None).unwrap(), None)
+            .unwrap();
+        let handle = server
+            .add_node(
+                node("fader7", &fader).with_osc_address(Some(OscAddress {
+                    address: "/dev/7/fader".into(),
+                    inbound: false,
+                })),
+                Some(desks),
+            )
+            .unwrap();
+        server.add_node(node("other", &other), None).unwrap();
+
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        for _ in 0..3 {
+            assert_eq!(read_text(&mut client)["COMMAND"], "PATH_ADDED");
+        }
+        listen(&mut client, server.ws.as_ref().unwrap(), "/desks/fader7");
+
+        //outgoing messages use the override
+        assert!(server.trigger(handle));
+        let mut buf = [0u8; 1024];
+        let (size, _) = udp.recv_from(&mut buf).expect("no message");
+        match crate::osc::decoder::decode(&buf[..size]) {
+            Ok(OscPacket::Message(m)) => assert_eq!(m.addr, "/dev/7/fader"),
+            other => panic!("unexpected packet {:?}", other),
+        }
+        assert_eq!(read_osc(&mut client).addr, "/dev/7/fader");
+
+        //the namespace keeps the full path
+        let (status, _, body) = get(server.http_local_addr().unwrap(), "/desks/fader7");
+        assert_eq!(status, 200);
+        let n: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(n["FULL_PATH"], "/desks/fader7");
+        assert_eq!(n["OSC_ADDRESS"], "/dev/7/fader");
+
+        let send = |addr: &str, v: i32| {
+            let packet = OscPacket::Message(OscMessage {
+                addr: addr.into(),
+                args: vec![OscType::Int(v)],
+            });
+            udp.send_to(
+                &crate::osc::encoder::encode(&packet).unwrap(),
+                server.osc_local_addr().unwrap(),
+            )
+            .unwrap();
+        };
+
+        //inbound only when enabled
+        send("/dev/7/fader", 5);
+        send("/other", 1);
+        wait_for(|| other.load(::atomic::Ordering::SeqCst) == 1);
+        assert_eq!(fader.load(::atomic::Ordering::SeqCst), 0);
+
+        assert!(server
+            .set_osc_address(
+                &handle,
+                Some(OscAddress {
+                    address: "/dev/7/fader".into(),
+                    inbound: true,
+                }),
+            )
+            .is_ok());
+        send("/dev/7/fader", 6);
+        wait_for(|| fader.load(::atomic::Ordering::SeqCst) == 6);
+        send("/desks/fader7", 7);
+        wait_for(|| fader.load(::atomic::Ordering::SeqCst) == 7);
+
+        assert!(server.set_osc_address(&desks, None).is_err());
+    }
+
+    #[test]
+    fn critical() {
+        let server = OscQueryServer::new(
+            None,
+            &"127.0.0.1:0".parse().unwrap(),
+            "127.0.0.1:0",
+            "127.0.0.1:0",
+        )
+        .expect("failed to create server");
+        let node = |name: &str| {
+            crate::node::Get::new(
+                name,
+                None,
+                vec![crate::param::ParamGet::Int(
+                    crate::value::ValueBuilder::new(Arc::new(::atomic::Atomic::new(1i32)) as _)
+                        .build(),
+                )],
+            )
+            .unwrap()
+        };
+        let mut client = connect(server.ws_local_addr().unwrap());
+        let critical = server
+            .add_node(node("critical").with_critical(true), None)
+            .unwrap();
+        let plain = server.add_node(node("plain"), None).unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        for _ in 0..2 {
+            assert_eq!(read_text(&mut client)["COMMAND"], "PATH_ADDED");
+        }
+        listen(&mut client, server.ws.as_ref().unwrap(), "/critical");
+
+        let http = server.http_local_addr().unwrap();
+        let json = |path: &str| -> serde_json::Value {
+            let (status, _, body) = get(http, path);
+            assert_eq!(status, 200, "{}", path);
+            serde_json::from_str(&body).unwrap()
+        };
+        let v = json("/");
+        assert_eq!(v["CONTENTS"]["critical"]["CRITICAL"], true);
+        assert!(v["CONTENTS"]["plain"].get("CRITICAL").is_none());
+        assert_eq!(
+            json("/critical?CRITICAL"),
+            serde_json::json!({"CRITICAL": true})
+        );
+        assert_eq!(
+            json("/plain?CRITICAL"),
+            serde_json::json!({"CRITICAL": false})
+        );
+        assert_eq!(get(http, "/?CRITICAL").0, 204);
+        assert_eq!(json("/?HOST_INFO")["EXTENSIONS"]["CRITICAL"], true);
+
+        //only the websocket clients get critical nodes
+        assert!(server.trigger(critical));
+        assert_eq!(read_osc(&mut client).addr, "/critical");
+        assert!(server.trigger(plain));
+        let mut buf = [0u8; 1024];
+        let (size, _) = udp.recv_from(&mut buf).expect("no message");
+        match crate::osc::decoder::decode(&buf[..size]) {
+            Ok(OscPacket::Message(m)) => assert_eq!(m.addr, "/plain"),
+            other => panic!("unexpected packet {:?}", other),
+        }
+
+        server.rm_node(critical).unwrap();
+        assert_eq!(json("/?HOST_INFO")["EXTENSIONS"]["CRITICAL"], false);
+    }
+
+    #[test]
+    fn alias() {
+        let server = OscQueryServer::new(
+            None,
+            &"127.0.0.1:0".parse().unwrap(),
+            "127.0.0.1:0",
+            "127.0.0.1:0",
+        )
+        .expect("failed to create server");
+        let gain = Arc::new(::atomic::Atomic::new(0i32));
+        let mut client = connect(server.ws_local_addr().unwrap());
+        let mixer = server
+            .add_node(crate::node::Container::new("mixer", None).unwrap(), None)
+            .unwrap();
+        let master = server
+            .add_node(
+                crate::node::Container::new("master", None).unwrap(),
+                Some(mixer),
+            )
+            .unwrap();
+        let handle = server
+            .add_node(
+                crate::node::GetSet::new(
+                    "gain",
+                    None,
+                    vec![crate::param::ParamGetSet::Int(
+                        crate::value::ValueBuilder::new(gain.clone() as _).build(),
+                    )],
+                    None,
+                )
+                .unwrap(),
+                Some(master),
+            )
+            .unwrap();
+        let volume = server.add_alias(handle, None, "volume").unwrap();
+        let short = server.add_alias(handle, Some(mixer), "g").unwrap();
+        assert!(server.add_alias(handle, None, "volume").is_err());
+        assert!(server.add_alias(master, None, "m").is_err());
+        assert!(server.add_alias(handle, Some(handle), "x").is_err());
+        for _ in 0..5 {
+            assert_eq!(read_text(&mut client)["COMMAND"], "PATH_ADDED");
+        }
+
+        //both paths serve the node, the alias with its own FULL_PATH
+        let query = |path: &str| {
+            let (status, _, body) = get(server.http_local_addr().unwrap(), path);
+            assert_eq!(status, 200, "{}", path);
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()
+        };
+        let n = query("/volume");
+        assert_eq!(n["FULL_PATH"], "/volume");
+        assert_eq!(n["ALIAS_OF"], "/mixer/master/gain");
+        assert_eq!(n["TYPE"], "i");
+        let n = query("/mixer/master/gain");
+        assert_eq!(n["FULL_PATH"], "/mixer/master/gain");
+        assert!(n.get("ALIAS_OF").is_none());
+        let n = query("/");
+        assert_eq!(n["CONTENTS"]["volume"]["FULL_PATH"], "/volume");
+        assert_eq!(
+            n["CONTENTS"]["mixer"]["CONTENTS"]["g"]["FULL_PATH"],
+            "/mixer/g"
+        );
+        assert_eq!(
+            n["CONTENTS"]["mixer"]["CONTENTS"]["master"]["CONTENTS"]["gain"]["FULL_PATH"],
+            "/mixer/master/gain"
+        );
+
+        //writes via the alias update the single storage
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let packet = OscPacket::Message(OscMessage {
+            addr: "/volume".into(),
+            args: vec![OscType::Int(3)],
+        });
+        udp.send_to(
+            &crate::osc::encoder::encode(&packet).unwrap(),
+            server.osc_local_addr().unwrap(),
+        )
+        .unwrap();
+        wait_for(|| gain.load(::atomic::Ordering::SeqCst) == 3);
+        assert_eq!(query("/mixer/master/gain")["VALUE"], serde_json::json!([3]));
+
+        //triggers emit at the aliases that ask for it
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        server.set_alias_trigger(volume, true).unwrap();
+        assert!(server.trigger(handle));
+        let mut addrs = Vec::new();
+        let mut buf = [0u8; 1024];
+        for _ in 0..2 {
+            let (size, _) = udp.recv_from(&mut buf).expect("no message");
+            match crate::osc::decoder::decode(&buf[..size]) {
+                Ok(OscPacket::Message(m)) => addrs.push(m.addr),
+                other => panic!("unexpected packet {:?}", other),
+            }
+        }
+        assert_eq!(addrs, vec!["/volume", "/mixer/master/gain"]);
+
+        //removing an alias leaves the target alone
+        server.rm_alias(short).unwrap();
+        assert!(server.rm_alias(short).is_err());
+        let removed = read_text(&mut client);
+        assert_eq!(removed["COMMAND"], "PATH_REMOVED");
+        assert_eq!(removed["DATA"], "/mixer/g");
+        assert_eq!(get(server.http_local_addr().unwrap(), "/mixer/g").0, 404);
+        query("/mixer/master/gain");
+
+        //removing the target removes its aliases
+        server.rm_node(handle).unwrap();
+        let mut removed: Vec<String> = (0..2)
+            .map(|_| {
+                let t = read_text(&mut client);
+                assert_eq!(t["COMMAND"], "PATH_REMOVED");
+                t["DATA"].as_str().unwrap().to_string()
+            })
+            .collect();
+        removed.sort();
+        assert_eq!(removed, vec!["/mixer/master/gain", "/volume"]);
+        assert_eq!(get(server.http_local_addr().unwrap(), "/volume").0, 404);
+        assert!(server.set_alias_trigger(volume, false).is_err());
+    }
+
+    #[test]
+    fn reset_to_defaults_trigger() {
+        let server = OscQueryServerBuilder::default()
+            .with_osc("127.0.0.1:0")
+            .build()
+            .expect("failed to create server");
+        let level = Arc::new(::atomic::Atomic::new(4));
+        server
+            .add_node(
+                crate::node::GetSet::new(
+                    "level",
+                    None,
+                    vec![crate::param::ParamGetSet::Int(
+                        crate::value::ValueBuilder::new(level.clone() as _)
+                            .with_default(1)
+                            .build(),
+                    )],
+                    None,
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        let mut buf = [0u8; 1024];
+
+        //without trigger nothing is sent
+        let report = server.reset_to_defaults(None, false);
+        assert_eq!(report.paths, vec!["/level".to_string()]);
+        assert!(report.triggered.is_empty());
+        assert_eq!(level.load(::atomic::Ordering::SeqCst), 1);
+        assert!(udp.recv_from(&mut buf).is_err());
+
+        level.store(4, ::atomic::Ordering::SeqCst);
+        let report = server.reset_to_defaults(None, true);
+        let msg = OscMessage {
+            addr: "/level".into(),
+            args: vec![OscType::Int(1)],
+        };
+        assert_eq!(report.triggered, vec![msg.clone()]);
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (size, _) = udp.recv_from(&mut buf).expect("no message");
+        assert_eq!(
+            crate::osc::decoder::decode(&buf[..size]).unwrap(),
+            OscPacket::Message(msg)
+        );
+    }
+
+    #[test]
+    fn trigger_without_receivers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let server = OscQueryServerBuilder::default()
+            .with_osc("127.0.0.1:0")
+            .with_ws("127.0.0.1:0")
+            .build()
+            .expect("failed to create server");
+        let renders = Arc::new(AtomicUsize::new(0));
+        let node = |name: &str| {
+            let r = renders.clone();
+            let func = crate::func_wrap::GetFunc::new(move || {
+                r.fetch_add(1, Ordering::SeqCst);
+                7i32
+            });
+            crate::node::Get::new(
+                name,
+                None,
+                vec![crate::param::ParamGet::Int(
+                    crate::value::ValueBuilder::new(Arc::new(func) as _).build(),
+                )],
+            )
+            .unwrap()
+        };
+        let mut client = connect(server.ws_local_addr().unwrap());
+        let foo = server.add_node(node("foo"), None).unwrap();
+        let bar = server.add_node(node("bar"), None).unwrap();
+        for _ in 0..2 {
+            assert_eq!(read_text(&mut client)["COMMAND"], "PATH_ADDED");
+        }
+        let renders = || renders.load(Ordering::SeqCst);
+
+        //nobody to send to, nothing is rendered
+        assert!(!server.trigger(foo));
+        assert!(!server.trigger_path("/bar"));
+        assert_eq!(renders(), 0);
+        server.set_force_trigger(true);
+        assert!(server.trigger(foo));
+        assert_eq!(renders(), 1);
+        server.set_force_trigger(false);
+
+        //only what a websocket client listens to
+        listen(&mut client, server.ws.as_ref().unwrap(), "/foo");
+        assert!(server.trigger(foo));
+        //skip any frames left over from listen
+        while read_osc(&mut client).args != vec![OscType::Int(7)] {}
+        assert!(!server.trigger(bar));
+        assert_eq!(renders(), 2);
+
+        //everything once there is an OSC send address
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        assert!(server.trigger(bar));
+        assert_eq!(renders(), 3);
+        let osc = &server.osc_services()[0];
+        assert!(osc.trigger(bar).is_some());
+        assert_eq!(renders(), 4);
+        server.remove_osc_send_addr(udp.local_addr().unwrap());
+        assert!(osc.trigger(bar).is_none());
+        assert_eq!(renders(), 4);
+    }
+
+    #[test]
+    fn trigger_bundle() {
+        let server = OscQueryServerBuilder::default()
+            .with_osc("127.0.0.1:0")
+            .with_ws("127.0.0.1:0")
+            .build()
+            .expect("failed to create server");
+        let mut client = connect(server.ws_local_addr().unwrap());
+        let node = |name: &str, v: i32| {
+            crate::node::Get::new(
+                name,
+                None,
+                vec![crate::param::ParamGet::Int(
+                    crate::value::ValueBuilder::new(Arc::new(::atomic::Atomic::new(v)) as _)
+                        .build(),
+                )],
+            )
+            .unwrap()
+        };
+        let x = server.add_node(node("x", 1), None).unwrap();
+        let y = server.add_node(node("y", 2), None).unwrap();
+        for _ in 0..2 {
+            assert_eq!(read_text(&mut client)["COMMAND"], "PATH_ADDED");
+        }
+        assert!(server.trigger_bundle(vec![x, y], None).is_none());
+
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        server.add_osc_send_addr(udp.local_addr().unwrap());
+        listen(&mut client, server.ws.as_ref().unwrap(), "/y");
+        let bundle = server.trigger_bundle(vec![x, y], Some((1, 2))).unwrap();
+        let mut buf = [0u8; 1024];
+        let (size, _) = udp.recv_from(&mut buf).expect("no bundle");
+        match crate::osc::decoder::decode(&buf[..size]) {
+            Ok(OscPacket::Bundle(b)) => {
+                assert_eq!(b, bundle);
+                assert_eq!(b.timetag, (1, 2));
+                let addrs: Vec<_> = b
+                    .content
+                    .iter()
+                    .map(|p| match p {
+                        OscPacket::Message(m) => m.addr.as_str(),
+                        p => panic!("unexpected packet {:?}", p),
+                    })
+                    .collect();
+                assert_eq!(addrs, vec!["/x", "/y"]);
+            }
+            other => panic!("unexpected packet {:?}", other),
         }
+        //the websocket client gets what it listens to as a message, skip any left over from listen
+        while read_osc(&mut client).args != vec![OscType::Int(2)] {}
     }
 }
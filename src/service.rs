@@ -1,3 +1,10 @@
+pub mod html;
 pub mod http;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(any(test, feature = "deterministic-test"))]
+pub mod memory;
 pub mod osc;
+pub mod osc_tcp;
+pub mod sse;
 pub mod websocket;
@@ -0,0 +1,129 @@
+//! The HTML extension, a human readable page of the namespace for browsers.
+//!
+//! The page is built from the same JSON the http service serves, so it shows what clients see.
+use crate::root::Root;
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Render the node at the path and everything below it as an HTML page, with the name, type,
+/// description and current value of each node. `None` if there is no node at the path.
+pub fn page(root: &Root, path: &str, extensions: bool) -> Option<String> {
+    let node = root
+        .serialize_node::<_, serde_json::value::Serializer>(path, None, extensions, |n| match n {
+            Some(n) => serde_json::to_value(n),
+            None => Ok(Value::Null),
+        })
+        .ok()?;
+    if node.is_null() {
+        return None;
+    }
+    let title = escape(&format!(
+        "{} {}",
+        root.name().unwrap_or_else(|| "oscquery".to_string()),
+        path
+    ));
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n\
+         <body>\n<h1>{}</h1>\n<ul>\n",
+        title, title
+    );
+    render_node(&mut out, path, &node);
+    out.push_str("</ul>\n</body>\n</html>\n");
+    Some(out)
+}
+
+//a list item for the node, with a nested list for its contents
+fn render_node(out: &mut String, path: &str, node: &Value) {
+    let path = node["FULL_PATH"].as_str().unwrap_or(path);
+    let _ = write!(
+        out,
+        "<li><a href=\"{}?HTML\">{}</a>",
+        escape(path),
+        escape(path)
+    );
+    if let Some(t) = node["TYPE"].as_str() {
+        let _ = write!(out, " <code>{}</code>", escape(t));
+    }
+    if let Some(v) = node.get("VALUE") {
+        let _ = write!(out, " = <samp>{}</samp>", escape(&v.to_string()));
+    }
+    if let Some(d) = node["DESCRIPTION"].as_str() {
+        let _ = write!(out, " <em>{}</em>", escape(d));
+    }
+    if let Some(contents) = node["CONTENTS"].as_object() {
+        out.push_str("\n<ul>\n");
+        for (address, child) in contents {
+            render_node(
+                out,
+                &format!("{}/{}", path.trim_end_matches('/'), address),
+                child,
+            );
+        }
+        out.push_str("</ul>\n");
+    }
+    out.push_str("</li>\n");
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{Container, Get};
+    use crate::param::ParamGet;
+    use crate::value::{MutexValue, ValueBuilder};
+    use ::atomic::Atomic;
+    use std::sync::Arc;
+
+    #[test]
+    fn render() {
+        let root = Root::new(Some("test <server>".into()));
+        let mixer = root
+            .add_node(Container::new("mixer", Some("the mixer")).unwrap(), None)
+            .unwrap();
+        root.add_node(
+            Get::new(
+                "gain",
+                Some("gain & trim"),
+                vec![
+                    ParamGet::Float(ValueBuilder::new(Arc::new(Atomic::new(0.5f32)) as _).build()),
+                    ParamGet::String(
+                        ValueBuilder::new(Arc::new(MutexValue::new("dB".to_string())) as _).build(),
+                    ),
+                ],
+            )
+            .unwrap(),
+            Some(mixer),
+        )
+        .unwrap();
+
+        let html = page(&root, "/", true).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>test &lt;server&gt; /</title>"));
+        assert!(html.contains("<a href=\"/mixer?HTML\">/mixer</a> <em>the mixer</em>"));
+        assert!(html.contains(
+            "<a href=\"/mixer/gain?HTML\">/mixer/gain</a> <code>fs</code> = \
+             <samp>[0.5,&quot;dB&quot;]</samp> <em>gain &amp; trim</em>"
+        ));
+
+        let html = page(&root, "/mixer/gain", true).unwrap();
+        assert!(html.contains("/mixer/gain"));
+        assert!(!html.contains("the mixer"));
+        assert!(page(&root, "/nope", true).is_none());
+    }
+}
@@ -1,42 +1,128 @@
-use crate::node::NodeQueryParam;
-use crate::root::Root;
+use crate::error::OscQueryError;
+use crate::node::{Node, NodeQueryParam};
+use crate::root::{ContentsSinceError, NodeFilter, Root};
+use crate::service::sse::EventStreams;
 
 use futures::future;
+use hyper::body::Bytes;
 use hyper::service::Service;
-use hyper::{header, Body, Method, Request, Response, Server};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
 use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::cell::Cell;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
 use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const METRICS_PATH: &str = "/metrics";
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const CONTENTS_SINCE: &str = "CONTENTS_SINCE=";
+const FILTER: &str = "FILTER=";
+const INDEX: &str = "&INDEX=";
+const HTML: &str = "HTML";
+const LISTEN: &str = "LISTEN";
+//the largest POST body read, larger ones are answered with 413
+const MAX_BODY: usize = 64 * 1024;
 
 /// The http server service for OSCQuery http requests.
 pub struct HttpService {
     tx: Option<tokio::sync::oneshot::Sender<()>>,
     addr: SocketAddr,
+    osc: Arc<RwLock<Vec<SocketAddr>>>,
+    shutdown_at: Arc<Mutex<Option<SystemTime>>>,
+    events: Option<EventStreams>,
+}
+
+/// Configuration for the http service.
+#[derive(Clone, Debug, Default)]
+pub struct HttpConfig {
+    /// Only serve what the OSCQueryProposal specifies, disabling vendor extensions.
+    pub strict: bool,
+    /// Serve the root's `Stats` at `/metrics` in the Prometheus text format.
+    ///
+    /// Ignored in strict mode.
+    /// *NOTE* when enabled, this shadows any node at the `/metrics` path.
+    pub metrics: bool,
+    /// The transport of the OSC addresses, advertised as `OSC_TRANSPORT` in `HOST_INFO`.
+    pub osc_transport: OscTransport,
+    /// The browser origins that may use the responses, also in strict mode.
+    pub cors: CorsPolicy,
+    /// Stream the paths added and removed as Server-Sent Events with the `LISTEN` query, see
+    /// `service::sse`.
+    ///
+    /// Ignored in strict mode.
+    pub sse: bool,
+    /// Let clients set values by POSTing a JSON array with the `application/json` content type,
+    /// see `Root::set_value_at_path_json`.
+    ///
+    /// Off by default: with `CorsPolicy::AllowAll` any web page could write values.
+    pub post_values: bool,
+}
+
+/// Which browser origins may use the responses, via CORS headers.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum CorsPolicy {
+    /// `Access-Control-Allow-Origin: *`, the default, so browser based clients work anywhere.
+    #[default]
+    AllowAll,
+    /// Only the listed origins, like `https://example.com`, the request's `Origin` is echoed
+    /// back if it is one of them.
+    AllowList(Vec<String>),
+    /// No CORS headers, browsers only allow requests from the same origin.
+    Deny,
 }
 
+/// The transport of an OSC service.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OscTransport {
+    /// `OscService`, the default.
+    #[default]
+    Udp,
+    /// `OscTcpService`.
+    Tcp,
+}
+
+#[derive(Clone)]
 struct Svc {
     root: Arc<Root>,
-    osc: Option<SocketAddr>,
+    osc: Arc<RwLock<Vec<SocketAddr>>>,
     ws: Option<SocketAddr>,
+    config: Arc<HttpConfig>,
+    shutdown_at: Arc<Mutex<Option<SystemTime>>>,
+    events: Option<EventStreams>,
 }
 
 struct MakeSvc {
     root: Arc<Root>,
-    osc: Option<SocketAddr>,
+    osc: Arc<RwLock<Vec<SocketAddr>>>,
     ws: Option<SocketAddr>,
+    config: Arc<HttpConfig>,
+    shutdown_at: Arc<Mutex<Option<SystemTime>>>,
+    events: Option<EventStreams>,
 }
 
 struct PathSerializeWrapper<'a> {
     root: Arc<Root>,
     path: &'a str,
     param: Option<NodeQueryParam>,
+    strict: bool,
+    //set when serializing, so a missing path can be told apart from a failure
+    found: Cell<bool>,
 }
 
 struct HostInfoWrapper {
     root: Arc<Root>,
     osc: Option<SocketAddr>,
+    osc_all: Vec<SocketAddr>,
+    osc_transport: OscTransport,
     ws: Option<SocketAddr>,
+    sse: bool,
+    strict: bool,
+    shutdown_at: Option<SystemTime>,
 }
 
 impl<'a> Serialize for PathSerializeWrapper<'a> {
@@ -45,8 +131,9 @@ impl<'a> Serialize for PathSerializeWrapper<'a> {
         S: Serializer,
     {
         self.root
-            .serialize_node::<_, S>(self.path, self.param, move |n| {
+            .serialize_node::<_, S>(self.path, self.param, !self.strict, move |n| {
                 if let Some(n) = n {
+                    self.found.set(true);
                     serializer.serialize_some(n)
                 } else {
                     Err(serde::ser::Error::custom("path not in namespace"))
@@ -55,6 +142,28 @@ impl<'a> Serialize for PathSerializeWrapper<'a> {
     }
 }
 
+struct FilterSerializeWrapper<'a> {
+    root: Arc<Root>,
+    path: &'a str,
+    filter: NodeFilter,
+}
+
+impl<'a> Serialize for FilterSerializeWrapper<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.root
+            .serialize_filtered::<_, S>(self.path, &self.filter, true, move |n| {
+                if let Some(n) = n {
+                    serializer.serialize_some(n)
+                } else {
+                    Err(serde::ser::Error::custom("container not in namespace"))
+                }
+            })
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub(crate) struct Extensions {
@@ -70,12 +179,11 @@ pub(crate) struct Extensions {
     path_renamed: bool,
     path_added: bool,
     path_removed: bool,
-
-    //TODO
+    overloads: bool,
+    default: bool,
     tags: bool,
-    extended_type: bool,
     critical: bool,
-    overloads: bool,
+    extended_type: bool,
     html: bool,
 }
 
@@ -94,12 +202,12 @@ impl Default for Extensions {
             path_renamed: false,
             path_added: false,
             path_removed: false,
-
-            tags: false,
-            extended_type: false,
-            critical: false,
-            overloads: false,
-            html: false,
+            overloads: true,
+            default: true,
+            tags: true,
+            critical: true,
+            extended_type: true,
+            html: true,
         }
     }
 }
@@ -107,6 +215,13 @@ impl Default for Extensions {
 impl Extensions {
     pub(crate) fn with_ws(&mut self) {
         self.listen = true;
+        self.path_changed = true;
+        self.path_renamed = true;
+        self.path_added = true;
+        self.path_removed = true;
+    }
+
+    pub(crate) fn with_sse(&mut self) {
         self.path_added = true;
         self.path_removed = true;
     }
@@ -121,9 +236,9 @@ impl Serialize for HostInfoWrapper {
         if let Some(name) = self.root.name() {
             m.serialize_entry("NAME".into(), &name)?;
         }
+        m.serialize_entry("OSCQUERY_VERSION", crate::capability::OSCQUERY_VERSION)?;
         if let Some(addr) = &self.osc {
-            //TODO TCP support?
-            m.serialize_entry("OSC_TRANSPORT", &"UDP")?;
+            m.serialize_entry("OSC_TRANSPORT", &self.osc_transport)?;
             m.serialize_entry("OSC_IP", &addr.ip())?;
             m.serialize_entry("OSC_PORT", &addr.port())?;
         }
@@ -133,7 +248,43 @@ impl Serialize for HostInfoWrapper {
             m.serialize_entry("WS_IP", &addr.ip())?;
             m.serialize_entry("WS_PORT", &addr.port())?;
         }
+        if self.sse {
+            e.with_sse();
+        }
+        //only advertised when some node uses them
+        e.tags = self.root.any_node(|n| n.tags().is_some());
+        e.critical = self.root.any_node(Node::critical);
         m.serialize_entry("EXTENSIONS".into(), &e)?;
+        if !self.strict {
+            //vendor extension, every OSC service when there is more than one
+            if self.osc_all.len() > 1 {
+                let all: Vec<_> = self
+                    .osc_all
+                    .iter()
+                    .map(|addr| {
+                        serde_json::json!({
+                            "OSC_TRANSPORT": self.osc_transport,
+                            "OSC_IP": addr.ip(),
+                            "OSC_PORT": addr.port(),
+                        })
+                    })
+                    .collect();
+                m.serialize_entry("OSC_SERVICES", &all)?;
+            }
+            m.serialize_entry("GENERATION", &self.root.generation())?;
+            m.serialize_entry(
+                "SERVER",
+                &serde_json::json!({
+                    "NAME": env!("CARGO_PKG_NAME"),
+                    "VERSION": env!("CARGO_PKG_VERSION"),
+                    "FEATURES": self.root.capabilities(),
+                }),
+            )?;
+            //vendor extension, milliseconds since the unix epoch
+            if let Some(Ok(at)) = self.shutdown_at.map(|at| at.duration_since(UNIX_EPOCH)) {
+                m.serialize_entry("SHUTDOWN_AT", &(at.as_millis() as u64))?;
+            }
+        }
         m.end()
     }
 }
@@ -141,68 +292,506 @@ impl Serialize for HostInfoWrapper {
 impl Service<Request<Body>> for Svc {
     type Response = Response<Body>;
     type Error = hyper::Error;
-    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Ok(()).into()
     }
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let svc = self.clone();
+        Box::pin(async move {
+            //only writes have a body worth reading, up to the limit
+            let (parts, body) = req.into_parts();
+            let body = if parts.method == Method::POST && svc.config.post_values {
+                read_body(&parts.headers, body).await?
+            } else {
+                Some(Bytes::new())
+            };
+            let too_large = body.is_none();
+            let req = Request::from_parts(parts, body.unwrap_or_default());
+            let respond = || {
+                if too_large {
+                    return built(
+                        Response::builder()
+                            .status(StatusCode::PAYLOAD_TOO_LARGE)
+                            .body(Body::from(format!("the body is over {} bytes", MAX_BODY))),
+                    );
+                }
+                svc.respond(&req)
+            };
+            //a panic would take the connection down with it, answer with a 500 instead
+            let mut rsp = match std::panic::catch_unwind(AssertUnwindSafe(respond)) {
+                Ok(rsp) => rsp,
+                Err(..) => {
+                    eprintln!("panic serving {} {}", req.method(), req.uri());
+                    svc.root.stats().inc_task_panics();
+                    internal_error()
+                }
+            };
+            svc.cors(&req, &mut rsp);
+            svc.root.stats().inc_http_requests(rsp.status().as_u16());
+            Ok(rsp)
+        })
+    }
+}
+
+//the body, `None` if it is over `MAX_BODY`, by its length or as it is read
+async fn read_body(
+    headers: &header::HeaderMap,
+    mut body: Body,
+) -> Result<Option<Bytes>, hyper::Error> {
+    use hyper::body::HttpBody;
+
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse::<u64>().ok());
+    if length.is_some_and(|l| l > MAX_BODY as u64) {
+        return Ok(None);
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if data.len() + chunk.len() > MAX_BODY {
+            return Ok(None);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Some(data.into()))
+}
+
+fn internal_error() -> Response<Body> {
+    let mut rsp = Response::new(Body::empty());
+    *rsp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    rsp
+}
+
+//the response, or a 500 if it could not be built
+fn built(rsp: hyper::http::Result<Response<Body>>) -> Response<Body> {
+    rsp.unwrap_or_else(|e| {
+        eprintln!("error building response {}", e);
+        internal_error()
+    })
+}
+
+impl Svc {
+    //allow the request's origin, if the policy does
+    fn cors(&self, req: &Request<Bytes>, rsp: &mut Response<Body>) {
+        let origin = match &self.config.cors {
+            CorsPolicy::AllowAll => header::HeaderValue::from_static("*"),
+            CorsPolicy::AllowList(origins) => {
+                rsp.headers_mut()
+                    .append(header::VARY, header::HeaderValue::from_static("Origin"));
+                match req.headers().get(header::ORIGIN) {
+                    Some(o) if origins.iter().any(|a| a.as_bytes() == o.as_bytes()) => o.clone(),
+                    _ => return,
+                }
+            }
+            CorsPolicy::Deny => return,
+        };
+        rsp.headers_mut()
+            .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    }
+
+    fn respond(&self, req: &Request<Bytes>) -> Response<Body> {
+        //CORS preflight, the origin is allowed, or not, like for any other response
+        if req.method() == Method::OPTIONS {
+            let methods = if self.config.post_values {
+                "GET, POST, OPTIONS"
+            } else {
+                "GET, OPTIONS"
+            };
+            return built(
+                Response::builder()
+                    .status(200)
+                    .header(header::ACCESS_CONTROL_ALLOW_METHODS, methods)
+                    .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type")
+                    .body(Body::empty()),
+            );
+        }
+        //so `/foo/` and `/foo//` resolve the same as `/foo`
+        let path = match crate::node::normalize_path(req.uri().path(), self.config.strict) {
+            Ok(path) => path,
+            Err(e) => {
+                return built(
+                    Response::builder()
+                        .status(400)
+                        .body(Body::from(e.to_string())),
+                );
+            }
+        };
+        if self.config.metrics
+            && !self.config.strict
+            && req.method() == Method::GET
+            && path == METRICS_PATH
+        {
+            return built(
+                Response::builder()
+                    .status(200)
+                    .header(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)
+                    .body(Body::from(
+                        self.root.stats().prometheus_text(self.root.node_count()),
+                    )),
+            );
+        }
         let rsp = if req.method() == &Method::GET {
+            //browsers get the HTML page unless they ask for something else
+            let html = match req.uri().query() {
+                Some(p) => p == HTML,
+                None => req
+                    .headers()
+                    .get(header::ACCEPT)
+                    .and_then(|a| a.to_str().ok())
+                    .is_some_and(|a| a.contains("text/html")),
+            };
+            if html {
+                return self.html(&path);
+            }
+            if let (Some(LISTEN), Some(events)) = (req.uri().query(), &self.events) {
+                return Self::listen(events, &path);
+            }
             let mut param: Option<NodeQueryParam> = None;
             if let Some(p) = req.uri().query() {
                 if p == "HOST_INFO" {
+                    let osc = self.osc.read().map_or_else(|_| Vec::new(), |o| o.clone());
                     let w = HostInfoWrapper {
                         root: self.root.clone(),
-                        osc: self.osc.clone(),
+                        osc: Self::osc_for(req, &osc),
+                        osc_all: osc,
+                        osc_transport: self.config.osc_transport,
                         ws: self.ws.clone(),
+                        sse: self.events.is_some(),
+                        strict: self.config.strict,
+                        shutdown_at: self.shutdown_at.lock().map_or(None, |at| *at),
                     };
-                    return future::ok(
-                        Response::builder()
-                            .status(200)
-                            .body(Body::from(
-                                serde_json::to_string(&w).expect("failed to HostInfoWrapper"),
-                            ))
-                            .unwrap(),
-                    );
+                    return built(match serde_json::to_string(&w) {
+                        Ok(s) => Response::builder().status(200).body(Body::from(s)),
+                        Err(e) => Response::builder()
+                            .status(500)
+                            .body(Body::from(e.to_string())),
+                    });
+                } else if !self.config.strict && p.starts_with(CONTENTS_SINCE) {
+                    return self.contents_since(&path, &p[CONTENTS_SINCE.len()..]);
+                } else if !self.config.strict && p.starts_with(FILTER) {
+                    return self.filtered(&path, &p[FILTER.len()..]);
+                } else if let (false, Some((attr, index))) =
+                    (self.config.strict, p.split_once(INDEX))
+                {
+                    return self.indexed(&path, attr, index);
                 } else {
                     let p: Result<NodeQueryParam, _> =
                         serde_json::from_value(serde_json::Value::String(p.to_string()));
                     match p {
                         Ok(p) => param = Some(p),
                         Err(e) => {
-                            return future::ok(
+                            return built(
                                 Response::builder()
                                     .status(400)
-                                    .body(Body::from(e.to_string()))
-                                    .unwrap(),
+                                    .body(Body::from(e.to_string())),
                             );
                         }
                     };
                 }
             };
-            let s = PathSerializeWrapper {
-                root: self.root.clone(),
-                path: req.uri().path(),
-                param,
-            };
+            return self.node(&path, param);
+        } else if req.method() == Method::POST && !self.config.post_values {
+            Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(header::ALLOW, "GET, OPTIONS")
+                .body(Body::from("setting values is disabled"))
+        } else if req.method() == Method::POST && !Self::is_json(req) {
+            Response::builder()
+                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .body(Body::from("the content type must be application/json"))
+        } else if req.method() == Method::POST {
+            match req.uri().query() {
+                None | Some("VALUE") => return self.set_value(&path, req.body()),
+                Some(p) => Response::builder()
+                    .status(400)
+                    .body(Body::from(format!("only VALUE can be set, not {}", p))),
+            }
+        } else {
+            Response::builder().status(404).body(Body::empty())
+        };
+        built(rsp)
+    }
+
+    //the node at the path, or one of its attributes
+    fn node(&self, path: &str, param: Option<NodeQueryParam>) -> Response<Body> {
+        let s = PathSerializeWrapper {
+            root: self.root.clone(),
+            path,
+            param,
+            strict: self.config.strict,
+            found: Cell::new(false),
+        };
+        built(match serde_json::to_value(&s) {
             //might be Null, in which case we should return 204
-            if let Ok(s) = serde_json::to_value(&s) {
-                Some(match s {
-                    serde_json::Value::Null => Response::builder().status(204).body(Body::empty()),
-                    _ => Response::builder()
-                        .status(200)
-                        .header(header::CONTENT_TYPE, "application/json")
-                        .body(Body::from(s.to_string())),
-                })
+            Ok(serde_json::Value::Null) => Response::builder().status(204).body(Body::empty()),
+            Ok(v) => Response::builder()
+                .status(200)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(v.to_string())),
+            Err(..) if !s.found.get() => return Self::not_found("no such node", path),
+            Err(e) => Response::builder()
+                .status(500)
+                .body(Body::from(e.to_string())),
+        })
+    }
+
+    //set the value of the node from a JSON array, answers with the new value
+    fn set_value(&self, path: &str, body: &[u8]) -> Response<Body> {
+        let bad_request = |e: String| built(Response::builder().status(400).body(Body::from(e)));
+        let value: serde_json::Value = match serde_json::from_slice(body) {
+            Ok(v) => v,
+            Err(e) => return bad_request(e.to_string()),
+        };
+        let status = match self.root.set_value_at_path_json(path, &value) {
+            Ok(()) => return self.node(path, Some(NodeQueryParam::Value)),
+            Err(OscQueryError::NodeNotInGraph) => return Self::not_found("no such node", path),
+            Err(e @ OscQueryError::TypeMismatch(..)) => return bad_request(e.to_string()),
+            Err(e @ OscQueryError::ReadOnly(..)) | Err(e @ OscQueryError::ContainerHasNoValue) => {
+                (403, e)
+            }
+            Err(e @ OscQueryError::WriteRejected(..)) => (422, e),
+            Err(e) => (500, e),
+        };
+        built(
+            Response::builder()
+                .status(status.0)
+                .body(Body::from(status.1.to_string())),
+        )
+    }
+
+    //is the content type JSON, parameters like the charset aside
+    fn is_json(req: &Request<Bytes>) -> bool {
+        req.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .and_then(|t| t.split(';').next())
+            .is_some_and(|t| t.trim().eq_ignore_ascii_case("application/json"))
+    }
+
+    fn not_found(error: &str, path: &str) -> Response<Body> {
+        built(
+            Response::builder()
+                .status(404)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({"error": error, "path": path}).to_string(),
+                )),
+        )
+    }
+
+    //the OSC service to advertise, the one bound to the ip the request was addressed to, if any
+    fn osc_for(req: &Request<Bytes>, osc: &[SocketAddr]) -> Option<SocketAddr> {
+        let ip = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse::<hyper::http::uri::Authority>().ok())
+            .and_then(|a| {
+                a.host()
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .parse::<IpAddr>()
+                    .ok()
+            });
+        ip.and_then(|ip| osc.iter().find(|a| a.ip() == ip))
+            .or_else(|| osc.iter().find(|a| a.ip().is_unspecified()))
+            .or_else(|| osc.first())
+            .copied()
+    }
+
+    //the HTML extension, a page of the namespace at the path
+    fn html(&self, path: &str) -> Response<Body> {
+        match super::html::page(&self.root, path, !self.config.strict) {
+            Some(page) => built(
+                Response::builder()
+                    .status(200)
+                    .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+                    .body(Body::from(page)),
+            ),
+            None => Self::not_found("no such node", path),
+        }
+    }
+
+    //vendor extension, the namespace changes at or below the path as Server-Sent Events
+    fn listen(events: &EventStreams, path: &str) -> Response<Body> {
+        match events.subscribe(path) {
+            Some(body) => built(
+                Response::builder()
+                    .status(200)
+                    .header(header::CONTENT_TYPE, "text/event-stream")
+                    .header(header::CACHE_CONTROL, "no-cache")
+                    .body(body),
+            ),
+            None => built(Response::builder().status(503).body(Body::empty())),
+        }
+    }
+
+    //vendor extension, the entry of an attribute for a single OSC argument, not wrapped in the
+    //array, the index counts arguments like `Root::value_at_path_indexed`
+    fn indexed(&self, path: &str, attr: &str, index: &str) -> Response<Body> {
+        let bad_request = |e: String| built(Response::builder().status(400).body(Body::from(e)));
+        let param = match serde_json::from_value(serde_json::Value::String(attr.to_string())) {
+            Ok(
+                p @ NodeQueryParam::Value
+                | p @ NodeQueryParam::Type
+                | p @ NodeQueryParam::Range
+                | p @ NodeQueryParam::ClipMode
+                | p @ NodeQueryParam::Unit
+                | p @ NodeQueryParam::ExtendedType,
+            ) => p,
+            _ => return bad_request(format!("INDEX is not supported with {}", attr)),
+        };
+        let index: usize = match index.parse() {
+            Ok(index) => index,
+            Err(e) => return bad_request(e.to_string()),
+        };
+        let value = matches!(param, NodeQueryParam::Value);
+        let s = PathSerializeWrapper {
+            root: self.root.clone(),
+            path,
+            param: Some(param),
+            strict: self.config.strict,
+            found: Cell::new(false),
+        };
+        let entries = match serde_json::to_value(&s) {
+            Ok(serde_json::Value::Object(mut m)) => m.remove(attr),
+            Ok(..) => return built(Response::builder().status(204).body(Body::empty())),
+            Err(..) if !s.found.get() => return Self::not_found("no such node", path),
+            Err(e) => {
+                return built(
+                    Response::builder()
+                        .status(500)
+                        .body(Body::from(e.to_string())),
+                )
+            }
+        };
+        let counts = self.root.param_arg_counts_at_path(path).unwrap_or_default();
+        let len = counts.iter().sum();
+        //the parameter taking the argument, and the argument's position among its own
+        let mut at = index;
+        let arg = counts.iter().enumerate().find_map(|(param, &count)| {
+            if at < count {
+                Some((param, at, count))
             } else {
+                at -= count;
                 None
             }
-        } else {
-            None
+        });
+        let entry = match (entries, arg) {
+            (Some(serde_json::Value::String(types)), Some(..)) => {
+                crate::convert::split_type_tags(&types)
+                    .get(index)
+                    .map(|t| serde_json::Value::from(*t))
+            }
+            (Some(serde_json::Value::Array(mut a)), Some((param, at, count)))
+                if param < a.len() =>
+            {
+                match a.swap_remove(param) {
+                    //the values of a parameter taking more than one argument
+                    serde_json::Value::Array(mut v) if value && v.len() == count && count > 1 => {
+                        Some(v.swap_remove(at))
+                    }
+                    e => Some(e),
+                }
+            }
+            _ => None,
+        };
+        match entry {
+            Some(entry) => built(
+                Response::builder()
+                    .status(200)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(serde_json::json!({ attr: entry }).to_string())),
+            ),
+            None => bad_request(OscQueryError::IndexOutOfRange { index, len }.to_string()),
         }
-        .unwrap_or(Response::builder().status(404).body(Body::from(Vec::new())));
-        future::ok(rsp.expect("expected response"))
+    }
+
+    //vendor extension, only the leaves of a container that match the filter
+    fn filtered(&self, path: &str, filter: &str) -> Response<Body> {
+        let filter: NodeFilter = match filter.parse() {
+            Ok(filter) => filter,
+            Err(e) => {
+                return built(
+                    Response::builder()
+                        .status(400)
+                        .body(Body::from(e.to_string())),
+                );
+            }
+        };
+        let s = FilterSerializeWrapper {
+            root: self.root.clone(),
+            path,
+            filter,
+        };
+        match serde_json::to_string(&s) {
+            Ok(s) => built(
+                Response::builder()
+                    .status(200)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(s)),
+            ),
+            Err(..) => Self::not_found("no such container", path),
+        }
+    }
+
+    //vendor extension, the net changes to the children of a container since a generation
+    fn contents_since(&self, path: &str, since: &str) -> Response<Body> {
+        let since: u64 = match since.parse() {
+            Ok(since) => since,
+            Err(e) => {
+                return built(
+                    Response::builder()
+                        .status(400)
+                        .body(Body::from(e.to_string())),
+                );
+            }
+        };
+        let (status, body) = match self.root.contents_since(path, since) {
+            Ok(diff) => {
+                let mut added = serde_json::Map::new();
+                for address in diff.added {
+                    let s = PathSerializeWrapper {
+                        root: self.root.clone(),
+                        path: &format!("{}/{}", path.trim_end_matches('/'), address),
+                        param: None,
+                        strict: self.config.strict,
+                        found: Cell::new(false),
+                    };
+                    //could have been removed since the diff was computed, skip it if so
+                    if let Ok(v) = serde_json::to_value(&s) {
+                        added.insert(address, v);
+                    }
+                }
+                (
+                    200,
+                    serde_json::json!({
+                        "GENERATION": diff.generation,
+                        "ADDED": added,
+                        "REMOVED": diff.removed,
+                        "CHANGED": diff.changed,
+                    }),
+                )
+            }
+            Err(ContentsSinceError::TooOld(generation)) => (
+                409,
+                serde_json::json!({
+                    "GENERATION": generation,
+                    "ERROR": "generation is older than the change journal, refetch the full CONTENTS",
+                }),
+            ),
+            Err(ContentsSinceError::NotFound) => return Self::not_found("no such node", path),
+        };
+        built(
+            Response::builder()
+                .status(status)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+        )
     }
 }
 
@@ -220,21 +809,45 @@ impl<T> Service<T> for MakeSvc {
             root: self.root.clone(),
             osc: self.osc.clone(),
             ws: self.ws.clone(),
+            config: self.config.clone(),
+            shutdown_at: self.shutdown_at.clone(),
+            events: self.events.clone(),
         })
     }
 }
 
 impl HttpService {
-    /// Construct a new http server.
+    /// Construct a new http server with the default configuration.
     pub fn new(
         root: Arc<Root>,
         addr: &SocketAddr,
         osc: Option<SocketAddr>,
         ws: Option<SocketAddr>,
-    ) -> Self {
-        let root = root.clone();
-        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-        let addr = addr.clone();
+    ) -> Result<Self, std::io::Error> {
+        Self::new_with_config(
+            root,
+            addr,
+            osc.into_iter().collect(),
+            ws,
+            Default::default(),
+        )
+    }
+
+    /// Construct a new http server with the given configuration.
+    ///
+    /// When there is more than one OSC address, `HOST_INFO` advertises the one bound to the ip
+    /// that the request's `Host` header names, falling back to one bound to an unspecified ip
+    /// and then the first. Outside of strict mode all of them are listed under `OSC_SERVICES`.
+    pub fn new_with_config(
+        root: Arc<Root>,
+        addr: &SocketAddr,
+        osc: Vec<SocketAddr>,
+        ws: Option<SocketAddr>,
+        config: HttpConfig,
+    ) -> Result<Self, std::io::Error> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let (service, make, shutdown) = Self::start(root, addr, osc, ws, config);
         std::thread::spawn(move || {
             let mut rt = tokio::runtime::Builder::new()
                 .basic_scheduler()
@@ -243,9 +856,15 @@ impl HttpService {
                 .build()
                 .expect("could not create runtime");
             rt.block_on(async {
-                let server = Server::bind(&addr).serve(MakeSvc { root, osc, ws });
+                let server = match Server::from_tcp(listener) {
+                    Ok(server) => server.serve(make),
+                    Err(e) => {
+                        eprintln!("server error: {}", e);
+                        return;
+                    }
+                };
                 let graceful = server.with_graceful_shutdown(async {
-                    rx.await.ok();
+                    shutdown.await;
                     println!("quitting");
                 });
 
@@ -254,7 +873,104 @@ impl HttpService {
                 }
             });
         });
-        Self { tx: Some(tx), addr }
+        Ok(service)
+    }
+
+    /// Like `new_with_config` but clients connect in memory, with the returned `Connector`, and
+    /// nothing runs until the returned future is spawned, see `service::memory`.
+    #[cfg(any(test, feature = "deterministic-test"))]
+    pub fn new_in_memory(
+        root: Arc<Root>,
+        osc: Vec<SocketAddr>,
+        ws: Option<SocketAddr>,
+        config: HttpConfig,
+    ) -> (
+        Self,
+        crate::service::memory::Connector,
+        impl std::future::Future<Output = ()>,
+    ) {
+        let (connector, incoming) = crate::service::memory::listener();
+        let (service, make, shutdown) = Self::start(root, *connector.local_addr(), osc, ws, config);
+        let incoming = futures::StreamExt::map(incoming, |a| a.map(|(stream, _)| stream));
+        let serve = async move {
+            let server = Server::builder(hyper::server::accept::from_stream(incoming)).serve(make);
+            let graceful = server.with_graceful_shutdown(shutdown);
+            if let Err(e) = graceful.await {
+                eprintln!("server error: {}", e);
+            }
+        };
+        (service, connector, serve)
+    }
+
+    //the service, what serves its requests and the future that completes when the service is
+    //dropped, ending any event streams so the server can shut down gracefully
+    fn start(
+        root: Arc<Root>,
+        addr: SocketAddr,
+        osc: Vec<SocketAddr>,
+        ws: Option<SocketAddr>,
+        config: HttpConfig,
+    ) -> (Self, MakeSvc, impl Future<Output = ()>) {
+        let mut events = None;
+        if !config.strict {
+            root.register_capability(crate::capability::CONTENTS_SINCE);
+            root.register_capability(crate::capability::FILTER);
+            if config.metrics {
+                root.register_capability(crate::capability::METRICS);
+            }
+            if config.sse {
+                root.register_capability(crate::capability::SSE);
+                events = Some(EventStreams::new(root.clone()));
+            }
+        }
+        let osc = Arc::new(RwLock::new(osc));
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown_at = Arc::new(Mutex::new(None));
+        let make = MakeSvc {
+            root,
+            osc: osc.clone(),
+            ws,
+            config: Arc::new(config),
+            shutdown_at: shutdown_at.clone(),
+            events: events.clone(),
+        };
+        let service = Self {
+            tx: Some(tx),
+            addr,
+            osc,
+            shutdown_at,
+            events: events.clone(),
+        };
+        let shutdown = async move {
+            rx.await.ok();
+            if let Some(events) = events {
+                events.close();
+            }
+        };
+        (service, make, shutdown)
+    }
+
+    ///Advertise, via `SHUTDOWN_AT` in `HOST_INFO`, that the server will stop at the given time.
+    ///
+    ///*NOTE* this is a vendor extension, omitted in strict mode.
+    pub fn announce_shutdown(&self, at: SystemTime) {
+        if let Ok(mut shutdown_at) = self.shutdown_at.lock() {
+            *shutdown_at = Some(at);
+        }
+    }
+
+    ///Set the OSC service addresses advertised in `HOST_INFO`, requests after this see them.
+    pub fn set_osc_addrs(&self, osc: Vec<SocketAddr>) {
+        if let Ok(mut o) = self.osc.write() {
+            *o = osc;
+        }
+    }
+
+    ///The number of clients streaming namespace changes, see `HttpConfig::sse`.
+    ///
+    ///Disconnected clients are noticed, and no longer counted, with the next event or keepalive.
+    pub fn event_stream_count(&self) -> usize {
+        self.events.as_ref().map_or(0, |e| e.count())
     }
 
     ///The the `SocketAddr` that the http service is bound to.
@@ -270,3 +986,1187 @@ impl Drop for HttpService {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::osc::{OscMessage, OscPacket, OscType};
+    use crate::param::ParamGetSet;
+    use crate::value::{MutexValue, ValueBuilder};
+    use ::atomic::Atomic;
+    use std::io::{Read, Write};
+    use std::net::{TcpStream, UdpSocket};
+    use std::time::{Duration, Instant};
+
+    //returns the status, the headers and the body
+    pub(crate) fn get(addr: &SocketAddr, path: &str) -> (u16, String, String) {
+        get_host(addr, "localhost", path)
+    }
+
+    pub(crate) fn get_host(addr: &SocketAddr, host: &str, path: &str) -> (u16, String, String) {
+        get_headers(addr, path, &format!("Host: {}\r\n", host))
+    }
+
+    //the headers each end with CRLF
+    fn get_headers(addr: &SocketAddr, path: &str, headers: &str) -> (u16, String, String) {
+        request(addr, "GET", path, headers, "")
+    }
+
+    fn post(addr: &SocketAddr, path: &str, body: &str) -> (u16, String, String) {
+        let headers = format!(
+            "Host: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+            body.len()
+        );
+        request(addr, "POST", path, &headers, body)
+    }
+
+    fn request(
+        addr: &SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> (u16, String, String) {
+        let mut stream = TcpStream::connect(addr).expect("failed to connect");
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\n{}Connection: close\r\n\r\n{}",
+            method, path, headers, body
+        )
+        .expect("failed to write request");
+        let mut rsp = String::new();
+        stream
+            .read_to_string(&mut rsp)
+            .expect("failed to read response");
+        let (head, body) = rsp.split_at(rsp.find("\r\n\r\n").expect("no header end"));
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .expect("no status");
+        (status, head.to_lowercase(), body[4..].to_string())
+    }
+
+    pub(crate) fn wait_for<F: Fn() -> bool>(f: F) {
+        let start = Instant::now();
+        while !f() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn not_found() {
+        let root = Arc::new(Root::new(None));
+        let c = root
+            .add_node(crate::node::Container::new("foo", None).unwrap(), None)
+            .unwrap();
+        let m = crate::node::GetSet::new(
+            "bar",
+            None,
+            vec![ParamGetSet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(3i32)) as _).build(),
+            )],
+            None,
+        );
+        root.add_node(m.unwrap(), Some(c)).unwrap();
+        let http = HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None)
+            .expect("failed to spawn http");
+        let addr = http.local_addr();
+
+        let (status, _, body) = get(addr, "/foo/bar");
+        assert_eq!(status, 200);
+        let bar: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(bar["FULL_PATH"], "/foo/bar");
+        for path in &["/foo/bar/", "/foo//bar"] {
+            let (status, _, body) = get(addr, path);
+            assert_eq!(status, 200, "{}", path);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+                bar
+            );
+        }
+        let (status, _, body) = get(addr, "/foo/?VALUE");
+        assert_eq!(status, 204, "{}", body);
+
+        let (status, head, body) = get(addr, "/foo/nope/");
+        assert_eq!(status, 404);
+        assert!(head.contains("content-type: application/json"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({"error": "no such node", "path": "/foo/nope"})
+        );
+        let (status, _, body) = get(addr, "/nope?FILTER=HAS_UNIT");
+        assert_eq!(status, 404);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({"error": "no such container", "path": "/nope"})
+        );
+
+        let (status, _, body) = get(addr, "/foo/bar?NOPE");
+        assert_eq!(status, 400);
+        assert!(body.contains("unknown variant `NOPE`"), "{}", body);
+        assert_eq!(get(addr, "/foo/nope?NOPE").0, 400);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn metrics() {
+        let root = Arc::new(Root::new(Some("test".into())));
+        let osc = root.spawn_osc("127.0.0.1:0").expect("failed to spawn osc");
+        let a = Arc::new(Atomic::new(0i32));
+        let m = crate::node::GetSet::new(
+            "foo",
+            None,
+            vec![ParamGetSet::Int(ValueBuilder::new(a.clone() as _).build())],
+            None,
+        );
+        assert!(root.add_node(m.unwrap(), None).is_ok());
+
+        let http = HttpService::new_with_config(
+            root.clone(),
+            &"127.0.0.1:0".parse().unwrap(),
+            vec![*osc.local_addr()],
+            None,
+            HttpConfig {
+                metrics: true,
+                ..Default::default()
+            },
+        )
+        .expect("failed to spawn http");
+
+        let sock = UdpSocket::bind("127.0.0.1:0").expect("failed to bind");
+        let buf = crate::osc::encoder::encode(&OscPacket::Message(OscMessage {
+            addr: "/foo".into(),
+            args: vec![OscType::Int(23)],
+        }))
+        .unwrap();
+        sock.send_to(&buf, osc.local_addr()).unwrap();
+        sock.send_to(&[1, 2, 3], osc.local_addr()).unwrap();
+        osc.add_send_addr(sock.local_addr().unwrap());
+        let stats = root.stats();
+        wait_for(|| stats.osc_packets_received() == 1 && stats.osc_decode_errors() == 1);
+        assert_eq!(a.load(::atomic::Ordering::SeqCst), 23);
+        assert!(osc.trigger_path("/foo").is_some());
+        wait_for(|| stats.osc_packets_sent() == 1);
+
+        assert_eq!(get(http.local_addr(), "/nothing").0, 404);
+        let (status, head, body) = get(http.local_addr(), "/metrics");
+        assert_eq!(status, 200);
+        assert!(head.contains("content-type: text/plain; version=0.0.4"));
+        for line in &[
+            "# TYPE oscquery_osc_packets_received_total counter",
+            "oscquery_osc_packets_received_total 1",
+            "oscquery_osc_packets_sent_total 1",
+            "oscquery_osc_decode_errors_total 1",
+            "# TYPE oscquery_ws_clients gauge",
+            "oscquery_ws_clients 0",
+            "oscquery_ws_frames_relayed_total 0",
+            "oscquery_http_requests_total{status=\"404\"} 1",
+            "# TYPE oscquery_nodes gauge",
+            "oscquery_nodes 2",
+            "oscquery_queue_depth{queue=\"osc\"} 0",
+            "oscquery_queue_depth{queue=\"ws\"} 0",
+        ] {
+            assert!(body.lines().any(|l| &l == line), "missing {}", line);
+        }
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn capabilities() {
+        use crate::service::websocket::tests::{connect, read_text};
+        use tungstenite::Message;
+
+        let host_info = |http: &HttpService| {
+            let (status, _, body) = get(http.local_addr(), "/?HOST_INFO");
+            assert_eq!(status, 200);
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()
+        };
+        let addr = "127.0.0.1:0".parse().unwrap();
+
+        let root = Arc::new(Root::new(None));
+        let http = HttpService::new(root.clone(), &addr, None, None).unwrap();
+        let info = host_info(&http);
+        assert_eq!(info["OSCQUERY_VERSION"], "1.0");
+        assert_eq!(info["SERVER"]["NAME"], "oscquery");
+        assert_eq!(info["SERVER"]["VERSION"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            info["SERVER"]["FEATURES"],
+            serde_json::json!(["contents-since", "filter"])
+        );
+
+        //enable the websocket service and metrics
+        let root = Arc::new(Root::new(None));
+        let ws = root.spawn_ws("127.0.0.1:0").unwrap();
+        let config = HttpConfig {
+            metrics: true,
+            ..Default::default()
+        };
+        let http =
+            HttpService::new_with_config(root.clone(), &addr, Vec::new(), None, config).unwrap();
+        let features = serde_json::json!([
+            "capabilities",
+            "contents-since",
+            "filter",
+            "listen-current",
+            "metrics",
+            "server-shutdown"
+        ]);
+        assert_eq!(host_info(&http)["SERVER"]["FEATURES"], features);
+        let mut client = connect(ws.local_addr());
+        client
+            .write_message(Message::Text("{\"COMMAND\":\"CAPABILITIES\"}".into()))
+            .unwrap();
+        assert_eq!(
+            read_text(&mut client),
+            serde_json::json!({"COMMAND": "CAPABILITIES", "DATA": features})
+        );
+        assert_eq!(root.stats().task_panics(), 0);
+
+        //strict hides the vendor parts
+        let root = Arc::new(Root::new(None));
+        let config = HttpConfig {
+            metrics: true,
+            strict: true,
+            ..Default::default()
+        };
+        let http =
+            HttpService::new_with_config(root.clone(), &addr, Vec::new(), None, config).unwrap();
+        let info = host_info(&http);
+        assert_eq!(info["OSCQUERY_VERSION"], "1.0");
+        assert!(info.get("SERVER").is_none());
+        assert!(root.capabilities().is_empty());
+    }
+
+    #[test]
+    fn panic() {
+        struct Boom;
+        impl crate::param::CustomParam for Boom {
+            fn osc_type_str(&self) -> String {
+                "i".into()
+            }
+            fn render(&self, args: &mut Vec<OscType>) {
+                args.push(OscType::Int(0));
+            }
+            fn update(&self, _args: &[OscType]) -> bool {
+                true
+            }
+            fn serialize_value(&self) -> serde_json::Value {
+                panic!("boom")
+            }
+        }
+
+        let root = Arc::new(Root::new(None));
+        let m = crate::node::GetSet::new(
+            "boom",
+            None,
+            vec![ParamGetSet::Custom(Box::new(Boom))],
+            None,
+        );
+        assert!(root.add_node(m.unwrap(), None).is_ok());
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let http = HttpService::new(root.clone(), &addr, None, None).unwrap();
+
+        //the panics are answered, the service keeps serving
+        assert_eq!(get(http.local_addr(), "/boom").0, 500);
+        assert_eq!(get(http.local_addr(), "/").0, 500);
+        assert_eq!(root.stats().task_panics(), 2);
+        assert_eq!(get(http.local_addr(), "/boom?TYPE").0, 200);
+        assert_eq!(get(http.local_addr(), "/?HOST_INFO").0, 200);
+        assert_eq!(root.stats().http_requests().get(&500), Some(&2));
+    }
+
+    #[test]
+    fn metrics_disabled() {
+        let root = Arc::new(Root::new(None));
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let http = HttpService::new(root.clone(), &addr, None, None).unwrap();
+        assert_eq!(get(http.local_addr(), "/metrics").0, 404);
+
+        let http = HttpService::new_with_config(
+            root.clone(),
+            &addr,
+            Vec::new(),
+            None,
+            HttpConfig {
+                metrics: true,
+                strict: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(get(http.local_addr(), "/metrics").0, 404);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn contents_since() {
+        let root = Arc::new(Root::new(Some("test".into())));
+        let c = root
+            .add_node(crate::node::Container::new("c", None).unwrap(), None)
+            .unwrap();
+        let add = |name: &str| {
+            root.add_node(crate::node::Container::new(name, None).unwrap(), Some(c))
+                .unwrap()
+        };
+        let start = root.generation();
+        let a = add("a");
+        let b = add("b");
+        let http =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let query = |since: u64| {
+            let (status, _, body) = get(http.local_addr(), &format!("/c?CONTENTS_SINCE={}", since));
+            let v: serde_json::Value = serde_json::from_str(&body).unwrap();
+            (status, v)
+        };
+
+        let (status, v) = query(start);
+        assert_eq!(status, 200);
+        assert_eq!(v["GENERATION"], root.generation());
+        assert_eq!(v["ADDED"]["a"]["FULL_PATH"], "/c/a");
+        assert_eq!(v["ADDED"]["b"]["FULL_PATH"], "/c/b");
+        assert_eq!(v["REMOVED"], serde_json::json!([]));
+
+        let mid = root.generation();
+        assert!(root.rm_node(a).is_ok());
+        assert!(root.rm_node(b).is_ok());
+        add("b");
+        let (status, v) = query(mid);
+        assert_eq!(status, 200);
+        assert_eq!(v["ADDED"], serde_json::json!({}));
+        assert_eq!(v["REMOVED"], serde_json::json!(["a"]));
+        assert_eq!(v["CHANGED"], serde_json::json!(["b"]));
+
+        //added and removed in the window cancel out
+        let (_, v) = query(start);
+        assert_eq!(v["ADDED"].as_object().unwrap().len(), 1);
+        assert!(v["ADDED"]["b"].is_object());
+        assert_eq!(v["REMOVED"], serde_json::json!([]));
+
+        let (_, v) = query(root.generation());
+        assert_eq!(v["ADDED"], serde_json::json!({}));
+        assert_eq!(v["CHANGED"], serde_json::json!([]));
+
+        //overflow the journal
+        for i in 0..64 {
+            add(&format!("x{}", i));
+        }
+        let (status, v) = query(start);
+        assert_eq!(status, 409);
+        assert_eq!(v["GENERATION"], root.generation());
+
+        assert_eq!(get(http.local_addr(), "/c?CONTENTS_SINCE=foo").0, 400);
+        assert_eq!(get(http.local_addr(), "/nope?CONTENTS_SINCE=0").0, 404);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn filter() {
+        use crate::node::{Container, Get, Set};
+        use crate::param::{ParamGet, ParamSet};
+        use crate::value::{ClipMode, Range};
+        use serde_json::json;
+
+        let root = Arc::new(Root::new(None));
+        let container = |name: &str, parent| {
+            root.add_node(Container::new(name, None).unwrap(), parent)
+                .unwrap()
+        };
+        let atomic = || Arc::new(Atomic::new(0f32));
+        let get_node = |name: &str, unit: bool| {
+            let v = ValueBuilder::new(atomic() as _);
+            let v = if unit { v.with_unit("gain".into()) } else { v };
+            Get::new(name, None, vec![ParamGet::Float(v.build())]).unwrap()
+        };
+
+        //the unit leaves are /mixer/ch1/gain, /mixer/master and /fx/deep/level
+        let mixer = container("mixer", None);
+        let ch1 = container("ch1", Some(mixer));
+        root.add_node(get_node("gain", true), Some(ch1)).unwrap();
+        root.add_node(get_node("pan", false), Some(ch1)).unwrap();
+        let ch2 = container("ch2", Some(mixer));
+        let mute = Set::new(
+            "mute",
+            None,
+            vec![ParamSet::Float(ValueBuilder::new(atomic() as _).build())],
+            None,
+        );
+        root.add_node(mute.unwrap(), Some(ch2)).unwrap();
+        let master = ParamGetSet::Float(
+            ValueBuilder::new(atomic() as _)
+                .with_unit("gain".into())
+                .with_range(Range::MinMax(0.0, 1.0))
+                .with_clip_mode(ClipMode::Both)
+                .build(),
+        );
+        let master = crate::node::GetSet::new("master", None, vec![master], None);
+        root.add_node(master.unwrap(), Some(mixer)).unwrap();
+        let fx = container("fx", None);
+        let deep = container("deep", Some(fx));
+        root.add_node(get_node("level", true), Some(deep)).unwrap();
+        root.add_node(get_node("dry", false), Some(fx)).unwrap();
+        container("empty", Some(fx));
+
+        let http =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let query = |path: &str| {
+            let (status, _, body) = get(http.local_addr(), path);
+            let v = serde_json::from_str(&body).unwrap_or(serde_json::Value::Null);
+            (status, v)
+        };
+        //just the full paths of the tree
+        fn paths(n: &serde_json::Value) -> serde_json::Value {
+            let mut m = serde_json::Map::new();
+            if let Some(c) = n["CONTENTS"].as_object() {
+                for (k, v) in c {
+                    m.insert(k.clone(), paths(v));
+                }
+            }
+            json!({"FULL_PATH": n["FULL_PATH"], "CONTENTS": m})
+        }
+        let leaf = |p: &str| json!({"FULL_PATH": p, "CONTENTS": {}});
+
+        let (status, v) = query("/?FILTER=HAS_UNIT");
+        assert_eq!(status, 200);
+        assert_eq!(
+            paths(&v),
+            json!({"FULL_PATH": "/", "CONTENTS": {
+                "mixer": {"FULL_PATH": "/mixer", "CONTENTS": {
+                    "ch1": {"FULL_PATH": "/mixer/ch1", "CONTENTS": {
+                        "gain": leaf("/mixer/ch1/gain"),
+                    }},
+                    "master": leaf("/mixer/master"),
+                }},
+                "fx": {"FULL_PATH": "/fx", "CONTENTS": {
+                    "deep": {"FULL_PATH": "/fx/deep", "CONTENTS": {
+                        "level": leaf("/fx/deep/level"),
+                    }},
+                }},
+            }})
+        );
+        //matching leaves keep all their attributes
+        let master = &v["CONTENTS"]["mixer"]["CONTENTS"]["master"];
+        assert_eq!(master["UNIT"], json!(["gain"]));
+        assert_eq!(master["CLIPMODE"], json!(["both"]));
+
+        let (_, v) = query("/mixer?FILTER=HAS_RANGE");
+        assert_eq!(
+            paths(&v),
+            json!({"FULL_PATH": "/mixer", "CONTENTS": {"master": leaf("/mixer/master")}})
+        );
+        let (_, v) = query("/mixer?FILTER=ACCESS:2");
+        assert_eq!(
+            paths(&v),
+            json!({"FULL_PATH": "/mixer", "CONTENTS": {
+                "ch2": {"FULL_PATH": "/mixer/ch2", "CONTENTS": {"mute": leaf("/mixer/ch2/mute")}},
+            }})
+        );
+        //nothing matches, the container is still given
+        let (status, v) = query("/fx?FILTER=HAS_DESCRIPTION");
+        assert_eq!(status, 200);
+        assert_eq!(paths(&v), json!({"FULL_PATH": "/fx", "CONTENTS": {}}));
+
+        assert_eq!(query("/?FILTER=TAG:").0, 400);
+        assert_eq!(query("/?FILTER=HAS_NOTHING").0, 400);
+        assert_eq!(query("/mixer/master?FILTER=HAS_UNIT").0, 404);
+        assert_eq!(query("/nope?FILTER=HAS_UNIT").0, 404);
+
+        let strict = HttpService::new_with_config(
+            root.clone(),
+            &"127.0.0.1:0".parse().unwrap(),
+            Vec::new(),
+            None,
+            HttpConfig {
+                strict: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(get(strict.local_addr(), "/?FILTER=HAS_UNIT").0, 400);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn tags() {
+        use serde_json::json;
+
+        let root = Arc::new(Root::new(None));
+        let synth = root
+            .add_node(
+                crate::node::Container::new("synth", None)
+                    .unwrap()
+                    .with_tags(vec!["adsr", "filter"]),
+                None,
+            )
+            .unwrap();
+        let int = || {
+            vec![crate::param::ParamGet::Int(
+                ValueBuilder::new(Arc::new(::atomic::Atomic::new(1)) as _).build(),
+            )]
+        };
+        root.add_node(
+            crate::node::Get::new("cutoff", None, int())
+                .unwrap()
+                .with_tags(vec!["filter".to_string()]),
+            Some(synth),
+        )
+        .unwrap();
+        root.add_node(
+            crate::node::Get::new("level", None, int()).unwrap(),
+            Some(synth),
+        )
+        .unwrap();
+        let http =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let addr = http.local_addr();
+        let json = |path: &str| -> serde_json::Value {
+            let (status, _, body) = get(addr, path);
+            assert_eq!(status, 200, "{}", path);
+            serde_json::from_str(&body).unwrap()
+        };
+
+        let v = json("/synth");
+        assert_eq!(v["TAGS"], json!(["adsr", "filter"]));
+        assert_eq!(v["CONTENTS"]["cutoff"]["TAGS"], json!(["filter"]));
+        assert!(v["CONTENTS"]["level"].get("TAGS").is_none());
+
+        assert_eq!(json("/synth?TAGS"), json!({"TAGS": ["adsr", "filter"]}));
+        assert_eq!(json("/synth/cutoff?TAGS"), json!({"TAGS": ["filter"]}));
+        assert_eq!(get(addr, "/synth/level?TAGS").0, 204);
+
+        let v = json("/?FILTER=TAG:filter");
+        let contents = v["CONTENTS"]["synth"]["CONTENTS"].as_object().unwrap();
+        assert_eq!(contents.keys().collect::<Vec<_>>(), vec!["cutoff"]);
+        let v = json("/?FILTER=HAS_TAGS");
+        assert_eq!(
+            v["CONTENTS"]["synth"]["CONTENTS"]
+                .as_object()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(json("/?HOST_INFO")["EXTENSIONS"]["TAGS"], true);
+        root.rm_node(synth).unwrap();
+        assert_eq!(json("/?HOST_INFO")["EXTENSIONS"]["TAGS"], false);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn all() {
+        let p: NodeQueryParam = serde_json::from_str("\"ALL\"").unwrap();
+        assert_eq!(p, NodeQueryParam::All);
+        assert_eq!(serde_json::to_string(&p).unwrap(), "\"ALL\"");
+
+        let root = Arc::new(Root::new(None));
+        let synth = root
+            .add_node(
+                crate::node::Container::new("synth", Some("a synth")).unwrap(),
+                None,
+            )
+            .unwrap();
+        root.add_node(
+            crate::node::Get::new(
+                "level",
+                None,
+                vec![crate::param::ParamGet::Int(
+                    ValueBuilder::new(Arc::new(::atomic::Atomic::new(1)) as _).build(),
+                )],
+            )
+            .unwrap(),
+            Some(synth),
+        )
+        .unwrap();
+        let http =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let addr = http.local_addr();
+        for path in &["/", "/synth", "/synth/level"] {
+            let (status, _, all) = get(addr, &format!("{}?ALL", path));
+            assert_eq!(status, 200, "{}", path);
+            let (_, _, node) = get(addr, path);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&all).unwrap(),
+                serde_json::from_str::<serde_json::Value>(&node).unwrap(),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(get(addr, "/nope?ALL").0, 404);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn html() {
+        let root = Arc::new(Root::new(None));
+        root.add_node(
+            crate::node::Get::new(
+                "level",
+                Some("output level"),
+                vec![crate::param::ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(7)) as _).build(),
+                )],
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let http =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let addr = http.local_addr();
+        let browser = "Host: localhost\r\nAccept: text/html,application/xhtml+xml,*/*\r\n";
+
+        for (status, head, body) in [get(addr, "/?HTML"), get_headers(addr, "/", browser)] {
+            assert_eq!(status, 200);
+            assert!(head.contains("content-type: text/html"), "{}", head);
+            assert!(body.contains("/level"), "{}", body);
+            assert!(
+                body.contains("<samp>[7]</samp> <em>output level</em>"),
+                "{}",
+                body
+            );
+        }
+        //queries and other clients still get JSON
+        let (status, head, body) = get_headers(addr, "/level?VALUE", browser);
+        assert_eq!(status, 200);
+        assert!(head.contains("application/json"));
+        assert_eq!(body, "{\"VALUE\":[7]}");
+        assert!(get(addr, "/").1.contains("application/json"));
+        assert_eq!(get(addr, "/nope?HTML").0, 404);
+        let (_, _, body) = get(addr, "/?HOST_INFO");
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(info["EXTENSIONS"]["HTML"], true);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn post_value() {
+        let root = Arc::new(Root::new(None));
+        let level = Arc::new(Atomic::new(0f32));
+        root.add_node(
+            crate::node::GetSet::new(
+                "level",
+                None,
+                vec![
+                    ParamGetSet::Float(
+                        ValueBuilder::new(level.clone() as _)
+                            .with_range(crate::value::Range::MinMax(0.0, 1.0))
+                            .with_clip_mode(crate::value::ClipMode::Both)
+                            .build(),
+                    ),
+                    ParamGetSet::Int(ValueBuilder::new(Arc::new(Atomic::new(0)) as _).build()),
+                ],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        root.add_node(
+            crate::node::Get::new(
+                "get",
+                None,
+                vec![crate::param::ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(7)) as _).build(),
+                )],
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let disabled =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        assert_eq!(post(disabled.local_addr(), "/level", "[0.25, 3]").0, 405);
+        let config = HttpConfig {
+            post_values: true,
+            ..Default::default()
+        };
+        let http = HttpService::new_with_config(
+            root.clone(),
+            &"127.0.0.1:0".parse().unwrap(),
+            Vec::new(),
+            None,
+            config,
+        )
+        .unwrap();
+        let addr = http.local_addr();
+
+        let (status, head, body) = post(addr, "/level", "[0.25, 3]");
+        assert_eq!(status, 200);
+        assert!(head.contains("application/json"));
+        assert_eq!(body, "{\"VALUE\":[0.25,3]}");
+        assert_eq!(level.load(std::sync::atomic::Ordering::SeqCst), 0.25);
+        //clipped like an OSC message
+        let (status, _, body) = post(addr, "/level?VALUE", "[2.0, 4]");
+        assert_eq!(status, 200);
+        assert_eq!(body, "{\"VALUE\":[1.0,4]}");
+        assert_eq!(get(addr, "/level?VALUE").2, body);
+
+        assert_eq!(post(addr, "/level", "[0.5").0, 400);
+        assert_eq!(post(addr, "/level", "[\"x\", 1]").0, 400);
+        assert_eq!(post(addr, "/level", "[0.5]").0, 400);
+        assert_eq!(post(addr, "/level?TYPE", "[0.5, 1]").0, 400);
+        assert_eq!(post(addr, "/get", "[1]").0, 403);
+        assert_eq!(post(addr, "/", "[1]").0, 403);
+        assert_eq!(post(addr, "/nope", "[1]").0, 404);
+        assert_eq!(level.load(std::sync::atomic::Ordering::SeqCst), 1.0);
+
+        //only JSON, and only so much of it
+        let headers = "Host: localhost\r\nContent-Length: 8\r\n";
+        assert_eq!(request(addr, "POST", "/level", headers, "[0.5, 1]").0, 415);
+        let headers = "Host: localhost\r\nContent-Type: application/json\r\n\
+                       Content-Length: 1000000\r\n";
+        assert_eq!(request(addr, "POST", "/level", headers, "[0.5, 1]").0, 413);
+        let headers = "Host: localhost\r\nContent-Type: application/json\r\n\
+                       Transfer-Encoding: chunked\r\n";
+        let body = format!(
+            "{:x}\r\n{}\r\n0\r\n\r\n",
+            MAX_BODY + 1,
+            " ".repeat(MAX_BODY + 1)
+        );
+        assert_eq!(request(addr, "POST", "/level", headers, &body).0, 413);
+        let headers = "Host: localhost\r\nContent-Type: application/json; charset=utf-8\r\n\
+                       Content-Length: 8\r\n";
+        assert_eq!(request(addr, "POST", "/level", headers, "[0.5, 1]").0, 200);
+
+        //a dropped write is reported
+        root.set_non_finite_policy(crate::value::NonFinitePolicy::RejectWrite);
+        let (status, _, body) = post(addr, "/level", "[\"nan\", 1]");
+        assert_eq!(status, 422, "{}", body);
+        assert_eq!(level.load(std::sync::atomic::Ordering::SeqCst), 0.5);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn in_memory() {
+        crate::service::memory::tests::run(async {
+            let root = Arc::new(Root::new(Some("memory".into())));
+            let (http, connector, serve) =
+                HttpService::new_in_memory(root.clone(), Vec::new(), None, Default::default());
+            let serve = tokio::spawn(serve);
+            let (mut send, conn) = hyper::client::conn::handshake(connector.connect())
+                .await
+                .unwrap();
+            tokio::spawn(conn);
+            let req = Request::get("/?HOST_INFO").body(Body::empty()).unwrap();
+            let rsp = send.send_request(req).await.unwrap();
+            assert_eq!(rsp.status(), 200);
+            let body = hyper::body::to_bytes(rsp.into_body()).await.unwrap();
+            let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(info["NAME"], "memory");
+
+            //the server stops once dropped
+            drop(http);
+            serve.await.unwrap();
+            assert_eq!(root.stats().task_panics(), 0);
+        });
+    }
+
+    #[test]
+    fn sse() {
+        crate::service::memory::tests::run(async {
+            use hyper::body::HttpBody;
+
+            let root = Arc::new(Root::new(None));
+            let config = HttpConfig {
+                sse: true,
+                ..Default::default()
+            };
+            let (http, connector, serve) =
+                HttpService::new_in_memory(root.clone(), Vec::new(), None, config);
+            let serve = tokio::spawn(serve);
+            let get = |path: &'static str| {
+                let stream = connector.connect();
+                async move {
+                    let (mut send, conn) = hyper::client::conn::handshake(stream).await.unwrap();
+                    tokio::spawn(conn);
+                    let req = Request::get(path).body(Body::empty()).unwrap();
+                    send.send_request(req).await.unwrap()
+                }
+            };
+            //the events as they arrive, until none arrives for a virtual second
+            async fn events(body: &mut Body) -> String {
+                let mut events = String::new();
+                while let Ok(Some(chunk)) =
+                    tokio::time::timeout(Duration::from_secs(1), body.data()).await
+                {
+                    events.push_str(std::str::from_utf8(&chunk.unwrap()).unwrap());
+                }
+                events
+            }
+
+            let info = hyper::body::to_bytes(get("/?HOST_INFO").await.into_body())
+                .await
+                .unwrap();
+            let info: serde_json::Value = serde_json::from_slice(&info).unwrap();
+            assert_eq!(info["EXTENSIONS"]["PATH_ADDED"], true);
+            assert_eq!(info["EXTENSIONS"]["PATH_REMOVED"], true);
+            assert_eq!(info["EXTENSIONS"]["LISTEN"], false);
+            assert!(root.capabilities().contains(&"sse".to_string()));
+
+            let all = get("/?LISTEN").await;
+            assert_eq!(all.status(), 200);
+            assert_eq!(all.headers()[header::CONTENT_TYPE], "text/event-stream");
+            let mut all = all.into_body();
+            let mut foo = get("/foo/?LISTEN").await.into_body();
+            assert_eq!(http.event_stream_count(), 2);
+
+            let parent = root
+                .add_node(crate::node::Container::new("foo", None).unwrap(), None)
+                .unwrap();
+            root.add_node(
+                crate::node::Container::new("bar", None).unwrap(),
+                Some(parent),
+            )
+            .unwrap();
+            let other = root
+                .add_node(crate::node::Container::new("foobar", None).unwrap(), None)
+                .unwrap();
+            root.rm_node(other).unwrap();
+            assert_eq!(
+                events(&mut all).await,
+                "event: PATH_ADDED\ndata: /foo\n\n\
+                 event: PATH_ADDED\ndata: /foo/bar\n\n\
+                 event: PATH_ADDED\ndata: /foobar\n\n\
+                 event: PATH_REMOVED\ndata: /foobar\n\n"
+            );
+            assert_eq!(
+                events(&mut foo).await,
+                "event: PATH_ADDED\ndata: /foo\n\n\
+                 event: PATH_ADDED\ndata: /foo/bar\n\n"
+            );
+
+            //renames are sent to the streams they touch, which follow the renamed subtree
+            root.rename_node(parent, "baz".to_string()).unwrap();
+            root.add_node(
+                crate::node::Container::new("qux", None).unwrap(),
+                Some(parent),
+            )
+            .unwrap();
+            let renamed = "event: PATH_RENAMED\ndata: {\"NEW\":\"/baz\",\"OLD\":\"/foo\"}\n\n\
+                           event: PATH_ADDED\ndata: /baz/qux\n\n";
+            assert_eq!(events(&mut all).await, renamed);
+            assert_eq!(events(&mut foo).await, renamed);
+
+            //idle streams get keepalives, disconnected clients are dropped with the next event
+            tokio::time::delay_for(Duration::from_secs(15)).await;
+            drop(all);
+            assert_eq!(events(&mut foo).await, ":\n\n");
+            root.rm_node(parent).unwrap();
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            assert_eq!(http.event_stream_count(), 1);
+
+            //the server stops once dropped, ending the remaining streams
+            drop(http);
+            serve.await.unwrap();
+            assert_eq!(root.stats().task_panics(), 0);
+        });
+    }
+
+    #[test]
+    fn cors() {
+        let root = Arc::new(Root::new(None));
+        let spawn = |cors| {
+            HttpService::new_with_config(
+                root.clone(),
+                &"127.0.0.1:0".parse().unwrap(),
+                Vec::new(),
+                None,
+                HttpConfig {
+                    cors,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let preflight = |addr: &SocketAddr, origin: &str| {
+            let headers = format!(
+                "Host: localhost\r\nOrigin: {}\r\nAccess-Control-Request-Method: POST\r\n",
+                origin
+            );
+            request(addr, "OPTIONS", "/foo", &headers, "")
+        };
+        let origin = |addr: &SocketAddr, origin: &str| {
+            let headers = format!("Host: localhost\r\nOrigin: {}\r\n", origin);
+            get_headers(addr, "/", &headers).1
+        };
+
+        let http = spawn(Default::default());
+        let addr = http.local_addr();
+        assert!(get(addr, "/").1.contains("access-control-allow-origin: *"));
+        assert!(get(addr, "/nope")
+            .1
+            .contains("access-control-allow-origin: *"));
+        let (status, head, _) = preflight(addr, "http://example.com");
+        assert_eq!(status, 200);
+        assert!(head.contains("access-control-allow-origin: *"));
+        assert!(head.contains("access-control-allow-methods: get, options"));
+        assert!(head.contains("access-control-allow-headers: content-type"));
+
+        let http = spawn(CorsPolicy::AllowList(vec!["http://example.com".into()]));
+        let addr = http.local_addr();
+        assert!(origin(addr, "http://example.com")
+            .contains("access-control-allow-origin: http://example.com"));
+        assert!(origin(addr, "http://example.com").contains("vary: origin"));
+        assert!(!origin(addr, "http://evil.com").contains("access-control-allow-origin"));
+        assert!(!get(addr, "/").1.contains("access-control-allow-origin"));
+        assert!(preflight(addr, "http://example.com")
+            .1
+            .contains("access-control-allow-origin: http://example.com"));
+
+        let http = spawn(CorsPolicy::Deny);
+        let addr = http.local_addr();
+        assert!(!origin(addr, "http://example.com").contains("access-control"));
+        let (status, head, _) = preflight(addr, "http://example.com");
+        assert_eq!(status, 200);
+        assert!(!head.contains("access-control-allow-origin"));
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn indexed() {
+        use serde_json::json;
+
+        let root = Arc::new(Root::new(None));
+        root.add_node(
+            crate::node::GetSet::new(
+                "ifs",
+                None,
+                vec![
+                    ParamGetSet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(1)) as _)
+                            .with_range(crate::value::Range::MinMax(0, 10))
+                            .build(),
+                    ),
+                    ParamGetSet::Float(
+                        ValueBuilder::new(Arc::new(Atomic::new(0.5f32)) as _)
+                            .with_unit("gain".into())
+                            .build(),
+                    ),
+                    ParamGetSet::String(
+                        ValueBuilder::new(Arc::new(MutexValue::new("x".to_string())) as _).build(),
+                    ),
+                ],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let http =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let addr = http.local_addr();
+        let json = |path: &str| -> serde_json::Value {
+            let (status, _, body) = get(addr, path);
+            assert_eq!(status, 200, "{}", path);
+            serde_json::from_str(&body).unwrap()
+        };
+
+        assert_eq!(json("/ifs?VALUE&INDEX=0"), json!({"VALUE": 1}));
+        assert_eq!(json("/ifs?VALUE&INDEX=1"), json!({"VALUE": 0.5}));
+        assert_eq!(json("/ifs?VALUE&INDEX=2"), json!({"VALUE": "x"}));
+        assert_eq!(json("/ifs?TYPE&INDEX=1"), json!({"TYPE": "f"}));
+        assert_eq!(
+            json("/ifs?RANGE&INDEX=0"),
+            json!({"RANGE": {"MIN": 0, "MAX": 10}})
+        );
+        assert_eq!(json("/ifs?UNIT&INDEX=1"), json!({"UNIT": "gain"}));
+        assert_eq!(json("/ifs?UNIT&INDEX=2"), json!({"UNIT": null}));
+        assert_eq!(json("/ifs?CLIPMODE&INDEX=2"), json!({"CLIPMODE": "none"}));
+
+        //writes show up in the indexed reads
+        root.set_value_at_path_indexed("/ifs", 2, crate::osc::OscType::String("y".into()))
+            .unwrap();
+        assert_eq!(json("/ifs?VALUE&INDEX=2"), json!({"VALUE": "y"}));
+
+        let (status, _, body) = get(addr, "/ifs?VALUE&INDEX=3");
+        assert_eq!(status, 400);
+        assert!(body.contains("out of range"), "{}", body);
+
+        //the index counts OSC arguments, like `Root::value_at_path_indexed`, the attributes of a
+        //parameter taking more than one are those of each of its arguments
+        #[derive(Clone, Copy, Default)]
+        struct Point(f32, f32);
+        impl crate::param::OscConvert for Point {
+            fn osc_type_str() -> String {
+                "ff".into()
+            }
+            fn to_osc(&self, args: &mut Vec<crate::osc::OscType>) {
+                args.push(crate::osc::OscType::Float(self.0));
+                args.push(crate::osc::OscType::Float(self.1));
+            }
+            fn from_osc(args: &[crate::osc::OscType]) -> Option<Self> {
+                match args {
+                    [crate::osc::OscType::Float(x), crate::osc::OscType::Float(y)] => {
+                        Some(Point(*x, *y))
+                    }
+                    _ => None,
+                }
+            }
+        }
+        let point: crate::value::ValueGetSet<Point> =
+            ValueBuilder::new(Arc::new(MutexValue::new(Point(0.25, 0.75))) as _)
+                .with_unit("position".into())
+                .build();
+        root.add_node(
+            crate::node::GetSet::new(
+                "pi",
+                None,
+                vec![
+                    ParamGetSet::Custom(Box::new(point)),
+                    ParamGetSet::Int(ValueBuilder::new(Arc::new(Atomic::new(3)) as _).build()),
+                ],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(json("/pi?VALUE&INDEX=1"), json!({"VALUE": 0.75}));
+        assert_eq!(json("/pi?VALUE&INDEX=2"), json!({"VALUE": 3}));
+        assert_eq!(json("/pi?TYPE&INDEX=2"), json!({"TYPE": "i"}));
+        assert_eq!(json("/pi?UNIT&INDEX=1"), json!({"UNIT": "position"}));
+        assert_eq!(json("/pi?UNIT&INDEX=2"), json!({"UNIT": null}));
+        assert_eq!(
+            root.value_at_path_indexed("/pi", 2),
+            Some(crate::osc::OscType::Int(3))
+        );
+        assert_eq!(get(addr, "/pi?VALUE&INDEX=3").0, 400);
+        assert_eq!(get(addr, "/ifs?VALUE&INDEX=x").0, 400);
+        assert_eq!(get(addr, "/ifs?DESCRIPTION&INDEX=0").0, 400);
+        assert_eq!(get(addr, "/nope?VALUE&INDEX=0").0, 404);
+        assert_eq!(get(addr, "/?VALUE&INDEX=0").0, 204);
+
+        let strict = HttpService::new_with_config(
+            root.clone(),
+            &"127.0.0.1:0".parse().unwrap(),
+            Vec::new(),
+            None,
+            HttpConfig {
+                strict: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(get(strict.local_addr(), "/ifs?VALUE&INDEX=0").0, 400);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn extended_type() {
+        use serde_json::json;
+
+        let root = Arc::new(Root::new(None));
+        root.add_node(
+            crate::node::GetSet::new(
+                "file",
+                None,
+                vec![
+                    ParamGetSet::String(
+                        ValueBuilder::new(Arc::new(MutexValue::new(String::new())) as _)
+                            .with_extended_type("filepath".into())
+                            .build(),
+                    ),
+                    ParamGetSet::Int(ValueBuilder::new(Arc::new(Atomic::new(0)) as _).build()),
+                ],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let http =
+            HttpService::new(root.clone(), &"127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let addr = http.local_addr();
+        let json = |path: &str| -> serde_json::Value {
+            let (status, _, body) = get(addr, path);
+            assert_eq!(status, 200, "{}", path);
+            serde_json::from_str(&body).unwrap()
+        };
+
+        assert_eq!(json("/file")["EXTENDED_TYPE"], json!(["filepath", null]));
+        assert_eq!(
+            json("/file?EXTENDED_TYPE"),
+            json!({"EXTENDED_TYPE": ["filepath", null]})
+        );
+        assert_eq!(get(addr, "/?EXTENDED_TYPE").0, 204);
+        let v = json("/?FILTER=HAS_EXTENDED_TYPE");
+        assert!(v["CONTENTS"]["file"].is_object());
+        assert_eq!(json("/?HOST_INFO")["EXTENSIONS"]["EXTENDED_TYPE"], true);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn lazy() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let root = Arc::new(Root::new(None));
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        let func = crate::func_wrap::GetFunc::new(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+            7i32
+        });
+        let m = crate::node::Get::new(
+            "lazy",
+            None,
+            vec![crate::param::ParamGet::Int(
+                ValueBuilder::new(Arc::new(func) as _).build(),
+            )],
+        );
+        assert!(root.add_node(m.unwrap().with_lazy(true), None).is_ok());
+        let count = || count.load(Ordering::SeqCst);
+
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let http = HttpService::new(root.clone(), &addr, None, None).unwrap();
+        let json = |http: &HttpService, path: &str| -> serde_json::Value {
+            let (status, _, body) = get(http.local_addr(), path);
+            assert_eq!(status, 200);
+            serde_json::from_str(&body).unwrap()
+        };
+
+        let v = json(&http, "/");
+        assert!(v["CONTENTS"]["lazy"].get("VALUE").is_none());
+        assert_eq!(v["CONTENTS"]["lazy"]["TYPE"], "i");
+        assert!(json(&http, "/lazy").get("VALUE").is_none());
+        assert_eq!(count(), 0);
+
+        assert_eq!(json(&http, "/lazy?VALUE")["VALUE"], serde_json::json!([7]));
+        assert_eq!(count(), 1);
+
+        //strict always computes
+        let strict = HttpService::new_with_config(
+            root.clone(),
+            &addr,
+            Vec::new(),
+            None,
+            HttpConfig {
+                strict: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            json(&strict, "/")["CONTENTS"]["lazy"]["VALUE"],
+            serde_json::json!([7])
+        );
+        assert_eq!(count(), 2);
+
+        //triggers still compute and send
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        osc.add_send_addr(udp.local_addr().unwrap());
+        assert!(osc.trigger_path("/lazy").is_some());
+        assert_eq!(count(), 3);
+        let mut buf = [0u8; 1024];
+        let (size, _) = udp.recv_from(&mut buf).unwrap();
+        assert_eq!(
+            crate::osc::decoder::decode(&buf[..size]).unwrap(),
+            OscPacket::Message(OscMessage {
+                addr: "/lazy".into(),
+                args: vec![OscType::Int(7)],
+            })
+        );
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+//! Zeroconf/Bonjour advertisement of the services, via mDNS.
+use crate::error::OscQueryError;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The service type that OSCQuery clients browse for.
+pub const OSCJSON_TYPE: &str = "_oscjson._tcp.local.";
+/// The service type for plain OSC over UDP.
+pub const OSC_UDP_TYPE: &str = "_osc._udp.local.";
+
+const UNREGISTER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Advertise an http service as `_oscjson._tcp` and OSC services as `_osc._udp`.
+///
+/// Drop to unregister the services.
+/// *NOTE* this waits, briefly, for the goodbye announcements to go out.
+pub struct MdnsService {
+    daemon: ServiceDaemon,
+    name: String,
+    http: String,
+    osc: Mutex<Vec<String>>,
+}
+
+fn err<E: std::fmt::Display>(e: E) -> OscQueryError {
+    OscQueryError::Mdns(e.to_string())
+}
+
+//loopback and unspecified addresses aren't reachable from elsewhere, advertise the interfaces
+fn service_info(ty: &str, instance: &str, addr: &SocketAddr) -> Result<ServiceInfo, OscQueryError> {
+    let host = format!(
+        "{}.local.",
+        instance
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect::<String>()
+    );
+    let ip = addr.ip();
+    if ip.is_loopback() || ip.is_unspecified() {
+        ServiceInfo::new(ty, instance, &host, (), addr.port(), None).map(|i| i.enable_addr_auto())
+    } else {
+        ServiceInfo::new(ty, instance, &host, ip, addr.port(), None)
+    }
+    .map_err(err)
+}
+
+impl MdnsService {
+    /// Register the services, the name is the instance name clients see.
+    ///
+    /// OSC services after the first get the index appended to the name.
+    pub fn new(name: &str, http: &SocketAddr, osc: &[SocketAddr]) -> Result<Self, OscQueryError> {
+        let daemon = ServiceDaemon::new().map_err(err)?;
+        let info = service_info(OSCJSON_TYPE, name, http)?;
+        let s = Self {
+            http: info.get_fullname().to_string(),
+            daemon,
+            name: name.to_string(),
+            osc: Default::default(),
+        };
+        s.daemon.register(info).map_err(err)?;
+        s.set_osc_addrs(osc)?;
+        Ok(s)
+    }
+
+    /// Replace the advertised OSC services.
+    pub fn set_osc_addrs(&self, osc: &[SocketAddr]) -> Result<(), OscQueryError> {
+        let mut names = self.osc.lock().map_err(|_| OscQueryError::PoisonedLock)?;
+        for n in names.drain(..) {
+            self.unregister(&n);
+        }
+        for (i, addr) in osc.iter().enumerate() {
+            let instance = match i {
+                0 => self.name.clone(),
+                i => format!("{} {}", self.name, i + 1),
+            };
+            let info = service_info(OSC_UDP_TYPE, &instance, addr)?;
+            names.push(info.get_fullname().to_string());
+            self.daemon.register(info).map_err(err)?;
+        }
+        Ok(())
+    }
+
+    fn unregister(&self, fullname: &str) {
+        if let Ok(r) = self.daemon.unregister(fullname) {
+            let _ = r.recv_timeout(UNREGISTER_TIMEOUT);
+        }
+    }
+}
+
+impl Drop for MdnsService {
+    fn drop(&mut self) {
+        if let Ok(mut names) = self.osc.lock() {
+            for n in names.drain(..) {
+                self.unregister(&n);
+            }
+        }
+        self.unregister(&self.http);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use mdns_sd::ServiceEvent;
+    use std::time::Instant;
+
+    //browse with a separate daemon, returns the resolved port of the instance, if found in time
+    pub(crate) fn browse(ty: &str, instance: &str) -> Option<u16> {
+        let daemon = ServiceDaemon::new().unwrap();
+        let events = daemon.browse(ty).unwrap();
+        let fullname = format!("{}.{}", instance, ty);
+        let start = Instant::now();
+        let mut port = None;
+        while port.is_none() && start.elapsed() < Duration::from_secs(10) {
+            if let Ok(ServiceEvent::ServiceResolved(info)) =
+                events.recv_timeout(Duration::from_millis(100))
+            {
+                if info.get_fullname() == fullname {
+                    port = Some(info.get_port());
+                }
+            }
+        }
+        let _ = daemon.shutdown();
+        port
+    }
+
+    #[test]
+    fn advertise() {
+        let http: SocketAddr = "127.0.0.1:5678".parse().unwrap();
+        let osc: SocketAddr = "0.0.0.0:5679".parse().unwrap();
+        let mdns = MdnsService::new("mdns test", &http, &[osc]).unwrap();
+        assert_eq!(browse(OSCJSON_TYPE, "mdns test"), Some(5678));
+        assert_eq!(browse(OSC_UDP_TYPE, "mdns test"), Some(5679));
+        drop(mdns);
+    }
+}
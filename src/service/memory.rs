@@ -0,0 +1,150 @@
+//! In-memory transports in place of sockets, so tests can drive the services deterministically.
+//!
+//! Stream services, websocket and http, accept `tokio::io::duplex` streams from a `Connector`
+//! and run as a future on a runtime the test provides. On a basic scheduler with
+//! `tokio::time::pause` their polling happens in virtual time, as soon as the runtime is idle.
+//! OSC services exchange datagrams over a `Network` and are stepped by the test, see
+//! `OscDriver`, with the root's clock, a `ManualClock`, for the send interval.
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use tokio::io::DuplexStream;
+
+//the bytes either end of a stream can write before waiting for the other to read
+const DUPLEX_LEN: usize = 64 * 1024;
+
+type Accepted = std::io::Result<(DuplexStream, SocketAddr)>;
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// Connects in-memory clients to a stream service.
+///
+/// Each client gets its own loopback address, with ports counting up from 1.
+#[derive(Clone)]
+pub struct Connector {
+    tx: UnboundedSender<Accepted>,
+    port: Arc<AtomicU16>,
+    local_addr: SocketAddr,
+}
+
+impl Connector {
+    /// Connect a client, returns its end of the stream.
+    ///
+    /// The stream is closed, rather than refused, if the service has stopped.
+    pub fn connect(&self) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(DUPLEX_LEN);
+        let port = self.port.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .tx
+            .unbounded_send(Ok((server, SocketAddr::from(([127, 0, 0, 1], port)))));
+        client
+    }
+
+    /// The address the service reports as bound to, port 0 as there is no socket.
+    pub fn local_addr(&self) -> &SocketAddr {
+        &self.local_addr
+    }
+}
+
+//the connector and the connections it makes, for the service to accept
+pub(crate) fn listener() -> (Connector, UnboundedReceiver<Accepted>) {
+    let (tx, rx) = unbounded();
+    let connector = Connector {
+        tx,
+        port: Arc::new(AtomicU16::new(1)),
+        local_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+    };
+    (connector, rx)
+}
+
+/// In-memory datagram sockets, what one sends to the address of another it receives.
+#[derive(Clone, Default)]
+pub struct Network {
+    sockets: Arc<Mutex<HashMap<SocketAddr, Sender<Datagram>>>>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Bind a socket to the address, replacing any that was bound to it.
+    pub fn bind(&self, addr: SocketAddr) -> MemorySocket {
+        let (tx, rx) = channel();
+        if let Ok(mut sockets) = self.sockets.lock() {
+            sockets.insert(addr, tx);
+        }
+        MemorySocket {
+            addr,
+            rx,
+            network: self.clone(),
+        }
+    }
+}
+
+/// A datagram socket on a `Network`.
+pub struct MemorySocket {
+    addr: SocketAddr,
+    rx: Receiver<Datagram>,
+    network: Network,
+}
+
+impl MemorySocket {
+    /// Send the datagram to the socket bound to the address, like UDP it is lost if there is none.
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        if let Ok(sockets) = self.network.sockets.lock() {
+            if let Some(tx) = sockets.get(&addr) {
+                let _ = tx.send((buf.to_vec(), self.addr));
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Receive a datagram, truncated to fit the buffer like UDP.
+    ///
+    /// Fails with `WouldBlock` when there is none rather than waiting.
+    pub fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        let (data, from) = self
+            .rx
+            .try_recv()
+            .map_err(|_| std::io::Error::from(ErrorKind::WouldBlock))?;
+        let size = data.len().min(buf.len());
+        buf[..size].copy_from_slice(&data[..size]);
+        Ok((size, from))
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl crate::service::osc::Socket for MemorySocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        MemorySocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        MemorySocket::recv_from(self, buf)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::future::Future;
+
+    //run the future on a basic scheduler runtime with the time paused
+    pub(crate) fn run<F: Future>(f: F) -> F::Output {
+        let mut rt = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .expect("could not create runtime");
+        rt.block_on(async {
+            tokio::time::pause();
+            f.await
+        })
+    }
+}
@@ -1,19 +1,34 @@
-use crate::node::OscRender;
-use crate::osc::{OscMessage, OscPacket};
+use crate::error::OscQueryError;
+use crate::osc::{OscBundle, OscMessage, OscPacket, OscType};
 use crate::root::{NodeHandle, NodeWrapper, RootInner};
+use crate::stats::Stats;
+use crate::time::{SharedClock, TimeTag};
 
-use std::collections::HashSet;
+#[cfg(any(test, feature = "deterministic-test"))]
+use crate::service::memory::MemorySocket;
+
+use socket2::{Domain, Protocol, Socket as RawSocket, Type};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::ErrorKind;
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
-use std::sync::mpsc::{sync_channel, SyncSender, TryRecvError};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError};
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{Mutex, MutexGuard, RwLock};
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 //TODO: what we set the TCP stream read timeout to?
 const READ_TIMEOUT: Duration = Duration::from_millis(1);
 const CHANNEL_LEN: usize = 1024;
+const QUEUE_LEN: usize = 1024;
+//at most one warning about a full queue this often
+const DROP_WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The address of the message carrying the sequence number in `Sequencing::Bundle` mode.
+pub const SEQUENCE_ADDR: &str = "/oscquery/sequence";
+
+//the address of the pending sends of bundles
+const BUNDLE_ADDR: &str = "#bundle";
 
 /// Manage a thread that reads and writes OSC to/from a socket and updates a values in an OSCQuery tree.
 ///
@@ -22,105 +37,796 @@ const CHANNEL_LEN: usize = 1024;
 
 pub struct OscService {
     root: Arc<RwLock<RootInner>>,
+    stats: Arc<Stats>,
     handle: Option<JoinHandle<()>>,
     cmd_sender: SyncSender<Command>,
+    queue: Arc<Mutex<VecDeque<Pending>>>,
+    send_interval: Arc<RwLock<Option<Duration>>>,
     local_addr: SocketAddr,
     send_addrs: RwLock<HashSet<SocketAddr>>,
+    sequenced: Arc<Mutex<HashMap<SocketAddr, Sequenced>>>,
+    //destinations that don't get their own writes back, and the window to consider
+    suppress_reflection: Mutex<HashMap<SocketAddr, Duration>>,
+    last_sent: Mutex<LastSent>,
+    multicast: Option<Multicast>,
+    //when a dropped send was last warned about
+    drop_warned: Mutex<Option<Instant>>,
+}
+
+//the last message sent to each OSC address, for `resend_last`
+#[derive(Default)]
+struct LastSent {
+    msgs: HashMap<String, OscMessage>,
+    //the generation of the root when the addresses nothing sends at any more were last dropped
+    generation: u64,
+}
+
+//the group joined by a multicast service, to leave it on drop
+struct Multicast {
+    sock: UdpSocket,
+    group: IpAddr,
+    interface: Option<IpAddr>,
+}
+
+impl Multicast {
+    fn interface_v4(&self) -> Result<Ipv4Addr, std::io::Error> {
+        match self.interface {
+            None => Ok(Ipv4Addr::UNSPECIFIED),
+            Some(IpAddr::V4(i)) => Ok(i),
+            Some(IpAddr::V6(..)) => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "IPv6 interface for an IPv4 multicast group",
+            )),
+        }
+    }
+
+    fn join(&self) -> Result<(), std::io::Error> {
+        match self.group {
+            IpAddr::V4(g) if g.is_multicast() => {
+                self.sock.join_multicast_v4(&g, &self.interface_v4()?)
+            }
+            IpAddr::V6(g) if g.is_multicast() => self.sock.join_multicast_v6(&g, 0),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "not a multicast address",
+            )),
+        }
+    }
+
+    fn leave(&self) -> Result<(), std::io::Error> {
+        match self.group {
+            IpAddr::V4(g) => self.sock.leave_multicast_v4(&g, &self.interface_v4()?),
+            IpAddr::V6(g) => self.sock.leave_multicast_v6(&g, 0),
+        }
+    }
+
+    //bind with SO_REUSEADDR so that other sockets on this host can bind the group port too
+    fn bind<A: ToSocketAddrs>(addr: A) -> Result<UdpSocket, std::io::Error> {
+        let mut err = None;
+        for addr in addr.to_socket_addrs()? {
+            let domain = if addr.is_ipv4() {
+                Domain::ipv4()
+            } else {
+                Domain::ipv6()
+            };
+            let sock = RawSocket::new(domain, Type::dgram(), Some(Protocol::udp()))?;
+            sock.set_reuse_address(true)?;
+            match sock.bind(&addr.into()) {
+                Ok(()) => return Ok(sock.into_udp_socket()),
+                Err(e) => err = Some(e),
+            }
+        }
+        Err(err
+            .unwrap_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "no address to bind")))
+    }
+}
+
+/// How the sequence number is added to the messages sent to a sequenced destination.
+///
+/// Sequence numbers are per destination, start at 0 and wrap around. They are taken when the
+/// message is sent, so they follow the order on the wire even when critical messages go ahead of
+/// the others. A message dropped because the queue is full uses up a number, so receivers see the
+/// gap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Sequencing {
+    /// Append the sequence number to the message as an extra trailing Int argument.
+    TrailingArg,
+    /// Send the message, untouched, in a bundle that first has a message at `SEQUENCE_ADDR`
+    /// with the sequence number as its only Int argument.
+    Bundle,
+}
+
+struct Sequenced {
+    mode: Sequencing,
+    next: i32,
+}
+
+impl Sequenced {
+    //encode the packet with the next sequence number, which is used up either way
+    //bundles get the sequence message first in their content, whatever the mode
+    fn encode(&mut self, packet: &OscPacket) -> Option<Vec<u8>> {
+        let seq = OscType::Int(self.take());
+        if let (Sequencing::TrailingArg, OscPacket::Message(msg)) = (self.mode, packet) {
+            let mut msg = msg.clone();
+            msg.args.push(seq);
+            return crate::osc::encoder::encode(&OscPacket::Message(msg)).ok();
+        }
+        let seq = OscPacket::Message(OscMessage {
+            addr: SEQUENCE_ADDR.to_string(),
+            args: vec![seq],
+        });
+        let packet = match packet {
+            OscPacket::Message(..) => OscPacket::Bundle(OscBundle {
+                //immediately
+                timetag: TimeTag::IMMEDIATE.into(),
+                content: vec![seq, packet.clone()],
+            }),
+            OscPacket::Bundle(bundle) => OscPacket::Bundle(OscBundle {
+                timetag: bundle.timetag,
+                content: std::iter::once(seq)
+                    .chain(bundle.content.iter().cloned())
+                    .collect(),
+            }),
+        };
+        crate::osc::encoder::encode(&packet).ok()
+    }
+
+    fn take(&mut self) -> i32 {
+        let seq = self.next;
+        self.next = self.next.wrapping_add(1);
+        seq
+    }
 }
 
 enum Command {
-    Send(Vec<u8>, SocketAddr),
     End,
 }
 
+/// An outgoing OSC message that has been queued but not yet sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingSend {
+    /// The OSC address of the message.
+    pub addr: String,
+    /// Where the message will be sent.
+    pub dest: SocketAddr,
+}
+
+struct Pending {
+    send: PendingSend,
+    buf: Vec<u8>,
+    //for sequenced destinations, encoded with the sequence number when sent
+    packet: Option<OscPacket>,
+    //sent ahead of the others, regardless of the send interval
+    critical: bool,
+}
+
+//a bundle of the rendered messages, each after those of its aliases, timed at the wall clock time
+pub(crate) fn trigger_bundle(
+    rendered: &[(String, OscMessage, Vec<String>)],
+    wall: SystemTime,
+) -> OscBundle {
+    trigger_bundle_at(rendered, TimeTag::from_system_time(wall))
+}
+
+//a bundle of the rendered messages, each after those of its aliases
+pub(crate) fn trigger_bundle_at(
+    rendered: &[(String, OscMessage, Vec<String>)],
+    timetag: TimeTag,
+) -> OscBundle {
+    let mut content = Vec::new();
+    for (_, msg, aliases) in rendered {
+        for alias in aliases {
+            content.push(OscPacket::Message(OscMessage {
+                addr: alias.clone(),
+                args: msg.args.clone(),
+            }));
+        }
+        content.push(OscPacket::Message(msg.clone()));
+    }
+    OscBundle {
+        timetag: timetag.into(),
+        content,
+    }
+}
+
+//what the worker needs of a socket, so tests can substitute an in-memory one
+pub(crate) trait Socket: Send + 'static {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize>;
+    //fails with `WouldBlock` or `TimedOut` when nothing arrives in time
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)>;
+}
+
+impl Socket for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+}
+
+//reads and writes the socket of an `OscService`
+struct Worker<S> {
+    sock: S,
+    cmd_recv: Receiver<Command>,
+    root: Arc<RwLock<RootInner>>,
+    stats: Arc<Stats>,
+    queue: Arc<Mutex<VecDeque<Pending>>>,
+    sequenced: Arc<Mutex<HashMap<SocketAddr, Sequenced>>>,
+    send_interval: Arc<RwLock<Option<Duration>>>,
+    clock: SharedClock,
+    last_send: Option<Instant>,
+    buf: Vec<u8>,
+}
+
+impl<S: Socket> Worker<S> {
+    //send the next queued message, if it is time, and handle what arrives, false once stopped
+    fn step(&mut self) -> bool {
+        match self.cmd_recv.try_recv() {
+            Ok(Command::End) => return false,
+            Err(TryRecvError::Disconnected) => {
+                return false;
+            }
+            Err(TryRecvError::Empty) => (),
+        }
+        let interval = self.send_interval.read().map_or(None, |i| *i);
+        let ready = match (interval, self.last_send) {
+            (Some(interval), Some(last)) => {
+                self.clock.now().saturating_duration_since(last) >= interval
+            }
+            _ => true,
+        };
+        //hold the lock while sending so a flush doesn't return early
+        if let Ok(mut queue) = self.queue.lock() {
+            //critical messages are at the front
+            if ready || queue.front().is_some_and(|p| p.critical) {
+                if let Some(p) = queue.pop_front() {
+                    self.stats.dec_osc_queue_depth();
+                    self.last_send = Some(self.clock.now());
+                    let sequenced = match (&p.packet, self.sequenced.lock()) {
+                        (Some(packet), Ok(mut sequenced)) => sequenced
+                            .get_mut(&p.send.dest)
+                            .and_then(|s| s.encode(packet)),
+                        _ => None,
+                    };
+                    //XXX indicate error?
+                    let buf = sequenced.as_ref().unwrap_or(&p.buf);
+                    if self.sock.send_to(buf, p.send.dest).is_ok() {
+                        self.stats.inc_osc_packets_sent();
+                    }
+                }
+            }
+        }
+        crate::root::RootInner::flush_coalesced(&self.root);
+        match self.sock.recv_from(&mut self.buf) {
+            Ok((size, addr)) => {
+                if size > 0 {
+                    match crate::osc::decoder::decode(&self.buf[..size]) {
+                        Ok(packet) => {
+                            self.stats.inc_osc_packets_received();
+                            //value queries are answered directly, not queued
+                            for reply in crate::root::RootInner::handle_osc_packet(
+                                &self.root,
+                                &packet,
+                                Some(addr),
+                                None,
+                            ) {
+                                let packet = OscPacket::Message(reply);
+                                if let Ok(buf) = crate::osc::encoder::encode(&packet) {
+                                    if self.sock.send_to(&buf, addr).is_ok() {
+                                        self.stats.inc_osc_packets_sent();
+                                    }
+                                }
+                            }
+                        }
+                        Err(..) => self.stats.inc_osc_decode_errors(),
+                    }
+                }
+            }
+            Err(e) => match e.kind() {
+                //timeout
+                //https://doc.rust-lang.org/std/net/struct.UdpSocket.html#method.set_read_timeout
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => (),
+                _ => {
+                    eprintln!("Error receiving from socket: {}", e);
+                    return false;
+                }
+            },
+        };
+        true
+    }
+}
+
+/// Steps an in-memory `OscService`, see `Root::spawn_osc_in_memory`.
+#[cfg(any(test, feature = "deterministic-test"))]
+pub struct OscDriver {
+    worker: Worker<MemorySocket>,
+}
+
+#[cfg(any(test, feature = "deterministic-test"))]
+impl OscDriver {
+    /// Run one iteration of the service loop: check for the end, send the next queued message
+    /// if the send interval allows and handle at most one received packet.
+    ///
+    /// Returns `false` once the service has been dropped, without doing anything else.
+    pub fn step(&mut self) -> bool {
+        self.worker.step()
+    }
+}
+
 impl OscService {
     /// Create and start an OscService
     pub(crate) fn new<A: ToSocketAddrs>(
         root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+        addr: A,
+    ) -> Result<Self, OscQueryError> {
+        Self::with_socket(root, stats, UdpSocket::bind(addr)?)
+    }
+
+    /// Create and start an OscService that joins a multicast group and sends to it, see
+    /// `Root::spawn_osc_multicast`.
+    pub(crate) fn new_multicast<A: ToSocketAddrs>(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
         addr: A,
-    ) -> Result<Self, std::io::Error> {
-        let sock = UdpSocket::bind(addr)?;
+        group: SocketAddr,
+        interface: Option<IpAddr>,
+    ) -> Result<Self, OscQueryError> {
+        let sock = Multicast::bind(addr)?;
+        let multicast = Multicast {
+            sock: sock.try_clone()?,
+            group: group.ip(),
+            interface,
+        };
+        multicast.join()?;
+        let mut service = Self::with_socket(root, stats, sock)?;
+        service.multicast = Some(multicast);
+        service.add_send_addr(group);
+        Ok(service)
+    }
+
+    fn with_socket(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+        sock: UdpSocket,
+    ) -> Result<Self, OscQueryError> {
         let local_addr = sock.local_addr()?;
-        let (cmd_sender, cmd_recv) = sync_channel(CHANNEL_LEN);
 
         //timeout reads so we can check our cmd queue
         sock.set_read_timeout(Some(READ_TIMEOUT))?;
 
-        let r = root.clone();
-        let handle = std::thread::spawn(move || {
-            let mut buf = [0u8; crate::osc::decoder::MTU];
-            loop {
-                match cmd_recv.try_recv() {
-                    Ok(Command::End) => return,
-                    Ok(Command::Send(buf, to_addr)) => {
-                        //XXX indicate error?
-                        let _ = sock.send_to(&buf, to_addr);
-                    }
-                    Err(TryRecvError::Disconnected) => {
-                        return;
+        let (mut service, mut worker) = Self::start(root, stats, sock, local_addr);
+        service.handle = Some(std::thread::spawn(move || while worker.step() {}));
+        Ok(service)
+    }
+
+    /// Like `new` but with an in-memory socket and nothing running, the returned `OscDriver`
+    /// steps the service.
+    #[cfg(any(test, feature = "deterministic-test"))]
+    pub(crate) fn new_in_memory(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+        sock: MemorySocket,
+    ) -> (Self, OscDriver) {
+        let local_addr = sock.local_addr();
+        let (service, worker) = Self::start(root, stats, sock, local_addr);
+        (service, OscDriver { worker })
+    }
+
+    //the service and the worker that reads and writes the socket, not yet running
+    fn start<S: Socket>(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+        sock: S,
+        local_addr: SocketAddr,
+    ) -> (Self, Worker<S>) {
+        let (cmd_sender, cmd_recv) = sync_channel(CHANNEL_LEN);
+        let queue: Arc<Mutex<VecDeque<Pending>>> = Default::default();
+        let sequenced: Arc<Mutex<HashMap<SocketAddr, Sequenced>>> = Default::default();
+        let send_interval: Arc<RwLock<Option<Duration>>> = Default::default();
+        let clock = root
+            .read()
+            .map_or_else(|_| crate::time::system_clock(), |root| root.clock().clone());
+        let worker = Worker {
+            sock,
+            cmd_recv,
+            root: root.clone(),
+            stats: stats.clone(),
+            queue: queue.clone(),
+            sequenced: sequenced.clone(),
+            send_interval: send_interval.clone(),
+            clock,
+            last_send: None,
+            buf: vec![0u8; crate::osc::decoder::MTU],
+        };
+        let service = Self {
+            root,
+            stats,
+            handle: None,
+            cmd_sender,
+            queue,
+            send_interval,
+            local_addr,
+            send_addrs: RwLock::new(HashSet::new()),
+            sequenced,
+            suppress_reflection: Default::default(),
+            last_sent: Default::default(),
+            multicast: None,
+            drop_warned: Default::default(),
+        };
+        (service, worker)
+    }
+
+    /// Queue a message, along with its encoding, to all the send addresses.
+    ///
+    /// Sequenced destinations get their own encoding, destinations that wrote the value are
+    /// skipped if they suppress reflection.
+    /// *NOTE* this locks the root, don't call it with the root locked.
+    pub(crate) fn send(&self, msg: &OscMessage, buf: &[u8]) {
+        self.send_as(msg, buf, false);
+    }
+
+    //like `send`, critical messages are sent ahead of the others, regardless of the interval.
+    //True if the message was queued to any send address
+    fn send_as(&self, msg: &OscMessage, buf: &[u8], critical: bool) -> bool {
+        if let Some(mut last) = self.last_sent() {
+            last.msgs.insert(msg.addr.clone(), msg.clone());
+        }
+        let reflected = self.reflected(std::iter::once(msg.addr.as_str()));
+        match self.send_addrs.read() {
+            Ok(dests) => self.queue_to(
+                &OscPacket::Message(msg.clone()),
+                &msg.addr,
+                buf,
+                dests.iter().filter(|d| !reflected.contains_key(d)),
+                critical,
+            ),
+            Err(..) => false,
+        }
+    }
+
+    /// Queue a bundle, along with its encoding, to all the send addresses.
+    ///
+    /// The messages in it count as sent for `resend_last`, the pending sends have the address
+    /// `#bundle`. Returns true if it was queued to any send address.
+    pub(crate) fn send_bundle(&self, bundle: &OscBundle, buf: &[u8]) -> bool {
+        if let Some(mut last) = self.last_sent() {
+            for p in &bundle.content {
+                if let OscPacket::Message(msg) = p {
+                    last.msgs.insert(msg.addr.clone(), msg.clone());
+                }
+            }
+        }
+        let reflected = self.reflected(bundle.content.iter().filter_map(|p| match p {
+            OscPacket::Message(msg) => Some(msg.addr.as_str()),
+            OscPacket::Bundle(..) => None,
+        }));
+        let mut queued = false;
+        if let Ok(dests) = self.send_addrs.read() {
+            queued = self.queue_to(
+                &OscPacket::Bundle(bundle.clone()),
+                BUNDLE_ADDR,
+                buf,
+                dests.iter().filter(|d| !reflected.contains_key(d)),
+                false,
+            );
+            //the others get the bundle without the messages they wrote
+            for (dest, addrs) in reflected.iter().filter(|(d, _)| dests.contains(d)) {
+                let content: Vec<OscPacket> = bundle
+                    .content
+                    .iter()
+                    .filter(|p| !matches!(p, OscPacket::Message(m) if addrs.contains(&m.addr)))
+                    .cloned()
+                    .collect();
+                if content.is_empty() {
+                    continue;
+                }
+                let packet = OscPacket::Bundle(OscBundle {
+                    timetag: bundle.timetag,
+                    content,
+                });
+                match crate::osc::encoder::encode(&packet) {
+                    Ok(buf) => {
+                        queued |=
+                            self.queue_to(&packet, BUNDLE_ADDR, &buf, std::iter::once(dest), false)
                     }
-                    Err(TryRecvError::Empty) => (),
-                }
-                match sock.recv_from(&mut buf) {
-                    Ok((size, addr)) => {
-                        if size > 0 {
-                            let packet = crate::osc::decoder::decode(&buf[..size]).unwrap();
-                            crate::root::RootInner::handle_osc_packet(
-                                &root,
-                                &packet,
-                                Some(addr),
-                                None,
-                            );
+                    Err(..) => eprintln!("error encoding"),
+                }
+            }
+        }
+        queued
+    }
+
+    //the last sent messages, without those of the addresses nothing sends at any more once the
+    //structure of the tree changed
+    fn last_sent(&self) -> Option<MutexGuard<'_, LastSent>> {
+        let mut last = self.last_sent.lock().ok()?;
+        if let Ok(root) = self.root.read() {
+            if root.generation() != last.generation {
+                last.generation = root.generation();
+                if !last.msgs.is_empty() {
+                    let live = root.send_addresses();
+                    last.msgs.retain(|addr, _| live.contains(addr.as_str()));
+                }
+            }
+        }
+        Some(last)
+    }
+
+    //the destinations that suppress reflection and wrote the values at any of the addresses
+    //within their window, along with those addresses
+    fn reflected<'a, I>(&self, addrs: I) -> HashMap<SocketAddr, HashSet<String>>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut reflected: HashMap<SocketAddr, HashSet<String>> = HashMap::new();
+        let suppress = match self.suppress_reflection.lock() {
+            Ok(s) if !s.is_empty() => s,
+            _ => return reflected,
+        };
+        if let Ok(root) = self.root.read() {
+            let now = root.clock().now();
+            for addr in addrs {
+                if let Some((writer, time)) = root.last_writer(addr) {
+                    match suppress.get(&writer) {
+                        Some(window) if now.saturating_duration_since(time) <= *window => {
+                            reflected
+                                .entry(writer)
+                                .or_default()
+                                .insert(addr.to_string());
                         }
+                        _ => (),
                     }
-                    Err(e) => match e.kind() {
-                        //timeout
-                        //https://doc.rust-lang.org/std/net/struct.UdpSocket.html#method.set_read_timeout
-                        ErrorKind::WouldBlock | ErrorKind::TimedOut => (),
-                        _ => {
-                            eprintln!("Error receiving from socket: {}", e);
-                            break;
+                }
+            }
+        }
+        reflected
+    }
+
+    fn queue_to<'a, I>(
+        &self,
+        packet: &OscPacket,
+        addr: &str,
+        buf: &[u8],
+        dests: I,
+        critical: bool,
+    ) -> bool
+    where
+        I: Iterator<Item = &'a SocketAddr>,
+    {
+        let mut queued = false;
+        if let (Ok(mut queue), Ok(mut sequenced)) = (self.queue.lock(), self.sequenced.lock()) {
+            for dest in dests {
+                if queue.len() >= QUEUE_LEN {
+                    //a critical message takes the place of the newest non-critical one
+                    let evict = if critical {
+                        queue.iter().rposition(|p| !p.critical)
+                    } else {
+                        None
+                    };
+                    let dropped = match evict.and_then(|i| queue.remove(i)) {
+                        Some(evicted) => {
+                            self.stats.dec_osc_queue_depth();
+                            evicted.send.dest
                         }
+                        None => *dest,
+                    };
+                    self.stats.inc_osc_sends_dropped();
+                    self.warn_dropped(dropped);
+                    //the sequence skips the dropped message's number
+                    if let Some(s) = sequenced.get_mut(&dropped) {
+                        s.take();
+                    }
+                    if evict.is_none() {
+                        continue;
+                    }
+                }
+                self.stats.inc_osc_queue_depth();
+                let pending = Pending {
+                    send: PendingSend {
+                        addr: addr.to_string(),
+                        dest: *dest,
+                    },
+                    buf: buf.to_vec(),
+                    packet: if sequenced.contains_key(dest) {
+                        Some(packet.clone())
+                    } else {
+                        None
                     },
+                    critical,
                 };
+                if critical {
+                    //after the critical messages already queued, ahead of the others
+                    let at = queue.iter().take_while(|p| p.critical).count();
+                    queue.insert(at, pending);
+                } else {
+                    queue.push_back(pending);
+                }
+                queued = true;
             }
-        });
-        Ok(Self {
-            root: r,
-            handle: Some(handle),
-            cmd_sender,
-            local_addr,
-            send_addrs: RwLock::new(HashSet::new()),
-        })
+        }
+        queued
+    }
+
+    //the count is in the stats, a full queue shouldn't flood the log as well
+    fn warn_dropped(&self, dest: SocketAddr) {
+        if let Ok(mut warned) = self.drop_warned.lock() {
+            let now = Instant::now();
+            if warned.is_none_or(|w| now.duration_since(w) >= DROP_WARNING_INTERVAL) {
+                *warned = Some(now);
+                log::warn!(
+                    "osc send queue full, dropping send to {}, {} dropped in total",
+                    dest,
+                    self.stats.osc_sends_dropped()
+                );
+            }
+        }
+    }
+
+    /// Add sequence numbers to the messages sent to the destination, so that it can detect
+    /// lost messages, or stop with `None`.
+    ///
+    /// *NOTE* only enable this for receivers that expect it, others see an unexpected argument
+    /// or bundle. Changing the mode keeps the sequence going, stopping resets it.
+    pub fn set_sequencing(&self, dest: SocketAddr, mode: Option<Sequencing>) {
+        if let Ok(mut sequenced) = self.sequenced.lock() {
+            match mode {
+                Some(mode) => {
+                    sequenced
+                        .entry(dest)
+                        .or_insert(Sequenced { mode, next: 0 })
+                        .mode = mode
+                }
+                None => {
+                    sequenced.remove(&dest);
+                }
+            }
+        }
     }
 
-    fn send(&self, buf: &Vec<u8>) {
-        if let Ok(addrs) = self.send_addrs.read() {
-            for addr in &*addrs {
-                if let Err(_) = self
-                    .cmd_sender
-                    .send(Command::Send(buf.clone(), addr.clone()))
-                {
-                    eprintln!("error sending to {}", addr);
+    /// Don't send values back to the destination if it wrote them, within the window, or stop
+    /// with `None`. Off by default.
+    ///
+    /// This avoids echoing a peer's writes back to it when it is both a send address and a
+    /// source of updates. Writes are matched by source address, so the peer has to send from
+    /// the address it receives at. Bundles get sent without the messages the peer wrote.
+    /// *NOTE* `resend_last` doesn't suppress anything.
+    pub fn set_suppress_reflection(&self, dest: SocketAddr, window: Option<Duration>) {
+        if let Ok(mut suppress) = self.suppress_reflection.lock() {
+            match window {
+                Some(window) => {
+                    suppress.insert(dest, window);
+                }
+                None => {
+                    suppress.remove(&dest);
                 }
             }
         }
     }
 
-    fn render_and_send(&self, node: &NodeWrapper) -> Option<OscMessage> {
-        let mut args = Vec::new();
-        node.node.osc_render(&mut args);
-        let addr = node.full_path.clone();
-        let msg = OscMessage {
-            addr: addr.clone(),
-            args,
-        };
+    /// The sequence number the next message to the destination will carry, if it is sequenced.
+    pub fn sequence(&self, dest: &SocketAddr) -> Option<i32> {
+        self.sequenced
+            .lock()
+            .map_or(None, |s| s.get(dest).map(|s| s.next))
+    }
+
+    /// Send the last message sent to the given OSC address again, only to the destination.
+    ///
+    /// The OSC address is the path unless the node has an `OscAddress`. Returns false if nothing
+    /// was sent to the address yet.
+    /// *NOTE* the destination doesn't have to be a send address.
+    pub fn resend_last(&self, dest: SocketAddr, addr: &str) -> bool {
+        let msg = self
+            .last_sent()
+            .and_then(|last| last.msgs.get(addr).cloned());
+        match msg.map(|m| {
+            (
+                crate::osc::encoder::encode(&OscPacket::Message(m.clone())),
+                m,
+            )
+        }) {
+            Some((Ok(buf), msg)) => {
+                self.queue_to(
+                    &OscPacket::Message(msg.clone()),
+                    &msg.addr,
+                    &buf,
+                    std::iter::once(&dest),
+                    false,
+                );
+                true
+            }
+            Some((Err(..), _)) => {
+                eprintln!("error encoding");
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// The number of queued messages that have not been sent yet.
+    pub fn pending(&self) -> usize {
+        self.queue.lock().map_or(0, |q| q.len())
+    }
+
+    /// Remove the queued messages that match the filter, returns the number removed.
+    pub fn cancel_pending<F>(&self, filter: F) -> usize
+    where
+        F: Fn(&PendingSend) -> bool,
+    {
+        if let Ok(mut queue) = self.queue.lock() {
+            let len = queue.len();
+            queue.retain(|p| !filter(&p.send));
+            let removed = len - queue.len();
+            for _ in 0..removed {
+                self.stats.dec_osc_queue_depth();
+            }
+            removed
+        } else {
+            0
+        }
+    }
+
+    /// Wait for the queued messages to be sent, returns false if the timeout elapsed first.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.pending() > 0 {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            std::thread::sleep(READ_TIMEOUT);
+        }
+        true
+    }
+
+    /// Set the minimum time between outgoing messages. Defaults to `None`, no limit.
+    pub fn set_send_interval(&self, interval: Option<Duration>) {
+        if let Ok(mut i) = self.send_interval.write() {
+            *i = interval;
+        }
+    }
+
+    //render a node along with the aliases that emit on trigger, and whether it is critical
+    fn render(
+        root: &RootInner,
+        node: &NodeWrapper,
+        now: Instant,
+    ) -> Option<(OscMessage, Vec<String>, bool)> {
+        Some((
+            node.osc_message(now)?,
+            root.alias_triggers(&node.full_path),
+            node.node.critical(),
+        ))
+    }
+
+    //send the message of a node after those of the aliases, `None` if encoding failed, else with
+    //whether it was queued to any send address
+    fn send_rendered(
+        &self,
+        msg: OscMessage,
+        aliases: Vec<String>,
+        critical: bool,
+    ) -> Option<(OscMessage, bool)> {
+        for alias in aliases {
+            self.send_msg(
+                &OscMessage {
+                    addr: alias,
+                    args: msg.args.clone(),
+                },
+                critical,
+            );
+        }
+        self.send_msg(&msg, critical).map(|queued| (msg, queued))
+    }
+
+    /// Encode and send a message to all the send addresses, `None` if encoding failed, else
+    /// whether it was queued to any of them.
+    pub(crate) fn send_msg(&self, msg: &OscMessage, critical: bool) -> Option<bool> {
         let buf = crate::osc::encoder::encode(&OscPacket::Message(msg.clone()));
         match buf {
-            Ok(buf) => {
-                self.send(&buf);
-                Some(msg)
-            }
+            Ok(buf) => Some(self.send_as(msg, &buf, critical)),
             Err(..) => {
                 eprintln!("error encoding");
                 None
@@ -135,35 +841,135 @@ impl OscService {
             .map_or(None, |root| root.handle_to_path(handle))
     }
 
+    //whether a triggered message would reach anyone, see `Root::set_force_trigger`
+    fn trigger_wanted(&self) -> bool {
+        self.has_send_addrs() || self.root.read().is_ok_and(|root| root.force_trigger())
+    }
+
     /// Trigger a OSC send for the node at the given handle, if it is valid.
     /// returns the address and renered buffer that was sent, if any
+    ///
+    /// Without send addresses nothing is rendered or sent, unless `Root::set_force_trigger`.
+    /// Critical nodes, see `Get::with_critical`, are sent ahead of the queued messages,
+    /// regardless of the send interval.
     pub fn trigger(&self, handle: NodeHandle) -> Option<OscMessage> {
-        if let Ok(root) = self.root.read() {
-            root.with_node_at_handle(&handle, |node| {
-                if let Some(node) = node {
-                    self.render_and_send(node)
-                } else {
-                    None
-                }
-            })
-        } else {
-            None
+        if !self.trigger_wanted() {
+            return None;
         }
+        let (msg, aliases, critical) = self.root.read().map_or(None, |root| {
+            let now = root.clock().now();
+            root.with_node_at_handle(&handle, |node| Self::render(&root, node?, now))
+        })?;
+        self.send_rendered(msg, aliases, critical)
+            .map(|(msg, _)| msg)
     }
 
     /// Trigger an OSC send for the node at the given path, if it is valid.
     /// returns the address and renered buffer that was sent, if any
+    ///
+    /// Without send addresses nothing is rendered or sent, unless `Root::set_force_trigger`.
+    /// Critical nodes are sent first, see `trigger`.
     pub fn trigger_path(&self, path: &str) -> Option<OscMessage> {
-        if let Ok(root) = self.root.read() {
-            root.with_node_at_path(path, |ni| {
-                if let Some((node, _)) = ni {
-                    self.render_and_send(node)
-                } else {
-                    None
+        if !self.trigger_wanted() {
+            return None;
+        }
+        let (msg, aliases, critical) = self.root.read().map_or(None, |root| {
+            let now = root.clock().now();
+            root.with_node_at_path(path, |ni| Self::render(&root, ni?.0, now))
+        })?;
+        self.send_rendered(msg, aliases, critical)
+            .map(|(msg, _)| msg)
+    }
+
+    /// Trigger an OSC send for every readable node in the tree, in path order.
+    ///
+    /// Aliases that emit on trigger are sent too. Returns the messages of the nodes that were
+    /// queued. Without send addresses nothing is rendered or sent, see `trigger`.
+    pub fn trigger_all(&self) -> Vec<OscMessage> {
+        if !self.trigger_wanted() {
+            return Vec::new();
+        }
+        let rendered: Vec<_> = self.root.read().map_or(Vec::new(), |root| {
+            root.render_all()
+                .into_iter()
+                .map(|(path, msg, aliases)| {
+                    let critical = root
+                        .with_node_at_path(&path, |n| n.is_some_and(|(n, _)| n.node.critical()));
+                    (msg, aliases, critical)
+                })
+                .collect()
+        });
+        rendered
+            .into_iter()
+            .filter_map(|(msg, aliases, critical)| {
+                match self.send_rendered(msg, aliases, critical) {
+                    Some((msg, true)) => Some(msg),
+                    _ => None,
                 }
             })
-        } else {
-            None
+            .collect()
+    }
+
+    /// Like `trigger_all` but send all the messages in a single bundle, timed with the current
+    /// wall clock time, so receivers can apply them together.
+    ///
+    /// Sequenced destinations get the sequence message first in the bundle, whatever the mode.
+    /// Returns the messages of the nodes in the bundle if it was queued, nothing is sent if there
+    /// are none. Without send addresses nothing is rendered or sent, see `trigger`.
+    /// *NOTE* the bundle of a large tree may not fit in a UDP datagram.
+    pub fn trigger_all_bundle(&self) -> Vec<OscMessage> {
+        if !self.trigger_wanted() {
+            return Vec::new();
+        }
+        let (rendered, wall) = match self.root.read() {
+            Ok(root) => (root.render_all(), root.clock().wall()),
+            Err(..) => return Vec::new(),
+        };
+        if rendered.is_empty() {
+            return Vec::new();
+        }
+        let bundle = trigger_bundle(&rendered, wall);
+        match crate::osc::encoder::encode(&OscPacket::Bundle(bundle.clone())) {
+            Ok(buf) if self.send_bundle(&bundle, &buf) => {
+                rendered.into_iter().map(|(_, msg, _)| msg).collect()
+            }
+            Ok(..) => Vec::new(),
+            Err(..) => {
+                eprintln!("error encoding");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Trigger the nodes at the handles together, sending their messages in a single bundle
+    /// with the timetag, immediate if `None`, so receivers can apply them at once.
+    ///
+    /// The messages are in the order of the handles, each after those of its aliases that emit on
+    /// trigger. Invalid handles and nodes that don't render are skipped. Returns the bundle that
+    /// was sent, `None` if no node rendered or there are no send addresses, see `trigger`.
+    pub fn trigger_bundle<I>(&self, handles: I, timetag: Option<(u32, u32)>) -> Option<OscBundle>
+    where
+        I: IntoIterator<Item = NodeHandle>,
+    {
+        if !self.trigger_wanted() {
+            return None;
+        }
+        let rendered = self
+            .root
+            .read()
+            .map_or(Vec::new(), |root| root.render_handles(handles));
+        if rendered.is_empty() {
+            return None;
+        }
+        let timetag = timetag.map_or(TimeTag::IMMEDIATE, TimeTag::from);
+        let bundle = trigger_bundle_at(&rendered, timetag);
+        match crate::osc::encoder::encode(&OscPacket::Bundle(bundle.clone())) {
+            Ok(buf) if self.send_bundle(&bundle, &buf) => Some(bundle),
+            Ok(..) => None,
+            Err(..) => {
+                eprintln!("error encoding");
+                None
+            }
         }
     }
 
@@ -178,6 +984,53 @@ impl OscService {
             .insert(addr);
     }
 
+    /// Stop sending outgoing OSC messages to an address.
+    ///
+    /// Returns false if the address wasn't being sent to.
+    /// This method locks.
+    pub fn remove_send_addr(&self, addr: SocketAddr) -> bool {
+        self.send_addrs
+            .write()
+            .is_ok_and(|mut addrs| addrs.remove(&addr))
+    }
+
+    /// Whether there are addresses that outgoing OSC messages are sent to.
+    pub fn has_send_addrs(&self) -> bool {
+        self.send_addrs.read().is_ok_and(|a| !a.is_empty())
+    }
+
+    /// The addresses that outgoing OSC messages are sent to.
+    pub fn send_addrs(&self) -> Vec<SocketAddr> {
+        self.send_addrs
+            .read()
+            .map_or_else(|_| Vec::new(), |a| a.iter().copied().collect())
+    }
+
+    //take over the send addresses, send interval, sequences, reflection suppression, last sent
+    //messages and queued messages of another service
+    pub(crate) fn migrate_from(&self, other: &OscService) {
+        if let (Ok(mut addrs), Ok(other)) = (self.send_addrs.write(), other.send_addrs.read()) {
+            addrs.extend(other.iter().cloned());
+        }
+        if let (Ok(mut seq), Ok(mut other)) = (self.sequenced.lock(), other.sequenced.lock()) {
+            seq.extend(other.drain());
+        }
+        if let (Ok(mut suppress), Ok(mut other)) = (
+            self.suppress_reflection.lock(),
+            other.suppress_reflection.lock(),
+        ) {
+            suppress.extend(other.drain());
+        }
+        if let (Ok(mut last), Ok(mut other)) = (self.last_sent.lock(), other.last_sent.lock()) {
+            last.msgs.extend(other.msgs.drain());
+        }
+        self.set_send_interval(other.send_interval.read().map_or(None, |i| *i));
+        //the queue depth stat is shared, moving doesn't change it
+        if let (Ok(mut queue), Ok(mut other)) = (self.queue.lock(), other.queue.lock()) {
+            queue.extend(other.drain(..));
+        }
+    }
+
     /// Returns the `SocketAddr` that the service bound to.
     pub fn local_addr(&self) -> &SocketAddr {
         &self.local_addr
@@ -191,5 +1044,876 @@ impl Drop for OscService {
                 let _ = handle.join();
             }
         }
+        if let Some(m) = &self.multicast {
+            let _ = m.leave();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::Get;
+    use crate::param::ParamGet;
+    use crate::root::Root;
+    use crate::value::ValueBuilder;
+    use ::atomic::Atomic;
+    use std::net::UdpSocket;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn send_addrs() {
+        let root = Root::new(None);
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let h = root
+            .add_node(
+                Get::new(
+                    "a",
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(1)) as _).build(),
+                    )],
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        let recvs: Vec<UdpSocket> = (0..3)
+            .map(|_| {
+                let r = UdpSocket::bind("127.0.0.1:0").unwrap();
+                r.set_read_timeout(Some(Duration::from_millis(500)))
+                    .unwrap();
+                osc.add_send_addr(r.local_addr().unwrap());
+                r
+            })
+            .collect();
+        let mut addrs: Vec<_> = recvs.iter().map(|r| r.local_addr().unwrap()).collect();
+        let mut sending = osc.send_addrs();
+        addrs.sort();
+        sending.sort();
+        assert_eq!(addrs, sending);
+
+        let received = |r: &UdpSocket| {
+            let mut buf = [0u8; crate::osc::decoder::MTU];
+            r.recv(&mut buf).is_ok()
+        };
+        assert!(osc.trigger(h).is_some());
+        assert!(recvs.iter().all(received));
+
+        let removed = recvs[1].local_addr().unwrap();
+        assert!(osc.remove_send_addr(removed));
+        assert!(!osc.remove_send_addr(removed));
+        assert_eq!(osc.send_addrs().len(), 2);
+        assert!(osc.trigger(h).is_some());
+        assert!(received(&recvs[0]));
+        assert!(!received(&recvs[1]));
+        assert!(received(&recvs[2]));
+    }
+
+    #[test]
+    fn multicast() {
+        use crate::error::OscQueryError;
+        use std::net::{Ipv4Addr, SocketAddr};
+        let group = Ipv4Addr::new(224, 0, 0, 1);
+        let recv = UdpSocket::bind("0.0.0.0:0").unwrap();
+        recv.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
+            .unwrap();
+        recv.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let group = SocketAddr::new(group.into(), recv.local_addr().unwrap().port());
+
+        let root = Root::new(None);
+        let h = root
+            .add_node(
+                Get::new(
+                    "a",
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(3)) as _).build(),
+                    )],
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        let osc = root.spawn_osc_multicast("0.0.0.0:0", group, None).unwrap();
+        assert_eq!(osc.send_addrs(), vec![group]);
+        assert!(osc.trigger(h).is_some());
+        let mut buf = [0u8; crate::osc::decoder::MTU];
+        let size = recv.recv(&mut buf).expect("no message");
+        assert_matches!(
+            crate::osc::decoder::decode(&buf[..size]),
+            Ok(crate::osc::OscPacket::Message(m)) if m.addr == "/a"
+        );
+        drop(osc);
+
+        assert_matches!(
+            root.spawn_osc_multicast("0.0.0.0:0", "127.0.0.1:9000".parse().unwrap(), None)
+                .err(),
+            Some(OscQueryError::IoError(..))
+        );
+    }
+
+    #[test]
+    fn multicast_receive() {
+        use crate::node::GetSet;
+        use crate::param::ParamGetSet;
+        use crate::service::http::tests::wait_for;
+        use std::net::{Ipv4Addr, SocketAddr};
+        use std::sync::atomic::Ordering;
+
+        let port = UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let group = SocketAddr::new(Ipv4Addr::new(224, 0, 0, 1).into(), port);
+        let bind = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+        let add = |root: &Root, v: &Arc<Atomic<i32>>| {
+            root.add_node(
+                GetSet::new(
+                    "a",
+                    None,
+                    vec![ParamGetSet::Int(ValueBuilder::new(v.clone() as _).build())],
+                    None,
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap()
+        };
+        let (sent, received) = (Arc::new(Atomic::new(3)), Arc::new(Atomic::new(0)));
+        let sender = Root::new(None);
+        let receiver = Root::new(None);
+        let h = add(&sender, &sent);
+        add(&receiver, &received);
+
+        //both services share the group port
+        let _listen = receiver.spawn_osc_multicast(bind, group, None).unwrap();
+        let osc = sender.spawn_osc_multicast(bind, group, None).unwrap();
+        assert_eq!(osc.local_addr().port(), port);
+        assert!(osc.trigger(h).is_some());
+        wait_for(|| received.load(Ordering::SeqCst) == 3);
+    }
+
+    #[test]
+    fn cancel_pending() {
+        let root = Root::new(None);
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let recv = UdpSocket::bind("127.0.0.1:0").unwrap();
+        recv.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        osc.add_send_addr(recv.local_addr().unwrap());
+        osc.set_send_interval(Some(Duration::from_millis(20)));
+
+        let mut handles = Vec::new();
+        for prefix in &["a", "b"] {
+            for i in 0..10 {
+                let n = Get::new(
+                    format!("{}{}", prefix, i),
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(i)) as _).build(),
+                    )],
+                )
+                .unwrap();
+                handles.push(root.add_node(n, None).unwrap());
+            }
+        }
+        for h in handles {
+            assert!(osc.trigger(h).is_some());
+        }
+        assert!(osc.pending() > 10);
+        assert_eq!(10, osc.cancel_pending(|p| p.addr.starts_with("/b")));
+        assert!(osc.pending() <= 10);
+        assert_eq!(0, osc.cancel_pending(|p| p.addr.starts_with("/b")));
+
+        assert!(osc.flush(Duration::from_secs(5)));
+        assert_eq!(0, osc.pending());
+        assert_eq!(0, root.stats().osc_queue_depth());
+
+        let mut buf = [0u8; crate::osc::decoder::MTU];
+        let mut received = Vec::new();
+        while let Ok(size) = recv.recv(&mut buf) {
+            match crate::osc::decoder::decode(&buf[..size]) {
+                Ok(crate::osc::OscPacket::Message(msg)) => received.push(msg.addr),
+                _ => panic!("expected a message"),
+            }
+        }
+        let expected: Vec<String> = (0..10).map(|i| format!("/a{}", i)).collect();
+        assert_eq!(expected, received);
+    }
+
+    #[test]
+    fn sequencing() {
+        use super::{Sequencing, SEQUENCE_ADDR};
+        use crate::osc::{OscPacket, OscType};
+        let root = Root::new(None);
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let recv = |osc: &super::OscService| {
+            let r = UdpSocket::bind("127.0.0.1:0").unwrap();
+            r.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            osc.add_send_addr(r.local_addr().unwrap());
+            r
+        };
+        let read = |r: &UdpSocket| {
+            let mut buf = [0u8; crate::osc::decoder::MTU];
+            let size = r.recv(&mut buf).expect("no message");
+            crate::osc::decoder::decode(&buf[..size]).unwrap()
+        };
+        let (plain, seq) = (recv(&osc), recv(&osc));
+        let seq_addr = seq.local_addr().unwrap();
+        let v = Arc::new(Atomic::new(0));
+        let h = root
+            .add_node(
+                Get::new(
+                    "a",
+                    None,
+                    vec![ParamGet::Int(ValueBuilder::new(v.clone() as _).build())],
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(osc.sequence(&seq_addr), None);
+        osc.set_sequencing(seq_addr, Some(Sequencing::TrailingArg));
+        assert_eq!(osc.sequence(&seq_addr), Some(0));
+        for i in 0..3 {
+            v.store(i * 10, ::atomic::Ordering::SeqCst);
+            assert!(osc.trigger(h).is_some());
+        }
+        for i in 0..3 {
+            match read(&plain) {
+                OscPacket::Message(m) => assert_eq!(m.args, vec![OscType::Int(i * 10)]),
+                p => panic!("unexpected packet {:?}", p),
+            }
+            match read(&seq) {
+                OscPacket::Message(m) => {
+                    assert_eq!(m.args, vec![OscType::Int(i * 10), OscType::Int(i)])
+                }
+                p => panic!("unexpected packet {:?}", p),
+            }
+        }
+        assert_eq!(osc.sequence(&seq_addr), Some(3));
+
+        //the bundle keeps the message as is, the sequence carries on
+        osc.set_sequencing(seq_addr, Some(Sequencing::Bundle));
+        assert!(osc.trigger(h).is_some());
+        match read(&seq) {
+            OscPacket::Bundle(b) => {
+                assert_eq!(
+                    b.content[0],
+                    OscPacket::Message(crate::osc::OscMessage {
+                        addr: SEQUENCE_ADDR.into(),
+                        args: vec![OscType::Int(3)],
+                    })
+                );
+                match &b.content[1] {
+                    OscPacket::Message(m) => assert_eq!(m.args, vec![OscType::Int(20)]),
+                    p => panic!("unexpected packet {:?}", p),
+                }
+            }
+            p => panic!("unexpected packet {:?}", p),
+        }
+        read(&plain);
+
+        //resend the last value, only to the given destination
+        osc.set_sequencing(seq_addr, None);
+        assert_eq!(osc.sequence(&seq_addr), None);
+        v.store(99, ::atomic::Ordering::SeqCst);
+        assert!(osc.resend_last(seq_addr, "/a"));
+        assert!(!osc.resend_last(seq_addr, "/b"));
+        match read(&seq) {
+            OscPacket::Message(m) => assert_eq!(m.args, vec![OscType::Int(20)]),
+            p => panic!("unexpected packet {:?}", p),
+        }
+        assert!(osc.flush(Duration::from_secs(5)));
+        plain
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        assert!(plain.recv(&mut [0u8; 16]).is_err());
+
+        //removed nodes are forgotten
+        root.rm_node(h).unwrap();
+        assert!(!osc.resend_last(seq_addr, "/a"));
+        assert!(osc.last_sent.lock().unwrap().msgs.is_empty());
+    }
+
+    #[test]
+    fn suppress_reflection() {
+        use crate::node::GetSet;
+        use crate::osc::{OscMessage, OscPacket, OscType};
+        use crate::param::ParamGetSet;
+        let root = Root::new(None);
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let v = Arc::new(Atomic::new(0));
+        let h = root
+            .add_node(
+                GetSet::new(
+                    "a",
+                    None,
+                    vec![ParamGetSet::Int(ValueBuilder::new(v.clone() as _).build())],
+                    None,
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        root.add_node(
+            Get::new(
+                "b",
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(2)) as _).build(),
+                )],
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let recv = || {
+            let r = UdpSocket::bind("127.0.0.1:0").unwrap();
+            r.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            osc.add_send_addr(r.local_addr().unwrap());
+            r
+        };
+        let read = |r: &UdpSocket| {
+            let mut buf = [0u8; crate::osc::decoder::MTU];
+            let size = r.recv(&mut buf).expect("no message");
+            crate::osc::decoder::decode(&buf[..size]).unwrap()
+        };
+        let nothing = |r: &UdpSocket| {
+            r.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+            let empty = r.recv(&mut [0u8; 16]).is_err();
+            r.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            empty
+        };
+        let (peer, other) = (recv(), recv());
+        let peer_addr = peer.local_addr().unwrap();
+        osc.set_suppress_reflection(peer_addr, Some(Duration::from_secs(60)));
+
+        //the peer writes from the address it receives at
+        let write = OscPacket::Message(OscMessage {
+            addr: "/a".into(),
+            args: vec![OscType::Int(7)],
+        });
+        peer.send_to(
+            &crate::osc::encoder::encode(&write).unwrap(),
+            osc.local_addr(),
+        )
+        .unwrap();
+        crate::service::http::tests::wait_for(|| v.load(::atomic::Ordering::SeqCst) == 7);
+
+        assert!(osc.trigger(h).is_some());
+        assert_eq!(read(&other), write);
+        assert!(osc.flush(Duration::from_secs(5)));
+        assert!(nothing(&peer));
+
+        //bundles go out without the messages the peer wrote
+        assert_eq!(osc.trigger_all_bundle().len(), 2);
+        match (read(&peer), read(&other)) {
+            (OscPacket::Bundle(p), OscPacket::Bundle(o)) => {
+                assert_eq!(p.timetag, o.timetag);
+                assert_eq!(o.content.len(), 2);
+                assert_eq!(&o.content[1..], &p.content[..]);
+            }
+            p => panic!("unexpected packets {:?}", p),
+        }
+
+        //a local write, or turning it off, sends to the peer again
+        root.touch(&h);
+        assert!(osc.trigger(h).is_some());
+        assert_eq!(read(&peer), write);
+        read(&other);
+        peer.send_to(
+            &crate::osc::encoder::encode(&write).unwrap(),
+            osc.local_addr(),
+        )
+        .unwrap();
+        crate::service::http::tests::wait_for(|| {
+            osc.root.read().unwrap().last_writer("/a").is_some()
+        });
+        osc.set_suppress_reflection(peer_addr, None);
+        assert!(osc.trigger(h).is_some());
+        assert_eq!(read(&peer), write);
+        assert_eq!(read(&other), write);
+    }
+
+    #[test]
+    fn trigger_all() {
+        use super::{Sequencing, SEQUENCE_ADDR};
+        use crate::osc::{OscMessage, OscPacket, OscType};
+        let root = Root::new(None);
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        assert!(osc.trigger_all().is_empty());
+        assert!(osc.trigger_all_bundle().is_empty());
+        let int = |v: i32| {
+            vec![ParamGet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(v)) as _).build(),
+            )]
+        };
+        let foo = root
+            .add_node(crate::node::Container::new("foo", None).unwrap(), None)
+            .unwrap();
+        root.add_node(Get::new("bar", None, int(2)).unwrap(), Some(foo))
+            .unwrap();
+        root.add_node(Get::new("baz", None, int(1)).unwrap(), None)
+            .unwrap();
+        //nothing is queued without a send address
+        assert!(osc.trigger_all().is_empty());
+        assert!(osc.trigger_all_bundle().is_empty());
+        root.set_force_trigger(true);
+        assert!(osc.trigger_all().is_empty());
+        assert!(osc.trigger_all_bundle().is_empty());
+        root.set_force_trigger(false);
+        let recv = UdpSocket::bind("127.0.0.1:0").unwrap();
+        recv.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        osc.add_send_addr(recv.local_addr().unwrap());
+        let read = || {
+            let mut buf = [0u8; crate::osc::decoder::MTU];
+            let size = recv.recv(&mut buf).expect("no message");
+            crate::osc::decoder::decode(&buf[..size]).unwrap()
+        };
+        let expected = vec![
+            OscMessage {
+                addr: "/baz".into(),
+                args: vec![OscType::Int(1)],
+            },
+            OscMessage {
+                addr: "/foo/bar".into(),
+                args: vec![OscType::Int(2)],
+            },
+        ];
+
+        assert_eq!(osc.trigger_all(), expected);
+        for m in &expected {
+            assert_eq!(read(), OscPacket::Message(m.clone()));
+        }
+
+        assert_eq!(osc.trigger_all_bundle(), expected);
+        match read() {
+            OscPacket::Bundle(b) => {
+                assert_ne!(b.timetag, (0, 1));
+                let content: Vec<_> = expected.iter().cloned().map(OscPacket::Message).collect();
+                assert_eq!(b.content, content);
+            }
+            p => panic!("unexpected packet {:?}", p),
+        }
+
+        //sequenced destinations get the sequence first in the bundle
+        osc.set_sequencing(recv.local_addr().unwrap(), Some(Sequencing::TrailingArg));
+        assert_eq!(osc.trigger_all_bundle().len(), 2);
+        match read() {
+            OscPacket::Bundle(b) => {
+                assert_eq!(b.content.len(), 3);
+                assert_eq!(
+                    b.content[0],
+                    OscPacket::Message(OscMessage {
+                        addr: SEQUENCE_ADDR.into(),
+                        args: vec![OscType::Int(0)],
+                    })
+                );
+            }
+            p => panic!("unexpected packet {:?}", p),
+        }
+        assert!(osc.resend_last(recv.local_addr().unwrap(), "/foo/bar"));
+    }
+
+    #[test]
+    fn trigger_bundle() {
+        use crate::osc::{OscMessage, OscPacket, OscType};
+        let root = Root::new(None);
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let int = |v: i32| {
+            vec![ParamGet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(v)) as _).build(),
+            )]
+        };
+        let x = root
+            .add_node(Get::new("x", None, int(1)).unwrap(), None)
+            .unwrap();
+        let y = root
+            .add_node(Get::new("y", None, int(2)).unwrap(), None)
+            .unwrap();
+        let gone = root
+            .add_node(Get::new("z", None, int(3)).unwrap(), None)
+            .unwrap();
+        root.rm_node(gone).unwrap();
+        //nobody to send to
+        assert!(osc.trigger_bundle(vec![x, y], None).is_none());
+
+        let recv = UdpSocket::bind("127.0.0.1:0").unwrap();
+        recv.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        osc.add_send_addr(recv.local_addr().unwrap());
+        let read = || {
+            let mut buf = [0u8; crate::osc::decoder::MTU];
+            let size = recv.recv(&mut buf).expect("no message");
+            crate::osc::decoder::decode(&buf[..size]).unwrap()
+        };
+        let msg = |addr: &str, v: i32| {
+            OscPacket::Message(OscMessage {
+                addr: addr.into(),
+                args: vec![OscType::Int(v)],
+            })
+        };
+
+        //in the order of the handles, skipping the invalid one
+        let sent = osc.trigger_bundle(vec![y, gone, x], Some((5, 6))).unwrap();
+        match read() {
+            OscPacket::Bundle(b) => {
+                assert_eq!(b, sent);
+                assert_eq!(b.timetag, (5, 6));
+                assert_eq!(b.content, vec![msg("/y", 2), msg("/x", 1)]);
+            }
+            p => panic!("unexpected packet {:?}", p),
+        }
+
+        assert!(osc.trigger_bundle(Some(x), None).is_some());
+        match read() {
+            OscPacket::Bundle(b) => {
+                assert_eq!(b.timetag, (0, 1));
+                assert_eq!(b.content, vec![msg("/x", 1)]);
+            }
+            p => panic!("unexpected packet {:?}", p),
+        }
+        assert!(osc.trigger_bundle(vec![gone], None).is_none());
+    }
+
+    #[test]
+    fn value_query() {
+        use crate::osc::{OscMessage, OscPacket, OscType};
+        let root = Root::new(None);
+        let foo = root
+            .add_node(crate::node::Container::new("foo", None).unwrap(), None)
+            .unwrap();
+        root.add_node(
+            Get::new(
+                "bar",
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(7)) as _).build(),
+                )],
+            )
+            .unwrap(),
+            Some(foo),
+        )
+        .unwrap();
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        osc.add_send_addr(listener.local_addr().unwrap());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let query = OscPacket::Message(OscMessage {
+            addr: "/foo/bar".into(),
+            args: Vec::new(),
+        });
+        client
+            .send_to(
+                &crate::osc::encoder::encode(&query).unwrap(),
+                osc.local_addr(),
+            )
+            .unwrap();
+        let mut buf = [0u8; crate::osc::decoder::MTU];
+        let size = client.recv(&mut buf).expect("no reply");
+        assert_eq!(
+            crate::osc::decoder::decode(&buf[..size]).unwrap(),
+            OscPacket::Message(OscMessage {
+                addr: "/foo/bar".into(),
+                args: vec![OscType::Int(7)],
+            })
+        );
+
+        //only the sender gets the reply
+        listener
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        assert!(listener.recv(&mut buf).is_err());
+
+        //a pattern query doesn't fan out into a reply per match
+        for name in &["baz", "bat"] {
+            root.add_node(
+                Get::new(
+                    *name,
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(8)) as _).build(),
+                    )],
+                )
+                .unwrap(),
+                Some(foo),
+            )
+            .unwrap();
+        }
+        let query = OscPacket::Message(OscMessage {
+            addr: "/foo/*".into(),
+            args: Vec::new(),
+        });
+        client
+            .send_to(
+                &crate::osc::encoder::encode(&query).unwrap(),
+                osc.local_addr(),
+            )
+            .unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(client.recv(&mut buf).is_err());
+        assert!(listener.recv(&mut buf).is_err());
+    }
+
+    #[test]
+    fn send_interval_clock() {
+        use crate::time::{Clock, ManualClock};
+        use std::time::SystemTime;
+
+        let clock = Arc::new(ManualClock::new());
+        let root = Root::new(None);
+        root.set_clock(clock.clone());
+        let osc = root.spawn_osc("127.0.0.1:0").unwrap();
+        let recv = UdpSocket::bind("127.0.0.1:0").unwrap();
+        recv.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        osc.add_send_addr(recv.local_addr().unwrap());
+        osc.set_send_interval(Some(Duration::from_secs(1)));
+        let h = root
+            .add_node(
+                Get::new(
+                    "a",
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(1)) as _).build(),
+                    )],
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+
+        assert!(osc.trigger(h).is_some());
+        assert!(osc.trigger(h).is_some());
+        //the first goes out right away
+        while osc.pending() > 1 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        //a wall clock jump doesn't release the next send, only monotonic time does
+        clock.set_wall(SystemTime::UNIX_EPOCH);
+        clock.set_wall(clock.wall() + Duration::from_secs(3600 * 24 * 365));
+        assert!(!osc.flush(Duration::from_millis(50)));
+        assert_eq!(1, osc.pending());
+        clock.advance(Duration::from_secs(1));
+        assert!(osc.flush(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn critical_first() {
+        use crate::osc::{OscPacket, OscType};
+        use crate::service::memory::Network;
+        use crate::time::ManualClock;
+
+        let clock = Arc::new(ManualClock::new());
+        let root = Root::new(None);
+        root.set_clock(clock.clone());
+        let node = |name: &str, v: i32| {
+            Get::new(
+                name,
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(v)) as _).build(),
+                )],
+            )
+            .unwrap()
+        };
+        let plain = root.add_node(node("plain", 1), None).unwrap();
+        let critical = root
+            .add_node(node("critical", 2).with_critical(true), None)
+            .unwrap();
+        let network = Network::new();
+        let (osc, mut driver) =
+            root.spawn_osc_in_memory(network.bind("10.0.0.1:9000".parse().unwrap()));
+        let recv = network.bind("10.0.0.2:9000".parse().unwrap());
+        osc.add_send_addr(recv.local_addr());
+        osc.set_send_interval(Some(Duration::from_secs(1)));
+        let mut buf = [0u8; 1024];
+        let mut received = || match recv.recv_from(&mut buf) {
+            Ok((size, _)) => match crate::osc::decoder::decode(&buf[..size]) {
+                Ok(OscPacket::Message(m)) => Some(m.args[0].clone()),
+                other => panic!("unexpected packet {:?}", other),
+            },
+            Err(..) => None,
+        };
+
+        for _ in 0..3 {
+            assert!(osc.trigger(plain).is_some());
+        }
+        driver.step();
+        assert_eq!(received(), Some(OscType::Int(1)));
+        driver.step();
+        assert_eq!(received(), None);
+
+        //critical messages jump the queue and the interval
+        assert!(osc.trigger(critical).is_some());
+        assert!(osc.trigger_path("/critical").is_some());
+        driver.step();
+        driver.step();
+        assert_eq!(received(), Some(OscType::Int(2)));
+        assert_eq!(received(), Some(OscType::Int(2)));
+        driver.step();
+        assert_eq!(received(), None);
+        assert_eq!(osc.pending(), 2);
+
+        clock.advance(Duration::from_secs(1));
+        driver.step();
+        assert_eq!(received(), Some(OscType::Int(1)));
+    }
+
+    #[test]
+    fn critical_sequenced() {
+        use super::{Sequencing, QUEUE_LEN};
+        use crate::osc::{OscPacket, OscType};
+        use crate::service::memory::Network;
+        use crate::time::ManualClock;
+
+        let clock = Arc::new(ManualClock::new());
+        let root = Root::new(None);
+        root.set_clock(clock.clone());
+        let node = |name: &str, v: i32| {
+            Get::new(
+                name,
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(v)) as _).build(),
+                )],
+            )
+            .unwrap()
+        };
+        let plain = root.add_node(node("plain", 1), None).unwrap();
+        let urgent = root
+            .add_node(node("urgent", 2).with_critical(true), None)
+            .unwrap();
+        let network = Network::new();
+        let (osc, mut driver) =
+            root.spawn_osc_in_memory(network.bind("10.0.0.1:9000".parse().unwrap()));
+        let recv = network.bind("10.0.0.2:9000".parse().unwrap());
+        osc.add_send_addr(recv.local_addr());
+        osc.set_sequencing(recv.local_addr(), Some(Sequencing::TrailingArg));
+        osc.set_send_interval(Some(Duration::from_secs(1)));
+        let mut buf = [0u8; 1024];
+        let mut received = || match recv.recv_from(&mut buf) {
+            Ok((size, _)) => match crate::osc::decoder::decode(&buf[..size]) {
+                Ok(OscPacket::Message(m)) => Some(m.args),
+                other => panic!("unexpected packet {:?}", other),
+            },
+            Err(..) => None,
+        };
+
+        //numbers follow the order on the wire, not the order of queueing
+        assert!(osc.trigger(plain).is_some());
+        assert!(osc.trigger(plain).is_some());
+        assert!(osc.trigger(urgent).is_some());
+        driver.step();
+        assert_eq!(received(), Some(vec![OscType::Int(2), OscType::Int(0)]));
+        clock.advance(Duration::from_secs(1));
+        driver.step();
+        assert_eq!(received(), Some(vec![OscType::Int(1), OscType::Int(1)]));
+        assert_eq!(osc.pending(), 1);
+
+        //a full queue drops the newest non-critical message to make room for a critical one
+        for _ in 1..QUEUE_LEN {
+            assert!(osc.trigger(plain).is_some());
+        }
+        assert_eq!(osc.pending(), QUEUE_LEN);
+        assert!(osc.trigger(plain).is_some());
+        assert!(osc.trigger(urgent).is_some());
+        assert_eq!(osc.pending(), QUEUE_LEN);
+        assert_eq!(root.stats().osc_sends_dropped(), 2);
+        driver.step();
+        assert_eq!(received(), Some(vec![OscType::Int(2), OscType::Int(4)]));
+
+        //trigger_all sends critical nodes ahead too
+        assert_eq!(osc.cancel_pending(|_| true), QUEUE_LEN - 1);
+        assert_eq!(osc.trigger_all().len(), 2);
+        driver.step();
+        assert_eq!(received(), Some(vec![OscType::Int(2), OscType::Int(5)]));
+        driver.step();
+        assert_eq!(received(), None);
+    }
+
+    #[test]
+    fn in_memory_drop() {
+        use crate::osc::{OscMessage, OscPacket, OscType};
+        use crate::service::memory::Network;
+        use crate::time::ManualClock;
+
+        let clock = Arc::new(ManualClock::new());
+        let root = Root::new(None);
+        root.set_clock(clock.clone());
+        let h = root
+            .add_node(
+                Get::new(
+                    "a",
+                    None,
+                    vec![ParamGet::Int(
+                        ValueBuilder::new(Arc::new(Atomic::new(1)) as _).build(),
+                    )],
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        let network = Network::new();
+        let (osc, mut driver) =
+            root.spawn_osc_in_memory(network.bind("10.0.0.1:9000".parse().unwrap()));
+        let recv = network.bind("10.0.0.2:9000".parse().unwrap());
+        osc.add_send_addr(recv.local_addr());
+        osc.set_send_interval(Some(Duration::from_secs(1)));
+        let local_addr = *osc.local_addr();
+        let mut buf = [0u8; 1024];
+        let mut received = || match recv.recv_from(&mut buf) {
+            Ok((size, from)) => {
+                assert_eq!(from, local_addr);
+                match crate::osc::decoder::decode(&buf[..size]) {
+                    Ok(OscPacket::Message(m)) => Some(m),
+                    other => panic!("unexpected packet {:?}", other),
+                }
+            }
+            Err(..) => None,
+        };
+        let value = Some(OscMessage {
+            addr: "/a".into(),
+            args: vec![OscType::Int(1)],
+        });
+
+        for _ in 0..3 {
+            assert!(osc.trigger(h).is_some());
+        }
+        assert!(driver.step());
+        assert_eq!(received(), value);
+        //the interval holds the next one back until the clock advances
+        assert!(driver.step());
+        assert_eq!(received(), None);
+        clock.advance(Duration::from_secs(1));
+        assert!(driver.step());
+        assert_eq!(received(), value);
+        assert_eq!(osc.pending(), 1);
+
+        //value queries are answered directly, in the same step
+        let query = crate::osc::encoder::encode(&OscPacket::Message(OscMessage {
+            addr: "/a".into(),
+            args: Vec::new(),
+        }))
+        .unwrap();
+        recv.send_to(&query, local_addr).unwrap();
+        assert!(driver.step());
+        assert_eq!(received(), value);
+
+        //dropping ends the service before anything else, what is still queued isn't sent
+        drop(osc);
+        clock.advance(Duration::from_secs(1));
+        assert!(!driver.step());
+        assert_eq!(received(), None);
     }
 }
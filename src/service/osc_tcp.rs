@@ -0,0 +1,609 @@
+//! OSC over TCP, with the SLIP framing of OSC 1.1 or the packet length framing of OSC 1.0.
+use crate::error::OscQueryError;
+use crate::osc::{OscMessage, OscPacket};
+use crate::root::{NodeHandle, NodeWrapper, RootInner};
+use crate::stats::Stats;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{sync_channel, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const READ_TIMEOUT: Duration = Duration::from_millis(1);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+const CHANNEL_LEN: usize = 1024;
+
+const END: u8 = 0xC0;
+const ESC: u8 = 0xDB;
+const ESC_END: u8 = 0xDC;
+const ESC_ESC: u8 = 0xDD;
+
+/// SLIP (RFC 1055) framing of packets in a byte stream.
+///
+/// Frames are written with an `END` byte on both sides, as OSC 1.1 suggests, empty frames are
+/// ignored when reading.
+#[derive(Debug, Default)]
+pub struct SlipFramer {
+    frame: Vec<u8>,
+    escaped: bool,
+}
+
+impl SlipFramer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Frame a packet.
+    pub fn encode(packet: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(packet.len() + 2);
+        buf.push(END);
+        for b in packet {
+            match *b {
+                END => buf.extend_from_slice(&[ESC, ESC_END]),
+                ESC => buf.extend_from_slice(&[ESC, ESC_ESC]),
+                b => buf.push(b),
+            }
+        }
+        buf.push(END);
+        buf
+    }
+
+    /// Feed bytes read from the stream, returns the packets completed by them.
+    ///
+    /// A partial packet is kept until the next call.
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        for b in bytes {
+            if self.escaped {
+                self.escaped = false;
+                match *b {
+                    ESC_END => self.frame.push(END),
+                    ESC_ESC => self.frame.push(ESC),
+                    //protocol violation, keep the byte like most implementations
+                    b => self.frame.push(b),
+                }
+                continue;
+            }
+            match *b {
+                END => {
+                    if !self.frame.is_empty() {
+                        packets.push(std::mem::take(&mut self.frame));
+                    }
+                }
+                ESC => self.escaped = true,
+                b => self.frame.push(b),
+            }
+        }
+        packets
+    }
+}
+
+/// Packet length framing of packets in a byte stream, as in OSC 1.0.
+///
+/// Each packet is preceded by its size as a big-endian int32, empty packets are ignored when
+/// reading.
+#[derive(Debug, Default)]
+pub struct LengthFramer {
+    buf: Vec<u8>,
+}
+
+impl LengthFramer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Frame a packet.
+    pub fn encode(packet: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(packet.len() + 4);
+        buf.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        buf.extend_from_slice(packet);
+        buf
+    }
+
+    /// Feed bytes read from the stream, returns the packets completed by them.
+    ///
+    /// A partial packet is kept until the next call.
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.buf.extend_from_slice(bytes);
+        let mut packets = Vec::new();
+        let mut start = 0;
+        while self.buf.len() - start >= 4 {
+            let mut size = [0u8; 4];
+            size.copy_from_slice(&self.buf[start..start + 4]);
+            let end = start + 4 + u32::from_be_bytes(size) as usize;
+            if self.buf.len() < end {
+                break;
+            }
+            if end > start + 4 {
+                packets.push(self.buf[start + 4..end].to_vec());
+            }
+            start = end;
+        }
+        self.buf.drain(..start);
+        packets
+    }
+}
+
+/// How packets are delimited in the TCP stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// SLIP, as OSC 1.1 specifies, see `SlipFramer`.
+    #[default]
+    Slip,
+    /// A size prefix, as in OSC 1.0, see `LengthFramer`.
+    LengthPrefixed,
+}
+
+impl Framing {
+    fn encode(&self, packet: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Slip => SlipFramer::encode(packet),
+            Self::LengthPrefixed => LengthFramer::encode(packet),
+        }
+    }
+}
+
+enum Framer {
+    Slip(SlipFramer),
+    Length(LengthFramer),
+}
+
+impl Framer {
+    fn decode(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        match self {
+            Self::Slip(f) => f.decode(bytes),
+            Self::Length(f) => f.decode(bytes),
+        }
+    }
+}
+
+struct Peer {
+    stream: TcpStream,
+    addr: SocketAddr,
+    framer: Framer,
+}
+
+impl Peer {
+    fn new(stream: TcpStream, addr: SocketAddr, framing: Framing) -> std::io::Result<Self> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            addr,
+            framer: match framing {
+                Framing::Slip => Framer::Slip(SlipFramer::new()),
+                Framing::LengthPrefixed => Framer::Length(LengthFramer::new()),
+            },
+        })
+    }
+}
+
+enum Command {
+    End,
+}
+
+/// Manage a thread that accepts TCP connections and reads and writes framed OSC to/from them,
+/// updating values in an OSCQuery tree.
+///
+/// Triggered messages go to every connected peer, the accepted connections and the ones made with
+/// `add_send_addr`.
+///
+/// Drop to stop the service and close the connections.
+/// *NOTE* this will block until the service thread completes.
+pub struct OscTcpService {
+    root: Arc<RwLock<RootInner>>,
+    stats: Arc<Stats>,
+    handle: Option<JoinHandle<()>>,
+    cmd_sender: SyncSender<Command>,
+    local_addr: SocketAddr,
+    peers: Arc<Mutex<Vec<Peer>>>,
+    framing: Framing,
+}
+
+impl OscTcpService {
+    /// Create and start an OscTcpService
+    pub(crate) fn new<A: ToSocketAddrs>(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+        addr: A,
+        framing: Framing,
+    ) -> Result<Self, OscQueryError> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        let (cmd_sender, cmd_recv) = sync_channel(CHANNEL_LEN);
+
+        let r = root.clone();
+        let s = stats.clone();
+        let peers: Arc<Mutex<Vec<Peer>>> = Default::default();
+        let p = peers.clone();
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0u8; crate::osc::decoder::MTU];
+            loop {
+                match cmd_recv.try_recv() {
+                    Ok(Command::End) | Err(TryRecvError::Disconnected) => break,
+                    Err(TryRecvError::Empty) => (),
+                }
+                loop {
+                    match listener.accept() {
+                        Ok((stream, addr)) => match Peer::new(stream, addr, framing) {
+                            Ok(peer) => peers.lock().expect("failed to lock").push(peer),
+                            Err(e) => log::warn!("error configuring connection {}: {}", addr, e),
+                        },
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            log::warn!("error accepting connection: {}", e);
+                            break;
+                        }
+                    }
+                }
+                crate::root::RootInner::flush_coalesced(&root);
+
+                //read without holding the lock while handling, handlers may trigger sends
+                let mut packets = Vec::new();
+                let idle = {
+                    let mut peers = peers.lock().expect("failed to lock");
+                    let mut i = 0;
+                    while i < peers.len() {
+                        let peer = &mut peers[i];
+                        let open = match peer.stream.read(&mut buf) {
+                            Ok(0) => false,
+                            Ok(size) => {
+                                for p in peer.framer.decode(&buf[..size]) {
+                                    packets.push((p, peer.addr));
+                                }
+                                true
+                            }
+                            Err(e) => {
+                                matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                            }
+                        };
+                        if open {
+                            i += 1;
+                        } else {
+                            let _ = peers.remove(i).stream.shutdown(Shutdown::Both);
+                        }
+                    }
+                    peers.is_empty()
+                };
+                for (p, addr) in packets {
+                    match crate::osc::decoder::decode(&p) {
+                        Ok(packet) => {
+                            stats.inc_osc_packets_received();
+                            crate::root::RootInner::handle_osc_packet(
+                                &root,
+                                &packet,
+                                Some(addr),
+                                None,
+                            );
+                        }
+                        Err(..) => stats.inc_osc_decode_errors(),
+                    }
+                }
+                if idle {
+                    std::thread::sleep(READ_TIMEOUT);
+                }
+            }
+            for peer in peers.lock().expect("failed to lock").drain(..) {
+                let _ = peer.stream.shutdown(Shutdown::Both);
+            }
+        });
+        Ok(Self {
+            root: r,
+            stats: s,
+            handle: Some(handle),
+            cmd_sender,
+            local_addr,
+            peers: p,
+            framing,
+        })
+    }
+
+    /// Frame and write an already encoded packet to all the peers, dropping the ones that fail.
+    pub(crate) fn send(&self, buf: &[u8]) {
+        let buf = self.framing.encode(buf);
+        if let Ok(mut peers) = self.peers.lock() {
+            peers.retain(|peer| {
+                let mut stream = &peer.stream;
+                match stream.write_all(&buf) {
+                    Ok(()) => {
+                        self.stats.inc_osc_packets_sent();
+                        true
+                    }
+                    Err(e) => {
+                        log::warn!("error writing to {}, closing: {}", peer.addr, e);
+                        let _ = peer.stream.shutdown(Shutdown::Both);
+                        false
+                    }
+                }
+            });
+        }
+    }
+
+    fn render_and_send(
+        &self,
+        node: &NodeWrapper,
+        now: Instant,
+        aliases: Vec<String>,
+    ) -> Option<OscMessage> {
+        let msg = node.osc_message(now)?;
+        for alias in aliases {
+            self.send_msg(&OscMessage {
+                addr: alias,
+                args: msg.args.clone(),
+            });
+        }
+        if self.send_msg(&msg) {
+            Some(msg)
+        } else {
+            None
+        }
+    }
+
+    /// Encode and send a message to all the peers, returns false if encoding failed.
+    pub(crate) fn send_msg(&self, msg: &OscMessage) -> bool {
+        match crate::osc::encoder::encode(&OscPacket::Message(msg.clone())) {
+            Ok(buf) => {
+                self.send(&buf);
+                true
+            }
+            Err(..) => {
+                log::error!("error encoding");
+                false
+            }
+        }
+    }
+
+    /// Trigger a OSC send for the node at the given handle, if it is valid.
+    /// returns the address and renered buffer that was sent, if any
+    pub fn trigger(&self, handle: NodeHandle) -> Option<OscMessage> {
+        if let Ok(root) = self.root.read() {
+            let now = root.clock().now();
+            root.with_node_at_handle(&handle, |node| {
+                node.and_then(|node| {
+                    self.render_and_send(node, now, root.alias_triggers(&node.full_path))
+                })
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Trigger an OSC send for the node at the given path, if it is valid.
+    /// returns the address and renered buffer that was sent, if any
+    pub fn trigger_path(&self, path: &str) -> Option<OscMessage> {
+        if let Ok(root) = self.root.read() {
+            let now = root.clock().now();
+            root.with_node_at_path(path, |ni| {
+                ni.and_then(|(node, _)| {
+                    self.render_and_send(node, now, root.alias_triggers(&node.full_path))
+                })
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Connect to an address and send all outgoing OSC messages to it, messages it sends are
+    /// read like the ones from accepted connections.
+    ///
+    /// *NOTE* adding an address that is already connected is okay, it is only connected once.
+    /// This method blocks while connecting.
+    pub fn add_send_addr(&self, addr: SocketAddr) -> Result<(), OscQueryError> {
+        let mut peers = self.peers.lock().map_err(|_| OscQueryError::PoisonedLock)?;
+        if peers.iter().any(|p| p.addr == addr) {
+            return Ok(());
+        }
+        let stream = TcpStream::connect(addr)?;
+        peers.push(Peer::new(stream, addr, self.framing)?);
+        Ok(())
+    }
+
+    /// The addresses of the connected peers.
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        self.peers
+            .lock()
+            .map_or_else(|_| Vec::new(), |p| p.iter().map(|p| p.addr).collect())
+    }
+
+    /// Returns the `SocketAddr` that the service bound to.
+    pub fn local_addr(&self) -> &SocketAddr {
+        &self.local_addr
+    }
+
+    /// The framing used for all the connections.
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+}
+
+impl Drop for OscTcpService {
+    fn drop(&mut self) {
+        if self.cmd_sender.send(Command::End).is_ok() {
+            if let Some(handle) = self.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::GetSet;
+    use crate::osc::OscType;
+    use crate::param::ParamGetSet;
+    use crate::root::Root;
+    use crate::service::http::tests::wait_for;
+    use crate::value::ValueBuilder;
+    use ::atomic::Atomic;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn framer() {
+        let packet = vec![1, END, 2, ESC, 3];
+        let framed = SlipFramer::encode(&packet);
+        assert_eq!(framed, vec![END, 1, ESC, ESC_END, 2, ESC, ESC_ESC, 3, END]);
+
+        //split anywhere, even inside an escape, and with empty frames between
+        let mut stream = framed.clone();
+        stream.extend_from_slice(&[END, END]);
+        stream.extend_from_slice(&SlipFramer::encode(&[4]));
+        let mut framer = SlipFramer::new();
+        let mut packets = framer.decode(&stream[..3]);
+        assert!(packets.is_empty());
+        packets.extend(framer.decode(&stream[3..]));
+        assert_eq!(packets, vec![packet, vec![4]]);
+    }
+
+    #[test]
+    fn length_framer() {
+        let framed = LengthFramer::encode(&[1, 2, 3]);
+        assert_eq!(framed, vec![0, 0, 0, 3, 1, 2, 3]);
+
+        //split anywhere, even inside the size, and with empty packets between
+        let mut stream = framed.clone();
+        stream.extend_from_slice(&LengthFramer::encode(&[]));
+        stream.extend_from_slice(&LengthFramer::encode(&[4]));
+        let mut framer = LengthFramer::new();
+        let mut packets = framer.decode(&stream[..2]);
+        assert!(packets.is_empty());
+        packets.extend(framer.decode(&stream[2..6]));
+        assert!(packets.is_empty());
+        packets.extend(framer.decode(&stream[6..]));
+        assert_eq!(packets, vec![vec![1, 2, 3], vec![4]]);
+    }
+
+    #[test]
+    fn length_prefixed() {
+        use crate::service::http::{tests::get, HttpConfig, HttpService, OscTransport};
+
+        let root = Arc::new(Root::new(None));
+        let value = Arc::new(Atomic::new(0i32));
+        root.add_node(
+            GetSet::new(
+                "a",
+                None,
+                vec![ParamGetSet::Int(
+                    ValueBuilder::new(value.clone() as _).build(),
+                )],
+                None,
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let osc = root
+            .spawn_osc_tcp_with_framing("127.0.0.1:0", Framing::LengthPrefixed)
+            .unwrap();
+        assert_eq!(osc.framing(), Framing::LengthPrefixed);
+        let http = HttpService::new_with_config(
+            root.clone(),
+            &"127.0.0.1:0".parse().unwrap(),
+            vec![*osc.local_addr()],
+            None,
+            HttpConfig {
+                osc_transport: OscTransport::Tcp,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let (_, _, body) = get(http.local_addr(), "/?HOST_INFO");
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(info["OSC_TRANSPORT"], "TCP");
+        assert_eq!(info["OSC_PORT"], osc.local_addr().port());
+
+        //set over the connection, then get the value back on it
+        let mut client = TcpStream::connect(osc.local_addr()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        wait_for(|| osc.peers().len() == 1);
+        let packet = OscPacket::Message(OscMessage {
+            addr: "/a".into(),
+            args: vec![OscType::Int(42)],
+        });
+        client
+            .write_all(&LengthFramer::encode(
+                &crate::osc::encoder::encode(&packet).unwrap(),
+            ))
+            .unwrap();
+        wait_for(|| value.load(Ordering::Relaxed) == 42);
+        assert!(osc.trigger_path("/a").is_some());
+        let mut framer = LengthFramer::new();
+        let mut buf = [0u8; 64];
+        let mut packets = Vec::new();
+        while packets.is_empty() {
+            let size = client.read(&mut buf).unwrap();
+            assert!(size > 0);
+            packets.extend(framer.decode(&buf[..size]));
+        }
+        assert_eq!(crate::osc::decoder::decode(&packets[0]).unwrap(), packet);
+    }
+
+    #[test]
+    fn service() {
+        let root = Root::new(None);
+        let value = Arc::new(Atomic::new(0i32));
+        let h = root
+            .add_node(
+                GetSet::new(
+                    "a",
+                    None,
+                    vec![ParamGetSet::Int(
+                        ValueBuilder::new(value.clone() as _).build(),
+                    )],
+                    None,
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+        let osc = root.spawn_osc_tcp("127.0.0.1:0").unwrap();
+        let mut clients: Vec<TcpStream> = (0..2)
+            .map(|_| TcpStream::connect(osc.local_addr()).unwrap())
+            .collect();
+        wait_for(|| osc.peers().len() == 2);
+
+        let packet = OscPacket::Message(OscMessage {
+            addr: "/a".into(),
+            args: vec![OscType::Int(7)],
+        });
+        let framed = SlipFramer::encode(&crate::osc::encoder::encode(&packet).unwrap());
+        clients[1].write_all(&framed).unwrap();
+        wait_for(|| value.load(Ordering::Relaxed) == 7);
+        assert_eq!(1, root.stats().osc_packets_received());
+
+        //triggered messages go to every client
+        value.store(8, Ordering::Relaxed);
+        assert!(osc.trigger(h).is_some());
+        for c in clients.iter_mut() {
+            c.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut framer = SlipFramer::new();
+            let mut buf = [0u8; 64];
+            let mut packets = Vec::new();
+            while packets.is_empty() {
+                let size = c.read(&mut buf).unwrap();
+                assert!(size > 0);
+                packets.extend(framer.decode(&buf[..size]));
+            }
+            match crate::osc::decoder::decode(&packets[0]).unwrap() {
+                OscPacket::Message(msg) => {
+                    assert_eq!("/a", msg.addr);
+                    assert_eq!(vec![OscType::Int(8)], msg.args);
+                }
+                _ => panic!("expected a message"),
+            }
+        }
+
+        //dropping closes the connections
+        drop(osc);
+        for c in clients.iter_mut() {
+            let mut buf = [0u8; 8];
+            assert_eq!(0, c.read(&mut buf).unwrap());
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Namespace changes as Server-Sent Events, for clients that can't use the websocket service.
+//!
+//! `GET /path?LISTEN` answers with a `text/event-stream` of the paths added and removed at or
+//! below the path, like `event: PATH_ADDED\ndata: /foo/bar\n\n`. Renames that touch the path
+//! are sent as `event: PATH_RENAMED\ndata: {"OLD":"/foo","NEW":"/baz"}\n\n`, a stream listening
+//! inside the renamed subtree follows it. Idle streams get a comment every `KEEPALIVE` so that
+//! proxies keep them open and disconnected clients are noticed.
+use crate::root::{NamespaceChange, Root};
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::StreamExt;
+use hyper::Body;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+const KEEPALIVE: Duration = Duration::from_secs(15);
+//the events a client can fall behind by before it is dropped
+const BUFFER_LEN: usize = 1024;
+
+type Event = Result<String, Infallible>;
+
+/// The event streams of a http service, fed from one namespace change receiver.
+#[derive(Clone)]
+pub(crate) struct EventStreams {
+    root: Arc<Root>,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    //the path each client listens at or below, and its stream
+    streams: Mutex<Vec<(String, Sender<Event>)>>,
+    relaying: AtomicBool,
+    closed: AtomicBool,
+}
+
+impl EventStreams {
+    pub(crate) fn new(root: Arc<Root>) -> Self {
+        Self {
+            root,
+            inner: Arc::new(Inner {
+                streams: Default::default(),
+                relaying: AtomicBool::new(false),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// A new stream of the changes at or below the path, `None` once closed.
+    ///
+    /// The first stream starts relaying, so it must be called from within the service's runtime.
+    pub(crate) fn subscribe(&self, path: &str) -> Option<Body> {
+        if self.inner.closed.load(Ordering::Relaxed) {
+            return None;
+        }
+        if !self.inner.relaying.swap(true, Ordering::Relaxed) {
+            match self.root.ns_change_stream() {
+                Ok(changes) => {
+                    tokio::spawn(relay(self.inner.clone(), changes));
+                }
+                Err(..) => {
+                    self.inner.relaying.store(false, Ordering::Relaxed);
+                    return None;
+                }
+            }
+        }
+        let (tx, rx) = channel(BUFFER_LEN);
+        self.inner.streams.lock().ok()?.push((path.to_string(), tx));
+        Some(Body::wrap_stream(rx))
+    }
+
+    /// End all the streams, and any new ones, so the service can shut down.
+    pub(crate) fn close(&self) {
+        self.inner.closed.store(true, Ordering::Relaxed);
+        if let Ok(mut streams) = self.inner.streams.lock() {
+            streams.clear();
+        }
+    }
+
+    /// The number of streams, those of disconnected clients count until the next event.
+    pub(crate) fn count(&self) -> usize {
+        self.inner.streams.lock().map_or(0, |s| s.len())
+    }
+}
+
+//the event for the change, if it is one that is streamed, and the paths it is about
+fn event(change: NamespaceChange) -> Option<(String, Vec<String>)> {
+    let (name, data, paths) = match change {
+        NamespaceChange::PathAdded(p) => ("PATH_ADDED", p.clone(), vec![p]),
+        NamespaceChange::PathRemoved(p) => ("PATH_REMOVED", p.clone(), vec![p]),
+        NamespaceChange::PathRenamed(old, new) => (
+            "PATH_RENAMED",
+            serde_json::json!({"OLD": old, "NEW": new}).to_string(),
+            vec![old, new],
+        ),
+        NamespaceChange::PathChanged(..) => return None,
+    };
+    Some((format!("event: {}\ndata: {}\n\n", name, data), paths))
+}
+
+fn at_or_below(path: &str, listen: &str) -> bool {
+    listen == "/"
+        || path == listen
+        || (path.starts_with(listen) && path[listen.len()..].starts_with('/'))
+}
+
+//send the changes to the streams until the streams are closed, dropping the clients that are
+//gone or too far behind
+async fn relay(inner: Arc<Inner>, mut changes: Receiver<NamespaceChange>) {
+    let mut last_sent = Instant::now();
+    loop {
+        let next = tokio::time::timeout_at(last_sent + KEEPALIVE, changes.next()).await;
+        if inner.closed.load(Ordering::Relaxed) {
+            return;
+        }
+        //the text, and the paths it is about, keepalives are for every stream
+        let (text, paths, renamed) = match next {
+            Ok(Some(change)) => {
+                let renamed = match &change {
+                    NamespaceChange::PathRenamed(old, new) => Some((old.clone(), new.clone())),
+                    _ => None,
+                };
+                match event(change) {
+                    Some((text, paths)) => (text, paths, renamed),
+                    None => continue,
+                }
+            }
+            Ok(None) => return,
+            Err(..) => (":\n\n".to_string(), Vec::new(), None),
+        };
+        last_sent = Instant::now();
+        if let Ok(mut streams) = inner.streams.lock() {
+            let kept = streams
+                .drain(..)
+                .filter_map(|(mut listen, mut tx)| {
+                    let listening = paths.is_empty()
+                        || paths.iter().any(|p| at_or_below(p, &listen))
+                        || renamed
+                            .as_ref()
+                            .is_some_and(|(old, _)| at_or_below(&listen, old));
+                    if listening && tx.try_send(Ok(text.clone())).is_err() {
+                        return None;
+                    }
+                    if tx.is_closed() {
+                        return None;
+                    }
+                    //streams inside a renamed subtree move along with it
+                    if let Some((old, new)) = &renamed {
+                        if at_or_below(&listen, old) {
+                            listen = format!("{}{}", new, &listen[old.len()..]);
+                        }
+                    }
+                    Some((listen, tx))
+                })
+                .collect();
+            *streams = kept;
+        }
+    }
+}
@@ -1,36 +1,56 @@
 use futures::stream::FuturesUnordered;
-use std::collections::{HashMap, HashSet};
-use std::io::ErrorKind;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
 
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Mutex,
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Mutex, MutexGuard, PoisonError,
 };
 
 use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
-use futures::sink::SinkExt;
-use futures::stream::StreamExt;
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{Stream, StreamExt};
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tungstenite::protocol::Message;
 
 use serde::{Deserialize, Serialize};
 
-use std::sync::mpsc::{sync_channel, SyncSender, TryRecvError};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError};
 
+use crate::error::OscQueryError;
 use crate::root::{NamespaceChange, RootInner};
+#[cfg(any(test, feature = "deterministic-test"))]
+use crate::service::memory::Connector;
+use crate::stats::Stats;
+use crate::time::SharedClock;
 use std::sync::Arc;
 use std::sync::RwLock;
 
 //what we set the TCP stream read timeout to
 const CHANNEL_LEN: usize = 1024;
 const EMPTY_DELAY: tokio::time::Duration = tokio::time::Duration::from_millis(1);
+const LISTEN_CURRENT_MAX: usize = 64;
+//unanswered latency probes are forgotten after this
+const PROBE_TTL: Duration = Duration::from_secs(10);
+
+//the TLS acceptor of a websocket secure (wss) service
+#[cfg(feature = "tls")]
+type Tls = Option<tokio_rustls::TlsAcceptor>;
+#[cfg(not(feature = "tls"))]
+type Tls = Option<NoTls>;
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+enum NoTls {}
 
 #[derive(Clone, Debug)]
 enum Command {
-    Osc(crate::osc::OscMessage),
+    //the path clients LISTEN to, the message, the latency probe sequence
+    Osc(String, crate::osc::OscMessage, Option<u64>),
+    Shutdown(Instant),
     Close,
 }
 
@@ -38,7 +58,240 @@ enum Command {
 pub struct WSService {
     handle: Option<JoinHandle<()>>,
     cmd_sender: SyncSender<Command>,
+    stats: Arc<Stats>,
     local_addr: SocketAddr,
+    policy: Policy,
+    options: Arc<ClientOptions>,
+    root: Arc<RwLock<RootInner>>,
+    clients: Arc<AtomicUsize>,
+}
+
+//vendor options, and the subscriptions, shared with the client connections
+struct ClientOptions {
+    strict_paths: AtomicBool,
+    listen_current: AtomicBool,
+    listen_current_max: AtomicUsize,
+    probes: Arc<LatencyProbes>,
+    subscriptions: Subscriptions,
+}
+
+/// Called with the path and `true` when a client LISTENs to it, `false` when one stops, see
+/// `WSService::on_listen`.
+pub type ListenHook = Box<dyn Fn(&str, bool) + Send + Sync>;
+
+//the paths LISTENed to, with the number of clients for each
+#[derive(Default)]
+struct Subscriptions {
+    counts: Mutex<HashMap<String, usize>>,
+    hook: RwLock<Option<ListenHook>>,
+}
+
+impl Subscriptions {
+    fn add(&self, path: &str) {
+        if let Ok(mut counts) = self.counts.lock() {
+            *counts.entry(path.to_string()).or_insert(0) += 1;
+        }
+        self.notify(path, true);
+    }
+
+    fn remove(&self, path: &str) {
+        if let Ok(mut counts) = self.counts.lock() {
+            if let Some(count) = counts.get_mut(path) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(path);
+                }
+            }
+        }
+        self.notify(path, false);
+    }
+
+    //outside of the counts lock, so the hook can query them
+    fn notify(&self, path: &str, added: bool) {
+        if let Ok(hook) = self.hook.read() {
+            if let Some(hook) = hook.as_ref() {
+                hook(path, added);
+            }
+        }
+    }
+
+    fn listeners(&self, path: &str) -> usize {
+        self.counts
+            .lock()
+            .map_or(0, |counts| counts.get(path).copied().unwrap_or(0))
+    }
+
+    fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .counts
+            .lock()
+            .map_or_else(|_| Vec::new(), |counts| counts.keys().cloned().collect());
+        paths.sort();
+        paths
+    }
+}
+
+//see `WSService::set_latency_probes`
+struct LatencyProbes {
+    enabled: AtomicBool,
+    seq: AtomicU64,
+    //in sequence order, so the oldest are at the front
+    pending: Mutex<VecDeque<Probe>>,
+    clock: SharedClock,
+}
+
+struct Probe {
+    path: String,
+    seq: u64,
+    sent: Instant,
+    //the clients that were sent the probe and haven't echoed it yet
+    waiting: HashSet<SocketAddr>,
+}
+
+impl LatencyProbes {
+    fn new(clock: SharedClock) -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            seq: AtomicU64::new(0),
+            pending: Default::default(),
+            clock,
+        }
+    }
+
+    //stamp a relay, returns its sequence, if enabled
+    fn stamp(&self, path: &str) -> Option<u64> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let now = self.clock.now();
+        let mut pending = self.pending.lock().ok()?;
+        self.expire(&mut pending, now);
+        //taken with the lock held so the queue stays in sequence order
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        pending.push_back(Probe {
+            path: path.to_string(),
+            seq,
+            sent: now,
+            waiting: HashSet::new(),
+        });
+        Some(seq)
+    }
+
+    //the client is about to be sent the probe, wait for its echo
+    fn expect(&self, seq: u64, client: SocketAddr) {
+        if let Ok(mut pending) = self.pending.lock() {
+            if let Ok(i) = pending.binary_search_by_key(&seq, |p| p.seq) {
+                pending[i].waiting.insert(client);
+            }
+        }
+    }
+
+    //the round trip time of an echoed probe, the probe is forgotten once every client that
+    //was sent it has echoed it
+    fn echo(&self, client: SocketAddr, path: &str, seq: u64) -> Option<Duration> {
+        let now = self.clock.now();
+        let mut pending = self.pending.lock().ok()?;
+        self.expire(&mut pending, now);
+        let i = pending.binary_search_by_key(&seq, |p| p.seq).ok()?;
+        let probe = &mut pending[i];
+        if probe.path != path || !probe.waiting.remove(&client) {
+            return None;
+        }
+        let rtt = now.saturating_duration_since(probe.sent);
+        if probe.waiting.is_empty() {
+            pending.remove(i);
+        }
+        Some(rtt)
+    }
+
+    //the number of probes waiting for an echo or to expire
+    fn pending(&self) -> usize {
+        let now = self.clock.now();
+        self.pending.lock().map_or(0, |mut p| {
+            self.expire(&mut p, now);
+            p.len()
+        })
+    }
+
+    fn expire(&self, pending: &mut VecDeque<Probe>, now: Instant) {
+        while pending
+            .front()
+            .is_some_and(|p| now.saturating_duration_since(p.sent) >= PROBE_TTL)
+        {
+            pending.pop_front();
+        }
+    }
+}
+
+/// Information about a connected websocket client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The client's remote address.
+    pub addr: SocketAddr,
+}
+
+/// Decide, per client and per path, what a websocket client may do.
+///
+/// All methods default to allowing everything.
+pub trait WsPolicy: Send + Sync {
+    /// May the client LISTEN to the path. Denied requests get an `ERROR` text message.
+    fn allow_listen(&self, _client: &ClientInfo, _path: &str) -> bool {
+        true
+    }
+
+    /// May a value update for the path be relayed to the client, checked at relay time.
+    fn allow_receive(&self, _client: &ClientInfo, _path: &str) -> bool {
+        true
+    }
+
+    /// May the client write to the OSC address. Denied messages are dropped and counted in
+    /// `Stats::ws_writes_denied`.
+    fn allow_write(&self, _client: &ClientInfo, _addr: &str) -> bool {
+        true
+    }
+}
+
+type Policy = Arc<RwLock<Option<Box<dyn WsPolicy>>>>;
+
+//check the policy, allowing everything if there isn't one
+fn allowed<F: Fn(&dyn WsPolicy) -> bool>(policy: &Policy, f: F) -> bool {
+    match policy.read() {
+        Ok(p) => match p.as_ref() {
+            Some(p) => f(p.as_ref()),
+            None => true,
+        },
+        Err(..) => false,
+    }
+}
+
+//remove the messages that the predicate rejects, returns the number removed
+fn filter_packet<F: Fn(&str) -> bool>(
+    packet: crate::osc::OscPacket,
+    f: &F,
+) -> (Option<crate::osc::OscPacket>, usize) {
+    use crate::osc::OscPacket;
+    match packet {
+        OscPacket::Message(m) => {
+            if f(&m.addr) {
+                (Some(OscPacket::Message(m)), 0)
+            } else {
+                (None, 1)
+            }
+        }
+        OscPacket::Bundle(mut b) => {
+            let mut removed = 0;
+            b.content = b
+                .content
+                .into_iter()
+                .filter_map(|p| {
+                    let (p, r) = filter_packet(p, f);
+                    removed += r;
+                    p
+                })
+                .collect();
+            (Some(OscPacket::Bundle(b)), removed)
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -46,37 +299,193 @@ pub struct WSService {
 enum ClientServerCmd {
     Listen,
     Ignore,
+    Capabilities,
+    #[serde(rename = "LATENCY_PROBES")]
+    LatencyProbes,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum LatencyEchoCmd {
+    LatencyEcho,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 enum ServerClientCmd {
-    //PathRenamed,
+    Error,
+    PathRenamed,
     PathRemoved,
     PathAdded,
+    PathChanged,
+    ServerShutdown,
+    Capabilities,
+    LatencyProbe,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
-struct WSCommandPacket<T> {
+struct WSCommandPacket<T, D = String> {
     command: T,
+    data: D,
+}
+
+//a command from a client, LISTEN may ask for the current values
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct ClientCommandPacket {
+    command: ClientServerCmd,
+    #[serde(default)]
     data: String,
+    #[serde(default)]
+    current: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct PathRenamedData {
+    old: String,
+    new: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct ErrorData {
+    path: String,
+    message: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct LatencyData {
+    path: String,
+    seq: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct ServerShutdownData {
+    grace_ms: u64,
+}
+
+//the paths a client LISTENs to, a set of strings stays sound after a panic while it was locked
+fn lock_listening(listening: &Mutex<HashSet<String>>) -> MutexGuard<'_, HashSet<String>> {
+    listening.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+//drop all of a connection's subscriptions
+fn unsubscribe_all(listening: &Mutex<HashSet<String>>, options: &ClientOptions) {
+    let gone: Vec<String> = lock_listening(listening).drain().collect();
+    for p in gone {
+        options.subscriptions.remove(&p);
+    }
+}
+
+//move any listened paths at or below old to be at or below new, returns the paths no longer
+//listened to and those newly listened to
+fn migrate_listening(
+    listening: &mut HashSet<String>,
+    old: &str,
+    new: &str,
+) -> (Vec<String>, Vec<String>) {
+    let prefix = format!("{}/", old);
+    let moved: Vec<String> = listening
+        .iter()
+        .filter(|p| p.as_str() == old || p.starts_with(&prefix))
+        .cloned()
+        .collect();
+    let mut added = Vec::new();
+    for p in &moved {
+        listening.remove(p);
+    }
+    for p in &moved {
+        let p = format!("{}{}", new, &p[old.len()..]);
+        if listening.insert(p.clone()) {
+            added.push(p);
+        }
+    }
+    (moved, added)
 }
 
 #[derive(Clone, Debug)]
 enum HandleCommand {
     Close,
-    Osc(crate::osc::OscMessage),
+    Osc(String, crate::osc::OscMessage, Option<u64>),
     NamespaceChange(NamespaceChange),
+    Shutdown(Duration),
 }
 
 type Broadcast = Arc<tokio::sync::Mutex<HashMap<SocketAddr, UnboundedSender<HandleCommand>>>>;
 
-async fn handle_connection(
-    stream: TcpStream,
+//send the command to every client
+async fn fan_out(broadcast: &Broadcast, c: HandleCommand) {
+    for mut b in broadcast.lock().await.values() {
+        if let Err(e) = b.send(c.clone()).await {
+            eprintln!("error writing HandleCommand {:?}", e);
+        }
+    }
+}
+
+//relays OSC messages to a single client, generic over the sink so it can be driven without a
+//socket
+struct Relay {
+    listening: Arc<Mutex<HashSet<String>>>,
+    client: ClientInfo,
+    policy: Policy,
+    //has the client asked for latency probes
+    probing: Arc<AtomicBool>,
+    probes: Arc<LatencyProbes>,
+    stats: Arc<Stats>,
+}
+
+impl Relay {
+    //relay the message if the client has subscribed to the path
+    async fn relay<S>(&self, out: &mut S, path: String, m: crate::osc::OscMessage, seq: Option<u64>)
+    where
+        S: Sink<Message> + Unpin,
+        S::Error: std::fmt::Debug,
+    {
+        let send = lock_listening(&self.listening).contains(&path)
+            && allowed(&self.policy, |p| p.allow_receive(&self.client, &path));
+        if !send {
+            return;
+        }
+        if let Ok(buf) = crate::osc::encoder::encode(&rosc::OscPacket::Message(m)) {
+            if let Err(e) = out.send(Message::Binary(buf)).await {
+                eprintln!("error writing osc message {:?}", e);
+            } else {
+                self.stats.inc_ws_frames_relayed();
+            }
+        }
+        //follow the frame with its probe, for clients that asked
+        if let (true, Some(seq)) = (self.probing.load(Ordering::Relaxed), seq) {
+            //before sending, the echo can come back before the send returns
+            self.probes.expect(seq, self.client.addr);
+            if let Ok(s) = serde_json::to_string(&WSCommandPacket {
+                command: ServerClientCmd::LatencyProbe,
+                data: LatencyData { path, seq },
+            }) {
+                if let Err(e) = out.send(Message::Text(s)).await {
+                    eprintln!("error writing latency probe {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+//the stream is a plain TCP stream or, for wss, the TLS stream over it
+async fn handle_connection<S>(
+    stream: S,
     mut rx: UnboundedReceiver<HandleCommand>,
     root: Arc<RwLock<RootInner>>,
-) -> Result<(), tungstenite::error::Error> {
+    stats: Arc<Stats>,
+    client: ClientInfo,
+    policy: Policy,
+    options: Arc<ClientOptions>,
+) -> Result<(), tungstenite::error::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     let ws = tokio_tungstenite::accept_async(stream).await?;
     let (mut outgoing, mut incoming) = ws.split();
     let mut tasks = FuturesUnordered::new();
@@ -102,10 +511,17 @@ async fn handle_connection(
     let mut outgoing = tx;
 
     let listening: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    //has the client asked for latency probes
+    let probing = Arc::new(AtomicBool::new(false));
 
     let ilistening = listening.clone();
     let iclose = close.clone();
     let mut out = outgoing.clone();
+    let iclient = client.clone();
+    let ipolicy = policy.clone();
+    let istats = stats.clone();
+    let ioptions = options.clone();
+    let iprobing = probing.clone();
     let incoming = tokio::spawn(async move {
         while let Some(msg) = incoming.next().await {
             match msg {
@@ -120,20 +536,117 @@ async fn handle_connection(
                     break;
                 }
                 Ok(Message::Text(v)) => {
-                    if let Ok(cmd) = serde_json::from_str::<WSCommandPacket<ClientServerCmd>>(&v) {
+                    if let Ok(mut cmd) = serde_json::from_str::<ClientCommandPacket>(&v) {
+                        if let ClientServerCmd::Listen | ClientServerCmd::Ignore = cmd.command {
+                            let strict = ioptions.strict_paths.load(Ordering::Relaxed);
+                            match crate::node::normalize_path(&cmd.data, strict) {
+                                Ok(path) => cmd.data = path,
+                                Err(e) => {
+                                    if let Ok(s) = serde_json::to_string(&WSCommandPacket {
+                                        command: ServerClientCmd::Error,
+                                        data: ErrorData {
+                                            path: cmd.data,
+                                            message: e.to_string(),
+                                        },
+                                    }) {
+                                        if let Err(e) = out.send(Message::Text(s)).await {
+                                            eprintln!("error writing error message {:?}", e);
+                                        }
+                                    }
+                                    continue;
+                                }
+                            }
+                        }
                         match cmd.command {
                             ClientServerCmd::Listen => {
-                                let _ = ilistening.lock().unwrap().insert(cmd.data);
+                                if allowed(&ipolicy, |p| p.allow_listen(&iclient, &cmd.data)) {
+                                    if lock_listening(&ilistening).insert(cmd.data.clone()) {
+                                        ioptions.subscriptions.add(&cmd.data);
+                                    }
+                                    //catch up, only this client gets the current values
+                                    if cmd.current.unwrap_or_else(|| {
+                                        ioptions.listen_current.load(Ordering::Relaxed)
+                                    }) {
+                                        let msgs = root.read().map_or(Vec::new(), |root| {
+                                            root.current_messages(
+                                                &cmd.data,
+                                                ioptions.listen_current_max.load(Ordering::Relaxed),
+                                            )
+                                        });
+                                        for (path, m) in msgs {
+                                            if !allowed(&ipolicy, |p| {
+                                                p.allow_receive(&iclient, &path)
+                                            }) {
+                                                continue;
+                                            }
+                                            if let Ok(buf) = crate::osc::encoder::encode(
+                                                &rosc::OscPacket::Message(m),
+                                            ) {
+                                                if let Err(e) = out.send(Message::Binary(buf)).await
+                                                {
+                                                    eprintln!("error writing osc message {:?}", e);
+                                                } else {
+                                                    istats.inc_ws_frames_relayed();
+                                                }
+                                            }
+                                        }
+                                    }
+                                } else if let Ok(s) = serde_json::to_string(&WSCommandPacket {
+                                    command: ServerClientCmd::Error,
+                                    data: ErrorData {
+                                        path: cmd.data,
+                                        message: "LISTEN not allowed".into(),
+                                    },
+                                }) {
+                                    if let Err(e) = out.send(Message::Text(s)).await {
+                                        eprintln!("error writing error message {:?}", e);
+                                    }
+                                }
                             }
                             ClientServerCmd::Ignore => {
-                                let _ = ilistening.lock().unwrap().remove(&cmd.data);
+                                if lock_listening(&ilistening).remove(&cmd.data) {
+                                    ioptions.subscriptions.remove(&cmd.data);
+                                }
+                            }
+                            ClientServerCmd::Capabilities => {
+                                let features =
+                                    root.read().map_or(Vec::new(), |root| root.capabilities());
+                                if let Ok(s) = serde_json::to_string(&WSCommandPacket {
+                                    command: ServerClientCmd::Capabilities,
+                                    data: features,
+                                }) {
+                                    if let Err(e) = out.send(Message::Text(s)).await {
+                                        eprintln!("error writing capabilities {:?}", e);
+                                    }
+                                }
+                            }
+                            ClientServerCmd::LatencyProbes => {
+                                iprobing.store(true, Ordering::Relaxed);
                             }
                         }
-                    };
+                    } else if let Ok(echo) =
+                        serde_json::from_str::<WSCommandPacket<LatencyEchoCmd, LatencyData>>(&v)
+                    {
+                        if let Some(rtt) =
+                            ioptions
+                                .probes
+                                .echo(iclient.addr, &echo.data.path, echo.data.seq)
+                        {
+                            istats.record_latency(iclient.addr, rtt);
+                        }
+                    }
                 }
                 Ok(Message::Binary(v)) => {
                     if let Ok(packet) = crate::osc::decoder::decode(&v) {
-                        crate::root::RootInner::handle_osc_packet(&root, &packet, None, None);
+                        let (packet, denied) = filter_packet(packet, &|addr: &str| {
+                            allowed(&ipolicy, |p| p.allow_write(&iclient, addr))
+                        });
+                        for _ in 0..denied {
+                            istats.inc_ws_writes_denied();
+                        }
+                        if let Some(packet) = packet {
+                            crate::root::RootInner::handle_osc_packet(&root, &packet, None, None);
+                        }
                     }
                 }
                 Err(e) => {
@@ -142,9 +655,25 @@ async fn handle_connection(
                 }
             };
         }
+        //the connection is gone, let the command task finish with its next command
+        unsubscribe_all(&ilistening, &ioptions);
+        iclose.store(true, Ordering::Relaxed);
     });
     tasks.push(incoming);
 
+    let panics = stats.clone();
+    let addr = client.addr;
+    let relay = Relay {
+        listening: listening.clone(),
+        client,
+        policy,
+        probing,
+        probes: options.probes.clone(),
+        stats,
+    };
+    let pclose = close.clone();
+    let coptions = options.clone();
+    let clistening = listening.clone();
     let cmds = tokio::spawn(async move {
         loop {
             if close.load(Ordering::Relaxed) {
@@ -156,37 +685,59 @@ async fn handle_connection(
                     close.store(true, Ordering::Relaxed);
                     break;
                 }
-                Some(HandleCommand::Osc(m)) => {
-                    //relay osc messages if the remote client has subscribed
-                    let send = if let Ok(l) = listening.lock() {
-                        l.contains(&m.addr)
-                    } else {
-                        false
-                    };
-                    if send {
-                        if let Ok(buf) =
-                            crate::osc::encoder::encode(&rosc::OscPacket::Message(m.clone()))
-                        {
-                            if let Err(e) = outgoing.send(Message::Binary(buf)).await {
-                                eprintln!("error writing osc message {:?}", e);
-                            }
-                        }
-                    }
+                Some(HandleCommand::Osc(path, m, seq)) => {
+                    relay.relay(&mut outgoing, path, m, seq).await;
                 }
                 Some(HandleCommand::NamespaceChange(c)) => {
-                    let s = serde_json::to_string(&match c {
-                        NamespaceChange::PathAdded(p) => WSCommandPacket {
+                    let s = match c {
+                        NamespaceChange::PathAdded(p) => serde_json::to_string(&WSCommandPacket {
                             command: ServerClientCmd::PathAdded,
                             data: p.clone(),
-                        },
-                        NamespaceChange::PathRemoved(p) => WSCommandPacket {
-                            command: ServerClientCmd::PathRemoved,
-                            data: p.clone(),
+                        }),
+                        NamespaceChange::PathRemoved(p) => {
+                            serde_json::to_string(&WSCommandPacket {
+                                command: ServerClientCmd::PathRemoved,
+                                data: p.clone(),
+                            })
+                        }
+                        NamespaceChange::PathChanged(p) => {
+                            serde_json::to_string(&WSCommandPacket {
+                                command: ServerClientCmd::PathChanged,
+                                data: p.clone(),
+                            })
+                        }
+                        NamespaceChange::PathRenamed(old, new) => {
+                            //keep relaying to subscribers of the renamed paths
+                            let (removed, added) =
+                                migrate_listening(&mut lock_listening(&clistening), &old, &new);
+                            for p in removed {
+                                coptions.subscriptions.remove(&p);
+                            }
+                            for p in added {
+                                coptions.subscriptions.add(&p);
+                            }
+                            serde_json::to_string(&WSCommandPacket {
+                                command: ServerClientCmd::PathRenamed,
+                                data: PathRenamedData { old, new },
+                            })
+                        }
+                    };
+                    if let Ok(s) = s {
+                        if let Err(e) = outgoing.send(Message::Text(s)).await {
+                            eprintln!("error writing ns message {:?}", e);
+                        }
+                    }
+                }
+                Some(HandleCommand::Shutdown(grace)) => {
+                    let s = serde_json::to_string(&WSCommandPacket {
+                        command: ServerClientCmd::ServerShutdown,
+                        data: ServerShutdownData {
+                            grace_ms: grace.as_millis() as u64,
                         },
                     });
                     if let Ok(s) = s {
                         if let Err(e) = outgoing.send(Message::Text(s)).await {
-                            eprintln!("error writing ns message {:?}", e);
+                            eprintln!("error writing shutdown message {:?}", e);
                         }
                     }
                 }
@@ -195,35 +746,217 @@ async fn handle_connection(
     });
     tasks.push(cmds);
 
-    while let Some(_) = tasks.next().await {}
+    while let Some(res) = tasks.next().await {
+        //a panic ends the connection, it finishes with the next command like a close
+        if let Err(e) = res {
+            if e.is_panic() {
+                eprintln!("panic in ws connection {}", addr);
+                panics.inc_task_panics();
+                pclose.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+    //in case the incoming task panicked
+    unsubscribe_all(&listening, &options);
     println!("ws exiting");
     Ok(())
 }
 
+//serves the clients of a `WSService`, wherever their connections come from
+struct Worker {
+    root: Arc<RwLock<RootInner>>,
+    stats: Arc<Stats>,
+    ns_change_recv: Receiver<NamespaceChange>,
+    cmd_recv: Receiver<Command>,
+    policy: Policy,
+    options: Arc<ClientOptions>,
+    clients: Arc<AtomicUsize>,
+    tls: Tls,
+}
+
+impl Worker {
+    //run until the service is dropped or accepting fails
+    async fn run<I, S>(self, incoming: I)
+    where
+        I: Stream<Item = std::io::Result<(S, SocketAddr)>> + Send + 'static,
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let Self {
+            root,
+            stats,
+            ns_change_recv,
+            cmd_recv,
+            policy,
+            options,
+            clients,
+            tls,
+        } = self;
+        let bc: Broadcast = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+
+        let broadcast = bc.clone();
+        let ns = tokio::spawn(async move {
+            //read from channel and write
+            loop {
+                let ns = ns_change_recv.try_recv();
+                match ns {
+                    Ok(c) => {
+                        let c = HandleCommand::NamespaceChange(c);
+                        for mut b in broadcast.lock().await.values() {
+                            if let Err(e) = b.send(c.clone()).await {
+                                eprintln!("error writing HandleCommand::NamespaceChange {:?}", e);
+                            }
+                        }
+                    }
+                    Err(TryRecvError::Empty) => tokio::time::delay_for(EMPTY_DELAY).await,
+                    Err(e) => {
+                        eprintln!("cmd error {:?}", e);
+                        return;
+                    }
+                };
+            }
+        });
+
+        let shutdown_at: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+        let cmd_shutdown_at = shutdown_at.clone();
+        let broadcast = bc.clone();
+        let cmd_stats = stats.clone();
+        let cmd = tokio::spawn(async move {
+            //read from channel and write
+            loop {
+                let cmd = cmd_recv.try_recv();
+                if let Ok(Command::Osc(..)) = cmd {
+                    cmd_stats.dec_ws_queue_depth();
+                }
+                match cmd {
+                    Ok(Command::Close) => {
+                        for mut b in broadcast.lock().await.values() {
+                            if let Err(e) = b.send(HandleCommand::Close).await {
+                                eprintln!("error writing HandleCommand::Close {:?}", e);
+                            }
+                        }
+                        return;
+                    }
+                    Ok(Command::Shutdown(at)) => {
+                        //set while locked so new connections get exactly one notice
+                        let broadcast = broadcast.lock().await;
+                        if let Ok(mut shutdown_at) = cmd_shutdown_at.lock() {
+                            *shutdown_at = Some(at);
+                        }
+                        let c =
+                            HandleCommand::Shutdown(at.saturating_duration_since(Instant::now()));
+                        for mut b in broadcast.values() {
+                            if let Err(e) = b.send(c.clone()).await {
+                                eprintln!("error writing HandleCommand::Shutdown {:?}", e);
+                            }
+                        }
+                    }
+                    Ok(Command::Osc(path, m, seq)) => {
+                        fan_out(&broadcast, HandleCommand::Osc(path, m, seq)).await;
+                    }
+                    Err(TryRecvError::Empty) => tokio::time::delay_for(EMPTY_DELAY).await,
+                    Err(e) => {
+                        eprintln!("cmd error {:?}", e);
+                        return;
+                    }
+                };
+            }
+        });
+
+        let broadcast = bc.clone();
+        let spawn = tokio::spawn(async move {
+            futures::pin_mut!(incoming);
+            while let Some(accepted) = incoming.next().await {
+                match accepted {
+                    Ok((stream, addr)) => {
+                        let (mut tx, rx) = unbounded();
+                        let mut broadcast_locked = broadcast.lock().await;
+                        //let clients connecting during a shutdown grace period know
+                        let at = shutdown_at.lock().map_or(None, |at| *at);
+                        if let Some(at) = at {
+                            let grace = at.saturating_duration_since(Instant::now());
+                            let _ = tx.send(HandleCommand::Shutdown(grace)).await;
+                        }
+                        broadcast_locked.insert(addr, tx);
+                        clients.fetch_add(1, Ordering::Relaxed);
+                        drop(broadcast_locked);
+                        stats.inc_ws_clients();
+                        let r = root.clone();
+                        let bc = broadcast.clone();
+                        let stats = stats.clone();
+                        let client = ClientInfo { addr };
+                        let policy = policy.clone();
+                        let options = options.clone();
+                        let clients = clients.clone();
+                        let tls = tls.clone();
+                        tokio::spawn(async move {
+                            let s = stats.clone();
+                            let _ = match tls {
+                                #[cfg(feature = "tls")]
+                                Some(tls) => match tls.accept(stream).await {
+                                    Ok(stream) => {
+                                        handle_connection(stream, rx, r, s, client, policy, options)
+                                            .await
+                                    }
+                                    Err(e) => {
+                                        eprintln!("error in tls handshake {:?}", e);
+                                        Ok(())
+                                    }
+                                },
+                                #[cfg(not(feature = "tls"))]
+                                Some(never) => match never {},
+                                None => {
+                                    handle_connection(stream, rx, r, s, client, policy, options)
+                                        .await
+                                }
+                            };
+                            bc.lock().await.remove(&addr);
+                            clients.fetch_sub(1, Ordering::Relaxed);
+                            stats.forget_latency(&addr);
+                            stats.dec_ws_clients();
+                        });
+                    }
+                    Err(e) => {
+                        eprintln!("error accept {:?}", e);
+                        break;
+                    }
+                };
+            }
+        });
+        tokio::select!(_ = ns => (), _ = cmd => (), _ = spawn => ());
+    }
+}
+
 impl WSService {
     pub(crate) fn new<A: ToSocketAddrs>(
         root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
         addr: A,
-    ) -> Result<Self, std::io::Error> {
-        //get the namespace change channel
-        let ns_change_recv = root
-            .write()
-            .expect("cannot write lock root")
-            .ns_change_recv();
-        if ns_change_recv.is_none() {
-            return Err(std::io::Error::new(
-                ErrorKind::Other,
-                "couldn't get namespace change from root",
-            ));
-        }
-        let ns_change_recv = ns_change_recv.unwrap();
+    ) -> Result<Self, OscQueryError> {
+        Self::with_tls(root, stats, addr, None)
+    }
 
-        let (cmd_send, cmd_recv) = sync_channel(CHANNEL_LEN);
+    /// Like `new` but clients connect with websocket secure (wss), the TLS handshake happens
+    /// before the websocket upgrade.
+    #[cfg(feature = "tls")]
+    pub(crate) fn new_tls<A: ToSocketAddrs>(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+        addr: A,
+        tls: tokio_rustls::TlsAcceptor,
+    ) -> Result<Self, OscQueryError> {
+        Self::with_tls(root, stats, addr, Some(tls))
+    }
 
+    fn with_tls<A: ToSocketAddrs>(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+        addr: A,
+        tls: Tls,
+    ) -> Result<Self, OscQueryError> {
         let listener = std::net::TcpListener::bind(addr)?;
         let local_addr = listener.local_addr()?;
-
-        let handle = spawn(move || {
+        let (mut service, worker) = Self::start(root, stats, local_addr, tls)?;
+        service.handle = Some(spawn(move || {
             let mut rt = tokio::runtime::Builder::new()
                 .basic_scheduler()
                 .threaded_scheduler()
@@ -231,102 +964,226 @@ impl WSService {
                 .build()
                 .expect("could not create runtime");
             rt.block_on(async move {
-                let bc: Broadcast = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
-
-                let broadcast = bc.clone();
-                let ns = tokio::spawn(async move {
-                    //read from channel and write
-                    loop {
-                        let ns = ns_change_recv.try_recv();
-                        match ns {
-                            Ok(c) => {
-                                let c = HandleCommand::NamespaceChange(c);
-                                for mut b in broadcast.lock().await.values() {
-                                    if let Err(e) = b.send(c.clone()).await {
-                                        eprintln!(
-                                            "error writing HandleCommand::NamespaceChange {:?}",
-                                            e
-                                        );
-                                    }
-                                }
-                            }
-                            Err(TryRecvError::Empty) => tokio::time::delay_for(EMPTY_DELAY).await,
-                            Err(e) => {
-                                eprintln!("cmd error {:?}", e);
-                                return;
-                            }
-                        };
-                    }
+                let listener = TcpListener::from_std(listener)
+                    .expect("failed to convert std::net::TcpListener to tokio::net::TcpListener");
+                let incoming = futures::stream::unfold(listener, |mut listener| async move {
+                    let accepted = listener.accept().await;
+                    Some((accepted, listener))
                 });
+                worker.run(incoming).await;
+            });
+        }));
+        Ok(service)
+    }
 
-                let broadcast = bc.clone();
-                let cmd = tokio::spawn(async move {
-                    //read from channel and write
-                    loop {
-                        let cmd = cmd_recv.try_recv();
-                        match cmd {
-                            Ok(Command::Close) => {
-                                for mut b in broadcast.lock().await.values() {
-                                    if let Err(e) = b.send(HandleCommand::Close).await {
-                                        eprintln!("error writing HandleCommand::Close {:?}", e);
-                                    }
-                                }
-                                return;
-                            }
-                            Ok(Command::Osc(m)) => {
-                                let c = HandleCommand::Osc(m);
-                                for mut b in broadcast.lock().await.values() {
-                                    if let Err(e) = b.send(c.clone()).await {
-                                        eprintln!("error writing HandleCommand::Osc {:?}", e);
-                                    }
-                                }
-                            }
-                            Err(TryRecvError::Empty) => tokio::time::delay_for(EMPTY_DELAY).await,
-                            Err(e) => {
-                                eprintln!("cmd error {:?}", e);
-                                return;
-                            }
-                        };
-                    }
-                });
+    /// Like `new` but clients connect in memory, with the returned `Connector`, and nothing
+    /// runs until the returned future is spawned on a runtime of the caller's choosing.
+    ///
+    /// On a basic scheduler runtime with `tokio::time::pause` the polling of the command and
+    /// namespace change channels happens in virtual time, so tests don't depend on timing.
+    #[cfg(any(test, feature = "deterministic-test"))]
+    pub(crate) fn new_in_memory(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+    ) -> Result<(Self, Connector, impl std::future::Future<Output = ()>), OscQueryError> {
+        let (connector, incoming) = crate::service::memory::listener();
+        let (service, worker) = Self::start(root, stats, *connector.local_addr(), None)?;
+        Ok((service, connector, worker.run(incoming)))
+    }
 
-                let broadcast = bc.clone();
-                let spawn = tokio::spawn(async move {
-                    let mut listener = TcpListener::from_std(listener).expect(
-                        "failed to convert std::net::TcpListener to tokio::net::TcpListener",
-                    );
-                    loop {
-                        match listener.accept().await {
-                            Ok((stream, addr)) => {
-                                let (tx, rx) = unbounded();
-                                broadcast.lock().await.insert(addr, tx);
-                                let r = root.clone();
-                                let bc = broadcast.clone();
-                                tokio::spawn(async move {
-                                    let _ = handle_connection(stream, rx, r).await;
-                                    bc.lock().await.remove(&addr);
-                                });
-                            }
-                            Err(e) => {
-                                eprintln!("error accept {:?}", e);
-                                break;
-                            }
-                        };
-                    }
-                });
-                tokio::select!(_ = ns => (), _ = cmd => (), _ = spawn => ());
-            });
+    //the service and the worker that serves its clients, not yet running
+    fn start(
+        root: Arc<RwLock<RootInner>>,
+        stats: Arc<Stats>,
+        local_addr: SocketAddr,
+        tls: Tls,
+    ) -> Result<(Self, Worker), OscQueryError> {
+        //get the namespace change channel
+        let ns_change_recv = {
+            let mut root = root.write().map_err(|_| OscQueryError::PoisonedLock)?;
+            for c in &[
+                crate::capability::CAPABILITIES,
+                crate::capability::LISTEN_CURRENT,
+                crate::capability::SERVER_SHUTDOWN,
+            ] {
+                root.register_capability(c);
+            }
+            root.ns_change_recv()
+        };
+
+        let (cmd_send, cmd_recv) = sync_channel(CHANNEL_LEN);
+
+        let policy: Policy = Default::default();
+        let options = Arc::new(ClientOptions {
+            strict_paths: AtomicBool::new(false),
+            listen_current: AtomicBool::new(true),
+            listen_current_max: AtomicUsize::new(LISTEN_CURRENT_MAX),
+            probes: Arc::new(LatencyProbes::new(
+                root.read()
+                    .map_or_else(|_| crate::time::system_clock(), |r| r.clock().clone()),
+            )),
+            subscriptions: Default::default(),
         });
+        let clients = Arc::new(AtomicUsize::new(0));
+        let worker = Worker {
+            root: root.clone(),
+            stats: stats.clone(),
+            ns_change_recv,
+            cmd_recv,
+            policy: policy.clone(),
+            options: options.clone(),
+            clients: clients.clone(),
+            tls,
+        };
+        Ok((
+            Self {
+                handle: None,
+                local_addr,
+                cmd_sender: cmd_send,
+                stats,
+                policy,
+                options,
+                root,
+                clients,
+            },
+            worker,
+        ))
+    }
 
-        Ok(Self {
-            handle: Some(handle),
-            local_addr,
-            cmd_sender: cmd_send,
-        })
+    /// Set the policy that decides what each client may LISTEN to, receive and write.
+    pub fn set_policy(&self, policy: Box<dyn WsPolicy>) {
+        if let Ok(mut p) = self.policy.write() {
+            *p = Some(policy);
+        }
+    }
+
+    /// Reject, rather than repair, LISTEN and IGNORE paths with whitespace or a malformed
+    /// percent-encoding. Defaults to `false`.
+    ///
+    /// Paths are always normalized with `node::normalize_path`, the ones that can't be get an
+    /// `ERROR` text message.
+    pub fn set_strict_paths(&self, strict: bool) {
+        self.options.strict_paths.store(strict, Ordering::Relaxed);
+    }
+
+    /// Send the current value of the path to a client right after it LISTENs to it, so it doesn't
+    /// have to wait for the next trigger. Defaults to `true`. Nothing is sent for paths that
+    /// don't exist or have no readable value.
+    ///
+    /// Clients can also choose per LISTEN, a vendor extension:
+    /// `{"COMMAND":"LISTEN","DATA":"/path","CURRENT":false}`. For a container path, the current
+    /// values of the readable nodes below it are sent, see `set_listen_current_max`.
+    pub fn set_listen_current(&self, enabled: bool) {
+        self.options
+            .listen_current
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    /// Set the most values sent for a single LISTEN to a container path. Defaults to 64.
+    pub fn set_listen_current_max(&self, max: usize) {
+        self.options
+            .listen_current_max
+            .store(max, Ordering::Relaxed);
+    }
+
+    /// Measure the latency from a relay to its delivery at the clients that cooperate. Defaults
+    /// to `false`.
+    ///
+    /// Clients opt in with `{"COMMAND":"LATENCY_PROBES"}`, a vendor extension. Every relayed OSC
+    /// frame is then followed by `{"COMMAND":"LATENCY_PROBE","DATA":{"PATH":...,"SEQ":...}}` and
+    /// the client echoes the `DATA` back with `{"COMMAND":"LATENCY_ECHO","DATA":{...}}`. The round
+    /// trip, from the relay request to the echo, is summarized per client in
+    /// `Stats::latency`. Probes that aren't echoed within 10 seconds are forgotten.
+    pub fn set_latency_probes(&self, enabled: bool) {
+        self.options
+            .probes
+            .enabled
+            .store(enabled, Ordering::Relaxed);
+        if let Ok(mut root) = self.root.write() {
+            if enabled {
+                root.register_capability(crate::capability::LATENCY_PROBES);
+            } else {
+                root.unregister_capability(crate::capability::LATENCY_PROBES);
+            }
+        }
+    }
+
+    /// The number of latency probes that are waiting for an echo or to expire.
+    pub fn latency_probes_pending(&self) -> usize {
+        self.options.probes.pending()
+    }
+
+    /// The number of connected clients.
+    pub fn client_count(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
     }
 
     pub fn send(&self, msg: crate::osc::OscMessage) {
-        let _ = self.cmd_sender.send(Command::Osc(msg));
+        self.send_for(msg.addr.clone(), msg);
+    }
+
+    /// The number of clients that LISTEN to the path.
+    pub fn listeners(&self, path: &str) -> usize {
+        let path = crate::node::normalize_path(path, false).unwrap_or_else(|_| path.to_string());
+        self.options.subscriptions.listeners(&path)
+    }
+
+    /// Whether any client LISTENs to the path, cheaper than `listeners` for normalized paths.
+    pub fn has_listeners(&self, path: &str) -> bool {
+        if self.client_count() == 0 {
+            return false;
+        }
+        if crate::node::is_normalized_path(path) {
+            self.options.subscriptions.listeners(path) > 0
+        } else {
+            self.listeners(path) > 0
+        }
+    }
+
+    /// The paths that at least one client LISTENs to, sorted.
+    pub fn listened_paths(&self) -> Vec<String> {
+        self.options.subscriptions.paths()
+    }
+
+    /// Call the hook with the path and `true` whenever a client LISTENs to a path, with `false`
+    /// whenever one stops, with IGNORE, by disconnecting or when the path is renamed. Replaces
+    /// any previous hook.
+    ///
+    /// *NOTE* the hook is called from the service's runtime, it should return quickly.
+    pub fn on_listen<F>(&self, hook: F)
+    where
+        F: Fn(&str, bool) + Send + Sync + 'static,
+    {
+        if let Ok(mut h) = self.options.subscriptions.hook.write() {
+            *h = Some(Box::new(hook));
+        }
+    }
+
+    /// Relay a message to the clients that LISTEN to the path, which can differ from the
+    /// message's address, see `Get::with_osc_address`.
+    ///
+    /// Does nothing while no client is connected.
+    pub(crate) fn send_for(&self, path: String, msg: crate::osc::OscMessage) {
+        if self.client_count() == 0 {
+            return;
+        }
+        //compared with the normalized paths clients LISTEN to
+        let path = crate::node::normalize_path(&path, false).unwrap_or(path);
+        self.stats.inc_ws_queue_depth();
+        let seq = self.options.probes.stamp(&path);
+        if self.cmd_sender.send(Command::Osc(path, msg, seq)).is_err() {
+            self.stats.dec_ws_queue_depth();
+        }
+    }
+
+    /// Tell all connected clients, and any that connect later, that the server will shut down
+    /// after the grace period.
+    ///
+    /// Sends `{"COMMAND":"SERVER_SHUTDOWN","DATA":{"GRACE_MS":...}}`, a vendor extension.
+    pub fn announce_shutdown(&self, grace: Duration) {
+        let _ = self
+            .cmd_sender
+            .send(Command::Shutdown(Instant::now() + grace));
     }
 
     /// Returns the `SocketAddr` that the service bound to.
@@ -346,3 +1203,999 @@ impl Drop for WSService {
         }
     }
 }
+
+/// The relay fan-out with in-memory channels in place of client sockets, for the benchmarks.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub struct RelayBench {
+    rt: tokio::runtime::Runtime,
+    broadcast: Broadcast,
+    sinks: Vec<UnboundedReceiver<Message>>,
+}
+
+#[cfg(feature = "bench")]
+impl RelayBench {
+    /// Create the subscribers, all of them LISTEN to the path.
+    pub fn new(subscribers: usize, path: &str) -> Self {
+        let rt = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .expect("could not create runtime");
+        let mut clients = HashMap::new();
+        let stats: Arc<Stats> = Default::default();
+        let mut sinks = Vec::new();
+        for i in 0..subscribers {
+            let addr = SocketAddr::from(([127, 0, 0, 1], i as u16 + 1));
+            let (tx, mut rx) = unbounded();
+            let (mut sink, srx) = unbounded();
+            clients.insert(addr, tx);
+            sinks.push(srx);
+            let relay = Relay {
+                listening: Arc::new(Mutex::new(std::iter::once(path.to_string()).collect())),
+                client: ClientInfo { addr },
+                policy: Default::default(),
+                probing: Default::default(),
+                probes: Arc::new(LatencyProbes::new(crate::time::system_clock())),
+                stats: stats.clone(),
+            };
+            rt.spawn(async move {
+                while let Some(c) = rx.next().await {
+                    if let HandleCommand::Osc(path, m, seq) = c {
+                        relay.relay(&mut sink, path, m, seq).await;
+                    }
+                }
+            });
+        }
+        Self {
+            rt,
+            broadcast: Arc::new(tokio::sync::Mutex::new(clients)),
+            sinks,
+        }
+    }
+
+    /// Relay the messages, returns the number of frames delivered once all are.
+    pub fn relay(&mut self, msgs: &[crate::osc::OscMessage]) -> usize {
+        let broadcast = &self.broadcast;
+        let sinks = &mut self.sinks;
+        self.rt.block_on(async move {
+            for m in msgs {
+                fan_out(
+                    broadcast,
+                    HandleCommand::Osc(m.addr.clone(), m.clone(), None),
+                )
+                .await;
+            }
+            let mut frames = 0;
+            for sink in sinks.iter_mut() {
+                for _ in msgs {
+                    if sink.next().await.is_some() {
+                        frames += 1;
+                    }
+                }
+            }
+            frames
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::osc::{OscMessage, OscPacket, OscType};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use tungstenite::WebSocket;
+
+    pub(crate) fn connect(addr: &SocketAddr) -> WebSocket<TcpStream> {
+        let stream = TcpStream::connect(addr).expect("failed to connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("failed to set timeout");
+        let (socket, _) =
+            tungstenite::client(format!("ws://{}/", addr), stream).expect("failed to handshake");
+        socket
+    }
+
+    pub(crate) fn read_osc(socket: &mut WebSocket<TcpStream>) -> OscMessage {
+        loop {
+            match socket.read_message().expect("failed to read") {
+                Message::Binary(v) => match crate::osc::decoder::decode(&v) {
+                    Ok(OscPacket::Message(m)) => return m,
+                    other => panic!("unexpected packet {:?}", other),
+                },
+                Message::Ping(..) | Message::Pong(..) => (),
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+    }
+
+    pub(crate) fn read_text(socket: &mut WebSocket<TcpStream>) -> serde_json::Value {
+        loop {
+            match socket.read_message().expect("failed to read") {
+                Message::Text(v) => return serde_json::from_str(&v).expect("invalid json"),
+                Message::Ping(..) | Message::Pong(..) => (),
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+    }
+
+    //LISTEN to the path, without the current value, and wait for the subscription to take effect
+    pub(crate) fn listen(socket: &mut WebSocket<TcpStream>, ws: &WSService, path: &str) {
+        socket
+            .write_message(Message::Text(format!(
+                "{{\"COMMAND\":\"LISTEN\",\"DATA\":\"{}\",\"CURRENT\":false}}",
+                path
+            )))
+            .unwrap();
+        let timeout = |socket: &mut WebSocket<TcpStream>, d| {
+            socket.get_ref().set_read_timeout(Some(d)).unwrap()
+        };
+        timeout(socket, Duration::from_millis(50));
+        loop {
+            ws.send(msg(path));
+            if socket.read_message().is_ok() {
+                break;
+            }
+        }
+        timeout(socket, Duration::from_secs(5));
+    }
+
+    fn msg(addr: &str) -> OscMessage {
+        OscMessage {
+            addr: addr.into(),
+            args: vec![OscType::Int(1)],
+        }
+    }
+
+    #[test]
+    fn migrate() {
+        let mut l: HashSet<String> = ["/foo", "/foo/bar", "/foobar", "/baz/bar", "/x"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        migrate_listening(&mut l, "/foo", "/baz");
+        let mut l: Vec<_> = l.into_iter().collect();
+        l.sort();
+        assert_eq!(l, vec!["/baz", "/baz/bar", "/foobar", "/x"]);
+    }
+
+    #[test]
+    fn listen_follows_rename() {
+        let root = Arc::new(RwLock::new(RootInner::new(None)));
+        let ws = WSService::new(root.clone(), Default::default(), "127.0.0.1:0")
+            .expect("failed to spawn ws");
+        let mut socket = connect(ws.local_addr());
+        listen(&mut socket, &ws, "/foo/bar");
+
+        root.read()
+            .unwrap()
+            .send_ns_change(NamespaceChange::PathRenamed("/foo".into(), "/baz".into()));
+        assert_eq!(
+            read_text(&mut socket),
+            serde_json::json!({
+                "COMMAND": "PATH_RENAMED",
+                "DATA": {"OLD": "/foo", "NEW": "/baz"}
+            })
+        );
+
+        ws.send(msg("/foo/bar"));
+        ws.send(msg("/baz/bar"));
+        assert_eq!(read_osc(&mut socket).addr, "/baz/bar");
+    }
+
+    #[test]
+    fn path_changed() {
+        use crate::error::OscQueryError;
+        use crate::node::GetSet;
+        use crate::param::ParamGetSet;
+        use crate::value::{Range, ValueBuilder};
+        use ::atomic::Atomic;
+
+        let root = crate::root::Root::new(None);
+        let value = Arc::new(Atomic::new(0f32));
+        let node = |address: &str, range| {
+            GetSet::new(
+                address,
+                None,
+                vec![ParamGetSet::Float(
+                    ValueBuilder::new(value.clone() as _)
+                        .with_range(range)
+                        .build(),
+                )],
+                None,
+            )
+            .unwrap()
+        };
+        let handle = root.add_node(node("gain", Range::Max(1.0)), None).unwrap();
+        let ws = root.spawn_ws("127.0.0.1:0").expect("failed to spawn ws");
+        let mut socket = connect(ws.local_addr());
+        listen(&mut socket, &ws, "/gain");
+        let changed = |path: &str| serde_json::json!({"COMMAND": "PATH_CHANGED", "DATA": path});
+
+        root.notify_changed(&handle);
+        assert_eq!(read_text(&mut socket), changed("/gain"));
+
+        let generation = root.generation();
+        let old = root
+            .replace_node(&handle, node("gain", Range::MinMax(-1.0, 1.0)))
+            .unwrap();
+        assert_eq!(old.address(), "gain");
+        assert_eq!(read_text(&mut socket), changed("/gain"));
+        let j = serde_json::to_value(&root).unwrap();
+        assert_eq!(
+            j["CONTENTS"]["gain"]["RANGE"],
+            serde_json::json!([{"MIN": -1.0, "MAX": 1.0}])
+        );
+        let diff = root.contents_since("/", generation).unwrap();
+        assert_eq!(diff.changed, vec!["gain".to_string()]);
+
+        //aliases show the target's attributes
+        root.add_alias(handle, None, "g").unwrap();
+        assert_eq!(read_text(&mut socket)["COMMAND"], "PATH_ADDED");
+        root.notify_changed(&handle);
+        assert_eq!(read_text(&mut socket), changed("/gain"));
+        assert_eq!(read_text(&mut socket), changed("/g"));
+
+        match root.replace_node(&handle, node("level", Range::None)) {
+            Err(OscQueryError::NodeNotAdded { node, reason }) => {
+                assert_eq!(node.address(), "level");
+                assert_matches!(*reason, OscQueryError::InvalidAddress(..));
+            }
+            _ => panic!("expected NodeNotAdded"),
+        }
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    //only the kiosk port is restricted
+    struct KioskPolicy(u16);
+
+    impl WsPolicy for KioskPolicy {
+        fn allow_listen(&self, client: &ClientInfo, path: &str) -> bool {
+            client.addr.port() != self.0 || path.starts_with("/public/")
+        }
+
+        fn allow_receive(&self, client: &ClientInfo, path: &str) -> bool {
+            client.addr.port() != self.0 || !path.ends_with("/hidden")
+        }
+
+        fn allow_write(&self, client: &ClientInfo, addr: &str) -> bool {
+            client.addr.port() != self.0 || addr.starts_with("/public/")
+        }
+    }
+
+    #[test]
+    fn policy() {
+        use crate::node::Set;
+        use crate::param::ParamSet;
+        use crate::value::ValueBuilder;
+        use ::atomic::Atomic;
+
+        let root = crate::root::Root::new(None);
+        let ws = root.spawn_ws("127.0.0.1:0").expect("failed to spawn ws");
+        let mut operator = connect(ws.local_addr());
+        let mut kiosk = connect(ws.local_addr());
+        let kiosk_port = kiosk.get_ref().local_addr().unwrap().port();
+        ws.set_policy(Box::new(KioskPolicy(kiosk_port)));
+
+        let mut values = Vec::new();
+        for path in &["private", "public"] {
+            let c = root
+                .add_node(crate::node::Container::new(*path, None).unwrap(), None)
+                .unwrap();
+            let a = Arc::new(Atomic::new(0i32));
+            let m = Set::new(
+                "x",
+                None,
+                vec![ParamSet::Int(ValueBuilder::new(a.clone() as _).build())],
+                None,
+            );
+            assert!(root.add_node(m.unwrap(), Some(c)).is_ok());
+            values.push(a);
+        }
+        //drain the PATH_ADDED notices
+        for socket in &mut [&mut operator, &mut kiosk] {
+            for _ in 0..4 {
+                assert_eq!(read_text(socket)["COMMAND"], "PATH_ADDED");
+            }
+        }
+
+        //listen
+        listen(&mut operator, &ws, "/private/x");
+        kiosk
+            .write_message(Message::Text(
+                "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/private/x\"}".into(),
+            ))
+            .unwrap();
+        assert_eq!(
+            read_text(&mut kiosk),
+            serde_json::json!({
+                "COMMAND": "ERROR",
+                "DATA": {"PATH": "/private/x", "MESSAGE": "LISTEN not allowed"}
+            })
+        );
+
+        //receive, the LISTEN for hidden is allowed but relays to it are not
+        kiosk
+            .write_message(Message::Text(
+                "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/public/hidden\"}".into(),
+            ))
+            .unwrap();
+        listen(&mut kiosk, &ws, "/public/x");
+        let last = OscMessage {
+            addr: "/public/x".into(),
+            args: vec![OscType::Int(2)],
+        };
+        ws.send(msg("/private/x"));
+        ws.send(msg("/public/hidden"));
+        ws.send(last.clone());
+        loop {
+            let m = read_osc(&mut kiosk);
+            assert_eq!(m.addr, "/public/x");
+            if m == last {
+                break;
+            }
+        }
+        assert_eq!(read_osc(&mut operator).addr, "/private/x");
+
+        //write
+        let write = |socket: &mut WebSocket<TcpStream>, addr: &str, v: i32| {
+            let packet = OscPacket::Bundle(crate::osc::OscBundle {
+                timetag: (0, 1),
+                content: vec![OscPacket::Message(OscMessage {
+                    addr: addr.into(),
+                    args: vec![OscType::Int(v)],
+                })],
+            });
+            socket
+                .write_message(Message::Binary(
+                    crate::osc::encoder::encode(&packet).unwrap(),
+                ))
+                .unwrap();
+        };
+        write(&mut kiosk, "/private/x", 1);
+        write(&mut kiosk, "/public/x", 2);
+        write(&mut operator, "/private/x", 3);
+        crate::service::http::tests::wait_for(|| {
+            values[1].load(::atomic::Ordering::SeqCst) == 2
+                && values[0].load(::atomic::Ordering::SeqCst) == 3
+        });
+        assert_eq!(root.stats().ws_writes_denied(), 1);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls() {
+        use crate::node::Get;
+        use crate::param::ParamGet;
+        use crate::value::ValueBuilder;
+        use ::atomic::Atomic;
+        use tokio_rustls::rustls::{
+            Certificate, ClientConfig, ClientSession, NoClientAuth, PrivateKey, ServerConfig,
+            StreamOwned,
+        };
+        use tokio_rustls::webpki::DNSNameRef;
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let der = Certificate(cert.serialize_der().unwrap());
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(
+                vec![der.clone()],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        let root = crate::root::Root::new(None);
+        let ws = root
+            .spawn_ws_tls("127.0.0.1:0", Arc::new(config).into())
+            .expect("failed to spawn ws");
+
+        let mut config = ClientConfig::new();
+        config.root_store.add(&der).unwrap();
+        let session = ClientSession::new(
+            &Arc::new(config),
+            DNSNameRef::try_from_ascii_str("localhost").unwrap(),
+        );
+        let stream = TcpStream::connect(ws.local_addr()).expect("failed to connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("failed to set timeout");
+        let (mut socket, _) = tungstenite::client(
+            format!("wss://localhost:{}/", ws.local_addr().port()),
+            StreamOwned::new(session, stream),
+        )
+        .expect("failed to handshake");
+        crate::service::http::tests::wait_for(|| ws.client_count() == 1);
+
+        root.add_node(
+            Get::new(
+                "a",
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(3)) as _).build(),
+                )],
+            )
+            .unwrap(),
+            None,
+        )
+        .unwrap();
+        let mut read = || loop {
+            match socket.read_message().expect("failed to read") {
+                Message::Ping(..) | Message::Pong(..) => (),
+                m => return m,
+            }
+        };
+        match read() {
+            Message::Text(v) => {
+                let v: serde_json::Value = serde_json::from_str(&v).unwrap();
+                assert_eq!(v["COMMAND"], "PATH_ADDED");
+                assert_eq!(v["DATA"], "/a");
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        socket
+            .write_message(Message::Text(
+                "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/a\"}".into(),
+            ))
+            .unwrap();
+        match socket.read_message().expect("failed to read") {
+            Message::Binary(v) => assert_matches!(
+                crate::osc::decoder::decode(&v),
+                Ok(OscPacket::Message(m)) if m.addr == "/a" && m.args == vec![OscType::Int(3)]
+            ),
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn listen_current() {
+        use crate::node::{Container, Get, Set};
+        use crate::param::{ParamGet, ParamSet};
+        use crate::value::ValueBuilder;
+        use ::atomic::Atomic;
+
+        let root = crate::root::Root::new(None);
+        let get = |name: &str, v: i32| {
+            Get::new(
+                name,
+                None,
+                vec![ParamGet::Int(
+                    ValueBuilder::new(Arc::new(Atomic::new(v)) as _).build(),
+                )],
+            )
+            .unwrap()
+        };
+        root.add_node(get("solo", 7), None).unwrap();
+        let mix = root
+            .add_node(Container::new("mix", None).unwrap(), None)
+            .unwrap();
+        for (i, name) in ["c", "a", "b"].iter().enumerate() {
+            root.add_node(get(name, i as i32), Some(mix)).unwrap();
+        }
+        let set = Set::new(
+            "s",
+            None,
+            vec![ParamSet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(0i32)) as _).build(),
+            )],
+            None,
+        );
+        root.add_node(set.unwrap(), Some(mix)).unwrap();
+        let ws = root.spawn_ws("127.0.0.1:0").expect("failed to spawn ws");
+
+        let write = |socket: &mut WebSocket<TcpStream>, cmd: &str| {
+            socket.write_message(Message::Text(cmd.into())).unwrap();
+        };
+        //a marker relayed after the catch up shows nothing else was sent
+        let marker = |socket: &mut WebSocket<TcpStream>| {
+            ws.send(OscMessage {
+                addr: "/solo".into(),
+                args: vec![OscType::Int(99)],
+            });
+            let m = read_osc(socket);
+            assert_eq!((m.addr.as_str(), m.args), ("/solo", vec![OscType::Int(99)]));
+        };
+
+        //the first frame after a plain LISTEN is the current value
+        let mut socket = connect(ws.local_addr());
+        write(&mut socket, "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/solo\"}");
+        let m = read_osc(&mut socket);
+        assert_eq!((m.addr.as_str(), m.args), ("/solo", vec![OscType::Int(7)]));
+        write(&mut socket, "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/nope\"}");
+        write(&mut socket, "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/mix\"}");
+        let mut current = Vec::new();
+        for _ in 0..3 {
+            let m = read_osc(&mut socket);
+            current.push((m.addr, m.args));
+        }
+        assert_eq!(
+            current,
+            vec![
+                ("/mix/a".to_string(), vec![OscType::Int(1)]),
+                ("/mix/b".to_string(), vec![OscType::Int(2)]),
+                ("/mix/c".to_string(), vec![OscType::Int(0)]),
+            ]
+        );
+        marker(&mut socket);
+
+        //opting out sends nothing until a trigger, commands are handled in order
+        let mut socket = connect(ws.local_addr());
+        write(
+            &mut socket,
+            "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/solo\",\"CURRENT\":false}",
+        );
+        write(&mut socket, "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/mix/s\"}");
+        write(&mut socket, "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/mix/a\"}");
+        assert_eq!(read_osc(&mut socket).addr, "/mix/a");
+        marker(&mut socket);
+
+        //disabled for the service, clients can still ask, bounded
+        ws.set_listen_current(false);
+        ws.set_listen_current_max(2);
+        let mut socket = connect(ws.local_addr());
+        write(&mut socket, "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/solo\"}");
+        write(
+            &mut socket,
+            "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/mix\",\"CURRENT\":true}",
+        );
+        assert_eq!(read_osc(&mut socket).addr, "/mix/a");
+        assert_eq!(read_osc(&mut socket).addr, "/mix/b");
+        marker(&mut socket);
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn normalized_listen() {
+        use crate::node::{Container, Get};
+        use crate::param::ParamGet;
+        use crate::value::ValueBuilder;
+        use ::atomic::Atomic;
+
+        #[derive(Debug, PartialEq)]
+        enum Outcome {
+            Relayed,
+            Error,
+            Nothing,
+        }
+        use Outcome::*;
+
+        let root = crate::root::Root::new(None);
+        let foo = root
+            .add_node(Container::new("foo", None).unwrap(), None)
+            .unwrap();
+        let bar = Get::new(
+            "bar",
+            None,
+            vec![ParamGet::Int(
+                ValueBuilder::new(Arc::new(Atomic::new(1i32)) as _).build(),
+            )],
+        );
+        root.add_node(bar.unwrap(), Some(foo)).unwrap();
+        let ws = root.spawn_ws("127.0.0.1:0").expect("failed to spawn ws");
+
+        //LISTEN to the path and a marker, relay both until the marker shows up, twice so the
+        //second round sees both subscriptions
+        let outcome = |path: &str| {
+            let mut socket = connect(ws.local_addr());
+            socket
+                .get_ref()
+                .set_read_timeout(Some(Duration::from_millis(50)))
+                .unwrap();
+            for p in &[path, "/marker"] {
+                let cmd = serde_json::json!({"COMMAND": "LISTEN", "DATA": p, "CURRENT": false});
+                socket
+                    .write_message(Message::Text(cmd.to_string()))
+                    .unwrap();
+            }
+            let mut outcome = Nothing;
+            for round in 0..2 {
+                let mut marked = false;
+                while !marked {
+                    ws.send(msg("/foo/bar"));
+                    ws.send(msg("/marker"));
+                    while let Ok(m) = socket.read_message() {
+                        match m {
+                            Message::Text(t) => {
+                                let t: serde_json::Value = serde_json::from_str(&t).unwrap();
+                                assert_eq!(t["COMMAND"], "ERROR");
+                                assert_eq!(t["DATA"]["PATH"], path);
+                                outcome = Error;
+                            }
+                            Message::Binary(v) => match crate::osc::decoder::decode(&v) {
+                                Ok(OscPacket::Message(m)) if m.addr == "/marker" => {
+                                    marked = true;
+                                    break;
+                                }
+                                Ok(OscPacket::Message(m)) if round == 1 => {
+                                    assert_eq!(m.addr, "/foo/bar");
+                                    outcome = Relayed;
+                                }
+                                _ => (),
+                            },
+                            _ => (),
+                        }
+                    }
+                }
+            }
+            outcome
+        };
+
+        for (path, lenient, strict) in &[
+            ("/foo/bar", Relayed, Relayed),
+            ("foo/bar", Relayed, Relayed),
+            ("/foo/bar/", Relayed, Relayed),
+            ("//foo///bar", Relayed, Relayed),
+            ("/foo/%62ar", Relayed, Relayed),
+            ("foo%2Fbar", Relayed, Relayed),
+            ("/foo/bar%", Nothing, Error),
+            ("/foo/b%zzar", Nothing, Error),
+            ("/foo/b ar", Nothing, Error),
+            ("/foo/bar ", Nothing, Error),
+            ("", Error, Error),
+            ("/foo/bar\0", Error, Error),
+            ("/foo/bar%00", Error, Error),
+            ("/foo/%ff", Error, Error),
+            ("/foo", Nothing, Nothing),
+        ] {
+            for (strict_paths, expected) in &[(false, lenient), (true, strict)] {
+                ws.set_strict_paths(*strict_paths);
+                assert_eq!(
+                    &outcome(path),
+                    *expected,
+                    "{:?} strict: {}",
+                    path,
+                    strict_paths
+                );
+            }
+        }
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn latency_probes() {
+        use crate::time::ManualClock;
+
+        let clock = Arc::new(ManualClock::new());
+        let root = crate::root::Root::new(None);
+        root.set_clock(clock.clone());
+        let ws = root.spawn_ws("127.0.0.1:0").expect("failed to spawn ws");
+        let stats = root.stats();
+
+        let mut echoing = connect(ws.local_addr());
+        let addr = echoing.get_ref().local_addr().unwrap();
+        echoing
+            .write_message(Message::Text("{\"COMMAND\":\"LATENCY_PROBES\"}".into()))
+            .unwrap();
+        listen(&mut echoing, &ws, "/foo");
+        let mut other = connect(ws.local_addr());
+        listen(&mut other, &ws, "/foo");
+
+        ws.set_latency_probes(true);
+        assert!(root.capabilities().contains(&"latency-probes".to_string()));
+        for (i, ms) in [10, 30, 20].iter().enumerate() {
+            ws.send(msg("/foo"));
+            clock.advance(Duration::from_millis(*ms));
+            //skip any frames left over from listen
+            let probe = loop {
+                if let Message::Text(t) = echoing.read_message().unwrap() {
+                    break serde_json::from_str::<serde_json::Value>(&t).unwrap();
+                }
+            };
+            assert_eq!(probe["COMMAND"], "LATENCY_PROBE");
+            assert_eq!(probe["DATA"]["PATH"], "/foo");
+            echoing
+                .write_message(Message::Text(
+                    serde_json::json!({"COMMAND": "LATENCY_ECHO", "DATA": probe["DATA"]})
+                        .to_string(),
+                ))
+                .unwrap();
+            crate::service::http::tests::wait_for(|| {
+                stats.latency(&addr).map(|l| l.samples) == Some(i + 1)
+            });
+        }
+        let l = stats.latency(&addr).unwrap();
+        assert_eq!(l.p50, Duration::from_millis(20));
+        assert_eq!(l.max, Duration::from_millis(30));
+        assert_eq!(l.one_way(), Duration::from_millis(10));
+
+        //clients that didn't ask only get the OSC frames
+        for _ in 0..3 {
+            assert_eq!(read_osc(&mut other).addr, "/foo");
+        }
+
+        //echoed probes are forgotten, echoing again gives nothing
+        assert_eq!(ws.latency_probes_pending(), 0);
+        assert_eq!(ws.options.probes.echo(addr, "/foo", 0), None);
+
+        //probes nobody echoes expire
+        for _ in 0..2 {
+            ws.send(msg("/foo"));
+            assert_eq!(read_osc(&mut other).addr, "/foo");
+        }
+        assert_eq!(ws.latency_probes_pending(), 2);
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(ws.latency_probes_pending(), 0);
+
+        //the connection task finishes with the next relay
+        drop(echoing);
+        crate::service::http::tests::wait_for(|| {
+            ws.send(msg("/bar"));
+            stats.latency(&addr).is_none()
+        });
+        ws.set_latency_probes(false);
+        assert!(!root.capabilities().contains(&"latency-probes".to_string()));
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    #[test]
+    fn poisoned_listening() {
+        let listening = Arc::new(Mutex::new(HashSet::new()));
+        let l = listening.clone();
+        let _ = std::thread::spawn(move || {
+            let mut l = l.lock().unwrap();
+            l.insert("/foo".to_string());
+            panic!("poison");
+        })
+        .join();
+        assert!(listening.is_poisoned());
+        assert!(lock_listening(&listening).contains("/foo"));
+        migrate_listening(&mut lock_listening(&listening), "/foo", "/bar");
+        assert!(lock_listening(&listening).contains("/bar"));
+    }
+
+    #[test]
+    fn panic() {
+        struct Boom;
+        impl WsPolicy for Boom {
+            fn allow_listen(&self, _client: &ClientInfo, path: &str) -> bool {
+                if path == "/boom" {
+                    panic!("boom");
+                }
+                true
+            }
+        }
+
+        let root = crate::root::Root::new(None);
+        let ws = root.spawn_ws("127.0.0.1:0").expect("failed to spawn ws");
+        ws.set_policy(Box::new(Boom));
+        let mut socket = connect(ws.local_addr());
+        listen(&mut socket, &ws, "/foo");
+        let mut other = connect(ws.local_addr());
+        listen(&mut other, &ws, "/foo");
+
+        //only the connection that panicked is closed, with the next command
+        socket
+            .write_message(Message::Text(
+                "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/boom\"}".into(),
+            ))
+            .unwrap();
+        crate::service::http::tests::wait_for(|| root.stats().task_panics() == 1);
+        crate::service::http::tests::wait_for(|| {
+            ws.send(msg("/foo"));
+            ws.client_count() == 1
+        });
+        assert_eq!(read_osc(&mut other).addr, "/foo");
+    }
+
+    #[test]
+    fn no_clients() {
+        let root = crate::root::Root::new(None);
+        let ws = root.spawn_ws("127.0.0.1:0").expect("failed to spawn ws");
+        //every queued relay leaves a pending probe behind
+        ws.set_latency_probes(true);
+        assert_eq!(ws.client_count(), 0);
+        ws.send(msg("/foo"));
+        assert_eq!(ws.latency_probes_pending(), 0);
+
+        let mut socket = connect(ws.local_addr());
+        crate::service::http::tests::wait_for(|| ws.client_count() == 1);
+        listen(&mut socket, &ws, "/foo");
+        assert!(ws.latency_probes_pending() > 0);
+
+        //the connection finishes with the next command after the client is gone
+        drop(socket);
+        crate::service::http::tests::wait_for(|| {
+            ws.send(msg("/foo"));
+            ws.client_count() == 0
+        });
+        assert_eq!(root.stats().task_panics(), 0);
+    }
+
+    type MemoryClient = tokio_tungstenite::WebSocketStream<tokio::io::DuplexStream>;
+
+    async fn connect_in_memory(connector: &Connector) -> MemoryClient {
+        let (client, _) = tokio_tungstenite::client_async("ws://localhost/", connector.connect())
+            .await
+            .expect("failed to handshake");
+        client
+    }
+
+    //the next message, failing if none arrives within a virtual second
+    async fn next_in_memory(client: &mut MemoryClient) -> Message {
+        let next = tokio::time::timeout(Duration::from_secs(1), client.next());
+        match next.await {
+            Ok(Some(Ok(m))) => m,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn in_memory_listen() {
+        crate::service::memory::tests::run(async {
+            let root = crate::root::Root::new(None);
+            root.add_node(
+                crate::node::Get::new(
+                    "foo",
+                    None,
+                    vec![crate::param::ParamGet::Int(
+                        crate::value::ValueBuilder::new(Arc::new(::atomic::Atomic::new(1)) as _)
+                            .build(),
+                    )],
+                )
+                .unwrap(),
+                None,
+            )
+            .unwrap();
+            let (ws, connector, serve) = root.spawn_ws_in_memory().unwrap();
+            let serve = tokio::spawn(serve);
+            let mut client = connect_in_memory(&connector).await;
+            assert_eq!(ws.client_count(), 1);
+
+            client
+                .send(Message::Text(
+                    "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/foo\"}".into(),
+                ))
+                .await
+                .unwrap();
+            //the current value, once it arrives the subscription is in place
+            let osc = |m: Message| match m {
+                Message::Binary(v) => match crate::osc::decoder::decode(&v) {
+                    Ok(OscPacket::Message(m)) => m,
+                    other => panic!("unexpected packet {:?}", other),
+                },
+                other => panic!("unexpected message {:?}", other),
+            };
+            assert_eq!(osc(next_in_memory(&mut client).await), msg("/foo"));
+            for i in 0..10 {
+                ws.send(OscMessage {
+                    addr: "/foo".into(),
+                    args: vec![OscType::Int(i)],
+                });
+            }
+            for i in 0..10 {
+                let m = osc(next_in_memory(&mut client).await);
+                assert_eq!(m.args, vec![OscType::Int(i)]);
+            }
+            ws.send(msg("/bar"));
+            assert!(
+                tokio::time::timeout(Duration::from_secs(1), client.next())
+                    .await
+                    .is_err(),
+                "relayed a path that isn't listened to"
+            );
+
+            //the service stops serving once dropped
+            drop(ws);
+            serve.await.unwrap();
+            assert_eq!(root.stats().task_panics(), 0);
+        });
+    }
+
+    #[test]
+    fn listeners() {
+        crate::service::memory::tests::run(async {
+            let root = crate::root::Root::new(None);
+            for name in &["foo", "bar"] {
+                root.add_node(
+                    crate::node::Get::new(
+                        *name,
+                        None,
+                        vec![
+                            crate::param::ParamGet::Int(
+                                crate::value::ValueBuilder::new(
+                                    Arc::new(::atomic::Atomic::new(1)) as _
+                                )
+                                .build(),
+                            ),
+                        ],
+                    )
+                    .unwrap(),
+                    None,
+                )
+                .unwrap();
+            }
+            let (ws, connector, serve) = root.spawn_ws_in_memory().unwrap();
+            tokio::spawn(serve);
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let hevents = events.clone();
+            ws.on_listen(move |path, added| {
+                hevents.lock().unwrap().push((path.to_string(), added));
+            });
+            let command = |command: &str, path: &str| {
+                Message::Text(format!(
+                    "{{\"COMMAND\":\"{}\",\"DATA\":\"{}\"}}",
+                    command, path
+                ))
+            };
+
+            let mut first = connect_in_memory(&connector).await;
+            let mut second = connect_in_memory(&connector).await;
+            //the current value arrives once the subscription is in place
+            for (client, path) in &mut [(&mut first, "/foo"), (&mut second, "/foo")] {
+                client.send(command("LISTEN", path)).await.unwrap();
+                next_in_memory(client).await;
+            }
+            first.send(command("LISTEN", "/bar")).await.unwrap();
+            next_in_memory(&mut first).await;
+            //listening again doesn't count twice
+            first.send(command("LISTEN", "/foo")).await.unwrap();
+            next_in_memory(&mut first).await;
+            assert_eq!(ws.listeners("/foo"), 2);
+            assert_eq!(ws.listeners("/foo/"), 2);
+            assert_eq!(ws.listeners("/bar"), 1);
+            assert_eq!(ws.listeners("/baz"), 0);
+            assert_eq!(ws.listened_paths(), vec!["/bar", "/foo"]);
+
+            second.send(command("IGNORE", "/foo")).await.unwrap();
+            //ignoring what isn't listened to does nothing
+            second.send(command("IGNORE", "/bar")).await.unwrap();
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            assert_eq!(ws.listeners("/foo"), 1);
+            assert_eq!(ws.listeners("/bar"), 1);
+
+            //disconnecting drops the client's subscriptions
+            drop(first);
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            assert_eq!(ws.listeners("/foo"), 0);
+            assert!(ws.listened_paths().is_empty());
+
+            let mut events = events.lock().unwrap().clone();
+            //the order of the disconnected client's paths isn't defined
+            events[4..].sort();
+            assert_eq!(
+                events,
+                vec![
+                    ("/foo".to_string(), true),
+                    ("/foo".to_string(), true),
+                    ("/bar".to_string(), true),
+                    ("/foo".to_string(), false),
+                    ("/bar".to_string(), false),
+                    ("/foo".to_string(), false),
+                ]
+            );
+            assert_eq!(root.stats().task_panics(), 0);
+        });
+    }
+
+    #[test]
+    fn in_memory_namespace_change() {
+        crate::service::memory::tests::run(async {
+            let root = crate::root::Root::new(None);
+            let (_ws, connector, serve) = root.spawn_ws_in_memory().unwrap();
+            tokio::spawn(serve);
+            let mut clients = [
+                connect_in_memory(&connector).await,
+                connect_in_memory(&connector).await,
+            ];
+
+            let handle = root
+                .add_node(crate::node::Container::new("foo", None).unwrap(), None)
+                .unwrap();
+            root.rm_node(handle).unwrap();
+            for client in clients.iter_mut() {
+                for (command, path) in &[("PATH_ADDED", "/foo"), ("PATH_REMOVED", "/foo")] {
+                    match next_in_memory(client).await {
+                        Message::Text(v) => assert_eq!(
+                            serde_json::from_str::<serde_json::Value>(&v).unwrap(),
+                            serde_json::json!({"COMMAND": command, "DATA": path})
+                        ),
+                        other => panic!("unexpected message {:?}", other),
+                    }
+                }
+            }
+            assert_eq!(root.stats().task_panics(), 0);
+        });
+    }
+}
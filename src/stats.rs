@@ -0,0 +1,485 @@
+//! Runtime statistics shared by the services of a `Root`.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const ORDERING: Ordering = Ordering::Relaxed;
+const CALLBACK_FAILURES_LEN: usize = 16;
+const LATENCY_WINDOW: usize = 128;
+
+//the panics caught at task boundaries by the services of every root in the process
+#[cfg(debug_assertions)]
+static CAUGHT_PANICS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of panics caught at task boundaries by the services of every root in this process.
+///
+/// Only counted in debug builds, it is a canary for tests, the integration tests expect it to
+/// stay zero. `Stats::task_panics` is the metric of a single root.
+#[cfg(debug_assertions)]
+pub fn caught_panics() -> u64 {
+    CAUGHT_PANICS.load(ORDERING)
+}
+
+/// Counters and gauges updated by the OSC, websocket and http services.
+///
+/// Get it from `Root::stats` and read it at any time, it never blocks the services.
+#[derive(Debug, Default)]
+pub struct Stats {
+    osc_packets_received: AtomicU64,
+    osc_packets_sent: AtomicU64,
+    osc_decode_errors: AtomicU64,
+    osc_unsupported_args: AtomicU64,
+    osc_non_finite_rejected: AtomicU64,
+    osc_args_rejected: AtomicU64,
+    osc_queue_depth: AtomicUsize,
+    osc_sends_dropped: AtomicU64,
+    ws_clients: AtomicUsize,
+    ws_frames_relayed: AtomicU64,
+    ws_queue_depth: AtomicUsize,
+    ws_writes_denied: AtomicU64,
+    http_requests: Mutex<BTreeMap<u16, u64>>,
+    slow_handlers: AtomicU64,
+    handler_timing: Mutex<HashMap<String, HandlerTiming>>,
+    callback_failures: AtomicU64,
+    task_panics: AtomicU64,
+    recent_callback_failures: Mutex<VecDeque<CallbackFailure>>,
+    latency: Mutex<HashMap<SocketAddr, VecDeque<Duration>>>,
+}
+
+/// Round trip latency of a websocket client, over its most recent probes.
+///
+/// See `WSService::set_latency_probes`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// The number of round trips summarized.
+    pub samples: usize,
+    /// The median round trip.
+    pub p50: Duration,
+    /// The 90th percentile round trip.
+    pub p90: Duration,
+    /// The 99th percentile round trip.
+    pub p99: Duration,
+    /// The longest round trip.
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    /// The estimated one way latency, half of the median round trip.
+    pub fn one_way(&self) -> Duration {
+        self.p50 / 2
+    }
+}
+
+/// An `OscWriteCallback` that returned an error or panicked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallbackFailure {
+    /// The address of the message whose handler returned the callback.
+    pub path: String,
+    /// The returned error or panic message.
+    pub message: String,
+}
+
+/// Execution time of the OSC update handling for a single node.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandlerTiming {
+    /// The number of invocations.
+    pub count: u64,
+    /// The total time spent in all invocations.
+    pub total: Duration,
+    /// The longest single invocation.
+    pub max: Duration,
+}
+
+impl HandlerTiming {
+    /// The mean invocation time.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::default()
+        } else {
+            Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+        }
+    }
+}
+
+impl Stats {
+    /// Number of OSC packets successfully decoded from the OSC socket(s).
+    pub fn osc_packets_received(&self) -> u64 {
+        self.osc_packets_received.load(ORDERING)
+    }
+
+    /// Number of OSC packets written to the OSC socket(s).
+    pub fn osc_packets_sent(&self) -> u64 {
+        self.osc_packets_sent.load(ORDERING)
+    }
+
+    /// Number of packets received on the OSC socket(s) that failed to decode.
+    pub fn osc_decode_errors(&self) -> u64 {
+        self.osc_decode_errors.load(ORDERING)
+    }
+
+    /// Number of received OSC arguments skipped because no parameter can hold their type.
+    pub fn osc_unsupported_args(&self) -> u64 {
+        self.osc_unsupported_args.load(ORDERING)
+    }
+
+    /// Number of received OSC messages dropped because of a NaN or infinite argument, see
+    /// `Root::set_non_finite_policy`.
+    pub fn osc_non_finite_rejected(&self) -> u64 {
+        self.osc_non_finite_rejected.load(ORDERING)
+    }
+
+    /// Number of received OSC messages dropped because their arguments don't match the node's
+    /// parameters, see `ArgMismatch::Reject`.
+    pub fn osc_args_rejected(&self) -> u64 {
+        self.osc_args_rejected.load(ORDERING)
+    }
+
+    /// Number of outgoing OSC packets queued but not yet written.
+    pub fn osc_queue_depth(&self) -> usize {
+        self.osc_queue_depth.load(ORDERING)
+    }
+
+    /// Number of outgoing OSC sends dropped because the send queue was full.
+    pub fn osc_sends_dropped(&self) -> u64 {
+        self.osc_sends_dropped.load(ORDERING)
+    }
+
+    /// Number of currently connected websocket clients.
+    pub fn ws_clients(&self) -> usize {
+        self.ws_clients.load(ORDERING)
+    }
+
+    /// Number of OSC messages relayed to websocket clients.
+    pub fn ws_frames_relayed(&self) -> u64 {
+        self.ws_frames_relayed.load(ORDERING)
+    }
+
+    /// Number of websocket service commands queued but not yet processed.
+    pub fn ws_queue_depth(&self) -> usize {
+        self.ws_queue_depth.load(ORDERING)
+    }
+
+    /// Number of OSC messages from websocket clients dropped by the `WsPolicy`.
+    pub fn ws_writes_denied(&self) -> u64 {
+        self.ws_writes_denied.load(ORDERING)
+    }
+
+    /// Number of http requests served, by response status code.
+    pub fn http_requests(&self) -> BTreeMap<u16, u64> {
+        self.http_requests
+            .lock()
+            .map(|m| m.clone())
+            .unwrap_or_default()
+    }
+
+    /// Number of OSC update handler invocations that exceeded the slow handler threshold.
+    pub fn slow_handlers(&self) -> u64 {
+        self.slow_handlers.load(ORDERING)
+    }
+
+    /// OSC update handler timing for the node at the given path, if it has received any updates.
+    pub fn handler_timing(&self, path: &str) -> Option<HandlerTiming> {
+        self.handler_timing
+            .lock()
+            .ok()
+            .and_then(|m| m.get(path).copied())
+    }
+
+    /// Number of `OscWriteCallback`s that returned an error or panicked.
+    pub fn callback_failures(&self) -> u64 {
+        self.callback_failures.load(ORDERING)
+    }
+
+    /// Number of panics caught while the http service served a request or a websocket connection
+    /// ran, the request got a 500 or the connection was closed. Expected to stay zero.
+    pub fn task_panics(&self) -> u64 {
+        self.task_panics.load(ORDERING)
+    }
+
+    /// The most recent `OscWriteCallback` failures, oldest first.
+    pub fn recent_callback_failures(&self) -> Vec<CallbackFailure> {
+        self.recent_callback_failures
+            .lock()
+            .map(|f| f.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The round trip latency of the websocket client at the address, if it has echoed any
+    /// probes. Clients are forgotten when they disconnect.
+    pub fn latency(&self, client: &SocketAddr) -> Option<LatencySummary> {
+        let mut samples: Vec<Duration> = self
+            .latency
+            .lock()
+            .ok()
+            .and_then(|l| l.get(client).map(|s| s.iter().copied().collect()))?;
+        samples.sort();
+        let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+        Some(LatencySummary {
+            samples: samples.len(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: percentile(100),
+        })
+    }
+
+    pub(crate) fn record_latency(&self, client: SocketAddr, rtt: Duration) {
+        if let Ok(mut l) = self.latency.lock() {
+            let samples = l.entry(client).or_default();
+            if samples.len() == LATENCY_WINDOW {
+                samples.pop_front();
+            }
+            samples.push_back(rtt);
+        }
+    }
+
+    pub(crate) fn forget_latency(&self, client: &SocketAddr) {
+        if let Ok(mut l) = self.latency.lock() {
+            l.remove(client);
+        }
+    }
+
+    pub(crate) fn record_callback_failure(&self, path: String, message: String) {
+        self.callback_failures.fetch_add(1, ORDERING);
+        if let Ok(mut f) = self.recent_callback_failures.lock() {
+            if f.len() == CALLBACK_FAILURES_LEN {
+                f.pop_front();
+            }
+            f.push_back(CallbackFailure { path, message });
+        }
+    }
+
+    pub(crate) fn inc_task_panics(&self) {
+        self.task_panics.fetch_add(1, ORDERING);
+        #[cfg(debug_assertions)]
+        CAUGHT_PANICS.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn inc_slow_handlers(&self) {
+        self.slow_handlers.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn record_handler_time(&self, path: &str, elapsed: Duration) {
+        if let Ok(mut m) = self.handler_timing.lock() {
+            let t = match m.get_mut(path) {
+                Some(t) => t,
+                None => m.entry(path.to_string()).or_default(),
+            };
+            t.count += 1;
+            t.total += elapsed;
+            t.max = std::cmp::max(t.max, elapsed);
+        }
+    }
+
+    pub(crate) fn inc_osc_packets_received(&self) {
+        self.osc_packets_received.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn inc_osc_packets_sent(&self) {
+        self.osc_packets_sent.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn inc_osc_decode_errors(&self) {
+        self.osc_decode_errors.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn add_osc_unsupported_args(&self, count: usize) {
+        self.osc_unsupported_args.fetch_add(count as u64, ORDERING);
+    }
+
+    pub(crate) fn inc_osc_non_finite_rejected(&self) {
+        self.osc_non_finite_rejected.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn inc_osc_args_rejected(&self) {
+        self.osc_args_rejected.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn inc_osc_queue_depth(&self) {
+        self.osc_queue_depth.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn dec_osc_queue_depth(&self) {
+        self.osc_queue_depth.fetch_sub(1, ORDERING);
+    }
+
+    pub(crate) fn inc_osc_sends_dropped(&self) {
+        self.osc_sends_dropped.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn inc_ws_clients(&self) {
+        self.ws_clients.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn dec_ws_clients(&self) {
+        self.ws_clients.fetch_sub(1, ORDERING);
+    }
+
+    pub(crate) fn inc_ws_frames_relayed(&self) {
+        self.ws_frames_relayed.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn inc_ws_queue_depth(&self) {
+        self.ws_queue_depth.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn dec_ws_queue_depth(&self) {
+        self.ws_queue_depth.fetch_sub(1, ORDERING);
+    }
+
+    pub(crate) fn inc_ws_writes_denied(&self) {
+        self.ws_writes_denied.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn inc_http_requests(&self, status: u16) {
+        if let Ok(mut m) = self.http_requests.lock() {
+            *m.entry(status).or_insert(0) += 1;
+        }
+    }
+
+    /// Render the statistics in the Prometheus text exposition format.
+    ///
+    /// The metric names are stable:
+    ///
+    /// * `oscquery_osc_packets_received_total` counter
+    /// * `oscquery_osc_packets_sent_total` counter
+    /// * `oscquery_osc_decode_errors_total` counter
+    /// * `oscquery_osc_unsupported_args_total` counter
+    /// * `oscquery_osc_non_finite_rejected_total` counter
+    /// * `oscquery_osc_args_rejected_total` counter
+    /// * `oscquery_osc_sends_dropped_total` counter
+    /// * `oscquery_ws_clients` gauge
+    /// * `oscquery_ws_frames_relayed_total` counter
+    /// * `oscquery_ws_writes_denied_total` counter
+    /// * `oscquery_http_requests_total{status="..."}` counter
+    /// * `oscquery_slow_handlers_total` counter
+    /// * `oscquery_callback_failures_total` counter
+    /// * `oscquery_task_panics_total` counter
+    /// * `oscquery_nodes` gauge
+    /// * `oscquery_queue_depth{queue="osc"|"ws"}` gauge
+    pub fn prometheus_text(&self, node_count: usize) -> String {
+        let mut s = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(s, "# HELP {} {}", name, help);
+            let _ = writeln!(s, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(s, "{}{} {}", name, labels, value);
+            }
+        };
+        let plain = |v: String| vec![(String::new(), v)];
+
+        metric(
+            "oscquery_osc_packets_received_total",
+            "counter",
+            "OSC packets decoded from the OSC socket.",
+            &plain(self.osc_packets_received().to_string()),
+        );
+        metric(
+            "oscquery_osc_packets_sent_total",
+            "counter",
+            "OSC packets written to the OSC socket.",
+            &plain(self.osc_packets_sent().to_string()),
+        );
+        metric(
+            "oscquery_osc_decode_errors_total",
+            "counter",
+            "Packets received on the OSC socket that failed to decode.",
+            &plain(self.osc_decode_errors().to_string()),
+        );
+        metric(
+            "oscquery_osc_unsupported_args_total",
+            "counter",
+            "Received OSC arguments skipped because of an unsupported type.",
+            &plain(self.osc_unsupported_args().to_string()),
+        );
+        metric(
+            "oscquery_osc_non_finite_rejected_total",
+            "counter",
+            "Received OSC messages dropped because of a NaN or infinite argument.",
+            &plain(self.osc_non_finite_rejected().to_string()),
+        );
+        metric(
+            "oscquery_osc_args_rejected_total",
+            "counter",
+            "Received OSC messages dropped because their arguments don't match the parameters.",
+            &plain(self.osc_args_rejected().to_string()),
+        );
+        metric(
+            "oscquery_osc_sends_dropped_total",
+            "counter",
+            "Outgoing OSC sends dropped because the send queue was full.",
+            &plain(self.osc_sends_dropped().to_string()),
+        );
+        metric(
+            "oscquery_ws_clients",
+            "gauge",
+            "Connected websocket clients.",
+            &plain(self.ws_clients().to_string()),
+        );
+        metric(
+            "oscquery_ws_frames_relayed_total",
+            "counter",
+            "OSC messages relayed to websocket clients.",
+            &plain(self.ws_frames_relayed().to_string()),
+        );
+        metric(
+            "oscquery_ws_writes_denied_total",
+            "counter",
+            "OSC messages from websocket clients dropped by the policy.",
+            &plain(self.ws_writes_denied().to_string()),
+        );
+        metric(
+            "oscquery_http_requests_total",
+            "counter",
+            "HTTP requests served, by response status.",
+            &self
+                .http_requests()
+                .iter()
+                .map(|(status, count)| (format!("{{status=\"{}\"}}", status), count.to_string()))
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "oscquery_slow_handlers_total",
+            "counter",
+            "OSC update handler invocations slower than the configured threshold.",
+            &plain(self.slow_handlers().to_string()),
+        );
+        metric(
+            "oscquery_callback_failures_total",
+            "counter",
+            "OSC write callbacks that returned an error or panicked.",
+            &plain(self.callback_failures().to_string()),
+        );
+        metric(
+            "oscquery_task_panics_total",
+            "counter",
+            "Panics caught while serving http requests or websocket connections.",
+            &plain(self.task_panics().to_string()),
+        );
+        metric(
+            "oscquery_nodes",
+            "gauge",
+            "Nodes in the namespace, including the root.",
+            &plain(node_count.to_string()),
+        );
+        metric(
+            "oscquery_queue_depth",
+            "gauge",
+            "Items waiting in the internal service queues.",
+            &[
+                (
+                    "{queue=\"osc\"}".to_string(),
+                    self.osc_queue_depth().to_string(),
+                ),
+                (
+                    "{queue=\"ws\"}".to_string(),
+                    self.ws_queue_depth().to_string(),
+                ),
+            ],
+        );
+        s
+    }
+}
@@ -0,0 +1,242 @@
+//! Time sources.
+//!
+//! Durations, expiries and rate limits use the monotonic time of a `Clock`, so they aren't
+//! affected when the system clock is adjusted, by NTP for instance. Wall clock time is only used
+//! where OSC timetags or clients need it.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The largest delay a timetag is converted to by default, see `timetag_deadline`.
+pub const DEFAULT_MAX_HORIZON: Duration = Duration::from_secs(60);
+
+//seconds from the NTP epoch, 1900, to the unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// An OSC timetag, the seconds since the NTP epoch, 1900, and the fraction of a second in units
+/// of 2^-32 seconds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TimeTag(pub u32, pub u32);
+
+impl TimeTag {
+    /// The timetag that means "now" rather than a point in time.
+    pub const IMMEDIATE: TimeTag = TimeTag(0, 1);
+
+    /// Is this the immediate timetag.
+    pub fn is_immediate(&self) -> bool {
+        *self == Self::IMMEDIATE
+    }
+
+    /// Convert to wall clock time, see `timetag_to_system_time`.
+    pub fn to_system_time(&self) -> SystemTime {
+        timetag_to_system_time((*self).into())
+    }
+
+    /// The timetag of a wall clock time.
+    ///
+    /// Times before the NTP epoch give the epoch. The seconds wrap around in 2036, like they do
+    /// for NTP.
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let offset = NTP_UNIX_OFFSET as u128 * NANOS_PER_SEC;
+        let nanos = match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => offset + d.as_nanos(),
+            Err(e) => offset.saturating_sub(e.duration().as_nanos()),
+        };
+        Self(
+            (nanos / NANOS_PER_SEC) as u32,
+            (((nanos % NANOS_PER_SEC) << 32) / NANOS_PER_SEC) as u32,
+        )
+    }
+}
+
+impl From<(u32, u32)> for TimeTag {
+    fn from(timetag: (u32, u32)) -> Self {
+        Self(timetag.0, timetag.1)
+    }
+}
+
+impl From<TimeTag> for (u32, u32) {
+    fn from(timetag: TimeTag) -> Self {
+        (timetag.0, timetag.1)
+    }
+}
+
+/// A source of monotonic and wall clock time.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, never goes backwards.
+    fn now(&self) -> Instant;
+
+    /// Wall clock time, can jump when the system clock is adjusted.
+    fn wall(&self) -> SystemTime;
+}
+
+/// A shareable `Clock`.
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The system's clocks, the default.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A `Clock` that only moves when told to, for tests.
+#[derive(Debug)]
+pub struct ManualClock {
+    //(monotonic, wall)
+    times: Mutex<(Instant, SystemTime)>,
+}
+
+impl ManualClock {
+    /// Create a clock starting at the current system times.
+    pub fn new() -> Self {
+        Self {
+            times: Mutex::new((Instant::now(), SystemTime::now())),
+        }
+    }
+
+    /// Move both the monotonic and the wall clock time forward.
+    pub fn advance(&self, d: Duration) {
+        let mut t = self.times.lock().expect("failed to lock");
+        t.0 += d;
+        t.1 += d;
+    }
+
+    /// Set the wall clock time, leaving the monotonic time alone, like a system clock adjustment.
+    pub fn set_wall(&self, wall: SystemTime) {
+        self.times.lock().expect("failed to lock").1 = wall;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.times.lock().expect("failed to lock").0
+    }
+
+    fn wall(&self) -> SystemTime {
+        self.times.lock().expect("failed to lock").1
+    }
+}
+
+pub(crate) fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// Convert an OSC timetag to wall clock time.
+pub fn timetag_to_system_time(timetag: (u32, u32)) -> SystemTime {
+    let secs = timetag.0 as u64;
+    let nanos = ((timetag.1 as u64 * 1_000_000_000) >> 32) as u32;
+    if secs >= NTP_UNIX_OFFSET {
+        UNIX_EPOCH + Duration::new(secs - NTP_UNIX_OFFSET, nanos)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(NTP_UNIX_OFFSET - secs)
+            + Duration::from_nanos(nanos as u64)
+    }
+}
+
+/// Convert an OSC timetag to a monotonic deadline.
+///
+/// The immediate timetag and timetags in the past give the current time, timetags further in the
+/// future than `max_horizon` are clamped to it. The delay is computed from the wall clock once,
+/// so later wall clock jumps don't move the deadline.
+pub fn timetag_deadline<T: Into<TimeTag>>(
+    clock: &dyn Clock,
+    timetag: T,
+    max_horizon: Duration,
+) -> Instant {
+    let now = clock.now();
+    let timetag = timetag.into();
+    if timetag.is_immediate() {
+        return now;
+    }
+    match timetag.to_system_time().duration_since(clock.wall()) {
+        Ok(delay) => now + std::cmp::min(delay, max_horizon),
+        Err(..) => now,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timetag(t: SystemTime) -> TimeTag {
+        TimeTag::from_system_time(t)
+    }
+
+    #[test]
+    fn time_tag() {
+        let ntp_epoch = UNIX_EPOCH - Duration::from_secs(NTP_UNIX_OFFSET);
+        assert!(TimeTag::IMMEDIATE.is_immediate());
+        assert!(TimeTag::from((0, 1)).is_immediate());
+        assert!(!TimeTag(0, 0).is_immediate());
+        assert!(!TimeTag(1, 1).is_immediate());
+
+        //around the NTP epoch
+        assert_eq!(TimeTag::from_system_time(ntp_epoch), TimeTag(0, 0));
+        assert_eq!(TimeTag(0, 0).to_system_time(), ntp_epoch);
+        assert_eq!(
+            TimeTag::from_system_time(ntp_epoch - Duration::from_secs(1)),
+            TimeTag(0, 0)
+        );
+        let half = ntp_epoch + Duration::from_millis(1500);
+        assert_eq!(TimeTag::from_system_time(half), TimeTag(1, 1 << 31));
+        assert_eq!(TimeTag(1, 1 << 31).to_system_time(), half);
+
+        //and the unix epoch
+        assert_eq!(
+            TimeTag::from_system_time(UNIX_EPOCH),
+            TimeTag(NTP_UNIX_OFFSET as u32, 0)
+        );
+        assert_eq!(
+            TimeTag(NTP_UNIX_OFFSET as u32, 0).to_system_time(),
+            UNIX_EPOCH
+        );
+
+        //the fraction loses less than a nanosecond
+        let now = SystemTime::now();
+        let back = TimeTag::from_system_time(now).to_system_time();
+        assert!(back <= now && now.duration_since(back).unwrap() < Duration::from_nanos(2));
+        assert_eq!(<(u32, u32)>::from(TimeTag::from((3, 4))), (3, 4),);
+    }
+
+    #[test]
+    fn deadline() {
+        let clock = ManualClock::new();
+        let now = clock.now();
+        let wall = clock.wall();
+        let horizon = Duration::from_secs(10);
+
+        assert_eq!(now, timetag_deadline(&clock, (0, 1), horizon));
+        assert_eq!(
+            now,
+            timetag_deadline(&clock, timetag(wall - Duration::from_secs(5)), horizon)
+        );
+        let d = timetag_deadline(&clock, timetag(wall + Duration::from_secs(2)), horizon);
+        assert!(d > now + Duration::from_millis(1900) && d <= now + Duration::from_secs(2));
+        assert_eq!(
+            now + horizon,
+            timetag_deadline(&clock, timetag(wall + Duration::from_secs(3600)), horizon)
+        );
+        assert_eq!(now, timetag_deadline(&clock, (1, 0), horizon));
+        assert_eq!(now, timetag_deadline(&clock, TimeTag::IMMEDIATE, horizon));
+
+        //a backwards wall clock jump makes a near tag look far away, it is clamped
+        let tag = timetag(wall + Duration::from_secs(2));
+        clock.set_wall(wall - Duration::from_secs(3600));
+        assert_eq!(now + horizon, timetag_deadline(&clock, tag, horizon));
+        assert_eq!(now, clock.now());
+    }
+}
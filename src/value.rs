@@ -1,9 +1,17 @@
 //! Parameter values and their attributes.
-use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
-use std::{fmt, sync::Arc};
+use crate::error::OscQueryError;
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{fmt, marker::PhantomData, str::FromStr, sync::Arc};
 
 mod atomic;
 mod dummy;
+mod rwlock;
+
+pub use rwlock::{MutexValue, RwLockValue};
 
 /// Identify how values outside of the associated `Range` should be handled (clipped).
 ///
@@ -75,6 +83,115 @@ where
     }
 }
 
+//the keys of a serialized Range, anything else is ignored
+#[derive(Deserialize)]
+#[serde(field_identifier)]
+enum RangeKey {
+    #[serde(rename = "MIN")]
+    Min,
+    #[serde(rename = "MAX")]
+    Max,
+    #[serde(rename = "VALS")]
+    Vals,
+    #[serde(other)]
+    Other,
+}
+
+struct RangeVisitor<T>(PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for RangeVisitor<T>
+where
+    T: Deserialize<'de>,
+{
+    type Value = Range<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a map with MIN and/or MAX, or VALS")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut min = None;
+        let mut max = None;
+        let mut vals = None;
+        while let Some(key) = map.next_key()? {
+            match key {
+                RangeKey::Min if min.is_some() => return Err(de::Error::duplicate_field("MIN")),
+                RangeKey::Max if max.is_some() => return Err(de::Error::duplicate_field("MAX")),
+                RangeKey::Vals if vals.is_some() => return Err(de::Error::duplicate_field("VALS")),
+                RangeKey::Min => min = Some(map.next_value()?),
+                RangeKey::Max => max = Some(map.next_value()?),
+                RangeKey::Vals => vals = Some(map.next_value()?),
+                RangeKey::Other => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(match (vals, min, max) {
+            (Some(vals), _, _) => Range::Vals(vals),
+            (None, Some(min), Some(max)) => Range::MinMax(min, max),
+            (None, Some(min), None) => Range::Min(min),
+            (None, None, Some(max)) => Range::Max(max),
+            (None, None, None) => Range::None,
+        })
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Range<T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(RangeVisitor(PhantomData))
+    }
+}
+
+/// What to do with a NaN or infinite float or double received via OSC, see
+/// `Root::set_non_finite_policy`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum NonFinitePolicy {
+    /// Store the value as is, the default.
+    #[default]
+    PassThrough,
+    /// Drop the whole message, the update handler doesn't run.
+    RejectWrite,
+    /// Replace infinities with the matching bound of the parameter's range, or the largest
+    /// finite value of the type if the range has none. A NaN has no bound to go to, messages
+    /// with one are dropped like with `RejectWrite`.
+    ClampToRange,
+}
+
+impl fmt::Display for ClipMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Low => "low",
+            Self::High => "high",
+            Self::Both => "both",
+        })
+    }
+}
+
+/// Parse the lowercase name, as serialized, ignoring case.
+impl FromStr for ClipMode {
+    type Err = OscQueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "low" => Ok(Self::Low),
+            "high" => Ok(Self::High),
+            "both" => Ok(Self::Both),
+            _ => Err(OscQueryError::InvalidClipMode(s.to_string())),
+        }
+    }
+}
+
 impl Default for ClipMode {
     fn default() -> Self {
         ClipMode::None
@@ -112,13 +229,15 @@ where
     }
 }
 
-/// A value with clip_mode, range and optional unit.
+/// A value with clip_mode, range, optional unit, optional extended type and optional default.
 #[derive(Clone, Debug)]
 pub struct Value<V, T> {
     pub value: V,
     pub clip_mode: ClipMode,
     pub range: Range<T>,
     pub unit: Option<String>,
+    pub extended_type: Option<String>,
+    pub default: Option<T>,
 }
 
 /// Build a value.
@@ -133,6 +252,8 @@ impl<V, T> ValueBuilder<V, T> {
             clip_mode: Default::default(),
             range: Default::default(),
             unit: Default::default(),
+            extended_type: Default::default(),
+            default: Default::default(),
         };
         Self { value }
     }
@@ -155,6 +276,20 @@ impl<V, T> ValueBuilder<V, T> {
         self
     }
 
+    /// Set the value's optional extended type, for instance `"filepath"` or `"color.rgba8"`.
+    /// Defaults to `None`.
+    pub fn with_extended_type(mut self, extended_type: String) -> Self {
+        self.value.extended_type = Some(extended_type);
+        self
+    }
+
+    /// Set the value's optional default, what `Root::reset_to_defaults` sets it to. Defaults to
+    /// `None`.
+    pub fn with_default(mut self, default: T) -> Self {
+        self.value.default = Some(default);
+        self
+    }
+
     /// Build the value.
     pub fn build(self) -> Value<V, T> {
         self.value
@@ -181,6 +316,116 @@ impl<V, T> Value<V, T> {
     pub fn unit(&self) -> &Option<String> {
         &self.unit
     }
+
+    /// Get the extended type.
+    pub fn extended_type(&self) -> &Option<String> {
+        &self.extended_type
+    }
+
+    /// Get the default.
+    pub fn default(&self) -> &Option<T> {
+        &self.default
+    }
+}
+
+/// The distance between two values, to snap incoming values to the nearest of `Range::Vals`.
+///
+/// `None` when there is no meaningful distance, then only the listed values are accepted.
+pub trait Distance {
+    fn distance(&self, other: &Self) -> Option<f64>;
+}
+
+macro_rules! impl_distance {
+    ($t:ty, |$v:ident| $f:expr) => {
+        impl Distance for $t {
+            fn distance(&self, other: &Self) -> Option<f64> {
+                let f = |$v: &$t| -> f64 { $f };
+                Some((f(self) - f(other)).abs())
+            }
+        }
+    };
+}
+
+impl_distance!(i32, |v| *v as f64);
+impl_distance!(f32, |v| *v as f64);
+impl_distance!(i64, |v| *v as f64);
+impl_distance!(f64, |v| *v);
+impl_distance!(char, |v| *v as u32 as f64);
+impl_distance!((u32, u32), |v| ((v.0 as u64) << 32 | v.1 as u64) as f64);
+
+impl Distance for String {
+    fn distance(&self, _other: &Self) -> Option<f64> {
+        None
+    }
+}
+
+impl Distance for bool {
+    fn distance(&self, _other: &Self) -> Option<f64> {
+        None
+    }
+}
+
+impl<V, T> Value<V, T>
+where
+    T: PartialOrd + Clone + Distance,
+{
+    /// Apply the clip mode and range to an incoming value, `None` if it should be dropped.
+    ///
+    /// `ClipMode::Low` clamps at the minimum, `High` at the maximum and `Both` at both. With
+    /// `Range::Vals` any clip mode but `None` snaps to the nearest listed value, values of types
+    /// without a `Distance` that aren't listed are dropped.
+    pub fn clip(&self, v: T) -> Option<T> {
+        let (low, high) = match self.clip_mode {
+            ClipMode::None => return Some(v),
+            ClipMode::Low => (true, false),
+            ClipMode::High => (false, true),
+            ClipMode::Both => (true, true),
+        };
+        let (min, max) = match &self.range {
+            Range::None => return Some(v),
+            Range::Min(min) => (Some(min), None),
+            Range::Max(max) => (None, Some(max)),
+            Range::MinMax(min, max) => (Some(min), Some(max)),
+            Range::Vals(vals) => {
+                if vals.is_empty() || vals.contains(&v) {
+                    return Some(v);
+                }
+                let mut nearest: Option<(&T, f64)> = None;
+                for c in vals {
+                    let d = c.distance(&v)?;
+                    if !matches!(nearest, Some((_, n)) if n <= d) {
+                        nearest = Some((c, d));
+                    }
+                }
+                return nearest.map(|(c, _)| c.clone());
+            }
+        };
+        Some(match (min, max) {
+            (Some(min), _) if low && v < *min => min.clone(),
+            (_, Some(max)) if high && v > *max => max.clone(),
+            _ => v,
+        })
+    }
+}
+
+/// Identifies the storage behind a value, equal for values built from clones of the same `Arc`.
+///
+/// Only the address is kept, so an id doesn't keep the storage alive.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct StorageId(usize);
+
+impl StorageId {
+    /// Get the id of the storage `Arc`.
+    pub fn of<S: ?Sized>(storage: &Arc<S>) -> Self {
+        StorageId(&**storage as *const S as *const () as usize)
+    }
+}
+
+impl<S: ?Sized, T> Value<Arc<S>, T> {
+    /// Get the id of the storage.
+    pub fn storage_id(&self) -> StorageId {
+        StorageId::of(&self.value)
+    }
 }
 
 pub type ValueGet<T> = Value<Arc<dyn Get<T>>, T>;
@@ -295,40 +540,109 @@ mod tests {
             let v = serde_json::to_value(&c);
             assert!(v.is_ok());
             assert_eq!(v.unwrap(), serde_json::Value::String(s.to_string()));
+            assert_eq!(c.to_string(), *s);
+            assert_eq!(s.parse::<ClipMode>().unwrap(), *c);
+            assert_eq!(s.to_uppercase().parse::<ClipMode>().unwrap(), *c);
+        }
+        for s in &["", "clip", "lo", "both "] {
+            assert_matches!(
+                s.parse::<ClipMode>(),
+                Err(OscQueryError::InvalidClipMode(e)) if e == *s
+            );
         }
     }
 
+    #[test]
+    fn clip() {
+        fn v<T>(clip_mode: ClipMode, range: Range<T>) -> Value<(), T> {
+            ValueBuilder::new(())
+                .with_clip_mode(clip_mode)
+                .with_range(range)
+                .build()
+        }
+        let r = Range::MinMax(0, 127);
+        assert_eq!(Some(127), v(ClipMode::Both, r.clone()).clip(200));
+        assert_eq!(Some(0), v(ClipMode::Both, r.clone()).clip(-3));
+        assert_eq!(Some(200), v(ClipMode::Low, r.clone()).clip(200));
+        assert_eq!(Some(0), v(ClipMode::Low, r.clone()).clip(-3));
+        assert_eq!(Some(127), v(ClipMode::High, r.clone()).clip(200));
+        assert_eq!(Some(-3), v(ClipMode::High, r.clone()).clip(-3));
+        assert_eq!(Some(-3), v(ClipMode::None, r).clip(-3));
+        assert_eq!(Some(5), v(ClipMode::Both, Range::Max(10)).clip(5));
+        assert_eq!(Some(1.5), v(ClipMode::Both, Range::Min(1.5)).clip(-2.0));
+
+        let vals = Range::Vals(vec![0.0, 0.5, 1.0]);
+        assert_eq!(Some(0.5), v(ClipMode::Both, vals.clone()).clip(0.6));
+        assert_eq!(Some(1.0), v(ClipMode::Low, vals.clone()).clip(7.0));
+        assert_eq!(Some(0.6), v(ClipMode::None, vals).clip(0.6));
+        assert_eq!(
+            Some('c'),
+            v(ClipMode::Both, Range::Vals(vec!['a', 'c'])).clip('d')
+        );
+
+        let words = Range::Vals(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(
+            Some("b".to_string()),
+            v(ClipMode::Both, words.clone()).clip("b".to_string())
+        );
+        assert_eq!(None, v(ClipMode::Both, words).clip("c".to_string()));
+    }
+
     #[test]
     fn range() {
         let r: Range<u32> = Range::None;
         let v = serde_json::to_value(&r);
         assert!(v.is_ok());
         assert_eq!(v.unwrap(), json!({}));
+        assert_eq!(serde_json::from_value::<Range<u32>>(json!({})).unwrap(), r);
 
         let r: Range<u32> = Range::Min(23);
         let v = serde_json::to_value(&r);
         assert!(v.is_ok());
         assert_eq!(v.unwrap(), json!({"MIN": 23}));
+        assert_eq!(
+            serde_json::from_value::<Range<u32>>(json!({"MIN": 23})).unwrap(),
+            r
+        );
 
         let r: Range<f32> = Range::Max(100f32);
         let v = serde_json::to_value(&r);
         assert!(v.is_ok());
         assert_eq!(v.unwrap(), json!({"MAX": 100.0}));
+        assert_eq!(
+            serde_json::from_value::<Range<f32>>(json!({"MAX": 100.0})).unwrap(),
+            r
+        );
 
         let r: Range<f32> = Range::MinMax(2f32, 100f32);
         let v = serde_json::to_value(&r);
         assert!(v.is_ok());
         assert_eq!(v.unwrap(), json!({"MAX": 100.0, "MIN": 2.0}));
+        assert_eq!(
+            serde_json::from_value::<Range<f32>>(json!({"MAX": 100.0, "MIN": 2.0})).unwrap(),
+            r
+        );
 
         let r: Range<i32> = Range::Vals(vec![-1i32, 2i32]);
         let v = serde_json::to_value(&r);
         assert!(v.is_ok());
         assert_eq!(v.unwrap(), json!({"VALS": [-1, 2]}));
+        assert_eq!(
+            serde_json::from_value::<Range<i32>>(json!({"VALS": [-1, 2]})).unwrap(),
+            r
+        );
 
         let r: Range<String> = Range::Vals(vec!["x".to_string(), "y".to_string(), "z".to_string()]);
         let v = serde_json::to_value(&r);
         assert!(v.is_ok());
         assert_eq!(v.unwrap(), json!({"VALS": ["x", "y", "z"]}));
+        assert_eq!(
+            serde_json::from_value::<Range<String>>(json!({"VALS": ["x", "y", "z"]})).unwrap(),
+            r
+        );
+
+        assert!(serde_json::from_value::<Range<u32>>(json!({"MIN": "x"})).is_err());
+        assert!(serde_json::from_value::<Range<u32>>(json!([1, 2])).is_err());
     }
 
     #[test]
@@ -0,0 +1,64 @@
+//! Lock backed `Get` and `Set` implementations, for the types that aren't `Copy` so they can't be
+//! stored in an `atomic::Atomic<T>`.
+use super::*;
+use std::sync::{Mutex, RwLock};
+
+/// Storage for a value behind a `RwLock`, for values that are read more often than written.
+///
+/// Panics if the lock is poisoned.
+#[derive(Debug, Default)]
+pub struct RwLockValue<T>(RwLock<T>);
+
+impl<T> RwLockValue<T> {
+    pub fn new(value: T) -> Self {
+        Self(RwLock::new(value))
+    }
+}
+
+impl<T> Get<T> for RwLockValue<T>
+where
+    T: Clone + Send + Sync,
+{
+    fn get(&self) -> T {
+        self.0.read().expect("failed to lock").clone()
+    }
+}
+
+impl<T> Set<T> for RwLockValue<T>
+where
+    T: Send + Sync,
+{
+    fn set(&self, value: T) {
+        *self.0.write().expect("failed to lock") = value;
+    }
+}
+
+/// Storage for a value behind a `Mutex`.
+///
+/// Panics if the lock is poisoned.
+#[derive(Debug, Default)]
+pub struct MutexValue<T>(Mutex<T>);
+
+impl<T> MutexValue<T> {
+    pub fn new(value: T) -> Self {
+        Self(Mutex::new(value))
+    }
+}
+
+impl<T> Get<T> for MutexValue<T>
+where
+    T: Clone + Send,
+{
+    fn get(&self) -> T {
+        self.0.lock().expect("failed to lock").clone()
+    }
+}
+
+impl<T> Set<T> for MutexValue<T>
+where
+    T: Send,
+{
+    fn set(&self, value: T) {
+        *self.0.lock().expect("failed to lock") = value;
+    }
+}
@@ -0,0 +1,23 @@
+use oscquery::value::Range;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+
+fn round_trip<T>(range: Range<T>)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let r: Range<T> = serde_json::from_value(serde_json::to_value(&range).unwrap()).unwrap();
+    assert_eq!(r, range);
+}
+
+#[test]
+fn every_variant() {
+    round_trip::<i32>(Range::None);
+    round_trip(Range::Min(-2i32));
+    round_trip(Range::Max(1.5f32));
+    round_trip(Range::MinMax(0.0f64, 1.0f64));
+    round_trip(Range::Vals(vec![1i64, 2, 3]));
+    round_trip(Range::Vals(vec!["a".to_string(), "b".to_string()]));
+    round_trip::<char>(Range::Vals(Vec::new()));
+    round_trip(Range::MinMax('a', 'z'));
+}
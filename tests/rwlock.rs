@@ -0,0 +1,72 @@
+use oscquery::node::GetSet;
+use oscquery::osc::{OscMessage, OscPacket, OscType};
+use oscquery::param::ParamGetSet;
+use oscquery::root::Root;
+use oscquery::value::{Get, RwLockValue, ValueBuilder};
+use std::sync::Arc;
+use std::thread;
+
+const WRITES: usize = 200;
+
+#[test]
+fn concurrent_string() {
+    let root = Arc::new(Root::new(None));
+    let storage = Arc::new(RwLockValue::new("start".to_string()));
+    root.add_node(
+        GetSet::new(
+            "name",
+            None,
+            vec![ParamGetSet::String(
+                ValueBuilder::new(storage.clone() as _).build(),
+            )],
+            None,
+        )
+        .unwrap(),
+        None,
+    )
+    .unwrap();
+
+    let writers: Vec<_> = (0..2)
+        .map(|w| {
+            let root = root.clone();
+            thread::spawn(move || {
+                for i in 0..WRITES {
+                    let packet = OscPacket::Message(OscMessage {
+                        addr: "/name".into(),
+                        args: vec![OscType::String(format!("writer {} write {}", w, i))],
+                    });
+                    root.handle_osc_packet(&packet, None);
+                }
+            })
+        })
+        .collect();
+    //every read sees a whole value, the initial one or one that was written
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let root = root.clone();
+            thread::spawn(move || {
+                for _ in 0..WRITES {
+                    let j = serde_json::to_value(&*root).unwrap();
+                    let v = j["CONTENTS"]["name"]["VALUE"][0]
+                        .as_str()
+                        .unwrap()
+                        .to_string();
+                    assert!(v == "start" || v.starts_with("writer "), "{}", v);
+                }
+            })
+        })
+        .collect();
+    for t in writers.into_iter().chain(readers) {
+        t.join().unwrap();
+    }
+
+    let last = storage.get();
+    assert!(
+        last == format!("writer 0 write {}", WRITES - 1)
+            || last == format!("writer 1 write {}", WRITES - 1),
+        "{}",
+        last
+    );
+    #[cfg(debug_assertions)]
+    assert_eq!(oscquery::stats::caught_panics(), 0);
+}
@@ -0,0 +1,85 @@
+use oscquery::node::{Container, GetSet};
+use oscquery::osc::{OscPacket, OscType};
+use oscquery::param::ParamGetSet;
+use oscquery::value::ValueBuilder;
+use oscquery::OscQueryServerBuilder;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tungstenite::Message;
+
+fn get(addr: &SocketAddr, path: &str) -> u16 {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    )
+    .unwrap();
+    let mut rsp = String::new();
+    stream.read_to_string(&mut rsp).unwrap();
+    rsp.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+#[test]
+fn serve() {
+    let server = OscQueryServerBuilder::new("127.0.0.1:0".parse().unwrap())
+        .with_ws("127.0.0.1:0")
+        .build()
+        .unwrap();
+    let mixer = server
+        .add_node(Container::new("mixer", None).unwrap(), None)
+        .unwrap();
+    let gain = server
+        .add_node(
+            GetSet::new(
+                "gain",
+                None,
+                vec![ParamGetSet::Float(
+                    ValueBuilder::new(Arc::new(::atomic::Atomic::new(0.5f32)) as _).build(),
+                )],
+                None,
+            )
+            .unwrap(),
+            Some(mixer),
+        )
+        .unwrap();
+
+    let http = *server.http_local_addr().unwrap();
+    assert_eq!(get(&http, "/"), 200);
+    assert_eq!(get(&http, "/?HOST_INFO"), 200);
+    assert_eq!(get(&http, "/mixer/gain?VALUE"), 200);
+    assert_eq!(get(&http, "/missing"), 404);
+    assert_eq!(get(&http, "/mixer?NOT_AN_ATTRIBUTE"), 400);
+
+    let ws = *server.ws_local_addr().unwrap();
+    let stream = TcpStream::connect(ws).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let (mut socket, _) = tungstenite::client(format!("ws://{}/", ws), stream).unwrap();
+    socket
+        .write_message(Message::Text(
+            "{\"COMMAND\":\"LISTEN\",\"DATA\":\"/mixer/gain\"}".into(),
+        ))
+        .unwrap();
+    //the listen is handled asynchronously, the trigger is sent once it is
+    let start = Instant::now();
+    while !server.trigger(gain) {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let value = loop {
+        if let Message::Binary(v) = socket.read_message().unwrap() {
+            if let Ok(OscPacket::Message(m)) = oscquery::osc::decoder::decode(&v) {
+                break m.args;
+            }
+        }
+    };
+    assert_eq!(value, vec![OscType::Float(0.5)]);
+    socket.close(None).unwrap();
+
+    #[cfg(debug_assertions)]
+    assert_eq!(oscquery::stats::caught_panics(), 0);
+}